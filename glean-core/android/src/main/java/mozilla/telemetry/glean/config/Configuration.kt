@@ -4,6 +4,7 @@
 
 package mozilla.telemetry.glean.config
 
+import mozilla.telemetry.glean.rust.Constants
 import mozilla.telemetry.glean.rust.toByte
 
 import com.sun.jna.Structure
@@ -17,6 +18,8 @@ import mozilla.telemetry.glean.net.PingUploader
  *  If this side is changed, the Rust side need to be changed, too.
  */
 @Structure.FieldOrder(
+    "structSize",
+    "abiVersion",
     "dataDir",
     "packageName",
     "languageBindingName",
@@ -37,6 +40,10 @@ internal class FfiConfiguration(
      * in order for Structure to turn them into the right memory representiation
      */
 
+    @JvmField
+    public var structSize: Int = 0
+    @JvmField
+    public var abiVersion: Int = Constants.GLEAN_FFI_ABI_VERSION
     @JvmField
     public var dataDir: String = dataDir
     @JvmField
@@ -53,6 +60,8 @@ internal class FfiConfiguration(
     init {
         // Force UTF-8 string encoding when passing strings over the FFI
         this.stringEncoding = "UTF-8"
+        // Let the Rust side check that it agrees on the memory layout
+        this.structSize = size()
     }
 }
 
@@ -37,6 +37,10 @@ class Constants {
 
         // A HTTP response code.
         val UPLOAD_RESULT_HTTP_STATUS: Int = 0x8000
+
+        // The version of the C ABI these bindings were written for,
+        // defined as `GLEAN_FFI_ABI_VERSION` in `glean-core/ffi/src/lib.rs`.
+        val GLEAN_FFI_ABI_VERSION: Int = 1
     }
 }
 
@@ -89,6 +93,8 @@ internal interface LibGleanFFI : Library {
 
     fun glean_initialize(cfg: FfiConfiguration): Byte
 
+    fun glean_ffi_abi_version(): Int
+
     fun glean_clear_application_lifetime_metrics()
 
     fun glean_set_dirty_flag(flag: Byte)
@@ -15,6 +15,12 @@ pub fn criterion_benchmark(c: &mut Criterion) {
         language_binding_name: "Rust".into(),
         max_events: None,
         delay_ping_lifetime_io: false,
+        key_provider: None,
+        upload_batch_limits: None,
+        ping_compression: Default::default(),
+        server_endpoint: None,
+        path_template: None,
+        storage_durability: Default::default(),
     };
 
     let mut glean = Glean::new(cfg).unwrap();
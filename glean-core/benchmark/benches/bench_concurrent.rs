@@ -0,0 +1,114 @@
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use benchmark::glean_core::{
+    metrics::*, CommonMetricData, Configuration, Glean, StorageDurability,
+};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+
+/// The number of recordings of each metric per thread and iteration.
+const RECORDINGS_PER_THREAD: usize = 1_000;
+
+fn metric(name: &str) -> CommonMetricData {
+    CommonMetricData {
+        name: name.into(),
+        category: "local".into(),
+        send_in_pings: vec!["store".into()],
+        ..Default::default()
+    }
+}
+
+/// Run the functions built by `make_fn` on `threads` threads and wait for all of them.
+fn spawn_and_join<F, G>(threads: usize, make_fn: G)
+where
+    F: FnOnce() + Send + 'static,
+    G: Fn() -> F,
+{
+    let handles: Vec<_> = (0..threads).map(|_| thread::spawn(make_fn())).collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+}
+
+/// Record counters and memory distributions from many threads.
+///
+/// Compares locking a global Glean object for every recording
+/// with recording into an `Accumulator`, merged into Glean once per iteration.
+pub fn criterion_benchmark(c: &mut Criterion) {
+    let data_dir = tempfile::tempdir().unwrap();
+    let tmpname = data_dir.path().display().to_string();
+    let cfg = Configuration {
+        upload_enabled: true,
+        data_path: tmpname,
+        application_id: "glean.bench".into(),
+        language_binding_name: "Rust".into(),
+        max_events: None,
+        delay_ping_lifetime_io: false,
+        key_provider: None,
+        upload_batch_limits: None,
+        ping_compression: Default::default(),
+        server_endpoint: None,
+        path_template: None,
+        storage_durability: StorageDurability::default(),
+    };
+    let glean = Arc::new(Mutex::new(Glean::new(cfg).unwrap()));
+    let accumulator = Arc::new(Accumulator::new());
+    let counter = CounterMetric::new(metric("counter"));
+    let distribution = MemoryDistributionMetric::new(metric("memory"), MemoryUnit::Byte);
+
+    let mut group = c.benchmark_group("concurrent recording");
+    group.sample_size(10);
+
+    for &threads in &[1, 4, 16] {
+        group.bench_with_input(
+            BenchmarkId::new("mutex", threads),
+            &threads,
+            |b, &threads| {
+                b.iter(|| {
+                    spawn_and_join(threads, || {
+                        let glean = Arc::clone(&glean);
+                        let counter = counter.clone();
+                        let distribution = distribution.clone();
+                        move || {
+                            for i in 0..RECORDINGS_PER_THREAD {
+                                let glean = glean.lock().unwrap();
+                                counter.add(&glean, 1);
+                                distribution.accumulate(&glean, i as u64);
+                            }
+                        }
+                    });
+                })
+            },
+        );
+
+        group.bench_with_input(
+            BenchmarkId::new("accumulator", threads),
+            &threads,
+            |b, &threads| {
+                b.iter(|| {
+                    spawn_and_join(threads, || {
+                        let accumulator = Arc::clone(&accumulator);
+                        let counter = counter.clone();
+                        let distribution = distribution.clone();
+                        move || {
+                            for i in 0..RECORDINGS_PER_THREAD {
+                                accumulator.add_to_counter(1, &counter, 1);
+                                accumulator.accumulate_memory_distribution(
+                                    2,
+                                    &distribution,
+                                    i as u64,
+                                );
+                            }
+                        }
+                    });
+                    accumulator.merge_into(&glean.lock().unwrap());
+                })
+            },
+        );
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, criterion_benchmark);
+criterion_main!(benches);
@@ -0,0 +1,77 @@
+use benchmark::glean_core::{
+    metrics::*, storage::StorageManager, CommonMetricData, Configuration, Glean, Lifetime,
+};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+
+const STORE: &str = "store";
+
+/// Create a Glean instance with `count` counters recorded in one store.
+fn glean_with_metrics(count: usize) -> (Glean, tempfile::TempDir) {
+    let data_dir = tempfile::tempdir().unwrap();
+    let tmpname = data_dir.path().display().to_string();
+    let cfg = Configuration {
+        upload_enabled: true,
+        data_path: tmpname,
+        application_id: "glean.bench".into(),
+        language_binding_name: "Rust".into(),
+        max_events: None,
+        delay_ping_lifetime_io: false,
+        key_provider: None,
+        upload_batch_limits: None,
+        ping_compression: Default::default(),
+        server_endpoint: None,
+        path_template: None,
+        storage_durability: Default::default(),
+    };
+    let glean = Glean::new(cfg).unwrap();
+
+    for i in 0..count {
+        let counter = CounterMetric::new(CommonMetricData {
+            name: format!("counter_{}", i),
+            category: "local".into(),
+            send_in_pings: vec![STORE.into()],
+            ..Default::default()
+        });
+        counter.add(&glean, 1);
+    }
+
+    (glean, data_dir)
+}
+
+/// Look up a single metric in stores of different sizes.
+///
+/// Compares the keyed lookup of `StorageManager::snapshot_metric`
+/// with iterating over the whole store of every lifetime.
+pub fn criterion_benchmark(c: &mut Criterion) {
+    let mut group = c.benchmark_group("metric lookup");
+
+    for &count in &[100, 1_000, 10_000] {
+        let (glean, _t) = glean_with_metrics(count);
+        let metric_id = format!("local.counter_{}", count / 2);
+
+        group.bench_with_input(BenchmarkId::new("keyed", count), &metric_id, |b, id| {
+            b.iter(|| StorageManager.snapshot_metric(glean.storage(), STORE, id))
+        });
+
+        group.bench_with_input(BenchmarkId::new("scan", count), &metric_id, |b, id| {
+            b.iter(|| {
+                let mut snapshot = None;
+                for &lifetime in &[Lifetime::Ping, Lifetime::Application, Lifetime::User] {
+                    glean
+                        .storage()
+                        .iter_store_from(lifetime, STORE, None, |metric_id, metric| {
+                            if metric_id == id.as_bytes() {
+                                snapshot = Some(metric.clone());
+                            }
+                        });
+                }
+                snapshot
+            })
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, criterion_benchmark);
+criterion_main!(benches);
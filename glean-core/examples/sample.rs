@@ -24,6 +24,12 @@ fn main() {
         upload_enabled: true,
         max_events: None,
         delay_ping_lifetime_io: false,
+        key_provider: None,
+        upload_batch_limits: None,
+        ping_compression: Default::default(),
+        server_endpoint: None,
+        path_template: None,
+        storage_durability: Default::default(),
     };
     let mut glean = Glean::new(cfg).unwrap();
     glean.register_ping_type(&PingType::new("baseline", true, false, vec![]));
@@ -9,6 +9,8 @@ int main(void)
 {
   glean_enable_logging();
   FfiConfiguration cfg = {
+    sizeof(FfiConfiguration),
+    GLEAN_FFI_ABI_VERSION,
     "/tmp/glean_data",
     "c-app",
     "C",
@@ -4,8 +4,7 @@
 
 /* DO NOT MODIFY THIS MANUALLY! This file was generated using cbindgen.
  * To generate this file:
- *   1. Get the latest cbindgen using `cargo install --force cbindgen`
- *      a. Alternatively, you can clone `https://github.com/eqrion/cbindgen` and use a tagged release
+ *   1. Get the cbindgen version used on CI using `cargo install --locked --version 0.24.5 cbindgen`
  *   2. Run `make cbindgen`
  */
 
@@ -16,6 +15,13 @@
 #include <stdint.h>
 #include <stdlib.h>
 
+/**
+ * The version of the C ABI of this library.
+ *
+ * This needs to be increased on every change to the layout of `FfiConfiguration`.
+ */
+#define GLEAN_FFI_ABI_VERSION 1
+
 /**
  * A HTTP response code.
  *
@@ -225,6 +231,30 @@ typedef struct {
   uint8_t *data;
 } ByteBuffer;
 
+/**
+ * A FFI-compatible representation of a single ping request in a batch.
+ *
+ * This is exposed as a C struct, like this:
+ *
+ * ```c
+ * typedef struct {
+ *   char *document_id;
+ *   char *path;
+ *   ByteBuffer body;
+ *   char *headers;
+ * } FfiPingRequest;
+ * ```
+ *
+ * The fields are encoded the same way as in the `Upload` variant of the `FfiPingUploadTask`
+ * and are freed together with the task.
+ */
+typedef struct {
+  char *document_id;
+  char *path;
+  ByteBuffer body;
+  char *headers;
+} FfiPingRequest;
+
 /**
  * A FFI-compatible representation for the PingUploadTask.
  *
@@ -235,6 +265,8 @@ typedef struct {
  *   FfiPingUploadTask_Upload,
  *   FfiPingUploadTask_Wait,
  *   FfiPingUploadTask_Done,
+ *   FfiPingUploadTask_UploadBatch,
+ *   FfiPingUploadTask_WaitFor,
  * };
  * typedef uint8_t FfiPingUploadTask_Tag;
  *
@@ -246,9 +278,23 @@ typedef struct {
  *   char *headers;
  * } FfiPingUploadTask_Upload_Body;
  *
+ * typedef struct {
+ *   FfiPingUploadTask_Tag tag;
+ *   FfiPingRequest *requests;
+ *   int32_t len;
+ * } FfiPingUploadTask_UploadBatch_Body;
+ *
+ * typedef struct {
+ *   FfiPingUploadTask_Tag tag;
+ *   uint8_t reason;
+ *   uint64_t delay;
+ * } FfiPingUploadTask_WaitFor_Body;
+ *
  * typedef union {
  *   FfiPingUploadTask_Tag tag;
  *   FfiPingUploadTask_Upload_Body upload;
+ *   FfiPingUploadTask_UploadBatch_Body upload_batch;
+ *   FfiPingUploadTask_WaitFor_Body wait_for;
  * } FfiPingUploadTask;
  *
  * ```
@@ -266,6 +312,15 @@ typedef struct {
  *   Instead `glean_process_ping_upload_response` will receive the whole enum, taking care of
  *   freeing the memory.
  *
+ * The `UploadBatch` variant carries an array of `len` requests, in upload order.
+ * It is only returned if batching is enabled (see `glean_set_upload_batch_limits`)
+ * and needs to be passed to `glean_process_ping_upload_batch_response`.
+ *
+ * The `WaitFor` variant is returned while pings are held back because of the network state
+ * (see `glean_set_network_state`). The `reason` is `1` if the device is offline
+ * and `2` if it is on a metered network. The uploader should ask again after `delay` milliseconds
+ * or as soon as the network state changes.
+ *
  *
  * The order of variants should be the same as in `glean-core/src/upload/mod.rs`
  * and `glean-core/android/src/main/java/mozilla/telemetry/glean/net/Upload.kt`.
@@ -275,6 +330,8 @@ enum FfiPingUploadTask_Tag {
   FfiPingUploadTask_Upload,
   FfiPingUploadTask_Wait,
   FfiPingUploadTask_Done,
+  FfiPingUploadTask_UploadBatch,
+  FfiPingUploadTask_WaitFor,
 };
 typedef uint8_t FfiPingUploadTask_Tag;
 
@@ -286,18 +343,44 @@ typedef struct {
   char *headers;
 } FfiPingUploadTask_Upload_Body;
 
+typedef struct {
+  FfiPingUploadTask_Tag tag;
+  FfiPingRequest *requests;
+  int32_t len;
+} FfiPingUploadTask_UploadBatch_Body;
+
+typedef struct {
+  FfiPingUploadTask_Tag tag;
+  uint8_t reason;
+  uint64_t delay;
+} FfiPingUploadTask_WaitFor_Body;
+
 typedef union {
   FfiPingUploadTask_Tag tag;
   FfiPingUploadTask_Upload_Body upload;
+  FfiPingUploadTask_UploadBatch_Body upload_batch;
+  FfiPingUploadTask_WaitFor_Body wait_for;
 } FfiPingUploadTask;
 
 /**
  * Configuration over FFI.
  *
  * **CAUTION**: This must match _exactly_ the definition on the Kotlin side.
- * If this side is changed, the Kotlin side need to be changed, too.
+ * If this side is changed, the Kotlin side need to be changed, too,
+ * and `GLEAN_FFI_ABI_VERSION` needs to be increased.
+ *
+ * The leading `struct_size` and `abi_version` fields must never move,
+ * so that a configuration built for another ABI can be detected and rejected.
  */
 typedef struct {
+  /**
+   * The size of the configuration as known to the caller, i.e. `sizeof(FfiConfiguration)`.
+   */
+  uint32_t struct_size;
+  /**
+   * The `GLEAN_FFI_ABI_VERSION` the caller was built against.
+   */
+  uint32_t abi_version;
   FfiStr data_dir;
   FfiStr package_name;
   FfiStr language_binding_name;
@@ -306,6 +389,21 @@ typedef struct {
   uint8_t delay_ping_lifetime_io;
 } FfiConfiguration;
 
+/**
+ * A function notified of every ping submitted and uploaded.
+ *
+ * `stage` is `1` when the ping was submitted and `2` when it is handed out for upload.
+ * `payload` and `headers` are JSON-encoded. All strings are only valid during the call.
+ *
+ * The callback is called while Glean is locked, so it must not call back into Glean.
+ */
+typedef void (*PingObserverCallback)(uint8_t stage,
+                                     const char *ping_name,
+                                     const char *document_id,
+                                     const char *path,
+                                     const char *payload,
+                                     const char *headers);
+
 /**
  * Identifier for a running timer.
  */
@@ -319,6 +417,11 @@ uint8_t glean_boolean_test_has_value(uint64_t metric_id, FfiStr storage_name);
 
 void glean_clear_application_lifetime_metrics(void);
 
+/**
+ * Remove all registered ping observers.
+ */
+void glean_clear_ping_observers(void);
+
 void glean_counter_add(uint64_t metric_id, int32_t amount);
 
 int32_t glean_counter_test_get_num_recorded_errors(uint64_t metric_id,
@@ -420,22 +523,55 @@ char *glean_experiment_test_get_data(FfiStr experiment_id);
 
 uint8_t glean_experiment_test_is_active(FfiStr experiment_id);
 
+/**
+ * Get the version of the C ABI of this library.
+ *
+ * Language bindings pass the `GLEAN_FFI_ABI_VERSION` they were built against in the configuration,
+ * and can compare it to this value beforehand to detect a mismatched library.
+ */
+uint32_t glean_ffi_abi_version(void);
+
+/**
+ * Get the server endpoint pings are uploaded to, if one was configured,
+ * e.g. with the `server_endpoint` key of `glean_initialize_with_json`.
+ *
+ * Uploaders send pings to this endpoint, followed by the path of each upload request.
+ * Returns a null pointer if no endpoint was configured, in which case the platform's default is used.
+ * The returned string needs to be freed with `glean_str_free`.
+ */
+char *glean_get_server_endpoint(void);
+
 void glean_get_upload_task(FfiPingUploadTask *result);
 
 /**
  * # Safety
  *
- * A valid and non-null configuration object is required for this function.
+ * A valid configuration object is required for this function.
+ * A null pointer or a configuration built for another ABI is reported as an error.
  */
 uint8_t glean_initialize(const FfiConfiguration *cfg);
 
 /**
  * # Safety
  *
- * A valid and non-null configuration object is required for this function.
+ * A valid configuration object is required for this function.
+ * A null pointer or a configuration built for another ABI is reported as an error.
  */
 uint8_t glean_initialize_for_subprocess(const FfiConfiguration *cfg);
 
+/**
+ * Initialize Glean from a JSON configuration document.
+ *
+ * This is an alternative to `glean_initialize` that can take new configuration options
+ * without changing the layout of `FfiConfiguration`.
+ * Only `data_path` and `application_id` are required, other keys take their default value if missing.
+ * Unknown keys are ignored and logged as a warning.
+ * The configured `server_endpoint` can be read back with `glean_get_server_endpoint`.
+ *
+ * An invalid document is reported as an error, see `glean_last_error_message`.
+ */
+uint8_t glean_initialize_with_json(FfiStr json);
+
 uint8_t glean_is_dirty_flag_set(void);
 
 uint8_t glean_is_first_run(void);
@@ -488,6 +624,20 @@ int32_t glean_labeled_string_test_get_num_recorded_errors(uint64_t metric_id,
                                                           int32_t error_type,
                                                           FfiStr storage_name);
 
+/**
+ * Take the message of the last error that occurred on the calling thread.
+ *
+ * Errors are not returned by the functions they occur in.
+ * Those functions log them and return a default value instead,
+ * and the message of the error is kept until it is taken with this function
+ * or the next call on the same thread.
+ * To tell whether a specific call failed, take the last error right after the call.
+ *
+ * Returns a null pointer if the last call succeeded or its error was already taken.
+ * The returned string needs to be freed with `glean_str_free`.
+ */
+char *glean_last_error_message(void);
+
 void glean_memory_distribution_accumulate(uint64_t metric_id, uint64_t sample);
 
 void glean_memory_distribution_accumulate_samples(uint64_t metric_id,
@@ -650,6 +800,23 @@ uint8_t glean_on_ready_to_submit_pings(void);
 
 char *glean_ping_collect(uint64_t ping_type_handle, FfiStr reason);
 
+/**
+ * Process and free a `FfiPingUploadTask` carrying a batch of pings.
+ *
+ * The upload result of each ping is passed in `statuses`, in the order of the requests
+ * in the batch. Pings without a result are treated as a recoverable failure.
+ *
+ * After return the `task` should not be used further by the caller.
+ *
+ * # Safety
+ *
+ * A valid and non-null upload task object is required for this function.
+ * `statuses` needs to point to an array of at least `statuses_len` upload results.
+ */
+void glean_process_ping_upload_batch_response(FfiPingUploadTask *task,
+                                              const uint32_t *statuses,
+                                              int32_t statuses_len);
+
 /**
  * Process and free a `FfiPingUploadTask`.
  *
@@ -674,10 +841,28 @@ int64_t glean_quantity_test_get_value(uint64_t metric_id, FfiStr storage_name);
 
 uint8_t glean_quantity_test_has_value(uint64_t metric_id, FfiStr storage_name);
 
+/**
+ * Register a callback to be notified of every ping submitted and uploaded.
+ *
+ * See `PingObserverCallback` for details.
+ */
+void glean_register_ping_observer(PingObserverCallback callback);
+
 void glean_register_ping_type(uint64_t ping_type_handle);
 
 uint8_t glean_set_debug_view_tag(FfiStr tag);
 
+/**
+ * Set a debug view tag that is only applied within the given limits.
+ *
+ * Non-positive `expires_after_ms` and `max_pings` and an empty list of pings mean no limit.
+ */
+uint8_t glean_set_debug_view_tag_with_limits(FfiStr tag,
+                                             int64_t expires_after_ms,
+                                             int32_t max_pings,
+                                             RawStringArray raw_pings,
+                                             int32_t pings_count);
+
 void glean_set_dirty_flag(uint8_t flag);
 
 void glean_set_experiment_active(FfiStr experiment_id,
@@ -690,10 +875,42 @@ void glean_set_experiment_inactive(FfiStr experiment_id);
 
 void glean_set_log_pings(uint8_t value);
 
+/**
+ * Update the network conditions pings are uploaded under.
+ *
+ * While pings are held back because of the network state,
+ * `glean_get_upload_task` returns a `WaitFor` task.
+ */
+void glean_set_network_state(uint8_t online, uint8_t metered);
+
 uint8_t glean_set_source_tags(RawStringArray raw_tags, int32_t tags_count);
 
+/**
+ * Set source tags that are only applied within the given limits.
+ *
+ * Non-positive `expires_after_ms` and `max_pings` and an empty list of pings mean no limit.
+ */
+uint8_t glean_set_source_tags_with_limits(RawStringArray raw_tags,
+                                          int32_t tags_count,
+                                          int64_t expires_after_ms,
+                                          int32_t max_pings,
+                                          RawStringArray raw_pings,
+                                          int32_t pings_count);
+
+/**
+ * Enable batching of multiple pings into a single upload task.
+ *
+ * Passing a `max_count` of `0` disables batching.
+ */
+void glean_set_upload_batch_limits(int32_t max_count, int64_t max_bytes);
+
 void glean_set_upload_enabled(uint8_t flag);
 
+/**
+ * Stop watching the `debug.json` file.
+ */
+void glean_stop_watching_debug_file(void);
+
 /**
  * Public destructor for strings managed by the other side of the FFI.
  *
@@ -777,3 +994,10 @@ void glean_uuid_set(uint64_t metric_id, FfiStr value);
 char *glean_uuid_test_get_value(uint64_t metric_id, FfiStr storage_name);
 
 uint8_t glean_uuid_test_has_value(uint64_t metric_id, FfiStr storage_name);
+
+/**
+ * Start watching the `debug.json` file in the data path for debug options.
+ *
+ * A non-positive `poll_interval_ms` polls the file once per second.
+ */
+uint8_t glean_watch_debug_file(int64_t poll_interval_ms);
@@ -4,7 +4,10 @@
 
 use ffi_support::FfiStr;
 
-use crate::{define_metric, handlemap_ext::HandleMapExtension, with_glean_value, Lifetime};
+use crate::{
+    define_metric, ffi_string_ext::FallibleToString, handlemap_ext::HandleMapExtension,
+    with_glean_value, Lifetime,
+};
 
 define_metric!(BooleanMetric => BOOLEAN_METRICS {
     new           -> glean_new_boolean_metric(),
@@ -23,10 +26,9 @@ pub extern "C" fn glean_boolean_set(metric_id: u64, value: u8) {
 #[no_mangle]
 pub extern "C" fn glean_boolean_test_has_value(metric_id: u64, storage_name: FfiStr) -> u8 {
     with_glean_value(|glean| {
-        BOOLEAN_METRICS.call_infallible(metric_id, |metric| {
-            metric
-                .test_get_value(glean, storage_name.as_str())
-                .is_some()
+        BOOLEAN_METRICS.call_with_log(metric_id, |metric| {
+            let storage_name = storage_name.to_string_fallible()?;
+            Ok(metric.test_get_value(glean, &storage_name).is_some())
         })
     })
 }
@@ -34,8 +36,11 @@ pub extern "C" fn glean_boolean_test_has_value(metric_id: u64, storage_name: Ffi
 #[no_mangle]
 pub extern "C" fn glean_boolean_test_get_value(metric_id: u64, storage_name: FfiStr) -> u8 {
     with_glean_value(|glean| {
-        BOOLEAN_METRICS.call_infallible(metric_id, |metric| {
-            metric.test_get_value(glean, storage_name.as_str()).unwrap()
+        BOOLEAN_METRICS.call_with_log(metric_id, |metric| {
+            let storage_name = storage_name.to_string_fallible()?;
+            metric
+                .test_get_value(glean, &storage_name)
+                .ok_or_else(glean_core::Error::no_value)
         })
     })
 }
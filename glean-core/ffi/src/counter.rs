@@ -4,23 +4,32 @@
 
 use ffi_support::FfiStr;
 
-use crate::{define_metric, handlemap_ext::HandleMapExtension, with_glean_value, Lifetime};
+use crate::{
+    define_metric, ffi_string_ext::FallibleToString, handlemap_ext::HandleMapExtension,
+    with_accumulator, with_glean_value, Lifetime,
+};
 
 define_metric!(CounterMetric => COUNTER_METRICS {
     new           -> glean_new_counter_metric(),
     test_get_num_recorded_errors -> glean_counter_test_get_num_recorded_errors,
     destroy       -> glean_destroy_counter_metric,
-
-    add -> glean_counter_add(amount: i32),
 });
 
+#[no_mangle]
+pub extern "C" fn glean_counter_add(metric_id: u64, amount: i32) {
+    with_accumulator(|accumulator| {
+        COUNTER_METRICS.call_infallible(metric_id, |metric| {
+            accumulator.add_to_counter(metric_id, metric, amount);
+        })
+    })
+}
+
 #[no_mangle]
 pub extern "C" fn glean_counter_test_has_value(metric_id: u64, storage_name: FfiStr) -> u8 {
     with_glean_value(|glean| {
-        COUNTER_METRICS.call_infallible(metric_id, |metric| {
-            metric
-                .test_get_value(&glean, storage_name.as_str())
-                .is_some()
+        COUNTER_METRICS.call_with_log(metric_id, |metric| {
+            let storage_name = storage_name.to_string_fallible()?;
+            Ok(metric.test_get_value(glean, &storage_name).is_some())
         })
     })
 }
@@ -28,10 +37,11 @@ pub extern "C" fn glean_counter_test_has_value(metric_id: u64, storage_name: Ffi
 #[no_mangle]
 pub extern "C" fn glean_counter_test_get_value(metric_id: u64, storage_name: FfiStr) -> i32 {
     with_glean_value(|glean| {
-        COUNTER_METRICS.call_infallible(metric_id, |metric| {
+        COUNTER_METRICS.call_with_log(metric_id, |metric| {
+            let storage_name = storage_name.to_string_fallible()?;
             metric
-                .test_get_value(&glean, storage_name.as_str())
-                .unwrap()
+                .test_get_value(glean, &storage_name)
+                .ok_or_else(glean_core::Error::no_value)
         })
     })
 }
@@ -7,7 +7,8 @@ use std::os::raw::c_char;
 use ffi_support::FfiStr;
 
 use crate::{
-    define_metric, handlemap_ext::HandleMapExtension, with_glean_value, Lifetime, TimeUnit,
+    define_metric, ffi_string_ext::FallibleToString, handlemap_ext::HandleMapExtension,
+    with_glean_value, Lifetime, TimeUnit,
 };
 
 define_metric!(DatetimeMetric => DATETIME_METRICS {
@@ -58,10 +59,11 @@ pub extern "C" fn glean_datetime_set(
 #[no_mangle]
 pub extern "C" fn glean_datetime_test_has_value(metric_id: u64, storage_name: FfiStr) -> u8 {
     with_glean_value(|glean| {
-        DATETIME_METRICS.call_infallible(metric_id, |metric| {
-            metric
-                .test_get_value_as_string(glean, storage_name.as_str())
-                .is_some()
+        DATETIME_METRICS.call_with_log(metric_id, |metric| {
+            let storage_name = storage_name.to_string_fallible()?;
+            Ok(metric
+                .test_get_value_as_string(glean, &storage_name)
+                .is_some())
         })
     })
 }
@@ -72,10 +74,11 @@ pub extern "C" fn glean_datetime_test_get_value_as_string(
     storage_name: FfiStr,
 ) -> *mut c_char {
     with_glean_value(|glean| {
-        DATETIME_METRICS.call_infallible(metric_id, |metric| {
+        DATETIME_METRICS.call_with_log(metric_id, |metric| {
+            let storage_name = storage_name.to_string_fallible()?;
             metric
-                .test_get_value_as_string(glean, storage_name.as_str())
-                .unwrap()
+                .test_get_value_as_string(glean, &storage_name)
+                .ok_or_else(glean_core::Error::no_value)
         })
     })
 }
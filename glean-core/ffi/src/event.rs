@@ -74,8 +74,9 @@ pub extern "C" fn glean_event_record(
 #[no_mangle]
 pub extern "C" fn glean_event_test_has_value(metric_id: u64, storage_name: FfiStr) -> u8 {
     with_glean_value(|glean| {
-        EVENT_METRICS.call_infallible(metric_id, |metric| {
-            metric.test_has_value(glean, storage_name.as_str())
+        EVENT_METRICS.call_with_log(metric_id, |metric| {
+            let storage_name = storage_name.to_string_fallible()?;
+            Ok(metric.test_has_value(glean, &storage_name))
         })
     })
 }
@@ -86,8 +87,9 @@ pub extern "C" fn glean_event_test_get_value_as_json_string(
     storage_name: FfiStr,
 ) -> *mut c_char {
     with_glean_value(|glean| {
-        EVENT_METRICS.call_infallible(metric_id, |metric| {
-            metric.test_get_value_as_json_string(glean, storage_name.as_str())
+        EVENT_METRICS.call_with_log(metric_id, |metric| {
+            let storage_name = storage_name.to_string_fallible()?;
+            Ok(metric.test_get_value_as_json_string(glean, &storage_name))
         })
     })
 }
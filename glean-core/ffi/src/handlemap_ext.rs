@@ -10,22 +10,48 @@
 //!
 //! The `HandleMapExtension` extension traits adds additional methods that log potential errors.
 //!
+//! Invalid handles, invalid arguments and panics are never fatal.
+//! They are logged and the message of the last one is kept per thread,
+//! so that the platform-side can retrieve it with `glean_last_error_message`.
+//!
 //! **Note: the platform-side still needs to check for null pointers or other default values before
 //! using returned values.
 //! This is only relevant for creation of the main object and metrics as its the only things where
 //! we return something potentially fallible.
 
+use std::cell::RefCell;
 use std::panic::UnwindSafe;
 
 use ffi_support::{ConcurrentHandleMap, ExternError, IntoFfi};
 
+thread_local! {
+    /// The message of the last error that occurred on this thread, until it is retrieved.
+    static LAST_ERROR: RefCell<Option<String>> = RefCell::new(None);
+}
+
+/// Take the message of the last error that occurred on this thread.
+///
+/// Returns `None` if the last call on this thread succeeded or the message was already taken.
+pub fn take_last_error() -> Option<String> {
+    LAST_ERROR.with(|last_error| last_error.borrow_mut().take())
+}
+
+/// Forget the last error of this thread.
+///
+/// Called at the start of every call over the FFI,
+/// so that an error is never reported for a later call that succeeded.
+pub fn clear_last_error() {
+    LAST_ERROR.with(|last_error| *last_error.borrow_mut() = None);
+}
+
 pub fn handle_result<R, F>(callback: F) -> R::Value
 where
     F: UnwindSafe + FnOnce() -> Result<R, glean_core::Error>,
     R: IntoFfi,
 {
+    clear_last_error();
     let mut error = ffi_support::ExternError::success();
-    let res = ffi_support::abort_on_panic::call_with_result(&mut error, callback);
+    let res = ffi_support::call_with_result(&mut error, callback);
     log_if_error(error);
     res
 }
@@ -36,16 +62,16 @@ where
 ///
 /// Adopted from the `consume_and_log_if_error` method, but with a changed log message.
 ///
+/// The message is also kept as the last error of this thread.
+///
 /// We assume we're not inside a catch_unwind, and so we wrap inside one ourselves.
 pub fn log_if_error(error: ExternError) {
     if !error.get_code().is_success() {
         // in practice this should never panic, but you never know...
         ffi_support::abort_on_panic::call_with_output(|| {
-            log::error!(
-                "Glean failed ({:?}): {}",
-                error.get_code(),
-                error.get_message().as_str()
-            );
+            let message = error.get_message().as_str().to_string();
+            log::error!("Glean failed ({:?}): {}", error.get_code(), message);
+            LAST_ERROR.with(|last_error| *last_error.borrow_mut() = Some(message));
             unsafe {
                 error.manually_release();
             }
@@ -69,7 +95,7 @@ pub trait HandleMapExtension {
 
     /// Call an infallible callback with the object identified by a handle.
     ///
-    /// This will ignore panics.
+    /// This will catch and log an invalid handle and panics of the callback.
     ///
     /// On success, it convert the callback return value into an FFI value and returns it.
     /// On failure, it will return the default FFI value.
@@ -80,7 +106,7 @@ pub trait HandleMapExtension {
 
     /// Call an infallible callback with the object identified by a handle.
     ///
-    /// This will ignore panics.
+    /// This will catch and log an invalid handle and panics of the callback.
     ///
     /// On success, it convert the callback return value into an FFI value and returns it.
     /// On failure, it will return the default FFI value.
@@ -100,6 +126,18 @@ pub trait HandleMapExtension {
     where
         F: UnwindSafe + FnOnce(&Self::Output) -> Result<R, glean_core::Error>,
         R: IntoFfi;
+
+    /// Call a callback with the object identified by a handle.
+    ///
+    /// This will catch and log any errors of the callback.
+    /// This will not panic on errors in the callback.
+    ///
+    /// On success, it convert the callback return value into an FFI value and returns it.
+    /// On failure, it will return the default FFI value.
+    fn call_with_log_mut<R, F>(&self, h: u64, callback: F) -> R::Value
+    where
+        F: UnwindSafe + FnOnce(&mut Self::Output) -> Result<R, glean_core::Error>,
+        R: IntoFfi;
 }
 
 impl<T> HandleMapExtension for ConcurrentHandleMap<T> {
@@ -109,6 +147,7 @@ impl<T> HandleMapExtension for ConcurrentHandleMap<T> {
     where
         F: UnwindSafe + FnOnce() -> Result<Self::Output, glean_core::Error>,
     {
+        clear_last_error();
         let mut error = ExternError::success();
         let res = self.insert_with_result(&mut error, constructor);
         log_if_error(error);
@@ -120,9 +159,10 @@ impl<T> HandleMapExtension for ConcurrentHandleMap<T> {
         F: UnwindSafe + FnOnce(&Self::Output) -> R,
         R: IntoFfi,
     {
+        clear_last_error();
         let mut error = ExternError::success();
         let res = self.call_with_output(&mut error, h, callback);
-        debug_assert!(error.get_code().is_success());
+        log_if_error(error);
         res
     }
 
@@ -131,9 +171,10 @@ impl<T> HandleMapExtension for ConcurrentHandleMap<T> {
         F: UnwindSafe + FnOnce(&mut Self::Output) -> R,
         R: IntoFfi,
     {
+        clear_last_error();
         let mut error = ExternError::success();
         let res = self.call_with_output_mut(&mut error, h, callback);
-        debug_assert!(error.get_code().is_success());
+        log_if_error(error);
         res
     }
 
@@ -142,9 +183,22 @@ impl<T> HandleMapExtension for ConcurrentHandleMap<T> {
         F: UnwindSafe + FnOnce(&Self::Output) -> Result<R, glean_core::Error>,
         R: IntoFfi,
     {
+        clear_last_error();
         let mut error = ExternError::success();
         let res = self.call_with_result(&mut error, h, callback);
         log_if_error(error);
         res
     }
+
+    fn call_with_log_mut<R, F>(&self, h: u64, callback: F) -> R::Value
+    where
+        F: UnwindSafe + FnOnce(&mut Self::Output) -> Result<R, glean_core::Error>,
+        R: IntoFfi,
+    {
+        clear_last_error();
+        let mut error = ExternError::success();
+        let res = self.call_with_result_mut(&mut error, h, callback);
+        log_if_error(error);
+        res
+    }
 }
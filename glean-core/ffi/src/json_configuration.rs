@@ -0,0 +1,262 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! A JSON configuration document, as an extensible alternative to `FfiConfiguration`.
+//!
+//! Only `data_path` and `application_id` are required:
+//!
+//! ```json
+//! {
+//!   "data_path": "/tmp/glean",
+//!   "application_id": "org.mozilla.sample",
+//!   "language_binding_name": "C",
+//!   "upload_enabled": true,
+//!   "max_events": 500,
+//!   "delay_ping_lifetime_io": false,
+//!   "upload_batch_limits": { "max_count": 10, "max_bytes": 1048576 },
+//!   "ping_compression": { "gzip": 6 },
+//!   "server_endpoint": "https://collector.example.com",
+//!   "path_template": "/submit/{app_id}/{ping}/{schema_version}/{doc_id}",
+//!   "storage_durability": { "coalesced": { "flush_interval_ms": 5000 } }
+//! }
+//! ```
+//!
+//! `ping_compression` is one of `"none"`, `{ "gzip": <level> }` or `{ "zstd": <level> }`.
+//! `storage_durability` is either `"immediate"` or `{ "coalesced": { "flush_interval_ms": <ms> } }`.
+//! Glean doesn't upload pings itself: the platform's uploader reads the `server_endpoint`
+//! back with `glean_get_server_endpoint` and sends pings there.
+
+use std::collections::BTreeMap;
+
+use serde::Deserialize;
+use serde_json::Value as JsonValue;
+
+use glean_core::upload::{BatchLimits, PingCompression};
+use glean_core::{Configuration, StorageDurability};
+
+/// The language binding name used if the document doesn't name one.
+const DEFAULT_LANGUAGE_BINDING_NAME: &str = "Unknown";
+
+#[derive(Debug, Deserialize)]
+struct JsonConfiguration {
+    data_path: String,
+    application_id: String,
+    #[serde(default = "default_language_binding_name")]
+    language_binding_name: String,
+    #[serde(default = "default_upload_enabled")]
+    upload_enabled: bool,
+    #[serde(default)]
+    max_events: Option<usize>,
+    #[serde(default)]
+    delay_ping_lifetime_io: bool,
+    #[serde(default)]
+    upload_batch_limits: Option<JsonBatchLimits>,
+    #[serde(default)]
+    ping_compression: Option<JsonPingCompression>,
+    #[serde(default)]
+    server_endpoint: Option<String>,
+    #[serde(default)]
+    path_template: Option<String>,
+    #[serde(default)]
+    storage_durability: Option<JsonStorageDurability>,
+    /// Keys not known to this version of Glean.
+    #[serde(flatten)]
+    unknown: BTreeMap<String, JsonValue>,
+}
+
+fn default_language_binding_name() -> String {
+    DEFAULT_LANGUAGE_BINDING_NAME.into()
+}
+
+fn default_upload_enabled() -> bool {
+    true
+}
+
+#[derive(Debug, Deserialize)]
+struct JsonBatchLimits {
+    max_count: usize,
+    max_bytes: usize,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum JsonPingCompression {
+    None,
+    Gzip(u32),
+    Zstd(i32),
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum JsonStorageDurability {
+    Immediate,
+    Coalesced { flush_interval_ms: u64 },
+}
+
+/// Parse a JSON configuration document.
+///
+/// Missing optional keys take their default value.
+/// Unknown keys are ignored and logged as a warning.
+///
+/// ## Arguments
+///
+/// * `json` - The JSON configuration document.
+///
+/// ## Return value
+///
+/// The configuration, or an error if the document is not valid JSON,
+/// misses a required key or has a value of the wrong type.
+pub fn parse(json: &str) -> glean_core::Result<Configuration> {
+    let cfg: JsonConfiguration = serde_json::from_str(json)?;
+
+    for key in cfg.unknown.keys() {
+        log::warn!("Ignoring unknown configuration key '{}'", key);
+    }
+
+    Ok(Configuration {
+        upload_enabled: cfg.upload_enabled,
+        data_path: cfg.data_path,
+        application_id: cfg.application_id,
+        language_binding_name: cfg.language_binding_name,
+        max_events: cfg.max_events,
+        delay_ping_lifetime_io: cfg.delay_ping_lifetime_io,
+        key_provider: None,
+        upload_batch_limits: cfg.upload_batch_limits.map(|limits| BatchLimits {
+            max_count: limits.max_count,
+            max_bytes: limits.max_bytes,
+        }),
+        ping_compression: match cfg.ping_compression {
+            Some(JsonPingCompression::None) => PingCompression::None,
+            Some(JsonPingCompression::Gzip(level)) => PingCompression::Gzip(level),
+            Some(JsonPingCompression::Zstd(level)) => PingCompression::Zstd(level),
+            None => PingCompression::default(),
+        },
+        server_endpoint: cfg.server_endpoint,
+        path_template: cfg.path_template,
+        storage_durability: match cfg.storage_durability {
+            Some(JsonStorageDurability::Immediate) => StorageDurability::Immediate,
+            Some(JsonStorageDurability::Coalesced { flush_interval_ms }) => {
+                StorageDurability::Coalesced { flush_interval_ms }
+            }
+            None => StorageDurability::default(),
+        },
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn minimal_document_takes_the_defaults() {
+        let cfg = parse(r#"{ "data_path": "/tmp/glean", "application_id": "org.mozilla.sample" }"#)
+            .unwrap();
+
+        assert_eq!("/tmp/glean", cfg.data_path);
+        assert_eq!("org.mozilla.sample", cfg.application_id);
+        assert_eq!(DEFAULT_LANGUAGE_BINDING_NAME, cfg.language_binding_name);
+        assert!(cfg.upload_enabled);
+        assert_eq!(None, cfg.max_events);
+        assert!(!cfg.delay_ping_lifetime_io);
+        assert_eq!(None, cfg.upload_batch_limits);
+        assert_eq!(PingCompression::default(), cfg.ping_compression);
+        assert_eq!(None, cfg.server_endpoint);
+        assert_eq!(None, cfg.path_template);
+        assert_eq!(StorageDurability::Immediate, cfg.storage_durability);
+    }
+
+    #[test]
+    fn every_key_maps_onto_the_configuration() {
+        let cfg = parse(
+            r#"{
+                "data_path": "/tmp/glean",
+                "application_id": "org.mozilla.sample",
+                "language_binding_name": "C",
+                "upload_enabled": false,
+                "max_events": 20,
+                "delay_ping_lifetime_io": true,
+                "upload_batch_limits": { "max_count": 10, "max_bytes": 1024 },
+                "ping_compression": { "zstd": 3 },
+                "server_endpoint": "https://collector.example.com",
+                "path_template": "/{app_id}/{ping}/{doc_id}",
+                "storage_durability": { "coalesced": { "flush_interval_ms": 500 } }
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!("C", cfg.language_binding_name);
+        assert!(!cfg.upload_enabled);
+        assert_eq!(Some(20), cfg.max_events);
+        assert!(cfg.delay_ping_lifetime_io);
+        assert_eq!(
+            Some(BatchLimits {
+                max_count: 10,
+                max_bytes: 1024
+            }),
+            cfg.upload_batch_limits
+        );
+        assert_eq!(PingCompression::Zstd(3), cfg.ping_compression);
+        assert_eq!(
+            Some("https://collector.example.com"),
+            cfg.server_endpoint.as_deref()
+        );
+        assert_eq!(
+            Some("/{app_id}/{ping}/{doc_id}"),
+            cfg.path_template.as_deref()
+        );
+        assert_eq!(
+            StorageDurability::Coalesced {
+                flush_interval_ms: 500
+            },
+            cfg.storage_durability
+        );
+
+        let cfg = parse(
+            r#"{
+                "data_path": "/tmp/glean",
+                "application_id": "org.mozilla.sample",
+                "ping_compression": "none",
+                "storage_durability": "immediate"
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(PingCompression::None, cfg.ping_compression);
+        assert_eq!(StorageDurability::Immediate, cfg.storage_durability);
+    }
+
+    #[test]
+    fn unknown_keys_are_ignored() {
+        let cfg = parse(
+            r#"{
+                "data_path": "/tmp/glean",
+                "application_id": "org.mozilla.sample",
+                "channel": "nightly",
+                "rate_limit": { "pings": 15, "seconds": 60 }
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!("org.mozilla.sample", cfg.application_id);
+    }
+
+    #[test]
+    fn invalid_documents_are_reported() {
+        let errors = [
+            "",
+            "[]",
+            r#"{ "data_path": "/tmp/glean" }"#,
+            r#"{ "data_path": "/tmp/glean", "application_id": 42 }"#,
+            r#"{ "data_path": "/tmp/glean", "application_id": "a", "max_events": -1 }"#,
+            r#"{ "data_path": "/tmp/glean", "application_id": "a", "ping_compression": "brotli" }"#,
+            r#"{ "data_path": "/tmp/glean", "application_id": "a", "upload_batch_limits": {} }"#,
+        ];
+
+        for json in errors.iter() {
+            assert!(parse(json).is_err(), "accepted {}", json);
+        }
+
+        let error = parse(r#"{ "data_path": "/tmp/glean" }"#).unwrap_err();
+        assert!(error.to_string().contains("application_id"), "{}", error);
+    }
+}
@@ -51,10 +51,9 @@ pub extern "C" fn glean_jwe_set(
 #[no_mangle]
 pub extern "C" fn glean_jwe_test_has_value(metric_id: u64, storage_name: FfiStr) -> u8 {
     with_glean_value(|glean| {
-        JWE_METRICS.call_infallible(metric_id, |metric| {
-            metric
-                .test_get_value(glean, storage_name.as_str())
-                .is_some()
+        JWE_METRICS.call_with_log(metric_id, |metric| {
+            let storage_name = storage_name.to_string_fallible()?;
+            Ok(metric.test_get_value(glean, &storage_name).is_some())
         })
     })
 }
@@ -62,8 +61,11 @@ pub extern "C" fn glean_jwe_test_has_value(metric_id: u64, storage_name: FfiStr)
 #[no_mangle]
 pub extern "C" fn glean_jwe_test_get_value(metric_id: u64, storage_name: FfiStr) -> *mut c_char {
     with_glean_value(|glean| {
-        JWE_METRICS.call_infallible(metric_id, |metric| {
-            metric.test_get_value(glean, storage_name.as_str()).unwrap()
+        JWE_METRICS.call_with_log(metric_id, |metric| {
+            let storage_name = storage_name.to_string_fallible()?;
+            metric
+                .test_get_value(glean, &storage_name)
+                .ok_or_else(glean_core::Error::no_value)
         })
     })
 }
@@ -74,10 +76,11 @@ pub extern "C" fn glean_jwe_test_get_value_as_json_string(
     storage_name: FfiStr,
 ) -> *mut c_char {
     with_glean_value(|glean| {
-        JWE_METRICS.call_infallible(metric_id, |metric| {
+        JWE_METRICS.call_with_log(metric_id, |metric| {
+            let storage_name = storage_name.to_string_fallible()?;
             metric
-                .test_get_value_as_json_string(glean, storage_name.as_str())
-                .unwrap()
+                .test_get_value_as_json_string(glean, &storage_name)
+                .ok_or_else(glean_core::Error::no_value)
         })
     })
 }
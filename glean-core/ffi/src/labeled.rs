@@ -74,9 +74,10 @@ macro_rules! impl_labeled_metric {
         /// Create a new instance of the sub-metric of this labeled metric.
         #[no_mangle]
         pub extern "C" fn $get_name(handle: u64, label: FfiStr) -> u64 {
-            $global.call_infallible_mut(handle, |labeled| {
-                let metric = labeled.get(label.as_str());
-                $metric_global.insert_with_log(|| Ok(metric))
+            $global.call_with_log_mut(handle, |labeled| {
+                let label = label.to_string_fallible()?;
+                let metric = labeled.get(&label);
+                Ok($metric_global.insert_with_log(|| Ok(metric)))
             })
         }
 
@@ -87,17 +88,16 @@ macro_rules! impl_labeled_metric {
             storage_name: FfiStr,
         ) -> i32 {
             crate::with_glean_value(|glean| {
-                crate::HandleMapExtension::call_infallible(&*$global, metric_id, |metric| {
-                    let error_type = std::convert::TryFrom::try_from(error_type).unwrap();
-                    let storage_name =
-                        crate::FallibleToString::to_string_fallible(&storage_name).unwrap();
-                    glean_core::test_get_num_recorded_errors(
+                crate::HandleMapExtension::call_with_log(&*$global, metric_id, |metric| {
+                    let error_type = std::convert::TryFrom::try_from(error_type)?;
+                    let storage_name = crate::FallibleToString::to_string_fallible(&storage_name)?;
+                    Ok(glean_core::test_get_num_recorded_errors(
                         glean,
                         &metric.get_submetric().meta(),
                         error_type,
                         Some(&storage_name),
                     )
-                    .unwrap_or(0)
+                    .unwrap_or(0))
                 })
             })
         }
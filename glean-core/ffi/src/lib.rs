@@ -4,11 +4,15 @@
 
 use std::convert::TryFrom;
 use std::ffi::CStr;
+use std::mem;
 use std::os::raw::c_char;
 use std::panic::UnwindSafe;
+use std::sync::PoisonError;
 
 use ffi_support::{define_string_destructor, ConcurrentHandleMap, FfiStr, IntoFfi};
+use once_cell::sync::Lazy;
 
+use glean_core::metrics::Accumulator;
 pub use glean_core::metrics::MemoryUnit;
 pub use glean_core::metrics::TimeUnit;
 pub use glean_core::upload::ffi_upload_result::*;
@@ -26,9 +30,11 @@ mod event;
 mod ffi_string_ext;
 mod from_raw;
 mod handlemap_ext;
+mod json_configuration;
 mod jwe;
 mod labeled;
 mod memory_distribution;
+pub mod ping_observer;
 pub mod ping_type;
 mod quantity;
 mod string;
@@ -44,6 +50,12 @@ use handlemap_ext::HandleMapExtension;
 use ping_type::PING_TYPES;
 use upload::FfiPingUploadTask;
 
+/// Recordings of counters, quantities and distributions made without locking Glean.
+///
+/// They are merged into Glean whenever it is locked, before anything else is done with it,
+/// so that every other operation sees them as if they were recorded directly.
+static ACCUMULATOR: Lazy<Accumulator> = Lazy::new(Accumulator::new);
+
 /// Execute the callback with a reference to the Glean singleton, returning a `Result`.
 ///
 /// The callback returns a `Result<T, E>` while:
@@ -51,23 +63,25 @@ use upload::FfiPingUploadTask;
 /// - Catching panics, and logging them.
 /// - Converting `T` to a C-compatible type using [`IntoFfi`].
 /// - Logging `E` and returning a default value.
+///
+/// Logged errors are kept as the last error of the thread, see `glean_last_error_message`.
 pub(crate) fn with_glean<R, F>(callback: F) -> R::Value
 where
     F: UnwindSafe + FnOnce(&Glean) -> Result<R, glean_core::Error>,
     R: IntoFfi,
 {
+    handlemap_ext::clear_last_error();
     let mut error = ffi_support::ExternError::success();
-    let res =
-        ffi_support::abort_on_panic::call_with_result(
-            &mut error,
-            || match glean_core::global_glean() {
-                Some(glean) => {
-                    let glean = glean.lock().unwrap();
-                    callback(&glean)
-                }
-                None => Err(glean_core::Error::not_initialized()),
-            },
-        );
+    let res = ffi_support::call_with_result(&mut error, || match glean_core::global_glean() {
+        Some(glean) => {
+            // A panic while Glean was locked is reported like any other error,
+            // Glean itself is still usable.
+            let glean = glean.lock().unwrap_or_else(PoisonError::into_inner);
+            ACCUMULATOR.merge_into(&glean);
+            callback(&glean)
+        }
+        None => Err(glean_core::Error::not_initialized()),
+    });
     handlemap_ext::log_if_error(error);
     res
 }
@@ -79,23 +93,25 @@ where
 /// - Catching panics, and logging them.
 /// - Converting `T` to a C-compatible type using [`IntoFfi`].
 /// - Logging `E` and returning a default value.
+///
+/// Logged errors are kept as the last error of the thread, see `glean_last_error_message`.
 pub(crate) fn with_glean_mut<R, F>(callback: F) -> R::Value
 where
     F: UnwindSafe + FnOnce(&mut Glean) -> Result<R, glean_core::Error>,
     R: IntoFfi,
 {
+    handlemap_ext::clear_last_error();
     let mut error = ffi_support::ExternError::success();
-    let res =
-        ffi_support::abort_on_panic::call_with_result(
-            &mut error,
-            || match glean_core::global_glean() {
-                Some(glean) => {
-                    let mut glean = glean.lock().unwrap();
-                    callback(&mut glean)
-                }
-                None => Err(glean_core::Error::not_initialized()),
-            },
-        );
+    let res = ffi_support::call_with_result(&mut error, || match glean_core::global_glean() {
+        Some(glean) => {
+            // A panic while Glean was locked is reported like any other error,
+            // Glean itself is still usable.
+            let mut glean = glean.lock().unwrap_or_else(PoisonError::into_inner);
+            ACCUMULATOR.merge_into(&glean);
+            callback(&mut glean)
+        }
+        None => Err(glean_core::Error::not_initialized()),
+    });
     handlemap_ext::log_if_error(error);
     res
 }
@@ -128,6 +144,31 @@ where
     with_glean_mut(|glean| Ok(callback(glean)))
 }
 
+/// Execute the callback with the accumulator, to record without locking Glean.
+///
+/// Like `with_glean`, this catches and logs panics, and does nothing if Glean is not initialized.
+/// Glean is only locked to merge the accumulator once it buffers too many recordings.
+pub(crate) fn with_accumulator<F>(callback: F)
+where
+    F: UnwindSafe + FnOnce(&Accumulator),
+{
+    handlemap_ext::clear_last_error();
+    let mut error = ffi_support::ExternError::success();
+    ffi_support::call_with_result(&mut error, || match glean_core::global_glean() {
+        Some(glean) => {
+            callback(&ACCUMULATOR);
+            // Distribution samples are buffered one by one, merge them before they pile up.
+            if ACCUMULATOR.should_merge() {
+                let glean = glean.lock().unwrap_or_else(PoisonError::into_inner);
+                ACCUMULATOR.merge_into(&glean);
+            }
+            Ok(())
+        }
+        None => Err(glean_core::Error::not_initialized()),
+    });
+    handlemap_ext::log_if_error(error);
+}
+
 /// Initialize the logging system based on the target platform. This ensures
 /// that logging is shown when executing the Glean SDK unit tests.
 #[no_mangle]
@@ -180,12 +221,25 @@ pub extern "C" fn glean_enable_logging() {
     }
 }
 
+/// The version of the C ABI of this library.
+///
+/// This needs to be increased on every change to the layout of `FfiConfiguration`.
+pub const GLEAN_FFI_ABI_VERSION: u32 = 1;
+
 /// Configuration over FFI.
 ///
 /// **CAUTION**: This must match _exactly_ the definition on the Kotlin side.
-/// If this side is changed, the Kotlin side need to be changed, too.
+/// If this side is changed, the Kotlin side need to be changed, too,
+/// and `GLEAN_FFI_ABI_VERSION` needs to be increased.
+///
+/// The leading `struct_size` and `abi_version` fields must never move,
+/// so that a configuration built for another ABI can be detected and rejected.
 #[repr(C)]
 pub struct FfiConfiguration<'a> {
+    /// The size of the configuration as known to the caller, i.e. `sizeof(FfiConfiguration)`.
+    pub struct_size: u32,
+    /// The `GLEAN_FFI_ABI_VERSION` the caller was built against.
+    pub abi_version: u32,
     pub data_dir: FfiStr<'a>,
     pub package_name: FfiStr<'a>,
     pub language_binding_name: FfiStr<'a>,
@@ -213,21 +267,64 @@ impl TryFrom<&FfiConfiguration<'_>> for glean_core::Configuration {
             language_binding_name,
             max_events,
             delay_ping_lifetime_io,
+            key_provider: None,
+            upload_batch_limits: None,
+            ping_compression: Default::default(),
+            server_endpoint: None,
+            path_template: None,
+            storage_durability: Default::default(),
         })
     }
 }
 
+/// Check that a configuration was built for the ABI of this library.
+///
+/// Only the leading `struct_size` and `abi_version` fields are read,
+/// which are at the same place for every ABI version.
+///
 /// # Safety
 ///
-/// A valid and non-null configuration object is required for this function.
+/// `cfg` needs to be a non-null pointer to a configuration object of any ABI version.
+unsafe fn check_abi(cfg: *const FfiConfiguration) -> glean_core::Result<()> {
+    // Only read the two leading `u32`s, as the rest of the layout may differ.
+    let leading = cfg as *const u32;
+    let found = (leading.add(1).read(), leading.read());
+    let expected = (
+        GLEAN_FFI_ABI_VERSION,
+        mem::size_of::<FfiConfiguration>() as u32,
+    );
+    if found != expected {
+        return Err(glean_core::ErrorKind::IncompatibleAbi { found, expected }.into());
+    }
+
+    Ok(())
+}
+
+/// Get the version of the C ABI of this library.
+///
+/// Language bindings pass the `GLEAN_FFI_ABI_VERSION` they were built against in the configuration,
+/// and can compare it to this value beforehand to detect a mismatched library.
 #[no_mangle]
-pub unsafe extern "C" fn glean_initialize(cfg: *const FfiConfiguration) -> u8 {
-    assert!(!cfg.is_null());
+pub extern "C" fn glean_ffi_abi_version() -> u32 {
+    GLEAN_FFI_ABI_VERSION
+}
 
+/// # Safety
+///
+/// A valid configuration object is required for this function.
+/// A null pointer or a configuration built for another ABI is reported as an error.
+#[no_mangle]
+pub unsafe extern "C" fn glean_initialize(cfg: *const FfiConfiguration) -> u8 {
     handlemap_ext::handle_result(|| {
+        if cfg.is_null() {
+            return Err(glean_core::Error::null_pointer());
+        }
+        check_abi(cfg)?;
+
         // We can create a reference to the FfiConfiguration struct:
-        // 1. We did a null check
-        // 2. We're not holding on to it beyond this function
+        // 1. We did a null check above
+        // 2. We checked that the caller uses the same layout
+        // 3. We're not holding on to it beyond this function
         //    and we copy out all data when needed.
         let glean_cfg = glean_core::Configuration::try_from(&*cfg)?;
         let glean = Glean::new(glean_cfg)?;
@@ -237,6 +334,53 @@ pub unsafe extern "C" fn glean_initialize(cfg: *const FfiConfiguration) -> u8 {
     })
 }
 
+/// Initialize Glean from a JSON configuration document.
+///
+/// This is an alternative to `glean_initialize` that can take new configuration options
+/// without changing the layout of `FfiConfiguration`.
+/// Only `data_path` and `application_id` are required, other keys take their default value if missing.
+/// Unknown keys are ignored and logged as a warning.
+/// The configured `server_endpoint` can be read back with `glean_get_server_endpoint`.
+///
+/// An invalid document is reported as an error, see `glean_last_error_message`.
+#[no_mangle]
+pub extern "C" fn glean_initialize_with_json(json: FfiStr) -> u8 {
+    handlemap_ext::handle_result(|| {
+        let json = json.to_string_fallible()?;
+        let glean_cfg = json_configuration::parse(&json)?;
+        let glean = Glean::new(glean_cfg)?;
+        glean_core::setup_glean(glean)?;
+        log::info!("Glean initialized");
+        Ok(true)
+    })
+}
+
+/// Get the server endpoint pings are uploaded to, if one was configured,
+/// e.g. with the `server_endpoint` key of `glean_initialize_with_json`.
+///
+/// Uploaders send pings to this endpoint, followed by the path of each upload request.
+/// Returns a null pointer if no endpoint was configured, in which case the platform's default is used.
+/// The returned string needs to be freed with `glean_str_free`.
+#[no_mangle]
+pub extern "C" fn glean_get_server_endpoint() -> *mut c_char {
+    with_glean_value(|glean| glean.server_endpoint().map(String::from))
+}
+
+/// Take the message of the last error that occurred on the calling thread.
+///
+/// Errors are not returned by the functions they occur in.
+/// Those functions log them and return a default value instead,
+/// and the message of the error is kept until it is taken with this function
+/// or the next call on the same thread.
+/// To tell whether a specific call failed, take the last error right after the call.
+///
+/// Returns a null pointer if the last call succeeded or its error was already taken.
+/// The returned string needs to be freed with `glean_str_free`.
+#[no_mangle]
+pub extern "C" fn glean_last_error_message() -> *mut c_char {
+    handlemap_ext::take_last_error().into_ffi_value()
+}
+
 #[no_mangle]
 pub extern "C" fn glean_on_ready_to_submit_pings() -> u8 {
     with_glean_value(|glean| glean.on_ready_to_submit_pings())
@@ -360,11 +504,15 @@ pub extern "C" fn glean_is_first_run() -> u8 {
 // * `result`: the object the output task will be written to.
 #[no_mangle]
 pub extern "C" fn glean_get_upload_task(result: *mut FfiPingUploadTask) {
-    with_glean_value(|glean| {
+    with_glean(|glean| {
+        if result.is_null() {
+            return Err(glean_core::Error::null_pointer());
+        }
         let ffi_task = FfiPingUploadTask::from(glean.get_upload_task());
         unsafe {
             std::ptr::write(result, ffi_task);
         }
+        Ok(())
     });
 }
 
@@ -387,7 +535,9 @@ pub unsafe extern "C" fn glean_process_ping_upload_response(
     // * We null-check the passed task before dereferencing.
     // * We replace data behind the pointer with another valid variant.
     // * We gracefully handle invalid data in strings.
+    handlemap_ext::clear_last_error();
     if task.is_null() {
+        handlemap_ext::log_if_error(glean_core::Error::null_pointer().into());
         return;
     }
 
@@ -398,7 +548,9 @@ pub unsafe extern "C" fn glean_process_ping_upload_response(
 
     with_glean(|glean| {
         if let FfiPingUploadTask::Upload { document_id, .. } = task {
-            assert!(!document_id.is_null());
+            if document_id.is_null() {
+                return Err(glean_core::Error::null_pointer());
+            }
             let document_id_str = CStr::from_ptr(document_id)
                 .to_str()
                 .map_err(|_| glean_core::Error::utf8_error())?;
@@ -408,17 +560,104 @@ pub unsafe extern "C" fn glean_process_ping_upload_response(
     });
 }
 
+/// Process and free a `FfiPingUploadTask` carrying a batch of pings.
+///
+/// The upload result of each ping is passed in `statuses`, in the order of the requests
+/// in the batch. Pings without a result are treated as a recoverable failure.
+///
+/// After return the `task` should not be used further by the caller.
+///
 /// # Safety
 ///
-/// A valid and non-null configuration object is required for this function.
+/// A valid and non-null upload task object is required for this function.
+/// `statuses` needs to point to an array of at least `statuses_len` upload results.
 #[no_mangle]
-pub unsafe extern "C" fn glean_initialize_for_subprocess(cfg: *const FfiConfiguration) -> u8 {
-    assert!(!cfg.is_null());
+pub unsafe extern "C" fn glean_process_ping_upload_batch_response(
+    task: *mut FfiPingUploadTask,
+    statuses: *const u32,
+    statuses_len: i32,
+) {
+    // Safety:
+    // * We null-check the passed task and statuses before dereferencing.
+    // * We replace data behind the pointer with another valid variant.
+    // * We gracefully handle invalid data in strings.
+    handlemap_ext::clear_last_error();
+    if task.is_null() {
+        handlemap_ext::log_if_error(glean_core::Error::null_pointer().into());
+        return;
+    }
+
+    let task = std::ptr::replace(task, FfiPingUploadTask::Done);
+    let statuses = if statuses.is_null() || statuses_len <= 0 {
+        &[]
+    } else {
+        std::slice::from_raw_parts(statuses, statuses_len as usize)
+    };
 
+    with_glean(|glean| {
+        let results = task
+            .batch_requests()
+            .iter()
+            .enumerate()
+            .map(|(i, request)| {
+                let document_id = request
+                    .document_id()
+                    .ok_or_else(glean_core::Error::utf8_error)?;
+                let status = statuses
+                    .get(i)
+                    .map(|&status| status.into())
+                    .unwrap_or(glean_core::upload::UploadResult::RecoverableFailure);
+                Ok((document_id.to_string(), status))
+            })
+            .collect::<Result<Vec<_>, glean_core::Error>>()?;
+        glean.process_ping_upload_batch_response(results);
+        Ok(())
+    });
+}
+
+/// Enable batching of multiple pings into a single upload task.
+///
+/// Passing a `max_count` of `0` disables batching.
+#[no_mangle]
+pub extern "C" fn glean_set_upload_batch_limits(max_count: i32, max_bytes: i64) {
+    with_glean_value_mut(|glean| {
+        let limits = if max_count > 0 {
+            Some(glean_core::upload::BatchLimits {
+                max_count: max_count as usize,
+                max_bytes: max_bytes.max(0) as usize,
+            })
+        } else {
+            None
+        };
+        glean.set_upload_batch_limits(limits);
+    });
+}
+
+/// Update the network conditions pings are uploaded under.
+///
+/// While pings are held back because of the network state,
+/// `glean_get_upload_task` returns a `WaitFor` task.
+#[no_mangle]
+pub extern "C" fn glean_set_network_state(online: u8, metered: u8) {
+    with_glean_value(|glean| glean.set_network_state(online != 0, metered != 0));
+}
+
+/// # Safety
+///
+/// A valid configuration object is required for this function.
+/// A null pointer or a configuration built for another ABI is reported as an error.
+#[no_mangle]
+pub unsafe extern "C" fn glean_initialize_for_subprocess(cfg: *const FfiConfiguration) -> u8 {
     handlemap_ext::handle_result(|| {
+        if cfg.is_null() {
+            return Err(glean_core::Error::null_pointer());
+        }
+        check_abi(cfg)?;
+
         // We can create a reference to the FfiConfiguration struct:
-        // 1. We did a null check
-        // 2. We're not holding on to it beyond this function
+        // 1. We did a null check above
+        // 2. We checked that the caller uses the same layout
+        // 3. We're not holding on to it beyond this function
         //    and we copy out all data when needed.
         let glean_cfg = glean_core::Configuration::try_from(&*cfg)?;
         let glean = Glean::new_for_subprocess(&glean_cfg)?;
@@ -436,11 +675,71 @@ pub extern "C" fn glean_set_debug_view_tag(tag: FfiStr) -> u8 {
     })
 }
 
+/// Convert the limits of a debug tag passed over FFI.
+///
+/// Non-positive values for `expires_after_ms` and `max_pings` and an empty list of pings
+/// mean no limit.
+fn debug_tag_limits(
+    expires_after_ms: i64,
+    max_pings: i32,
+    raw_pings: RawStringArray,
+    pings_count: i32,
+) -> glean_core::Result<glean_core::DebugTagLimits> {
+    let pings = from_raw_string_array(raw_pings, pings_count)?;
+    Ok(glean_core::DebugTagLimits {
+        expires_after: u64::try_from(expires_after_ms)
+            .ok()
+            .filter(|&ms| ms > 0)
+            .map(std::time::Duration::from_millis),
+        max_pings: u32::try_from(max_pings).ok().filter(|&n| n > 0),
+        pings: Some(pings).filter(|pings| !pings.is_empty()),
+    })
+}
+
+/// Set a debug view tag that is only applied within the given limits.
+///
+/// Non-positive `expires_after_ms` and `max_pings` and an empty list of pings mean no limit.
+#[no_mangle]
+pub extern "C" fn glean_set_debug_view_tag_with_limits(
+    tag: FfiStr,
+    expires_after_ms: i64,
+    max_pings: i32,
+    raw_pings: RawStringArray,
+    pings_count: i32,
+) -> u8 {
+    with_glean_mut(|glean| {
+        let tag = tag.to_string_fallible()?;
+        let limits = debug_tag_limits(expires_after_ms, max_pings, raw_pings, pings_count)?;
+        Ok(glean.set_debug_view_tag_with_limits(&tag, limits))
+    })
+}
+
 #[no_mangle]
 pub extern "C" fn glean_set_log_pings(value: u8) {
     with_glean_mut(|glean| Ok(glean.set_log_pings(value != 0)));
 }
 
+/// Start watching the `debug.json` file in the data path for debug options.
+///
+/// A non-positive `poll_interval_ms` polls the file once per second.
+#[no_mangle]
+pub extern "C" fn glean_watch_debug_file(poll_interval_ms: i64) -> u8 {
+    with_glean_mut(|glean| {
+        let poll_interval = match u64::try_from(poll_interval_ms) {
+            Ok(ms) if ms > 0 => std::time::Duration::from_millis(ms),
+            _ => std::time::Duration::from_secs(1),
+        };
+        glean.watch_debug_file(poll_interval)?;
+        Ok(true)
+    })
+}
+
+/// Stop watching the `debug.json` file.
+#[no_mangle]
+pub extern "C" fn glean_stop_watching_debug_file() {
+    with_glean_value_mut(|glean| glean.stop_watching_debug_file());
+}
+
 #[no_mangle]
 pub extern "C" fn glean_set_source_tags(raw_tags: RawStringArray, tags_count: i32) -> u8 {
     with_glean_mut(|glean| {
@@ -449,4 +748,23 @@ pub extern "C" fn glean_set_source_tags(raw_tags: RawStringArray, tags_count: i3
     })
 }
 
+/// Set source tags that are only applied within the given limits.
+///
+/// Non-positive `expires_after_ms` and `max_pings` and an empty list of pings mean no limit.
+#[no_mangle]
+pub extern "C" fn glean_set_source_tags_with_limits(
+    raw_tags: RawStringArray,
+    tags_count: i32,
+    expires_after_ms: i64,
+    max_pings: i32,
+    raw_pings: RawStringArray,
+    pings_count: i32,
+) -> u8 {
+    with_glean_mut(|glean| {
+        let tags = from_raw_string_array(raw_tags, tags_count)?;
+        let limits = debug_tag_limits(expires_after_ms, max_pings, raw_pings, pings_count)?;
+        Ok(glean.set_source_tags_with_limits(tags, limits))
+    })
+}
+
 define_string_destructor!(glean_str_free);
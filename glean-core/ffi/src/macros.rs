@@ -3,8 +3,9 @@ macro_rules! define_infallible_handle_map_deleter {
     ($HANDLE_MAP_NAME:ident, $destructor_name:ident) => {
         #[no_mangle]
         pub extern "C" fn $destructor_name(v: u64) {
+            $crate::handlemap_ext::clear_last_error();
             let mut error = ffi_support::ExternError::success();
-            let res = ffi_support::abort_on_panic::call_with_result(&mut error, || {
+            let res = ffi_support::call_with_result(&mut error, || {
                 let map: &$crate::ConcurrentHandleMap<_> = &*$HANDLE_MAP_NAME;
                 map.delete_u64(v)
             });
@@ -88,15 +89,15 @@ macro_rules! define_metric {
             storage_name: FfiStr
         ) -> i32 {
                 crate::HandleMapExtension::call_infallible(&*$metric_map, metric_id, |metric| {
-                    crate::with_glean_value(|glean| {
-                        let error_type = std::convert::TryFrom::try_from(error_type).unwrap();
-                        let storage_name = crate::FallibleToString::to_string_fallible(&storage_name).unwrap();
-                        glean_core::test_get_num_recorded_errors(
+                    crate::with_glean(|glean| {
+                        let error_type = std::convert::TryFrom::try_from(error_type)?;
+                        let storage_name = crate::FallibleToString::to_string_fallible(&storage_name)?;
+                        Ok(glean_core::test_get_num_recorded_errors(
                             &glean,
                             glean_core::metrics::MetricType::meta(metric),
                             error_type,
                             Some(&storage_name)
-                        ).unwrap_or(0)
+                        ).unwrap_or(0))
                     })
                 })
         }
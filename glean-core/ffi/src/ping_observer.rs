@@ -0,0 +1,83 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::ffi::CString;
+use std::os::raw::c_char;
+
+use glean_core::upload::HeaderMap;
+use glean_core::{PingObserver, PingStage};
+use serde_json::Value as JsonValue;
+
+use crate::with_glean_value_mut;
+
+/// A function notified of every ping submitted and uploaded.
+///
+/// `stage` is `1` when the ping was submitted and `2` when it is handed out for upload.
+/// `payload` and `headers` are JSON-encoded. All strings are only valid during the call.
+///
+/// The callback is called while Glean is locked, so it must not call back into Glean.
+pub type PingObserverCallback = extern "C" fn(
+    stage: u8,
+    ping_name: *const c_char,
+    document_id: *const c_char,
+    path: *const c_char,
+    payload: *const c_char,
+    headers: *const c_char,
+);
+
+/// Forwards pings to a foreign callback.
+#[derive(Debug)]
+struct FfiPingObserver {
+    callback: PingObserverCallback,
+}
+
+impl PingObserver for FfiPingObserver {
+    fn on_ping(
+        &self,
+        stage: PingStage,
+        ping_name: &str,
+        document_id: &str,
+        path: &str,
+        payload: &JsonValue,
+        headers: &HeaderMap,
+    ) {
+        let strings = vec![
+            CString::new(ping_name),
+            CString::new(document_id),
+            CString::new(path),
+            CString::new(payload.to_string()),
+            CString::new(serde_json::to_string(headers).unwrap_or_default()),
+        ];
+        let strings = match strings.into_iter().collect::<Result<Vec<_>, _>>() {
+            Ok(strings) => strings,
+            Err(e) => {
+                log::error!("Unable to pass ping {} to the observer: {}", document_id, e);
+                return;
+            }
+        };
+
+        (self.callback)(
+            stage as u8,
+            strings[0].as_ptr(),
+            strings[1].as_ptr(),
+            strings[2].as_ptr(),
+            strings[3].as_ptr(),
+            strings[4].as_ptr(),
+        );
+    }
+}
+
+/// Register a callback to be notified of every ping submitted and uploaded.
+///
+/// See `PingObserverCallback` for details.
+#[no_mangle]
+pub extern "C" fn glean_register_ping_observer(callback: PingObserverCallback) {
+    with_glean_value_mut(|glean| glean.add_ping_observer(Box::new(FfiPingObserver { callback })));
+}
+
+/// Remove all registered ping observers.
+#[no_mangle]
+pub extern "C" fn glean_clear_ping_observers() {
+    with_glean_value_mut(|glean| glean.clear_ping_observers());
+}
@@ -9,7 +9,7 @@ use glean_core::metrics::PingType;
 
 use crate::ffi_string_ext::FallibleToString;
 use crate::handlemap_ext::HandleMapExtension;
-use crate::{from_raw_string_array, with_glean_value, with_glean_value_mut, RawStringArray};
+use crate::{from_raw_string_array, with_glean, with_glean_value_mut, RawStringArray};
 
 pub(crate) static PING_TYPES: Lazy<ConcurrentHandleMap<PingType>> =
     Lazy::new(ConcurrentHandleMap::new);
@@ -37,7 +37,10 @@ pub extern "C" fn glean_new_ping_type(
 
 #[no_mangle]
 pub extern "C" fn glean_test_has_ping_type(ping_name: FfiStr) -> u8 {
-    with_glean_value(|glean| glean.get_ping_by_name(ping_name.as_str()).is_some() as u8)
+    with_glean(|glean| {
+        let ping_name = ping_name.to_string_fallible()?;
+        Ok(glean.get_ping_by_name(&ping_name).is_some())
+    })
 }
 
 #[no_mangle]
@@ -4,23 +4,32 @@
 
 use ffi_support::FfiStr;
 
-use crate::{define_metric, handlemap_ext::HandleMapExtension, with_glean_value, Lifetime};
+use crate::{
+    define_metric, ffi_string_ext::FallibleToString, handlemap_ext::HandleMapExtension,
+    with_accumulator, with_glean_value, Lifetime,
+};
 
 define_metric!(QuantityMetric => QUANTITY_METRICS {
     new           -> glean_new_quantity_metric(),
     test_get_num_recorded_errors -> glean_quantity_test_get_num_recorded_errors,
     destroy       -> glean_destroy_quantity_metric,
-
-    set -> glean_quantity_set(value: i64),
 });
 
+#[no_mangle]
+pub extern "C" fn glean_quantity_set(metric_id: u64, value: i64) {
+    with_accumulator(|accumulator| {
+        QUANTITY_METRICS.call_infallible(metric_id, |metric| {
+            accumulator.set_quantity(metric_id, metric, value);
+        })
+    })
+}
+
 #[no_mangle]
 pub extern "C" fn glean_quantity_test_has_value(metric_id: u64, storage_name: FfiStr) -> u8 {
     with_glean_value(|glean| {
-        QUANTITY_METRICS.call_infallible(metric_id, |metric| {
-            metric
-                .test_get_value(glean, storage_name.as_str())
-                .is_some()
+        QUANTITY_METRICS.call_with_log(metric_id, |metric| {
+            let storage_name = storage_name.to_string_fallible()?;
+            Ok(metric.test_get_value(glean, &storage_name).is_some())
         })
     })
 }
@@ -28,8 +37,11 @@ pub extern "C" fn glean_quantity_test_has_value(metric_id: u64, storage_name: Ff
 #[no_mangle]
 pub extern "C" fn glean_quantity_test_get_value(metric_id: u64, storage_name: FfiStr) -> i64 {
     with_glean_value(|glean| {
-        QUANTITY_METRICS.call_infallible(metric_id, |metric| {
-            metric.test_get_value(glean, storage_name.as_str()).unwrap()
+        QUANTITY_METRICS.call_with_log(metric_id, |metric| {
+            let storage_name = storage_name.to_string_fallible()?;
+            metric
+                .test_get_value(glean, &storage_name)
+                .ok_or_else(glean_core::Error::no_value)
         })
     })
 }
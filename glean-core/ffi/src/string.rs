@@ -31,10 +31,9 @@ pub extern "C" fn glean_string_set(metric_id: u64, value: FfiStr) {
 #[no_mangle]
 pub extern "C" fn glean_string_test_has_value(metric_id: u64, storage_name: FfiStr) -> u8 {
     with_glean_value(|glean| {
-        STRING_METRICS.call_infallible(metric_id, |metric| {
-            metric
-                .test_get_value(&glean, storage_name.as_str())
-                .is_some()
+        STRING_METRICS.call_with_log(metric_id, |metric| {
+            let storage_name = storage_name.to_string_fallible()?;
+            Ok(metric.test_get_value(glean, &storage_name).is_some())
         })
     })
 }
@@ -42,10 +41,11 @@ pub extern "C" fn glean_string_test_has_value(metric_id: u64, storage_name: FfiS
 #[no_mangle]
 pub extern "C" fn glean_string_test_get_value(metric_id: u64, storage_name: FfiStr) -> *mut c_char {
     with_glean_value(|glean| {
-        STRING_METRICS.call_infallible(metric_id, |metric| {
+        STRING_METRICS.call_with_log(metric_id, |metric| {
+            let storage_name = storage_name.to_string_fallible()?;
             metric
-                .test_get_value(&glean, storage_name.as_str())
-                .unwrap()
+                .test_get_value(glean, &storage_name)
+                .ok_or_else(glean_core::Error::no_value)
         })
     })
 }
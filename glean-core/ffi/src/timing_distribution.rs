@@ -7,8 +7,9 @@ use std::os::raw::c_char;
 use ffi_support::FfiStr;
 
 use crate::{
-    define_metric, from_raw_int64_array, handlemap_ext::HandleMapExtension, with_glean_value,
-    Lifetime, RawInt64Array, TimeUnit,
+    define_metric, ffi_string_ext::FallibleToString, from_raw_int64_array,
+    handlemap_ext::HandleMapExtension, with_accumulator, with_glean_value, Lifetime, RawInt64Array,
+    TimeUnit,
 };
 use glean_core::metrics::TimerId;
 
@@ -50,15 +51,15 @@ pub extern "C" fn glean_timing_distribution_accumulate_samples(
     raw_samples: RawInt64Array,
     num_samples: i32,
 ) {
-    with_glean_value(|glean| {
-        TIMING_DISTRIBUTION_METRICS.call_infallible_mut(metric_id, |metric| {
+    with_accumulator(|accumulator| {
+        TIMING_DISTRIBUTION_METRICS.call_infallible(metric_id, |metric| {
             // The Kotlin code is sending Long(s), which are 64 bits, as there's
             // currently no stable UInt type. The positive part of [Int] would not
             // be enough to represent the values coming in:.
             // Here Long(s) are handled as i64 and then casted in `accumulate_samples_signed`
             // to u32.
             let samples = from_raw_int64_array(raw_samples, num_samples);
-            metric.accumulate_samples_signed(glean, samples);
+            accumulator.accumulate_timing_distribution_samples(metric_id, metric, samples);
         })
     })
 }
@@ -69,10 +70,9 @@ pub extern "C" fn glean_timing_distribution_test_has_value(
     storage_name: FfiStr,
 ) -> u8 {
     with_glean_value(|glean| {
-        TIMING_DISTRIBUTION_METRICS.call_infallible(metric_id, |metric| {
-            metric
-                .test_get_value(glean, storage_name.as_str())
-                .is_some()
+        TIMING_DISTRIBUTION_METRICS.call_with_log(metric_id, |metric| {
+            let storage_name = storage_name.to_string_fallible()?;
+            Ok(metric.test_get_value(glean, &storage_name).is_some())
         })
     })
 }
@@ -83,10 +83,11 @@ pub extern "C" fn glean_timing_distribution_test_get_value_as_json_string(
     storage_name: FfiStr,
 ) -> *mut c_char {
     with_glean_value(|glean| {
-        TIMING_DISTRIBUTION_METRICS.call_infallible(metric_id, |metric| {
+        TIMING_DISTRIBUTION_METRICS.call_with_log(metric_id, |metric| {
+            let storage_name = storage_name.to_string_fallible()?;
             metric
-                .test_get_value_as_json_string(glean, storage_name.as_str())
-                .unwrap()
+                .test_get_value_as_json_string(glean, &storage_name)
+                .ok_or_else(glean_core::Error::no_value)
         })
     })
 }
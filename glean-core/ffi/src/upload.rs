@@ -4,8 +4,8 @@
 
 //! FFI compatible types for the upload mechanism.
 //!
-//! These are used in the `glean_get_upload_task` and `glean_process_ping_upload_response`
-//! functions.
+//! These are used in the `glean_get_upload_task`, `glean_process_ping_upload_response`
+//! and `glean_process_ping_upload_batch_response` functions.
 
 use std::ffi::CString;
 use std::os::raw::c_char;
@@ -13,7 +13,7 @@ use std::os::raw::c_char;
 use ffi_support::IntoFfi;
 
 use crate::{byte_buffer::ByteBuffer, glean_str_free};
-use glean_core::upload::PingUploadTask;
+use glean_core::upload::{PingRequest, PingUploadTask};
 
 /// Result values of attempted ping uploads encoded for FFI use.
 ///
@@ -51,6 +51,74 @@ pub mod upload_result {
     pub const UPLOAD_RESULT_HTTP_STATUS: u32 = 0x8000;
 }
 
+/// A FFI-compatible representation of a single ping request in a batch.
+///
+/// This is exposed as a C struct, like this:
+///
+/// ```c
+/// typedef struct {
+///   char *document_id;
+///   char *path;
+///   ByteBuffer body;
+///   char *headers;
+/// } FfiPingRequest;
+/// ```
+///
+/// The fields are encoded the same way as in the `Upload` variant of the `FfiPingUploadTask`
+/// and are freed together with the task.
+#[repr(C)]
+pub struct FfiPingRequest {
+    document_id: *mut c_char,
+    path: *mut c_char,
+    body: ByteBuffer,
+    headers: *mut c_char,
+}
+
+impl From<PingRequest> for FfiPingRequest {
+    fn from(request: PingRequest) -> Self {
+        // Safe unwraps:
+        // 1. CString::new(..) should not fail as we are the ones that created the strings being transformed;
+        // 2. serde_json::to_string(&request.headers) should not fail as request.headers is a HashMap of Strings.
+        let document_id = CString::new(request.document_id).unwrap();
+        let path = CString::new(request.path).unwrap();
+        let headers = CString::new(serde_json::to_string(&request.headers).unwrap()).unwrap();
+        FfiPingRequest {
+            document_id: document_id.into_raw(),
+            path: path.into_raw(),
+            body: ByteBuffer::from_vec(request.body),
+            headers: headers.into_raw(),
+        }
+    }
+}
+
+impl FfiPingRequest {
+    /// The document ID of this request.
+    ///
+    /// # Safety
+    ///
+    /// The document ID needs to be a valid, null-terminated C string, as created by `From<PingRequest>`.
+    pub(crate) unsafe fn document_id(&self) -> Option<&str> {
+        if self.document_id.is_null() {
+            return None;
+        }
+        std::ffi::CStr::from_ptr(self.document_id).to_str().ok()
+    }
+}
+
+impl Drop for FfiPingRequest {
+    fn drop(&mut self) {
+        // We need to free the previously allocated strings before dropping.
+        unsafe {
+            glean_str_free(self.document_id);
+            glean_str_free(self.path);
+            glean_str_free(self.headers);
+        }
+        // See `Drop for FfiPingUploadTask` on why we replace the body first.
+        let body = std::mem::replace(&mut self.body, ByteBuffer::new_with_size(0));
+        body.destroy();
+    }
+}
+
 /// A FFI-compatible representation for the PingUploadTask.
 ///
 /// This is exposed as a C-compatible tagged union, like this:
@@ -60,6 +128,8 @@ pub mod upload_result {
 ///   FfiPingUploadTask_Upload,
 ///   FfiPingUploadTask_Wait,
 ///   FfiPingUploadTask_Done,
+///   FfiPingUploadTask_UploadBatch,
+///   FfiPingUploadTask_WaitFor,
 /// };
 /// typedef uint8_t FfiPingUploadTask_Tag;
 ///
@@ -71,9 +141,23 @@ pub mod upload_result {
 ///   char *headers;
 /// } FfiPingUploadTask_Upload_Body;
 ///
+/// typedef struct {
+///   FfiPingUploadTask_Tag tag;
+///   FfiPingRequest *requests;
+///   int32_t len;
+/// } FfiPingUploadTask_UploadBatch_Body;
+///
+/// typedef struct {
+///   FfiPingUploadTask_Tag tag;
+///   uint8_t reason;
+///   uint64_t delay;
+/// } FfiPingUploadTask_WaitFor_Body;
+///
 /// typedef union {
 ///   FfiPingUploadTask_Tag tag;
 ///   FfiPingUploadTask_Upload_Body upload;
+///   FfiPingUploadTask_UploadBatch_Body upload_batch;
+///   FfiPingUploadTask_WaitFor_Body wait_for;
 /// } FfiPingUploadTask;
 ///
 /// ```
@@ -91,6 +175,15 @@ pub mod upload_result {
 ///   Instead `glean_process_ping_upload_response` will receive the whole enum, taking care of
 ///   freeing the memory.
 ///
+/// The `UploadBatch` variant carries an array of `len` requests, in upload order.
+/// It is only returned if batching is enabled (see `glean_set_upload_batch_limits`)
+/// and needs to be passed to `glean_process_ping_upload_batch_response`.
+///
+/// The `WaitFor` variant is returned while pings are held back because of the network state
+/// (see `glean_set_network_state`). The `reason` is `1` if the device is offline
+/// and `2` if it is on a metered network. The uploader should ask again after `delay` milliseconds
+/// or as soon as the network state changes.
+///
 ///
 /// The order of variants should be the same as in `glean-core/src/upload/mod.rs`
 /// and `glean-core/android/src/main/java/mozilla/telemetry/glean/net/Upload.kt`.
@@ -106,6 +199,14 @@ pub enum FfiPingUploadTask {
     },
     Wait,
     Done,
+    UploadBatch {
+        requests: *mut FfiPingRequest,
+        len: i32,
+    },
+    WaitFor {
+        reason: u8,
+        delay: u64,
+    },
 }
 
 impl From<PingUploadTask> for FfiPingUploadTask {
@@ -128,6 +229,34 @@ impl From<PingUploadTask> for FfiPingUploadTask {
             }
             PingUploadTask::Wait => FfiPingUploadTask::Wait,
             PingUploadTask::Done => FfiPingUploadTask::Done,
+            PingUploadTask::UploadBatch(requests) => {
+                let requests: Box<[FfiPingRequest]> =
+                    requests.into_iter().map(FfiPingRequest::from).collect();
+                let len = requests.len() as i32;
+                FfiPingUploadTask::UploadBatch {
+                    requests: Box::into_raw(requests) as *mut FfiPingRequest,
+                    len,
+                }
+            }
+            PingUploadTask::WaitFor(reason, delay) => FfiPingUploadTask::WaitFor {
+                reason: reason as u8,
+                delay,
+            },
+        }
+    }
+}
+
+impl FfiPingUploadTask {
+    /// The requests in an `UploadBatch` task.
+    ///
+    /// Returns an empty slice for any other task.
+    pub(crate) fn batch_requests(&self) -> &[FfiPingRequest] {
+        match self {
+            // Safety: the array was created from a boxed slice of exactly `len` requests.
+            FfiPingUploadTask::UploadBatch { requests, len } if !requests.is_null() => unsafe {
+                std::slice::from_raw_parts(*requests, *len as usize)
+            },
+            _ => &[],
         }
     }
 }
@@ -154,6 +283,19 @@ impl Drop for FfiPingUploadTask {
             let body = std::mem::replace(body, ByteBuffer::new_with_size(0));
             body.destroy();
         }
+
+        if let FfiPingUploadTask::UploadBatch { requests, len } = self {
+            if !requests.is_null() {
+                // Safety: the array was created from a boxed slice of exactly `len` requests.
+                // Dropping the box drops each request, freeing its fields.
+                unsafe {
+                    let requests = std::ptr::slice_from_raw_parts_mut(*requests, *len as usize);
+                    drop(Box::from_raw(requests));
+                }
+            }
+            *requests = std::ptr::null_mut();
+            *len = 0;
+        }
     }
 }
 
@@ -190,4 +332,28 @@ mod test {
             glean_core::upload::ffi_upload_result::UPLOAD_RESULT_HTTP_STATUS
         );
     }
+
+    #[test]
+    fn batches_are_converted_and_freed() {
+        let requests = (0..3)
+            .map(|i| {
+                PingRequest::builder("Rust")
+                    .document_id(format!("doc-{}", i))
+                    .path("/submit/app/ping/1/doc")
+                    .body("{}")
+                    .build()
+            })
+            .collect();
+
+        let task = FfiPingUploadTask::from(PingUploadTask::UploadBatch(requests));
+        let document_ids: Vec<_> = task
+            .batch_requests()
+            .iter()
+            .map(|request| unsafe { request.document_id() }.unwrap().to_string())
+            .collect();
+        assert_eq!(vec!["doc-0", "doc-1", "doc-2"], document_ids);
+
+        assert!(FfiPingUploadTask::Done.batch_requests().is_empty());
+        drop(task);
+    }
 }
@@ -0,0 +1,956 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Drives the exported functions with invalid handles, strings and pointers,
+//! the way a misbehaving language binding would.
+//!
+//! None of these calls may abort the process.
+//! They return a default value and report the error through `glean_last_error_message`.
+
+use std::ffi::{CStr, CString};
+use std::mem;
+use std::os::raw::c_char;
+use std::ptr;
+
+use ffi_support::FfiStr;
+use glean_ffi::upload::FfiPingUploadTask;
+use glean_ffi::{FfiConfiguration, Lifetime, MemoryUnit, TimeUnit};
+
+type RawStringArray = *const *const c_char;
+
+// The exported functions, declared as a C caller sees them in `glean.h`.
+extern "C" {
+    fn glean_last_error_message() -> *mut c_char;
+    fn glean_str_free(s: *mut c_char);
+
+    fn glean_initialize(cfg: *const FfiConfiguration) -> u8;
+    fn glean_initialize_for_subprocess(cfg: *const FfiConfiguration) -> u8;
+    fn glean_initialize_with_json(json: *const c_char) -> u8;
+    fn glean_clear_application_lifetime_metrics();
+    fn glean_get_server_endpoint() -> *mut c_char;
+    fn glean_clear_ping_observers();
+    fn glean_is_dirty_flag_set() -> u8;
+    fn glean_is_first_run() -> u8;
+    fn glean_is_upload_enabled() -> u8;
+    fn glean_on_ready_to_submit_pings() -> u8;
+    fn glean_set_dirty_flag(flag: u8);
+    fn glean_set_log_pings(value: u8);
+    fn glean_set_network_state(online: u8, metered: u8);
+    fn glean_set_upload_batch_limits(max_count: i32, max_bytes: i64);
+    fn glean_set_upload_enabled(flag: u8);
+    fn glean_stop_watching_debug_file();
+    fn glean_test_clear_all_stores();
+    fn glean_watch_debug_file(poll_interval_ms: i64) -> u8;
+    fn glean_get_upload_task(result: *mut FfiPingUploadTask);
+    fn glean_process_ping_upload_response(task: *mut FfiPingUploadTask, status: u32);
+    fn glean_process_ping_upload_batch_response(
+        task: *mut FfiPingUploadTask,
+        statuses: *const u32,
+        statuses_len: i32,
+    );
+
+    fn glean_set_debug_view_tag(tag: *const c_char) -> u8;
+    fn glean_set_debug_view_tag_with_limits(
+        tag: *const c_char,
+        expires_after_ms: i64,
+        max_pings: i32,
+        raw_pings: RawStringArray,
+        pings_count: i32,
+    ) -> u8;
+    fn glean_set_source_tags(raw_tags: RawStringArray, tags_count: i32) -> u8;
+    fn glean_set_source_tags_with_limits(
+        raw_tags: RawStringArray,
+        tags_count: i32,
+        expires_after_ms: i64,
+        max_pings: i32,
+        raw_pings: RawStringArray,
+        pings_count: i32,
+    ) -> u8;
+    fn glean_set_experiment_active(
+        experiment_id: *const c_char,
+        branch: *const c_char,
+        extra_keys: RawStringArray,
+        extra_values: RawStringArray,
+        extra_len: i32,
+    );
+    fn glean_set_experiment_inactive(experiment_id: *const c_char);
+    fn glean_experiment_test_is_active(experiment_id: *const c_char) -> u8;
+    fn glean_experiment_test_get_data(experiment_id: *const c_char) -> *mut c_char;
+    fn glean_submit_ping_by_name(ping_name: *const c_char, reason: *const c_char) -> u8;
+
+    fn glean_new_ping_type(
+        ping_name: *const c_char,
+        include_client_id: u8,
+        send_if_empty: u8,
+        reason_codes: RawStringArray,
+        reason_codes_len: i32,
+    ) -> u64;
+    fn glean_destroy_ping_type(v: u64);
+    fn glean_register_ping_type(ping_type_handle: u64);
+    fn glean_test_has_ping_type(ping_name: *const c_char) -> u8;
+    fn glean_ping_collect(ping_type_handle: u64, reason: *const c_char) -> *mut c_char;
+
+    fn glean_new_boolean_metric(
+        category: *const c_char,
+        name: *const c_char,
+        send_in_pings: RawStringArray,
+        send_in_pings_len: i32,
+        lifetime: Lifetime,
+        disabled: u8,
+    ) -> u64;
+    fn glean_destroy_boolean_metric(v: u64);
+    fn glean_boolean_set(metric_id: u64, value: u8);
+    fn glean_boolean_test_get_value(metric_id: u64, storage_name: *const c_char) -> u8;
+    fn glean_boolean_test_has_value(metric_id: u64, storage_name: *const c_char) -> u8;
+
+    fn glean_new_counter_metric(
+        category: *const c_char,
+        name: *const c_char,
+        send_in_pings: RawStringArray,
+        send_in_pings_len: i32,
+        lifetime: Lifetime,
+        disabled: u8,
+    ) -> u64;
+    fn glean_destroy_counter_metric(v: u64);
+    fn glean_counter_add(metric_id: u64, amount: i32);
+    fn glean_counter_test_get_num_recorded_errors(
+        metric_id: u64,
+        error_type: i32,
+        storage_name: *const c_char,
+    ) -> i32;
+    fn glean_counter_test_get_value(metric_id: u64, storage_name: *const c_char) -> i32;
+    fn glean_counter_test_has_value(metric_id: u64, storage_name: *const c_char) -> u8;
+
+    fn glean_new_custom_distribution_metric(
+        category: *const c_char,
+        name: *const c_char,
+        send_in_pings: RawStringArray,
+        send_in_pings_len: i32,
+        lifetime: Lifetime,
+        disabled: u8,
+        range_min: u64,
+        range_max: u64,
+        bucket_count: u64,
+        histogram_type: i32,
+    ) -> u64;
+    fn glean_destroy_custom_distribution_metric(v: u64);
+    fn glean_custom_distribution_accumulate_samples(
+        metric_id: u64,
+        raw_samples: *const i64,
+        num_samples: i32,
+    );
+    fn glean_custom_distribution_test_get_num_recorded_errors(
+        metric_id: u64,
+        error_type: i32,
+        storage_name: *const c_char,
+    ) -> i32;
+    fn glean_custom_distribution_test_get_value_as_json_string(
+        metric_id: u64,
+        storage_name: *const c_char,
+    ) -> *mut c_char;
+    fn glean_custom_distribution_test_has_value(metric_id: u64, storage_name: *const c_char) -> u8;
+
+    fn glean_new_datetime_metric(
+        category: *const c_char,
+        name: *const c_char,
+        send_in_pings: RawStringArray,
+        send_in_pings_len: i32,
+        lifetime: Lifetime,
+        disabled: u8,
+        time_unit: TimeUnit,
+    ) -> u64;
+    fn glean_destroy_datetime_metric(v: u64);
+    fn glean_datetime_set(
+        metric_id: u64,
+        year: i32,
+        month: u32,
+        day: u32,
+        hour: u32,
+        minute: u32,
+        second: u32,
+        nano: i64,
+        offset_seconds: i32,
+    );
+    fn glean_datetime_test_get_num_recorded_errors(
+        metric_id: u64,
+        error_type: i32,
+        storage_name: *const c_char,
+    ) -> i32;
+    fn glean_datetime_test_get_value_as_string(
+        metric_id: u64,
+        storage_name: *const c_char,
+    ) -> *mut c_char;
+    fn glean_datetime_test_has_value(metric_id: u64, storage_name: *const c_char) -> u8;
+
+    fn glean_new_event_metric(
+        category: *const c_char,
+        name: *const c_char,
+        send_in_pings: RawStringArray,
+        send_in_pings_len: i32,
+        lifetime: i32,
+        disabled: u8,
+        extra_keys: RawStringArray,
+        extra_keys_len: i32,
+    ) -> u64;
+    fn glean_destroy_event_metric(v: u64);
+    fn glean_event_record(
+        metric_id: u64,
+        timestamp: u64,
+        extra_keys: *const i32,
+        extra_values: RawStringArray,
+        extra_len: i32,
+    );
+    fn glean_event_test_get_num_recorded_errors(
+        metric_id: u64,
+        error_type: i32,
+        storage_name: *const c_char,
+    ) -> i32;
+    fn glean_event_test_get_value_as_json_string(
+        metric_id: u64,
+        storage_name: *const c_char,
+    ) -> *mut c_char;
+    fn glean_event_test_has_value(metric_id: u64, storage_name: *const c_char) -> u8;
+
+    fn glean_new_jwe_metric(
+        category: *const c_char,
+        name: *const c_char,
+        send_in_pings: RawStringArray,
+        send_in_pings_len: i32,
+        lifetime: Lifetime,
+        disabled: u8,
+    ) -> u64;
+    fn glean_destroy_jwe_metric(v: u64);
+    fn glean_jwe_set(
+        metric_id: u64,
+        header: *const c_char,
+        key: *const c_char,
+        init_vector: *const c_char,
+        cipher_text: *const c_char,
+        auth_tag: *const c_char,
+    );
+    fn glean_jwe_set_with_compact_representation(metric_id: u64, value: *const c_char);
+    fn glean_jwe_test_get_num_recorded_errors(
+        metric_id: u64,
+        error_type: i32,
+        storage_name: *const c_char,
+    ) -> i32;
+    fn glean_jwe_test_get_value(metric_id: u64, storage_name: *const c_char) -> *mut c_char;
+    fn glean_jwe_test_get_value_as_json_string(
+        metric_id: u64,
+        storage_name: *const c_char,
+    ) -> *mut c_char;
+    fn glean_jwe_test_has_value(metric_id: u64, storage_name: *const c_char) -> u8;
+
+    fn glean_new_labeled_boolean_metric(
+        category: *const c_char,
+        name: *const c_char,
+        send_in_pings: RawStringArray,
+        send_in_pings_len: i32,
+        lifetime: i32,
+        disabled: u8,
+        labels: RawStringArray,
+        label_count: i32,
+    ) -> u64;
+    fn glean_destroy_labeled_boolean_metric(v: u64);
+    fn glean_labeled_boolean_metric_get(handle: u64, label: *const c_char) -> u64;
+    fn glean_labeled_boolean_test_get_num_recorded_errors(
+        metric_id: u64,
+        error_type: i32,
+        storage_name: *const c_char,
+    ) -> i32;
+
+    fn glean_new_labeled_counter_metric(
+        category: *const c_char,
+        name: *const c_char,
+        send_in_pings: RawStringArray,
+        send_in_pings_len: i32,
+        lifetime: i32,
+        disabled: u8,
+        labels: RawStringArray,
+        label_count: i32,
+    ) -> u64;
+    fn glean_destroy_labeled_counter_metric(v: u64);
+    fn glean_labeled_counter_metric_get(handle: u64, label: *const c_char) -> u64;
+    fn glean_labeled_counter_test_get_num_recorded_errors(
+        metric_id: u64,
+        error_type: i32,
+        storage_name: *const c_char,
+    ) -> i32;
+
+    fn glean_new_labeled_string_metric(
+        category: *const c_char,
+        name: *const c_char,
+        send_in_pings: RawStringArray,
+        send_in_pings_len: i32,
+        lifetime: i32,
+        disabled: u8,
+        labels: RawStringArray,
+        label_count: i32,
+    ) -> u64;
+    fn glean_destroy_labeled_string_metric(v: u64);
+    fn glean_labeled_string_metric_get(handle: u64, label: *const c_char) -> u64;
+    fn glean_labeled_string_test_get_num_recorded_errors(
+        metric_id: u64,
+        error_type: i32,
+        storage_name: *const c_char,
+    ) -> i32;
+
+    fn glean_new_memory_distribution_metric(
+        category: *const c_char,
+        name: *const c_char,
+        send_in_pings: RawStringArray,
+        send_in_pings_len: i32,
+        lifetime: Lifetime,
+        disabled: u8,
+        memory_unit: MemoryUnit,
+    ) -> u64;
+    fn glean_destroy_memory_distribution_metric(v: u64);
+    fn glean_memory_distribution_accumulate(metric_id: u64, sample: u64);
+    fn glean_memory_distribution_accumulate_samples(
+        metric_id: u64,
+        raw_samples: *const i64,
+        num_samples: i32,
+    );
+    fn glean_memory_distribution_test_get_num_recorded_errors(
+        metric_id: u64,
+        error_type: i32,
+        storage_name: *const c_char,
+    ) -> i32;
+    fn glean_memory_distribution_test_get_value_as_json_string(
+        metric_id: u64,
+        storage_name: *const c_char,
+    ) -> *mut c_char;
+    fn glean_memory_distribution_test_has_value(metric_id: u64, storage_name: *const c_char) -> u8;
+
+    fn glean_new_quantity_metric(
+        category: *const c_char,
+        name: *const c_char,
+        send_in_pings: RawStringArray,
+        send_in_pings_len: i32,
+        lifetime: Lifetime,
+        disabled: u8,
+    ) -> u64;
+    fn glean_destroy_quantity_metric(v: u64);
+    fn glean_quantity_set(metric_id: u64, value: i64);
+    fn glean_quantity_test_get_num_recorded_errors(
+        metric_id: u64,
+        error_type: i32,
+        storage_name: *const c_char,
+    ) -> i32;
+    fn glean_quantity_test_get_value(metric_id: u64, storage_name: *const c_char) -> i64;
+    fn glean_quantity_test_has_value(metric_id: u64, storage_name: *const c_char) -> u8;
+
+    fn glean_new_string_metric(
+        category: *const c_char,
+        name: *const c_char,
+        send_in_pings: RawStringArray,
+        send_in_pings_len: i32,
+        lifetime: Lifetime,
+        disabled: u8,
+    ) -> u64;
+    fn glean_destroy_string_metric(v: u64);
+    fn glean_string_set(metric_id: u64, value: *const c_char);
+    fn glean_string_test_get_num_recorded_errors(
+        metric_id: u64,
+        error_type: i32,
+        storage_name: *const c_char,
+    ) -> i32;
+    fn glean_string_test_get_value(metric_id: u64, storage_name: *const c_char) -> *mut c_char;
+    fn glean_string_test_has_value(metric_id: u64, storage_name: *const c_char) -> u8;
+
+    fn glean_new_string_list_metric(
+        category: *const c_char,
+        name: *const c_char,
+        send_in_pings: RawStringArray,
+        send_in_pings_len: i32,
+        lifetime: Lifetime,
+        disabled: u8,
+    ) -> u64;
+    fn glean_destroy_string_list_metric(v: u64);
+    fn glean_string_list_add(metric_id: u64, value: *const c_char);
+    fn glean_string_list_set(metric_id: u64, values: RawStringArray, values_len: i32);
+    fn glean_string_list_test_get_num_recorded_errors(
+        metric_id: u64,
+        error_type: i32,
+        storage_name: *const c_char,
+    ) -> i32;
+    fn glean_string_list_test_get_value_as_json_string(
+        metric_id: u64,
+        storage_name: *const c_char,
+    ) -> *mut c_char;
+    fn glean_string_list_test_has_value(metric_id: u64, storage_name: *const c_char) -> u8;
+
+    fn glean_new_timespan_metric(
+        category: *const c_char,
+        name: *const c_char,
+        send_in_pings: RawStringArray,
+        send_in_pings_len: i32,
+        lifetime: Lifetime,
+        disabled: u8,
+        time_unit: i32,
+    ) -> u64;
+    fn glean_destroy_timespan_metric(v: u64);
+    fn glean_timespan_cancel(metric_id: u64);
+    fn glean_timespan_set_raw_nanos(metric_id: u64, elapsed_nanos: u64);
+    fn glean_timespan_set_start(metric_id: u64, start_time: u64);
+    fn glean_timespan_set_stop(metric_id: u64, stop_time: u64);
+    fn glean_timespan_test_get_num_recorded_errors(
+        metric_id: u64,
+        error_type: i32,
+        storage_name: *const c_char,
+    ) -> i32;
+    fn glean_timespan_test_get_value(metric_id: u64, storage_name: *const c_char) -> u64;
+    fn glean_timespan_test_has_value(metric_id: u64, storage_name: *const c_char) -> u8;
+
+    fn glean_new_timing_distribution_metric(
+        category: *const c_char,
+        name: *const c_char,
+        send_in_pings: RawStringArray,
+        send_in_pings_len: i32,
+        lifetime: Lifetime,
+        disabled: u8,
+        time_unit: TimeUnit,
+    ) -> u64;
+    fn glean_destroy_timing_distribution_metric(v: u64);
+    fn glean_timing_distribution_accumulate_samples(
+        metric_id: u64,
+        raw_samples: *const i64,
+        num_samples: i32,
+    );
+    fn glean_timing_distribution_cancel(metric_id: u64, timer_id: u64);
+    fn glean_timing_distribution_set_start(metric_id: u64, start_time: u64) -> u64;
+    fn glean_timing_distribution_set_stop_and_accumulate(
+        metric_id: u64,
+        timer_id: u64,
+        stop_time: u64,
+    );
+    fn glean_timing_distribution_test_get_num_recorded_errors(
+        metric_id: u64,
+        error_type: i32,
+        storage_name: *const c_char,
+    ) -> i32;
+    fn glean_timing_distribution_test_get_value_as_json_string(
+        metric_id: u64,
+        storage_name: *const c_char,
+    ) -> *mut c_char;
+    fn glean_timing_distribution_test_has_value(metric_id: u64, storage_name: *const c_char) -> u8;
+
+    fn glean_new_uuid_metric(
+        category: *const c_char,
+        name: *const c_char,
+        send_in_pings: RawStringArray,
+        send_in_pings_len: i32,
+        lifetime: Lifetime,
+        disabled: u8,
+    ) -> u64;
+    fn glean_destroy_uuid_metric(v: u64);
+    fn glean_uuid_set(metric_id: u64, value: *const c_char);
+    fn glean_uuid_test_get_value(metric_id: u64, storage_name: *const c_char) -> *mut c_char;
+    fn glean_uuid_test_has_value(metric_id: u64, storage_name: *const c_char) -> u8;
+}
+
+/// A handle that was never handed out by any handle map.
+const BOGUS_HANDLE: u64 = 0x7fff_0000_dead_beef;
+
+const HANDLE_ERROR: &str = "handle";
+const STRING_ERROR: &str = "invalid utf-8";
+const NULL_ERROR: &str = "null pointer";
+const NO_VALUE_ERROR: &str = "no value stored";
+
+/// Take the last error and check that it mentions `expected`.
+fn assert_last_error(call: &str, expected: &str) {
+    let message = unsafe { glean_last_error_message() };
+    assert!(!message.is_null(), "`{}` did not report an error", call);
+    let text = unsafe { CStr::from_ptr(message) }
+        .to_string_lossy()
+        .to_lowercase();
+    unsafe { glean_str_free(message) };
+    assert!(
+        text.contains(expected),
+        "`{}` reported `{}`, expected `{}`",
+        call,
+        text,
+        expected
+    );
+}
+
+fn is_default<T: Default + PartialEq>(value: &T) -> bool {
+    *value == T::default()
+}
+
+/// Check that each call returns the default value and reports an error mentioning `$expected`.
+macro_rules! assert_fails {
+    ($expected:expr => $($call:expr),+ $(,)?) => {
+        $(
+            let value = $call;
+            assert!(is_default(&value), "`{}` returned a value", stringify!($call));
+            assert_last_error(stringify!($call), $expected);
+        )+
+    };
+}
+
+/// Check that each call returns a null string and reports an error mentioning `$expected`.
+macro_rules! assert_fails_null {
+    ($expected:expr => $($call:expr),+ $(,)?) => {
+        $(
+            let value = $call;
+            assert!(value.is_null(), "`{}` returned a string", stringify!($call));
+            assert_last_error(stringify!($call), $expected);
+        )+
+    };
+}
+
+#[test]
+fn exported_functions_report_invalid_arguments() {
+    let _ = env_logger::try_init();
+
+    let valid = CString::new("valid").unwrap();
+    let valid = valid.as_ptr();
+    let metrics = CString::new("metrics").unwrap();
+    let metrics = metrics.as_ptr();
+    let invalid_utf8 = CString::new(vec![0xff, 0xfe]).unwrap();
+    let invalid = invalid_utf8.as_ptr();
+    let null: *const c_char = ptr::null();
+    let invalid_array = [valid, invalid];
+    let invalid_array = invalid_array.as_ptr();
+    let pings = [metrics];
+    let pings = pings.as_ptr();
+    let samples = [1i64, 2, 3];
+    let samples = samples.as_ptr();
+    let mut task = FfiPingUploadTask::Done;
+
+    // Nothing can be done before Glean is initialized.
+    unsafe {
+        assert_fails!("global glean object missing" =>
+            glean_clear_application_lifetime_metrics(),
+            glean_clear_ping_observers(),
+            glean_is_dirty_flag_set(),
+            glean_is_first_run(),
+            glean_is_upload_enabled(),
+            glean_on_ready_to_submit_pings(),
+            glean_set_dirty_flag(1),
+            glean_set_log_pings(1),
+            glean_set_network_state(1, 0),
+            glean_set_upload_batch_limits(1, 1),
+            glean_set_upload_enabled(1),
+            glean_stop_watching_debug_file(),
+            glean_test_clear_all_stores(),
+            glean_watch_debug_file(100),
+            glean_get_upload_task(&mut task),
+            glean_set_debug_view_tag(valid),
+            glean_submit_ping_by_name(valid, null),
+            glean_counter_add(BOGUS_HANDLE, 1),
+        );
+    }
+    unsafe {
+        assert_fails_null!("global glean object missing" => glean_get_server_endpoint());
+    }
+
+    unsafe {
+        assert_fails!(NULL_ERROR =>
+            glean_initialize(ptr::null()),
+            glean_initialize_for_subprocess(ptr::null()),
+        );
+    }
+
+    let missing_application_id = CString::new(r#"{ "data_path": "/tmp" }"#).unwrap();
+    unsafe {
+        assert_fails!(STRING_ERROR =>
+            glean_initialize_with_json(null),
+            glean_initialize_with_json(invalid),
+        );
+    }
+    unsafe {
+        assert_fails!("json" =>
+            glean_initialize_with_json(valid),
+            glean_initialize_with_json(missing_application_id.as_ptr()),
+        );
+    }
+
+    let dir = tempfile::tempdir().unwrap();
+    let data_dir = CString::new(dir.path().display().to_string()).unwrap();
+    let mut cfg = FfiConfiguration {
+        struct_size: mem::size_of::<FfiConfiguration>() as u32,
+        abi_version: glean_ffi::GLEAN_FFI_ABI_VERSION,
+        data_dir: FfiStr::from_cstr(&data_dir),
+        package_name: FfiStr::from_cstr(CStr::from_bytes_with_nul(b"org.mozilla.ffi\0").unwrap()),
+        language_binding_name: FfiStr::from_cstr(CStr::from_bytes_with_nul(b"Rust\0").unwrap()),
+        upload_enabled: 1,
+        max_events: None,
+        delay_ping_lifetime_io: 0,
+    };
+
+    // A configuration built for another ABI is rejected.
+    cfg.abi_version += 1;
+    unsafe {
+        assert_fails!("incompatible abi" => glean_initialize(&cfg));
+    }
+    cfg.abi_version -= 1;
+    cfg.struct_size -= 1;
+    unsafe {
+        assert_fails!("incompatible abi" => glean_initialize_for_subprocess(&cfg));
+    }
+    cfg.struct_size += 1;
+
+    assert_eq!(1, unsafe { glean_initialize(&cfg) });
+    assert!(unsafe { glean_last_error_message() }.is_null());
+    // The configuration has no server endpoint, the platform's default is used.
+    assert!(unsafe { glean_get_server_endpoint() }.is_null());
+
+    // A valid JSON document initializes Glean, replacing the previous instance.
+    let json_dir = tempfile::tempdir().unwrap();
+    let json = serde_json::json!({
+        "data_path": json_dir.path().display().to_string(),
+        "application_id": "org.mozilla.ffi",
+        "server_endpoint": "https://collector.example.com",
+    });
+    let json = CString::new(json.to_string()).unwrap();
+    assert_eq!(1, unsafe { glean_initialize_with_json(json.as_ptr()) });
+    assert!(unsafe { glean_last_error_message() }.is_null());
+    let endpoint = unsafe { glean_get_server_endpoint() };
+    assert!(!endpoint.is_null());
+    assert_eq!(
+        "https://collector.example.com",
+        unsafe { CStr::from_ptr(endpoint) }.to_str().unwrap()
+    );
+    unsafe { glean_str_free(endpoint) };
+
+    unsafe {
+        assert_fails!(NULL_ERROR =>
+            glean_get_upload_task(ptr::null_mut()),
+            glean_process_ping_upload_response(ptr::null_mut(), 200),
+            glean_process_ping_upload_batch_response(ptr::null_mut(), ptr::null(), 0),
+        );
+    }
+
+    // Invalid strings.
+    unsafe {
+        assert_fails!(STRING_ERROR =>
+            glean_set_debug_view_tag(invalid),
+            glean_set_debug_view_tag(null),
+            glean_set_debug_view_tag_with_limits(invalid, 0, 0, ptr::null(), 0),
+            glean_set_debug_view_tag_with_limits(valid, 0, 0, invalid_array, 2),
+            glean_set_source_tags(invalid_array, 2),
+            glean_set_source_tags_with_limits(invalid_array, 2, 0, 0, ptr::null(), 0),
+            glean_set_experiment_active(invalid, valid, ptr::null(), ptr::null(), 0),
+            glean_set_experiment_active(valid, null, ptr::null(), ptr::null(), 0),
+            glean_set_experiment_inactive(invalid),
+            glean_experiment_test_is_active(null),
+            glean_submit_ping_by_name(invalid, null),
+            glean_test_has_ping_type(invalid),
+            glean_new_ping_type(invalid, 1, 1, ptr::null(), 0),
+            glean_new_ping_type(valid, 1, 1, invalid_array, 2),
+            glean_new_boolean_metric(invalid, valid, pings, 1, Lifetime::Ping, 0),
+            glean_new_counter_metric(valid, null, pings, 1, Lifetime::Ping, 0),
+            glean_new_custom_distribution_metric(valid, invalid, pings, 1, Lifetime::Ping, 0, 1, 100, 10, 0),
+            glean_new_datetime_metric(valid, valid, invalid_array, 2, Lifetime::Ping, 0, TimeUnit::Second),
+            glean_new_event_metric(valid, valid, pings, 1, 0, 0, invalid_array, 2),
+            glean_new_jwe_metric(null, valid, pings, 1, Lifetime::Ping, 0),
+            glean_new_labeled_boolean_metric(valid, valid, pings, 1, 0, 0, invalid_array, 2),
+            glean_new_labeled_counter_metric(invalid, valid, pings, 1, 0, 0, ptr::null(), 0),
+            glean_new_labeled_string_metric(valid, invalid, pings, 1, 0, 0, ptr::null(), 0),
+            glean_new_memory_distribution_metric(invalid, valid, pings, 1, Lifetime::Ping, 0, MemoryUnit::Byte),
+            glean_new_quantity_metric(invalid, valid, pings, 1, Lifetime::Ping, 0),
+            glean_new_string_metric(invalid, valid, pings, 1, Lifetime::Ping, 0),
+            glean_new_string_list_metric(invalid, valid, pings, 1, Lifetime::Ping, 0),
+            glean_new_timespan_metric(invalid, valid, pings, 1, Lifetime::Ping, 0, 0),
+            glean_new_timing_distribution_metric(invalid, valid, pings, 1, Lifetime::Ping, 0, TimeUnit::Second),
+            glean_new_uuid_metric(invalid, valid, pings, 1, Lifetime::Ping, 0),
+        );
+    }
+    unsafe {
+        assert_fails_null!(STRING_ERROR => glean_experiment_test_get_data(invalid));
+    }
+
+    // Invalid enum values.
+    unsafe {
+        assert_fails!("conversion" =>
+            glean_new_custom_distribution_metric(valid, valid, pings, 1, Lifetime::Ping, 0, 1, 100, 10, 42),
+            glean_new_event_metric(valid, valid, pings, 1, 42, 0, ptr::null(), 0),
+            glean_new_labeled_counter_metric(valid, valid, pings, 1, 42, 0, ptr::null(), 0),
+            glean_new_timespan_metric(valid, valid, pings, 1, Lifetime::Ping, 0, 42),
+        );
+    }
+
+    // Invalid handles.
+    unsafe {
+        assert_fails!(HANDLE_ERROR =>
+            glean_destroy_ping_type(BOGUS_HANDLE),
+            glean_register_ping_type(BOGUS_HANDLE),
+            glean_destroy_boolean_metric(BOGUS_HANDLE),
+            glean_boolean_set(BOGUS_HANDLE, 1),
+            glean_boolean_test_get_value(BOGUS_HANDLE, metrics),
+            glean_boolean_test_has_value(BOGUS_HANDLE, metrics),
+            glean_destroy_counter_metric(BOGUS_HANDLE),
+            glean_counter_add(BOGUS_HANDLE, 1),
+            glean_counter_test_get_num_recorded_errors(BOGUS_HANDLE, 0, metrics),
+            glean_counter_test_get_value(BOGUS_HANDLE, metrics),
+            glean_counter_test_has_value(BOGUS_HANDLE, metrics),
+            glean_destroy_custom_distribution_metric(BOGUS_HANDLE),
+            glean_custom_distribution_accumulate_samples(BOGUS_HANDLE, samples, 3),
+            glean_custom_distribution_test_get_num_recorded_errors(BOGUS_HANDLE, 0, metrics),
+            glean_custom_distribution_test_has_value(BOGUS_HANDLE, metrics),
+            glean_destroy_datetime_metric(BOGUS_HANDLE),
+            glean_datetime_set(BOGUS_HANDLE, 2020, 1, 1, 0, 0, 0, 0, 0),
+            glean_datetime_test_get_num_recorded_errors(BOGUS_HANDLE, 0, metrics),
+            glean_datetime_test_has_value(BOGUS_HANDLE, metrics),
+            glean_destroy_event_metric(BOGUS_HANDLE),
+            glean_event_record(BOGUS_HANDLE, 0, ptr::null(), ptr::null(), 0),
+            glean_event_test_get_num_recorded_errors(BOGUS_HANDLE, 0, metrics),
+            glean_event_test_has_value(BOGUS_HANDLE, metrics),
+            glean_destroy_jwe_metric(BOGUS_HANDLE),
+            glean_jwe_set(BOGUS_HANDLE, valid, valid, valid, valid, valid),
+            glean_jwe_set_with_compact_representation(BOGUS_HANDLE, valid),
+            glean_jwe_test_get_num_recorded_errors(BOGUS_HANDLE, 0, metrics),
+            glean_jwe_test_has_value(BOGUS_HANDLE, metrics),
+            glean_destroy_labeled_boolean_metric(BOGUS_HANDLE),
+            glean_labeled_boolean_metric_get(BOGUS_HANDLE, valid),
+            glean_labeled_boolean_test_get_num_recorded_errors(BOGUS_HANDLE, 0, metrics),
+            glean_destroy_labeled_counter_metric(BOGUS_HANDLE),
+            glean_labeled_counter_metric_get(BOGUS_HANDLE, valid),
+            glean_labeled_counter_test_get_num_recorded_errors(BOGUS_HANDLE, 0, metrics),
+            glean_destroy_labeled_string_metric(BOGUS_HANDLE),
+            glean_labeled_string_metric_get(BOGUS_HANDLE, valid),
+            glean_labeled_string_test_get_num_recorded_errors(BOGUS_HANDLE, 0, metrics),
+            glean_destroy_memory_distribution_metric(BOGUS_HANDLE),
+            glean_memory_distribution_accumulate(BOGUS_HANDLE, 1),
+            glean_memory_distribution_accumulate_samples(BOGUS_HANDLE, samples, 3),
+            glean_memory_distribution_test_get_num_recorded_errors(BOGUS_HANDLE, 0, metrics),
+            glean_memory_distribution_test_has_value(BOGUS_HANDLE, metrics),
+            glean_destroy_quantity_metric(BOGUS_HANDLE),
+            glean_quantity_set(BOGUS_HANDLE, 1),
+            glean_quantity_test_get_num_recorded_errors(BOGUS_HANDLE, 0, metrics),
+            glean_quantity_test_get_value(BOGUS_HANDLE, metrics),
+            glean_quantity_test_has_value(BOGUS_HANDLE, metrics),
+            glean_destroy_string_metric(BOGUS_HANDLE),
+            glean_string_set(BOGUS_HANDLE, valid),
+            glean_string_test_get_num_recorded_errors(BOGUS_HANDLE, 0, metrics),
+            glean_string_test_has_value(BOGUS_HANDLE, metrics),
+            glean_destroy_string_list_metric(BOGUS_HANDLE),
+            glean_string_list_add(BOGUS_HANDLE, valid),
+            glean_string_list_set(BOGUS_HANDLE, pings, 1),
+            glean_string_list_test_get_num_recorded_errors(BOGUS_HANDLE, 0, metrics),
+            glean_string_list_test_has_value(BOGUS_HANDLE, metrics),
+            glean_destroy_timespan_metric(BOGUS_HANDLE),
+            glean_timespan_cancel(BOGUS_HANDLE),
+            glean_timespan_set_raw_nanos(BOGUS_HANDLE, 1),
+            glean_timespan_set_start(BOGUS_HANDLE, 1),
+            glean_timespan_set_stop(BOGUS_HANDLE, 2),
+            glean_timespan_test_get_num_recorded_errors(BOGUS_HANDLE, 0, metrics),
+            glean_timespan_test_get_value(BOGUS_HANDLE, metrics),
+            glean_timespan_test_has_value(BOGUS_HANDLE, metrics),
+            glean_destroy_timing_distribution_metric(BOGUS_HANDLE),
+            glean_timing_distribution_accumulate_samples(BOGUS_HANDLE, samples, 3),
+            glean_timing_distribution_cancel(BOGUS_HANDLE, 1),
+            glean_timing_distribution_set_start(BOGUS_HANDLE, 1),
+            glean_timing_distribution_set_stop_and_accumulate(BOGUS_HANDLE, 1, 2),
+            glean_timing_distribution_test_get_num_recorded_errors(BOGUS_HANDLE, 0, metrics),
+            glean_timing_distribution_test_has_value(BOGUS_HANDLE, metrics),
+            glean_destroy_uuid_metric(BOGUS_HANDLE),
+            glean_uuid_set(BOGUS_HANDLE, valid),
+            glean_uuid_test_has_value(BOGUS_HANDLE, metrics),
+        );
+    }
+    unsafe {
+        assert_fails_null!(HANDLE_ERROR =>
+            glean_ping_collect(BOGUS_HANDLE, null),
+            glean_custom_distribution_test_get_value_as_json_string(BOGUS_HANDLE, metrics),
+            glean_datetime_test_get_value_as_string(BOGUS_HANDLE, metrics),
+            glean_event_test_get_value_as_json_string(BOGUS_HANDLE, metrics),
+            glean_jwe_test_get_value(BOGUS_HANDLE, metrics),
+            glean_jwe_test_get_value_as_json_string(BOGUS_HANDLE, metrics),
+            glean_memory_distribution_test_get_value_as_json_string(BOGUS_HANDLE, metrics),
+            glean_string_test_get_value(BOGUS_HANDLE, metrics),
+            glean_string_list_test_get_value_as_json_string(BOGUS_HANDLE, metrics),
+            glean_timing_distribution_test_get_value_as_json_string(BOGUS_HANDLE, metrics),
+            glean_uuid_test_get_value(BOGUS_HANDLE, metrics),
+        );
+    }
+
+    // Valid handles with invalid strings, error types and missing values.
+    let name = |name: &str| CString::new(name).unwrap();
+    let (boolean, counter, custom, datetime, event, jwe) = (
+        name("boolean"),
+        name("counter"),
+        name("custom"),
+        name("datetime"),
+        name("event"),
+        name("jwe"),
+    );
+    let (labeled, memory, quantity, string, string_list, timespan, timing, uuid) = (
+        name("labeled"),
+        name("memory"),
+        name("quantity"),
+        name("string"),
+        name("string_list"),
+        name("timespan"),
+        name("timing"),
+        name("uuid"),
+    );
+    unsafe {
+        let boolean =
+            glean_new_boolean_metric(valid, boolean.as_ptr(), pings, 1, Lifetime::Ping, 0);
+        let counter =
+            glean_new_counter_metric(valid, counter.as_ptr(), pings, 1, Lifetime::Ping, 0);
+        let custom = glean_new_custom_distribution_metric(
+            valid,
+            custom.as_ptr(),
+            pings,
+            1,
+            Lifetime::Ping,
+            0,
+            1,
+            100,
+            10,
+            0,
+        );
+        let datetime = glean_new_datetime_metric(
+            valid,
+            datetime.as_ptr(),
+            pings,
+            1,
+            Lifetime::Ping,
+            0,
+            TimeUnit::Second,
+        );
+        let event = glean_new_event_metric(valid, event.as_ptr(), pings, 1, 0, 0, ptr::null(), 0);
+        let jwe = glean_new_jwe_metric(valid, jwe.as_ptr(), pings, 1, Lifetime::Ping, 0);
+        let labeled_boolean = glean_new_labeled_boolean_metric(
+            valid,
+            labeled.as_ptr(),
+            pings,
+            1,
+            0,
+            0,
+            ptr::null(),
+            0,
+        );
+        let labeled_counter = glean_new_labeled_counter_metric(
+            valid,
+            labeled.as_ptr(),
+            pings,
+            1,
+            0,
+            0,
+            ptr::null(),
+            0,
+        );
+        let labeled_string = glean_new_labeled_string_metric(
+            valid,
+            labeled.as_ptr(),
+            pings,
+            1,
+            0,
+            0,
+            ptr::null(),
+            0,
+        );
+        let memory = glean_new_memory_distribution_metric(
+            valid,
+            memory.as_ptr(),
+            pings,
+            1,
+            Lifetime::Ping,
+            0,
+            MemoryUnit::Byte,
+        );
+        let quantity =
+            glean_new_quantity_metric(valid, quantity.as_ptr(), pings, 1, Lifetime::Ping, 0);
+        let string = glean_new_string_metric(valid, string.as_ptr(), pings, 1, Lifetime::Ping, 0);
+        let string_list =
+            glean_new_string_list_metric(valid, string_list.as_ptr(), pings, 1, Lifetime::Ping, 0);
+        let timespan =
+            glean_new_timespan_metric(valid, timespan.as_ptr(), pings, 1, Lifetime::Ping, 0, 0);
+        let timing = glean_new_timing_distribution_metric(
+            valid,
+            timing.as_ptr(),
+            pings,
+            1,
+            Lifetime::Ping,
+            0,
+            TimeUnit::Second,
+        );
+        let uuid = glean_new_uuid_metric(valid, uuid.as_ptr(), pings, 1, Lifetime::Ping, 0);
+        assert!(glean_last_error_message().is_null());
+
+        assert_fails!(STRING_ERROR =>
+            glean_boolean_test_has_value(boolean, null),
+            glean_boolean_test_get_value(boolean, invalid),
+            glean_counter_test_has_value(counter, invalid),
+            glean_counter_test_get_value(counter, null),
+            glean_counter_test_get_num_recorded_errors(counter, 0, invalid),
+            glean_custom_distribution_test_has_value(custom, invalid),
+            glean_datetime_test_has_value(datetime, invalid),
+            glean_event_record(event, 0, [0].as_ptr(), [invalid].as_ptr(), 1),
+            glean_event_test_has_value(event, invalid),
+            glean_jwe_set(jwe, valid, invalid, valid, valid, valid),
+            glean_jwe_set_with_compact_representation(jwe, null),
+            glean_jwe_test_has_value(jwe, invalid),
+            glean_labeled_boolean_metric_get(labeled_boolean, invalid),
+            glean_labeled_counter_metric_get(labeled_counter, null),
+            glean_labeled_string_metric_get(labeled_string, invalid),
+            glean_labeled_counter_test_get_num_recorded_errors(labeled_counter, 0, invalid),
+            glean_memory_distribution_test_has_value(memory, invalid),
+            glean_quantity_test_has_value(quantity, invalid),
+            glean_quantity_test_get_value(quantity, invalid),
+            glean_string_set(string, invalid),
+            glean_string_test_has_value(string, invalid),
+            glean_string_list_add(string_list, invalid),
+            glean_string_list_set(string_list, invalid_array, 2),
+            glean_string_list_test_has_value(string_list, invalid),
+            glean_timespan_test_has_value(timespan, invalid),
+            glean_timespan_test_get_value(timespan, invalid),
+            glean_timing_distribution_test_has_value(timing, invalid),
+            glean_uuid_set(uuid, invalid),
+            glean_uuid_test_has_value(uuid, invalid),
+        );
+        assert_fails_null!(STRING_ERROR =>
+            glean_custom_distribution_test_get_value_as_json_string(custom, invalid),
+            glean_datetime_test_get_value_as_string(datetime, invalid),
+            glean_event_test_get_value_as_json_string(event, invalid),
+            glean_jwe_test_get_value(jwe, invalid),
+            glean_jwe_test_get_value_as_json_string(jwe, invalid),
+            glean_memory_distribution_test_get_value_as_json_string(memory, invalid),
+            glean_string_test_get_value(string, null),
+            glean_string_list_test_get_value_as_json_string(string_list, invalid),
+            glean_timing_distribution_test_get_value_as_json_string(timing, invalid),
+            glean_uuid_test_get_value(uuid, invalid),
+        );
+
+        assert_fails!("errortype conversion" =>
+            glean_counter_test_get_num_recorded_errors(counter, 42, metrics),
+            glean_labeled_string_test_get_num_recorded_errors(labeled_string, 42, metrics),
+        );
+
+        assert_fails!(NO_VALUE_ERROR =>
+            glean_boolean_test_get_value(boolean, metrics),
+            glean_counter_test_get_value(counter, metrics),
+            glean_quantity_test_get_value(quantity, metrics),
+            glean_timespan_test_get_value(timespan, metrics),
+        );
+        assert_fails_null!(NO_VALUE_ERROR =>
+            glean_custom_distribution_test_get_value_as_json_string(custom, metrics),
+            glean_datetime_test_get_value_as_string(datetime, metrics),
+            glean_jwe_test_get_value(jwe, metrics),
+            glean_jwe_test_get_value_as_json_string(jwe, metrics),
+            glean_memory_distribution_test_get_value_as_json_string(memory, metrics),
+            glean_string_test_get_value(string, metrics),
+            glean_string_list_test_get_value_as_json_string(string_list, metrics),
+            glean_timing_distribution_test_get_value_as_json_string(timing, metrics),
+            glean_uuid_test_get_value(uuid, metrics),
+        );
+
+        // Successful calls don't report errors, and a destroyed handle is no longer valid.
+        glean_counter_add(counter, 1);
+        assert_eq!(1, glean_counter_test_get_value(counter, metrics));
+        assert!(glean_last_error_message().is_null());
+        // An error is forgotten by the next call on the thread.
+        glean_counter_test_has_value(counter, invalid);
+        glean_counter_test_has_value(counter, metrics);
+        assert!(glean_last_error_message().is_null());
+        glean_destroy_counter_metric(counter);
+        assert!(glean_last_error_message().is_null());
+        assert_fails!(HANDLE_ERROR => glean_counter_test_has_value(counter, metrics));
+    }
+}
@@ -136,6 +136,8 @@ func withFfiConfiguration<R>(
     }
 
     let cfg = FfiConfiguration(
+        struct_size: UInt32(MemoryLayout<FfiConfiguration>.stride),
+        abi_version: UInt32(GLEAN_FFI_ABI_VERSION),
         data_dir: dataDir,
         package_name: packageName,
         language_binding_name: languageBindingName,
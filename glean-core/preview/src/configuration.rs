@@ -2,6 +2,10 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
+use std::sync::Arc;
+
+use crate::net::PingUploader;
+
 /// The Glean configuration.
 ///
 /// Optional values will be filled in with default values.
@@ -19,4 +23,11 @@ pub struct Configuration {
     pub delay_ping_lifetime_io: bool,
     /// The release channel the application is on, if known.
     pub channel: Option<String>,
+    /// The server pings are sent to. Defaults to `net::DEFAULT_SERVER_ENDPOINT`.
+    pub server_endpoint: Option<String>,
+    /// The uploader to send pings with.
+    ///
+    /// If not set, pings are stored until an uploader is configured.
+    /// The uploader must support the `server_endpoint`, otherwise initialization fails.
+    pub uploader: Option<Arc<dyn PingUploader>>,
 }
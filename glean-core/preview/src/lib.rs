@@ -26,6 +26,8 @@
 //!     max_events: None,
 //!     delay_ping_lifetime_io: false,
 //!     channel: None,
+//!     server_endpoint: None,
+//!     uploader: None,
 //! };
 //! glean_preview::initialize(cfg, ClientInfoMetrics::unknown())?;
 //!
@@ -38,8 +40,11 @@
 //! # }
 //! ```
 
-use once_cell::sync::OnceCell;
-use std::sync::Mutex;
+use once_cell::sync::{Lazy, OnceCell};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use glean_core::metrics::Accumulator;
 
 pub use configuration::Configuration;
 pub use core_metrics::ClientInfoMetrics;
@@ -48,6 +53,7 @@ pub use glean_core::{global_glean, setup_glean, CommonMetricData, Error, Glean,
 mod configuration;
 mod core_metrics;
 pub mod metrics;
+pub mod net;
 mod system;
 
 const LANGUAGE_BINDING_NAME: &str = "Rust";
@@ -60,6 +66,9 @@ struct AppState {
 
     /// Client info metrics set by the application.
     client_info: ClientInfoMetrics,
+
+    /// The uploader to send pings with, if any.
+    uploader: Option<Arc<dyn net::PingUploader>>,
 }
 
 /// A global singleton storing additional state for Glean.
@@ -84,28 +93,74 @@ fn setup_state(state: AppState) {
     }
 }
 
+/// Recordings of counters, quantities and distributions made without locking Glean.
+///
+/// They are merged into Glean whenever it is locked, before anything else is done with it,
+/// so that every other operation sees them as if they were recorded directly.
+static ACCUMULATOR: Lazy<Accumulator> = Lazy::new(Accumulator::new);
+
+/// The source of the IDs identifying metrics in the accumulator.
+static NEXT_METRIC_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Allocate the ID of a new metric recorded through the accumulator.
+fn next_metric_id() -> u64 {
+    NEXT_METRIC_ID.fetch_add(1, Ordering::Relaxed)
+}
+
 fn with_glean<F, R>(f: F) -> R
 where
-    F: Fn(&Glean) -> R,
+    F: FnOnce(&Glean) -> R,
 {
     let glean = global_glean().expect("Global Glean object not initialized");
     let lock = glean.lock().unwrap();
+    ACCUMULATOR.merge_into(&lock);
     f(&lock)
 }
 
+/// Record into the accumulator, without locking Glean.
+///
+/// Glean is only locked to merge the accumulator once it buffers too many recordings.
+fn with_accumulator<F>(f: F)
+where
+    F: FnOnce(&Accumulator),
+{
+    let glean = global_glean().expect("Global Glean object not initialized");
+    f(&ACCUMULATOR);
+    if ACCUMULATOR.should_merge() {
+        ACCUMULATOR.merge_into(&glean.lock().unwrap());
+    }
+}
+
 fn with_glean_mut<F, R>(f: F) -> R
 where
-    F: Fn(&mut Glean) -> R,
+    F: FnOnce(&mut Glean) -> R,
 {
     let glean = global_glean().expect("Global Glean object not initialized");
     let mut lock = glean.lock().unwrap();
+    ACCUMULATOR.merge_into(&lock);
     f(&mut lock)
 }
 
 /// Create and initialize a new Glean object.
 ///
 /// See `glean_core::Glean::new`.
+///
+/// Fails if the configured uploader doesn't support the server endpoint.
 pub fn initialize(cfg: Configuration, client_info: ClientInfoMetrics) -> Result<()> {
+    let endpoint = cfg
+        .server_endpoint
+        .as_deref()
+        .unwrap_or(net::DEFAULT_SERVER_ENDPOINT);
+    if let Some(uploader) = &cfg.uploader {
+        if !uploader.supports_endpoint(endpoint) {
+            log::error!("The configured uploader can't upload to {}", endpoint);
+            return Err(glean_core::ErrorKind::InvalidServerEndpoint(endpoint.to_string()).into());
+        }
+    }
+
+    // An upload worker of a previous initialization must not act on the new Glean object.
+    net::join_upload_thread();
+
     let core_cfg = glean_core::Configuration {
         upload_enabled: cfg.upload_enabled,
         data_path: cfg.data_path.clone(),
@@ -113,6 +168,12 @@ pub fn initialize(cfg: Configuration, client_info: ClientInfoMetrics) -> Result<
         language_binding_name: LANGUAGE_BINDING_NAME.into(),
         max_events: cfg.max_events,
         delay_ping_lifetime_io: cfg.delay_ping_lifetime_io,
+        key_provider: None,
+        upload_batch_limits: None,
+        ping_compression: Default::default(),
+        server_endpoint: cfg.server_endpoint.clone(),
+        path_template: None,
+        storage_durability: Default::default(),
     };
     let glean = Glean::new(core_cfg)?;
 
@@ -123,9 +184,13 @@ pub fn initialize(cfg: Configuration, client_info: ClientInfoMetrics) -> Result<
     setup_state(AppState {
         channel: cfg.channel,
         client_info,
+        uploader: cfg.uploader,
     });
     glean_core::setup_glean(glean)?;
 
+    // Upload any pings still pending from a previous run.
+    net::trigger_upload();
+
     Ok(())
 }
 
@@ -159,7 +224,7 @@ fn initialize_core_metrics(
 ///
 /// See `glean_core::Glean.set_upload_enabled`.
 pub fn set_upload_enabled(enabled: bool) -> bool {
-    with_glean_mut(|glean| {
+    let enabled = with_glean_mut(|glean| {
         let state = global_state().lock().unwrap();
         let old_enabled = glean.is_upload_enabled();
         glean.set_upload_enabled(enabled);
@@ -171,7 +236,13 @@ pub fn set_upload_enabled(enabled: bool) -> bool {
         }
 
         enabled
-    })
+    });
+
+    // Disabling upload submits a `deletion-request` ping.
+    if !enabled {
+        net::trigger_upload();
+    }
+    enabled
 }
 
 /// Determine whether upload is enabled.
@@ -181,6 +252,14 @@ pub fn is_upload_enabled() -> bool {
     with_glean(|glean| glean.is_upload_enabled())
 }
 
+/// Set the network conditions pings are uploaded under.
+///
+/// See `glean_core::Glean.set_network_state`.
+pub fn set_network_state(online: bool, metered: bool) {
+    with_glean(|glean| glean.set_network_state(online, metered));
+    net::trigger_upload();
+}
+
 /// Register a new [`PingType`](metrics/struct.PingType.html).
 pub fn register_ping_type(ping: &metrics::PingType) {
     with_glean_mut(|glean| {
@@ -207,7 +286,11 @@ pub fn submit_ping(ping: &metrics::PingType, reason: Option<&str>) -> bool {
 ///
 /// Returns true if a ping was assembled and queued, false otherwise.
 pub fn submit_ping_by_name(ping: &str, reason: Option<&str>) -> bool {
-    with_glean(|glean| glean.submit_ping_by_name(ping, reason).unwrap_or(false))
+    let submitted = with_glean(|glean| glean.submit_ping_by_name(ping, reason).unwrap_or(false));
+    if submitted {
+        net::trigger_upload();
+    }
+    submitted
 }
 
 #[cfg(test)]
@@ -0,0 +1,51 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use glean_core::metrics::MetricType;
+use glean_core::CommonMetricData;
+
+/// A counter metric.
+///
+/// Used to count things.
+/// Increments are recorded without locking Glean, see `glean_core::metrics::Accumulator`.
+#[derive(Clone, Debug)]
+pub struct CounterMetric {
+    id: u64,
+    inner: glean_core::metrics::CounterMetric,
+}
+
+impl CounterMetric {
+    /// Create a new counter metric.
+    pub fn new(meta: CommonMetricData) -> Self {
+        Self {
+            id: crate::next_metric_id(),
+            inner: glean_core::metrics::CounterMetric::new(meta),
+        }
+    }
+
+    /// Increase the counter by `amount`.
+    ///
+    /// See `glean_core::metrics::CounterMetric::add`.
+    ///
+    /// ## Arguments
+    ///
+    /// * `amount` - The amount to increase by. Should be positive.
+    pub fn add(&self, amount: i32) {
+        crate::with_accumulator(|accumulator| {
+            accumulator.add_to_counter(self.id, &self.inner, amount)
+        });
+    }
+
+    /// **Test-only API.**
+    ///
+    /// Get the currently stored value as an integer.
+    ///
+    /// ## Arguments
+    ///
+    /// * `storage_name` - The ping to look into. Defaults to the first ping of the metric.
+    pub fn test_get_value(&self, storage_name: Option<&str>) -> Option<i32> {
+        let storage_name = storage_name.unwrap_or(&self.inner.meta().send_in_pings[0]);
+        crate::with_glean(|glean| self.inner.test_get_value(glean, storage_name))
+    }
+}
@@ -0,0 +1,64 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use glean_core::metrics::{DistributionData, MemoryUnit, MetricType};
+use glean_core::CommonMetricData;
+
+/// A memory distribution metric.
+///
+/// Samples are recorded without locking Glean, see `glean_core::metrics::Accumulator`.
+#[derive(Clone, Debug)]
+pub struct MemoryDistributionMetric {
+    id: u64,
+    inner: glean_core::metrics::MemoryDistributionMetric,
+}
+
+impl MemoryDistributionMetric {
+    /// Create a new memory distribution metric.
+    pub fn new(meta: CommonMetricData, memory_unit: MemoryUnit) -> Self {
+        Self {
+            id: crate::next_metric_id(),
+            inner: glean_core::metrics::MemoryDistributionMetric::new(meta, memory_unit),
+        }
+    }
+
+    /// Accumulate a single sample.
+    ///
+    /// See `glean_core::metrics::MemoryDistributionMetric::accumulate`.
+    ///
+    /// ## Arguments
+    ///
+    /// * `sample` - The sample, in the memory unit of the metric.
+    pub fn accumulate(&self, sample: u64) {
+        crate::with_accumulator(|accumulator| {
+            accumulator.accumulate_memory_distribution(self.id, &self.inner, sample)
+        });
+    }
+
+    /// Accumulate the provided samples in the metric.
+    ///
+    /// See `glean_core::metrics::MemoryDistributionMetric::accumulate_samples_signed`.
+    ///
+    /// ## Arguments
+    ///
+    /// * `samples` - The samples to record, in the memory unit of the metric.
+    ///   Negative samples are discarded and reported as errors.
+    pub fn accumulate_samples_signed(&self, samples: Vec<i64>) {
+        crate::with_accumulator(|accumulator| {
+            accumulator.accumulate_memory_distribution_samples(self.id, &self.inner, samples)
+        });
+    }
+
+    /// **Test-only API.**
+    ///
+    /// Get the currently stored value.
+    ///
+    /// ## Arguments
+    ///
+    /// * `storage_name` - The ping to look into. Defaults to the first ping of the metric.
+    pub fn test_get_value(&self, storage_name: Option<&str>) -> Option<DistributionData> {
+        let storage_name = storage_name.unwrap_or(&self.inner.meta().send_in_pings[0]);
+        crate::with_glean(|glean| self.inner.test_get_value(glean, storage_name))
+    }
+}
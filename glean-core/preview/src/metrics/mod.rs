@@ -4,6 +4,18 @@
 
 //! The different metric types supported by the Glean SDK to handle data.
 
+mod counter;
+mod custom_distribution;
+mod memory_distribution;
 mod ping;
+mod quantity;
+mod timing_distribution;
 
+pub use glean_core::metrics::{DistributionData, HistogramType, MemoryUnit, TimeUnit};
+
+pub use counter::CounterMetric;
+pub use custom_distribution::CustomDistributionMetric;
+pub use memory_distribution::MemoryDistributionMetric;
 pub use ping::PingType;
+pub use quantity::QuantityMetric;
+pub use timing_distribution::TimingDistributionMetric;
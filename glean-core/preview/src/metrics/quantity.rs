@@ -0,0 +1,51 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use glean_core::metrics::MetricType;
+use glean_core::CommonMetricData;
+
+/// A quantity metric.
+///
+/// Used to store explicit non-negative integers.
+/// Values are recorded without locking Glean, see `glean_core::metrics::Accumulator`.
+#[derive(Clone, Debug)]
+pub struct QuantityMetric {
+    id: u64,
+    inner: glean_core::metrics::QuantityMetric,
+}
+
+impl QuantityMetric {
+    /// Create a new quantity metric.
+    pub fn new(meta: CommonMetricData) -> Self {
+        Self {
+            id: crate::next_metric_id(),
+            inner: glean_core::metrics::QuantityMetric::new(meta),
+        }
+    }
+
+    /// Set the value.
+    ///
+    /// See `glean_core::metrics::QuantityMetric::set`.
+    ///
+    /// ## Arguments
+    ///
+    /// * `value` - The value. Must be non-negative.
+    pub fn set(&self, value: i64) {
+        crate::with_accumulator(|accumulator| {
+            accumulator.set_quantity(self.id, &self.inner, value)
+        });
+    }
+
+    /// **Test-only API.**
+    ///
+    /// Get the currently stored value as an integer.
+    ///
+    /// ## Arguments
+    ///
+    /// * `storage_name` - The ping to look into. Defaults to the first ping of the metric.
+    pub fn test_get_value(&self, storage_name: Option<&str>) -> Option<i64> {
+        let storage_name = storage_name.unwrap_or(&self.inner.meta().send_in_pings[0]);
+        crate::with_glean(|glean| self.inner.test_get_value(glean, storage_name))
+    }
+}
@@ -0,0 +1,51 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use glean_core::metrics::{DistributionData, MetricType, TimeUnit};
+use glean_core::CommonMetricData;
+
+/// A timing distribution metric.
+///
+/// Samples are recorded without locking Glean, see `glean_core::metrics::Accumulator`.
+#[derive(Clone, Debug)]
+pub struct TimingDistributionMetric {
+    id: u64,
+    inner: glean_core::metrics::TimingDistributionMetric,
+}
+
+impl TimingDistributionMetric {
+    /// Create a new timing distribution metric.
+    pub fn new(meta: CommonMetricData, time_unit: TimeUnit) -> Self {
+        Self {
+            id: crate::next_metric_id(),
+            inner: glean_core::metrics::TimingDistributionMetric::new(meta, time_unit),
+        }
+    }
+
+    /// Accumulate the provided samples in the metric.
+    ///
+    /// See `glean_core::metrics::TimingDistributionMetric::accumulate_samples_signed`.
+    ///
+    /// ## Arguments
+    ///
+    /// * `samples` - The samples to record, in the time unit of the metric.
+    ///   Negative samples are discarded and reported as errors.
+    pub fn accumulate_samples_signed(&self, samples: Vec<i64>) {
+        crate::with_accumulator(|accumulator| {
+            accumulator.accumulate_timing_distribution_samples(self.id, &self.inner, samples)
+        });
+    }
+
+    /// **Test-only API.**
+    ///
+    /// Get the currently stored value.
+    ///
+    /// ## Arguments
+    ///
+    /// * `storage_name` - The ping to look into. Defaults to the first ping of the metric.
+    pub fn test_get_value(&self, storage_name: Option<&str>) -> Option<DistributionData> {
+        let storage_name = storage_name.unwrap_or(&self.inner.meta().send_in_pings[0]);
+        crate::with_glean(|glean| self.inner.test_get_value(glean, storage_name))
+    }
+}
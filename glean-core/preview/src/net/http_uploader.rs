@@ -0,0 +1,121 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+use super::{PingUploader, UploadResult};
+
+/// The default timeout for connecting, sending the ping and receiving the response.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The only URL scheme supported.
+const HTTP_SCHEME: &str = "http://";
+
+/// A simple uploader sending pings over plain HTTP.
+///
+/// Only `http://` URLs are supported, e.g. for a collector on the local network
+/// or for testing. As the default server endpoint uses `https://`,
+/// an `http://` endpoint must be configured explicitly, otherwise initialization fails.
+#[derive(Debug, Clone)]
+pub struct HttpUploader {
+    timeout: Duration,
+}
+
+impl Default for HttpUploader {
+    fn default() -> Self {
+        Self::with_timeout(DEFAULT_TIMEOUT)
+    }
+}
+
+impl HttpUploader {
+    /// Creates an uploader with a custom timeout.
+    ///
+    /// ## Arguments
+    ///
+    /// * `timeout` - The timeout for each of connecting, sending the ping and reading the response.
+    pub fn with_timeout(timeout: Duration) -> Self {
+        Self { timeout }
+    }
+
+    fn post(
+        &self,
+        authority: &str,
+        path: &str,
+        body: &[u8],
+        headers: &[(String, String)],
+    ) -> io::Result<u32> {
+        let address = if authority.contains(':') {
+            authority.to_string()
+        } else {
+            format!("{}:80", authority)
+        };
+        let address = address.to_socket_addrs()?.next().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                "Failed to resolve the server address",
+            )
+        })?;
+
+        let mut stream = TcpStream::connect_timeout(&address, self.timeout)?;
+        stream.set_read_timeout(Some(self.timeout))?;
+        stream.set_write_timeout(Some(self.timeout))?;
+
+        let mut request = format!(
+            "POST {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\nContent-Length: {}\r\n",
+            path,
+            authority,
+            body.len()
+        );
+        for (name, value) in headers {
+            if !name.eq_ignore_ascii_case("Content-Length") {
+                request.push_str(&format!("{}: {}\r\n", name, value));
+            }
+        }
+        request.push_str("\r\n");
+        stream.write_all(request.as_bytes())?;
+        stream.write_all(body)?;
+        stream.flush()?;
+
+        // The status line looks like `HTTP/1.1 200 OK`.
+        let mut status_line = String::new();
+        BufReader::new(stream).read_line(&mut status_line)?;
+        status_line
+            .split_whitespace()
+            .nth(1)
+            .and_then(|status| status.parse().ok())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Malformed response"))
+    }
+}
+
+impl PingUploader for HttpUploader {
+    fn upload(&self, url: String, body: Vec<u8>, headers: Vec<(String, String)>) -> UploadResult {
+        if !self.supports_endpoint(&url) {
+            log::error!("Unsupported URL, only http:// is supported: {}", url);
+            return UploadResult::RecoverableFailure;
+        }
+        let rest = &url[HTTP_SCHEME.len()..];
+        let (authority, path) = match rest.find('/') {
+            Some(index) => rest.split_at(index),
+            None => (rest, "/"),
+        };
+        if authority.is_empty() {
+            log::error!("Malformed upload URL: {}", url);
+            return UploadResult::UnrecoverableFailure;
+        }
+
+        match self.post(authority, path, &body, &headers) {
+            Ok(status) => UploadResult::HttpStatus(status),
+            Err(e) => {
+                log::warn!("Failed to upload a ping to {}: {}", url, e);
+                UploadResult::RecoverableFailure
+            }
+        }
+    }
+
+    fn supports_endpoint(&self, endpoint: &str) -> bool {
+        endpoint.starts_with(HTTP_SCHEME)
+    }
+}
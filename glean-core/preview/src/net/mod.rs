@@ -0,0 +1,187 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Handling the upload of pings.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use once_cell::sync::Lazy;
+
+pub use glean_core::upload::UploadResult;
+use glean_core::upload::{PingRequest, PingUploadTask};
+
+pub use http_uploader::HttpUploader;
+
+mod http_uploader;
+
+/// The endpoint pings are uploaded to, unless another one is configured.
+pub const DEFAULT_SERVER_ENDPOINT: &str = "https://incoming.telemetry.mozilla.org";
+
+/// How often to ask again for an upload task when told to wait, before giving up.
+const MAX_WAIT_ATTEMPTS: u32 = 3;
+
+/// How many recoverable upload failures to tolerate before giving up.
+const MAX_RETRIES: u32 = 3;
+
+/// Whether an upload worker is currently running.
+static UPLOAD_IN_PROGRESS: AtomicBool = AtomicBool::new(false);
+
+/// Whether an upload was requested since the running worker started.
+static UPLOAD_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// The thread of the most recently started upload worker.
+static UPLOAD_THREAD: Lazy<Mutex<Option<JoinHandle<()>>>> = Lazy::new(|| Mutex::new(None));
+
+/// A description of a component used to upload pings.
+pub trait PingUploader: std::fmt::Debug + Send + Sync {
+    /// Uploads a ping to a server.
+    ///
+    /// ## Arguments
+    ///
+    /// * `url` - The URL to upload the ping to, including the server endpoint.
+    /// * `body` - The (possibly compressed) ping body.
+    /// * `headers` - The headers to send with the request.
+    ///
+    /// ## Return value
+    ///
+    /// The result of the upload, e.g. the HTTP status code of the response.
+    fn upload(&self, url: String, body: Vec<u8>, headers: Vec<(String, String)>) -> UploadResult;
+
+    /// Whether pings can be uploaded to the given server endpoint.
+    ///
+    /// Checked when Glean is initialized, so that a misconfiguration is reported right away
+    /// instead of failing every upload.
+    fn supports_endpoint(&self, _endpoint: &str) -> bool {
+        true
+    }
+}
+
+/// Uploads all pending pings on a background thread, if an uploader is configured.
+///
+/// If an upload is already in progress, it picks up the new pings instead.
+pub(crate) fn trigger_upload() {
+    let uploader = match crate::global_state().lock().unwrap().uploader.clone() {
+        Some(uploader) => uploader,
+        None => return,
+    };
+
+    UPLOAD_REQUESTED.store(true, Ordering::SeqCst);
+    if !start_upload() {
+        return;
+    }
+
+    let spawned = thread::Builder::new()
+        .name("glean.upload".into())
+        .spawn(move || loop {
+            UPLOAD_REQUESTED.store(false, Ordering::SeqCst);
+            process_pending_pings(&uploader);
+            UPLOAD_IN_PROGRESS.store(false, Ordering::SeqCst);
+
+            // Pings might have been submitted after the last upload task was requested.
+            if !UPLOAD_REQUESTED.load(Ordering::SeqCst) || !start_upload() {
+                break;
+            }
+        });
+    match spawned {
+        Ok(handle) => {
+            // A previous worker has already given up on uploading, it's about to exit.
+            let previous = UPLOAD_THREAD.lock().unwrap().replace(handle);
+            if let Some(previous) = previous {
+                let _ = previous.join();
+            }
+        }
+        Err(e) => {
+            log::error!("Failed to spawn the upload thread: {}", e);
+            UPLOAD_IN_PROGRESS.store(false, Ordering::SeqCst);
+        }
+    }
+}
+
+/// Waits for the running upload worker, if any, to finish.
+///
+/// Used when Glean is re-initialized, so that a worker started for the old Glean object
+/// doesn't upload the pings of the new one.
+pub(crate) fn join_upload_thread() {
+    let handle = UPLOAD_THREAD.lock().unwrap().take();
+    if let Some(handle) = handle {
+        let _ = handle.join();
+    }
+}
+
+/// Marks an upload as in progress, unless one already is.
+///
+/// ## Return value
+///
+/// Whether the caller should run the upload.
+fn start_upload() -> bool {
+    UPLOAD_IN_PROGRESS
+        .compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst)
+        .is_ok()
+}
+
+/// Uploads pings until there are none left.
+///
+/// Like the upload workers of the other language bindings, this gives up after being asked
+/// to wait a few times or after a few recoverable failures.
+fn process_pending_pings(uploader: &Arc<dyn PingUploader>) {
+    let endpoint = crate::with_glean(|glean| {
+        glean
+            .server_endpoint()
+            .unwrap_or(DEFAULT_SERVER_ENDPOINT)
+            .to_string()
+    });
+
+    let mut wait_attempts = 0;
+    let mut failures = 0;
+    while failures < MAX_RETRIES {
+        match crate::with_glean(|glean| glean.get_upload_task()) {
+            PingUploadTask::Upload(request) => {
+                let (document_id, result) = upload_request(&**uploader, &endpoint, request);
+                if let UploadResult::RecoverableFailure = result {
+                    failures += 1;
+                }
+                crate::with_glean(|glean| glean.process_ping_upload_response(&document_id, result));
+            }
+            PingUploadTask::UploadBatch(requests) => {
+                let results: Vec<_> = requests
+                    .into_iter()
+                    .map(|request| upload_request(&**uploader, &endpoint, request))
+                    .collect();
+                failures += results
+                    .iter()
+                    .filter(|(_, result)| match result {
+                        UploadResult::RecoverableFailure => true,
+                        _ => false,
+                    })
+                    .count() as u32;
+                crate::with_glean(|glean| glean.process_ping_upload_batch_response(results));
+            }
+            PingUploadTask::Wait => {
+                if wait_attempts >= MAX_WAIT_ATTEMPTS {
+                    break;
+                }
+                wait_attempts += 1;
+                thread::sleep(Duration::from_secs(1));
+            }
+            PingUploadTask::WaitFor(..) | PingUploadTask::Done => break,
+        }
+    }
+}
+
+/// Uploads a single request to the endpoint.
+fn upload_request(
+    uploader: &dyn PingUploader,
+    endpoint: &str,
+    request: PingRequest,
+) -> (String, UploadResult) {
+    let result = uploader.upload(
+        format!("{}{}", endpoint, request.path),
+        request.body,
+        request.headers.into_iter().collect(),
+    );
+    (request.document_id, result)
+}
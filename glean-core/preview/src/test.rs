@@ -25,6 +25,8 @@ fn new_glean() -> tempfile::TempDir {
         max_events: None,
         delay_ping_lifetime_io: false,
         channel: Some("testing".into()),
+        server_endpoint: None,
+        uploader: None,
     };
 
     initialize(cfg, ClientInfoMetrics::unknown()).unwrap();
@@ -89,3 +91,41 @@ fn client_info_reset_after_toggle() {
             .is_some());
     });
 }
+
+#[test]
+fn recordings_from_many_threads_are_merged() {
+    let _lock = GLOBAL_LOCK.lock().unwrap();
+    env_logger::try_init().ok();
+
+    let _t = new_glean();
+
+    let meta = |name: &str| CommonMetricData {
+        name: name.into(),
+        category: "local".into(),
+        send_in_pings: vec!["store1".into()],
+        ..Default::default()
+    };
+    let counter = metrics::CounterMetric::new(meta("counter"));
+    let distribution =
+        metrics::MemoryDistributionMetric::new(meta("memory"), metrics::MemoryUnit::Byte);
+
+    let threads: Vec<_> = (0..4)
+        .map(|_| {
+            let counter = counter.clone();
+            let distribution = distribution.clone();
+            std::thread::spawn(move || {
+                for _ in 0..100 {
+                    counter.add(1);
+                    distribution.accumulate(10);
+                }
+            })
+        })
+        .collect();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+
+    // Reading the values merges the recordings into Glean.
+    assert_eq!(Some(400), counter.test_get_value(None));
+    assert_eq!(4000, distribution.test_get_value(None).unwrap().sum);
+}
@@ -0,0 +1,272 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! An in-process mock of the ingestion server, to test the upload of pings end-to-end.
+
+// Not every test uses every function of this module.
+#![allow(dead_code)]
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde_json::Value as JsonValue;
+
+/// How long to wait for pings to arrive before failing.
+const WAIT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long to hold a connection open without responding, to simulate a timeout.
+const TIMEOUT_HOLD: Duration = Duration::from_secs(2);
+
+/// A scripted response of the mock server.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Response {
+    /// Respond with the given HTTP status code.
+    Status(u16),
+    /// Read the request, but don't respond until the client times out.
+    Timeout,
+}
+
+/// A ping request received by the mock server.
+#[derive(Debug, Clone)]
+pub struct ReceivedPing {
+    /// The URL path of the request.
+    pub path: String,
+    /// The request headers, with lowercase names.
+    pub headers: HashMap<String, String>,
+    /// The decompressed and parsed ping payload, or `Null` if it couldn't be decoded.
+    pub payload: JsonValue,
+    /// The status the server responded with, or `None` for simulated timeouts.
+    pub status: Option<u16>,
+}
+
+impl ReceivedPing {
+    /// The name of the ping, from the `/submit/<app_id>/<ping>/<version>/<doc_id>` path.
+    pub fn ping_name(&self) -> Option<&str> {
+        self.path.split('/').nth(3)
+    }
+
+    fn is_accepted(&self) -> bool {
+        match self.status {
+            Some(status) => (200..300).contains(&status),
+            None => false,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct State {
+    responses: VecDeque<Response>,
+    received: Vec<ReceivedPing>,
+    /// The indices of the pings already returned by `wait_for_ping`.
+    taken: HashSet<usize>,
+}
+
+/// An HTTP server on localhost, recording the pings it receives.
+///
+/// Responds with `200` unless other responses are scripted with `respond_with`.
+/// The server is shut down when dropped.
+pub struct MockServer {
+    port: u16,
+    state: Arc<(Mutex<State>, Condvar)>,
+    shutdown: Arc<AtomicBool>,
+}
+
+impl MockServer {
+    /// Starts a server on a free port.
+    pub fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let state = Arc::new((Mutex::new(State::default()), Condvar::new()));
+        let shutdown = Arc::new(AtomicBool::new(false));
+
+        let server_state = Arc::clone(&state);
+        let server_shutdown = Arc::clone(&shutdown);
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                if server_shutdown.load(Ordering::SeqCst) {
+                    break;
+                }
+                if let Ok(stream) = stream {
+                    let state = Arc::clone(&server_state);
+                    thread::spawn(move || handle_connection(stream, &state));
+                }
+            }
+        });
+
+        Self {
+            port,
+            state,
+            shutdown,
+        }
+    }
+
+    /// The endpoint to configure Glean with.
+    pub fn endpoint(&self) -> String {
+        format!("http://127.0.0.1:{}", self.port)
+    }
+
+    /// Queues a response for the next request without a scripted response.
+    pub fn respond_with(&self, response: Response) {
+        self.state.0.lock().unwrap().responses.push_back(response);
+    }
+
+    /// All requests received so far, in order.
+    pub fn received(&self) -> Vec<ReceivedPing> {
+        self.state.0.lock().unwrap().received.clone()
+    }
+
+    /// Waits for the next accepted ping with the given name and returns its payload.
+    ///
+    /// Panics if no such ping arrives in time.
+    pub fn wait_for_ping(&self, name: &str) -> JsonValue {
+        self.wait_until(|state| {
+            let index = state
+                .received
+                .iter()
+                .enumerate()
+                .find(|(index, ping)| {
+                    ping.is_accepted()
+                        && ping.ping_name() == Some(name)
+                        && !state.taken.contains(index)
+                })
+                .map(|(index, _)| index)?;
+            state.taken.insert(index);
+            Some(state.received[index].payload.clone())
+        })
+        .unwrap_or_else(|| panic!("No '{}' ping received in time", name))
+    }
+
+    /// Waits until at least `count` requests were received and returns all of them.
+    ///
+    /// Panics if not enough requests arrive in time.
+    pub fn wait_for_requests(&self, count: usize) -> Vec<ReceivedPing> {
+        self.wait_until(|state| {
+            if state.received.len() >= count {
+                Some(state.received.clone())
+            } else {
+                None
+            }
+        })
+        .unwrap_or_else(|| panic!("Fewer than {} requests received in time", count))
+    }
+
+    fn wait_until<T, F>(&self, mut condition: F) -> Option<T>
+    where
+        F: FnMut(&mut State) -> Option<T>,
+    {
+        let (lock, cvar) = &*self.state;
+        let deadline = Instant::now() + WAIT_TIMEOUT;
+        let mut state = lock.lock().unwrap();
+        loop {
+            if let Some(result) = condition(&mut state) {
+                return Some(result);
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return None;
+            }
+            state = cvar.wait_timeout(state, deadline - now).unwrap().0;
+        }
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        // Wake up the listener, so it notices the shutdown.
+        self.shutdown.store(true, Ordering::SeqCst);
+        let _ = TcpStream::connect(("127.0.0.1", self.port));
+    }
+}
+
+fn handle_connection(stream: TcpStream, state: &(Mutex<State>, Condvar)) {
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+    let mut request_line = String::new();
+    // Connections closed without a request, e.g. to wake up the listener, are ignored.
+    if reader.read_line(&mut request_line).unwrap_or(0) == 0 {
+        return;
+    }
+    let path = request_line
+        .split_whitespace()
+        .nth(1)
+        .unwrap_or_default()
+        .to_string();
+
+    let mut headers = HashMap::new();
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).unwrap_or(0) == 0 || line.trim().is_empty() {
+            break;
+        }
+        if let Some(index) = line.find(':') {
+            headers.insert(
+                line[..index].trim().to_lowercase(),
+                line[index + 1..].trim().to_string(),
+            );
+        }
+    }
+
+    let length = headers
+        .get("content-length")
+        .and_then(|length| length.parse().ok())
+        .unwrap_or(0);
+    let mut body = vec![0; length];
+    if reader.read_exact(&mut body).is_err() {
+        return;
+    }
+    let payload = decode_body(&body, headers.get("content-encoding").map(|e| &e[..]));
+
+    let (lock, cvar) = state;
+    let status = {
+        let mut state = lock.lock().unwrap();
+        let status = match state.responses.pop_front() {
+            Some(Response::Status(status)) => Some(status),
+            Some(Response::Timeout) => None,
+            None => Some(200),
+        };
+        state.received.push(ReceivedPing {
+            path,
+            headers,
+            payload,
+            status,
+        });
+        status
+    };
+    cvar.notify_all();
+
+    let mut stream = stream;
+    match status {
+        Some(status) => {
+            let _ = write!(
+                stream,
+                "HTTP/1.1 {} Mock\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                status
+            );
+        }
+        None => thread::sleep(TIMEOUT_HOLD),
+    }
+}
+
+/// Decompresses and parses a ping body, returning `Null` if that fails.
+fn decode_body(body: &[u8], encoding: Option<&str>) -> JsonValue {
+    let decoded = match encoding {
+        Some("gzip") => {
+            let mut decoded = Vec::new();
+            flate2::read::GzDecoder::new(body)
+                .read_to_end(&mut decoded)
+                .map(|_| decoded)
+                .ok()
+        }
+        Some("zstd") => zstd::decode_all(body).ok(),
+        _ => Some(body.to_vec()),
+    };
+    decoded
+        .and_then(|decoded| serde_json::from_slice(&decoded).ok())
+        .unwrap_or(JsonValue::Null)
+}
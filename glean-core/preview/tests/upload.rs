@@ -0,0 +1,123 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+mod common;
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use once_cell::sync::Lazy;
+
+use common::{MockServer, Response};
+use glean::{metrics::PingType, net::HttpUploader, ClientInfoMetrics, Configuration};
+use glean_preview as glean;
+
+// Glean is a global singleton, so the tests must not run concurrently.
+static GLOBAL_LOCK: Lazy<Mutex<()>> = Lazy::new(|| Mutex::new(()));
+const GLOBAL_APPLICATION_ID: &str = "org.mozilla.glean.test.app";
+
+// Create a new instance of Glean uploading to the given server.
+// We need to keep the `TempDir` alive, so that it's not deleted before we stop using it.
+fn new_glean(server: &MockServer) -> tempfile::TempDir {
+    let dir = tempfile::tempdir().unwrap();
+
+    let cfg = Configuration {
+        data_path: dir.path().display().to_string(),
+        application_id: GLOBAL_APPLICATION_ID.into(),
+        upload_enabled: true,
+        max_events: None,
+        delay_ping_lifetime_io: false,
+        channel: None,
+        server_endpoint: Some(server.endpoint()),
+        uploader: Some(Arc::new(HttpUploader::with_timeout(Duration::from_millis(
+            500,
+        )))),
+    };
+    glean::initialize(cfg, ClientInfoMetrics::unknown()).unwrap();
+
+    dir
+}
+
+#[test]
+fn pings_are_uploaded_end_to_end() {
+    let _lock = GLOBAL_LOCK.lock().unwrap();
+    let server = MockServer::start();
+    let _t = new_glean(&server);
+
+    let ping = PingType::new("test", true, /* send_if_empty */ true, vec![]);
+    glean::register_ping_type(&ping);
+    ping.submit(None);
+
+    let payload = server.wait_for_ping("test");
+    assert_eq!(0, payload["ping_info"]["seq"]);
+    assert!(payload["client_info"]["client_id"].is_string());
+
+    let request = &server.received()[0];
+    assert!(request
+        .path
+        .starts_with("/submit/org-mozilla-glean-test-app/test/1/"));
+    assert_eq!("gzip", request.headers["content-encoding"]);
+    assert!(request.headers["content-type"].starts_with("application/json"));
+
+    // Disabling upload sends a `deletion-request` ping.
+    glean::set_upload_enabled(false);
+    server.wait_for_ping("deletion-request");
+}
+
+#[test]
+fn failed_uploads_are_retried() {
+    let _lock = GLOBAL_LOCK.lock().unwrap();
+    let server = MockServer::start();
+    server.respond_with(Response::Status(500));
+    server.respond_with(Response::Timeout);
+    let _t = new_glean(&server);
+
+    let ping = PingType::new("test", true, /* send_if_empty */ true, vec![]);
+    glean::register_ping_type(&ping);
+    ping.submit(None);
+
+    let payload = server.wait_for_ping("test");
+    assert_eq!(0, payload["ping_info"]["seq"]);
+
+    let statuses: Vec<_> = server.received().iter().map(|ping| ping.status).collect();
+    assert_eq!(vec![Some(500), None, Some(200)], statuses);
+}
+
+#[test]
+fn pings_rejected_by_the_server_are_not_retried() {
+    let _lock = GLOBAL_LOCK.lock().unwrap();
+    let server = MockServer::start();
+    server.respond_with(Response::Status(413));
+    let _t = new_glean(&server);
+
+    let ping = PingType::new("test", true, /* send_if_empty */ true, vec![]);
+    glean::register_ping_type(&ping);
+    ping.submit(None);
+    assert_eq!(Some(413), server.wait_for_requests(1)[0].status);
+
+    // Only the next ping arrives, the rejected one was discarded.
+    ping.submit(None);
+    let payload = server.wait_for_ping("test");
+    assert_eq!(1, payload["ping_info"]["seq"]);
+    assert_eq!(2, server.received().len());
+}
+
+#[test]
+fn initialization_fails_if_the_uploader_does_not_support_the_endpoint() {
+    let _lock = GLOBAL_LOCK.lock().unwrap();
+    let dir = tempfile::tempdir().unwrap();
+
+    // `HttpUploader` can't upload to the default `https://` endpoint.
+    let cfg = Configuration {
+        data_path: dir.path().display().to_string(),
+        application_id: GLOBAL_APPLICATION_ID.into(),
+        upload_enabled: true,
+        max_events: None,
+        delay_ping_lifetime_io: false,
+        channel: None,
+        server_endpoint: None,
+        uploader: Some(Arc::new(HttpUploader::default())),
+    };
+    assert!(glean::initialize(cfg, ClientInfoMetrics::unknown()).is_err());
+}
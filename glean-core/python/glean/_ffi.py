@@ -51,6 +51,8 @@ def make_config(
 
     cfg = ffi.new("FfiConfiguration *")
 
+    cfg.struct_size = ffi.sizeof("FfiConfiguration")
+    cfg.abi_version = lib.GLEAN_FFI_ABI_VERSION
     cfg.data_dir = data_dir
     cfg.package_name = package_name
     cfg.language_binding_name = language_binding_name
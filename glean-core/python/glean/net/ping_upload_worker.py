@@ -17,7 +17,7 @@ from .. import _ffi
 from .._glean_ffi import ffi as ffi_support  # type: ignore
 from .._dispatcher import Dispatcher
 from .._process_dispatcher import ProcessDispatcher
-from .ping_uploader import RecoverableFailure
+from .ping_uploader import RecoverableFailure, UploadResult
 
 
 log = logging.getLogger(__name__)
@@ -102,6 +102,31 @@ def _parse_ping_headers(
     return headers
 
 
+def _upload(request, configuration) -> UploadResult:
+    """
+    Upload a single ping of an upload task.
+
+    Args:
+        request: The FFI structure of the ping, either the `upload` variant of
+            an upload task or one of the requests of an `upload_batch`.
+        configuration (glean.config.Configuration): The configuration holding
+            the ping uploader to use.
+
+    Returns:
+        upload_result (UploadResult): The result of the upload.
+    """
+    # Parse the structure but make sure to let Rust free the memory.
+    doc_id = _ffi.ffi_decode_string(request.document_id, free_memory=False)
+    url_path = _ffi.ffi_decode_string(request.path, free_memory=False)
+    body = _ffi.ffi_decode_byte_buffer(request.body)
+    headers = _ffi.ffi_decode_string(request.headers, free_memory=False)
+
+    # Delegate the upload to the uploader.
+    return configuration.ping_uploader.do_upload(
+        url_path, body, _parse_ping_headers(headers, doc_id), configuration
+    )
+
+
 def _process(data_dir: Path, application_id: str, configuration) -> bool:
 
     # Import here to avoid cyclical import
@@ -128,23 +153,7 @@ def _process(data_dir: Path, application_id: str, configuration) -> bool:
 
         tag = incoming_task.tag
         if tag == UploadTaskTag.UPLOAD:
-            # Ping data is available for upload: parse the structure but make
-            # sure to let Rust free the memory.
-            doc_id = _ffi.ffi_decode_string(
-                incoming_task.upload.document_id, free_memory=False
-            )
-            url_path = _ffi.ffi_decode_string(
-                incoming_task.upload.path, free_memory=False
-            )
-            body = _ffi.ffi_decode_byte_buffer(incoming_task.upload.body)
-            headers = _ffi.ffi_decode_string(
-                incoming_task.upload.headers, free_memory=False
-            )
-
-            # Delegate the upload to the uploader.
-            upload_result = configuration.ping_uploader.do_upload(
-                url_path, body, _parse_ping_headers(headers, doc_id), configuration
-            )
+            upload_result = _upload(incoming_task.upload, configuration)
 
             if isinstance(upload_result, RecoverableFailure):
                 upload_failures = upload_failures + 1
@@ -153,6 +162,25 @@ def _process(data_dir: Path, application_id: str, configuration) -> bool:
             _ffi.lib.glean_process_ping_upload_response(
                 incoming_task, upload_result.to_ffi()
             )
+        elif tag == UploadTaskTag.UPLOAD_BATCH:
+            batch = incoming_task.upload_batch
+            statuses = ffi_support.new("uint32_t[]", batch.len)
+            for i in range(batch.len):
+                upload_result = _upload(batch.requests[i], configuration)
+
+                if isinstance(upload_result, RecoverableFailure):
+                    upload_failures = upload_failures + 1
+
+                statuses[i] = upload_result.to_ffi()
+
+            # Process the responses, in the order of the requests in the batch.
+            _ffi.lib.glean_process_ping_upload_batch_response(
+                incoming_task, statuses, batch.len
+            )
+        elif tag == UploadTaskTag.WAIT_FOR:
+            # The network conditions don't allow uploading right now.
+            # The pings stay pending until the worker runs again.
+            break
         elif tag == UploadTaskTag.WAIT:
             # Try not to be stuck waiting forever.
             if wait_attempts < MAX_WAIT_ATTEMPTS:
@@ -27,3 +27,14 @@ class UploadTaskTag(IntEnum):
     """
     No data available
     """
+
+    UPLOAD_BATCH = _ffi.lib.FfiPingUploadTask_UploadBatch
+    """
+    Data of multiple pings is available for upload
+    """
+
+    WAIT_FOR = _ffi.lib.FfiPingUploadTask_WaitFor
+    """
+    Network conditions don't allow uploading, with a suggested delay before
+    requesting new data
+    """
@@ -7,9 +7,12 @@ import uuid
 
 
 from glean import Glean
+from glean import _ffi
 from glean import metrics
 from glean._process_dispatcher import ProcessDispatcher
+from glean.net import base_uploader
 from glean.net import PingUploadWorker
+from glean.net import ping_upload_worker
 from glean.net.http_client import HttpClientUploader
 from glean.net import ping_uploader
 
@@ -31,6 +34,28 @@ def get_upload_failure_metric():
     )
 
 
+class _PathRecordingUploader(base_uploader.BaseUploader):
+    """
+    A ping uploader that keeps the paths of the uploaded pings, in-process.
+    """
+
+    def __init__(self):
+        self.paths = []
+
+    def do_upload(self, path, data, headers, config):
+        self.paths.append(path)
+        return ping_uploader.HttpResponse(status_code=200)
+
+
+def _process_in_process():
+    """
+    Run the ping upload worker loop in the current process.
+    """
+    return ping_upload_worker._process(
+        Glean._data_dir, Glean._application_id, Glean._configuration
+    )
+
+
 def test_400_error(safe_httpserver):
     safe_httpserver.serve_content(b"", code=400)
 
@@ -171,3 +196,42 @@ def test_unknown_url_no_exception():
     )
 
     assert type(response) is ping_uploader.RecoverableFailure
+
+
+def test_uploading_pings_in_a_batch(monkeypatch):
+    uploader = _PathRecordingUploader()
+    Glean._configuration.ping_uploader = uploader
+
+    # Keep the pings pending until they are uploaded together.
+    monkeypatch.setattr(PingUploadWorker, "process", lambda: None)
+    _ffi.lib.glean_set_upload_batch_limits(10, 1024 * 1024)
+
+    for _ in range(3):
+        Glean._submit_ping_by_name("baseline")
+
+    assert _process_in_process()
+
+    assert 3 == len(uploader.paths)
+    assert all("/baseline/" in path for path in uploader.paths)
+
+    # Every ping of the batch was processed.
+    assert _process_in_process()
+    assert 3 == len(uploader.paths)
+
+
+def test_waiting_for_the_network_keeps_pings_pending(monkeypatch):
+    uploader = _PathRecordingUploader()
+    Glean._configuration.ping_uploader = uploader
+
+    monkeypatch.setattr(PingUploadWorker, "process", lambda: None)
+    _ffi.lib.glean_set_network_state(0, 0)
+
+    Glean._submit_ping_by_name("baseline")
+
+    assert _process_in_process()
+    assert 0 == len(uploader.paths)
+
+    _ffi.lib.glean_set_network_state(1, 0)
+
+    assert _process_in_process()
+    assert 1 == len(uploader.paths)
@@ -0,0 +1,275 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! An in-memory key-value store, used instead of rkv when the `wasm` feature is enabled.
+//!
+//! It implements the subset of the rkv API the database uses, so that the database code
+//! stays the same for both backends.
+//! Environments are shared by path for the lifetime of the process,
+//! so a database opened again on the same path sees the same data.
+//!
+//! Nothing is persisted. An embedder can keep the data across sessions
+//! in any storage it has available, using `Database::export_lifetime`
+//! and `Database::import_lifetime`.
+
+use std::collections::btree_map::{self, BTreeMap};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use once_cell::sync::Lazy;
+
+pub mod error {
+    //! The errors of the in-memory store.
+
+    use std::fmt;
+
+    /// An error of the in-memory store.
+    #[derive(Debug)]
+    pub enum StoreError {
+        /// The key to delete doesn't exist.
+        KeyValuePairNotFound,
+        /// A thread panicked while holding the environment lock.
+        EnvPoisonError,
+    }
+
+    impl fmt::Display for StoreError {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match self {
+                StoreError::KeyValuePairNotFound => write!(f, "KeyValuePairNotFound"),
+                StoreError::EnvPoisonError => write!(f, "EnvPoisonError"),
+            }
+        }
+    }
+
+    impl std::error::Error for StoreError {}
+}
+
+use self::error::StoreError;
+
+/// The stored values, by store name and key.
+pub type Stores = BTreeMap<String, BTreeMap<Vec<u8>, OwnedValue>>;
+
+/// All environments opened in this process, by path.
+static ENVIRONMENTS: Lazy<Mutex<HashMap<PathBuf, Arc<RwLock<Stores>>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// A value to store or a stored value.
+#[derive(Debug, PartialEq)]
+pub enum Value<'a> {
+    U64(u64),
+    Blob(&'a [u8]),
+}
+
+/// A stored value.
+#[derive(Clone, Debug)]
+pub enum OwnedValue {
+    U64(u64),
+    Blob(Vec<u8>),
+}
+
+impl OwnedValue {
+    fn as_value(&self) -> Value<'_> {
+        match self {
+            OwnedValue::U64(v) => Value::U64(*v),
+            OwnedValue::Blob(v) => Value::Blob(v),
+        }
+    }
+}
+
+impl From<&Value<'_>> for OwnedValue {
+    fn from(value: &Value) -> Self {
+        match value {
+            Value::U64(v) => OwnedValue::U64(*v),
+            Value::Blob(v) => OwnedValue::Blob(v.to_vec()),
+        }
+    }
+}
+
+/// Options to open a store. Stores are always created if they don't exist.
+pub struct StoreOptions;
+
+impl StoreOptions {
+    pub fn create() -> Self {
+        StoreOptions
+    }
+}
+
+/// A storage environment, holding any number of named stores.
+#[derive(Debug)]
+pub struct Rkv {
+    stores: Arc<RwLock<Stores>>,
+}
+
+impl Rkv {
+    /// Open the environment for the given path, sharing the data with
+    /// all other environments opened for the same path.
+    pub fn new(path: &Path) -> Result<Rkv, StoreError> {
+        let mut environments = ENVIRONMENTS
+            .lock()
+            .map_err(|_| StoreError::EnvPoisonError)?;
+        let stores = environments.entry(path.to_path_buf()).or_default().clone();
+        Ok(Rkv { stores })
+    }
+
+    pub fn open_single(&self, name: &str, _opts: StoreOptions) -> Result<SingleStore, StoreError> {
+        self.write_stores()?.entry(name.to_string()).or_default();
+        Ok(SingleStore { name: name.into() })
+    }
+
+    pub fn read(&self) -> Result<Reader<'_>, StoreError> {
+        let stores = self.stores.read().map_err(|_| StoreError::EnvPoisonError)?;
+        Ok(Reader { stores })
+    }
+
+    /// Start a write transaction.
+    ///
+    /// Changes are applied as they are made and undone if the transaction is not committed.
+    pub fn write(&self) -> Result<Writer<'_>, StoreError> {
+        Ok(Writer {
+            stores: self.write_stores()?,
+            undo: Vec::new(),
+        })
+    }
+
+    fn write_stores(&self) -> Result<RwLockWriteGuard<'_, Stores>, StoreError> {
+        self.stores.write().map_err(|_| StoreError::EnvPoisonError)
+    }
+}
+
+/// Access to the stored data, for reading.
+pub trait Readable {
+    fn stores(&self) -> &Stores;
+}
+
+/// A read transaction.
+pub struct Reader<'env> {
+    stores: RwLockReadGuard<'env, Stores>,
+}
+
+impl Readable for Reader<'_> {
+    fn stores(&self) -> &Stores {
+        &self.stores
+    }
+}
+
+/// A write transaction.
+pub struct Writer<'env> {
+    stores: RwLockWriteGuard<'env, Stores>,
+    /// The previous values of all changed keys, in the order of the changes.
+    undo: Vec<(String, Vec<u8>, Option<OwnedValue>)>,
+}
+
+impl Writer<'_> {
+    pub fn commit(mut self) -> Result<(), StoreError> {
+        self.undo.clear();
+        Ok(())
+    }
+
+    fn store_mut(&mut self, name: &str) -> &mut BTreeMap<Vec<u8>, OwnedValue> {
+        self.stores.entry(name.to_string()).or_default()
+    }
+}
+
+impl Readable for Writer<'_> {
+    fn stores(&self) -> &Stores {
+        &self.stores
+    }
+}
+
+impl Drop for Writer<'_> {
+    fn drop(&mut self) {
+        while let Some((name, key, value)) = self.undo.pop() {
+            let store = self.store_mut(&name);
+            match value {
+                Some(value) => store.insert(key, value),
+                None => store.remove(&key),
+            };
+        }
+    }
+}
+
+/// The stored key-value pairs, in key order.
+pub type Iter<'r> =
+    Box<dyn Iterator<Item = Result<(&'r [u8], Option<Value<'r>>), StoreError>> + 'r>;
+
+/// A handle to a named store.
+#[derive(Clone, Debug)]
+pub struct SingleStore {
+    name: String,
+}
+
+impl SingleStore {
+    pub fn get<'r, R: Readable, K: AsRef<[u8]>>(
+        &self,
+        reader: &'r R,
+        k: K,
+    ) -> Result<Option<Value<'r>>, StoreError> {
+        Ok(reader
+            .stores()
+            .get(&self.name)
+            .and_then(|store| store.get(k.as_ref()))
+            .map(OwnedValue::as_value))
+    }
+
+    pub fn put<K: AsRef<[u8]>>(
+        &self,
+        writer: &mut Writer,
+        k: K,
+        v: &Value,
+    ) -> Result<(), StoreError> {
+        let key = k.as_ref().to_vec();
+        let previous = writer.store_mut(&self.name).insert(key.clone(), v.into());
+        writer.undo.push((self.name.clone(), key, previous));
+        Ok(())
+    }
+
+    pub fn delete<K: AsRef<[u8]>>(&self, writer: &mut Writer, k: K) -> Result<(), StoreError> {
+        let key = k.as_ref().to_vec();
+        match writer.store_mut(&self.name).remove(&key) {
+            Some(previous) => {
+                writer.undo.push((self.name.clone(), key, Some(previous)));
+                Ok(())
+            }
+            None => Err(StoreError::KeyValuePairNotFound),
+        }
+    }
+
+    pub fn clear(&self, writer: &mut Writer) -> Result<(), StoreError> {
+        let cleared = std::mem::take(writer.store_mut(&self.name));
+        for (key, value) in cleared {
+            writer.undo.push((self.name.clone(), key, Some(value)));
+        }
+        Ok(())
+    }
+
+    pub fn iter_start<'r, R: Readable>(&self, reader: &'r R) -> Result<Iter<'r>, StoreError> {
+        Ok(self.iter(reader, ..))
+    }
+
+    pub fn iter_from<'r, R: Readable, K: AsRef<[u8]>>(
+        &self,
+        reader: &'r R,
+        k: K,
+    ) -> Result<Iter<'r>, StoreError> {
+        Ok(self.iter(reader, k.as_ref().to_vec()..))
+    }
+
+    fn iter<'r, R: Readable>(
+        &self,
+        reader: &'r R,
+        range: impl std::ops::RangeBounds<Vec<u8>>,
+    ) -> Iter<'r> {
+        match reader.stores().get(&self.name) {
+            Some(store) => Box::new(range_iter(store.range(range))),
+            None => Box::new(std::iter::empty()),
+        }
+    }
+}
+
+fn range_iter<'r>(
+    range: btree_map::Range<'r, Vec<u8>, OwnedValue>,
+) -> impl Iterator<Item = Result<(&'r [u8], Option<Value<'r>>), StoreError>> {
+    range.map(|(key, value)| Ok((key.as_slice(), Some(value.as_value()))))
+}
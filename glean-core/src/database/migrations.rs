@@ -0,0 +1,372 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Versioning of the storage format.
+//!
+//! The version of the storage format is kept in the `glean_internal_info` storage
+//! of the user lifetime store.
+//! It is stored as a plain integer, so that it can always be read,
+//! independent of the encoding of `Metric`.
+//!
+//! When the database is opened, every migration with a version newer than the stored one
+//! is run, in order, and the stored version is bumped after each successful migration.
+//!
+//! Whenever the storage encoding changes (e.g. a variant of `Metric` changes its layout),
+//! add a migration to the end of `MIGRATIONS` and a fixture test with data
+//! written by the previous version.
+
+use std::str;
+
+use super::kv::{self, SingleStore, Writer};
+
+use super::Database;
+use crate::metrics::Metric;
+use crate::Lifetime;
+use crate::Result;
+use crate::INTERNAL_STORAGE;
+
+/// The key the storage format version is stored under, in the user lifetime store.
+pub(super) fn schema_version_key() -> String {
+    Database::get_storage_key(INTERNAL_STORAGE, Some("database_schema_version"))
+}
+
+/// A single migration of the stored data.
+pub(super) struct Migration {
+    /// The storage format version after this migration has run.
+    pub version: u64,
+    /// A short description, used for logging.
+    pub description: &'static str,
+    /// The migration function.
+    pub migrate: fn(&Database) -> Result<()>,
+}
+
+/// All migrations, ordered by version.
+///
+/// Databases without a stored version were written before versioning was introduced
+/// and are considered to be at version 0.
+pub(super) const MIGRATIONS: &[Migration] = &[Migration {
+    version: 1,
+    description: "remove values that can no longer be decoded",
+    migrate: remove_undecodable_values,
+}];
+
+/// The current version of the storage format.
+pub(super) fn current_version() -> u64 {
+    MIGRATIONS.last().map(|m| m.version).unwrap_or(0)
+}
+
+/// Write the storage format version into the user lifetime store.
+///
+/// The caller is responsible to commit the transaction.
+pub(super) fn put_schema_version(
+    writer: &mut Writer,
+    store: &SingleStore,
+    version: u64,
+) -> Result<()> {
+    store.put(writer, schema_version_key(), &kv::Value::U64(version))?;
+    Ok(())
+}
+
+/// Read the stored storage format version.
+///
+/// ## Return value
+///
+/// Returns `None` if no version is stored.
+pub(super) fn schema_version(db: &Database) -> Result<Option<u64>> {
+    let reader = db.stores.rkv.read()?;
+    match db
+        .get_store(Lifetime::User)
+        .get(&reader, schema_version_key())?
+    {
+        Some(kv::Value::U64(version)) => Ok(Some(version)),
+        _ => Ok(None),
+    }
+}
+
+/// Whether nothing at all is stored in the database.
+fn is_empty(db: &Database) -> Result<bool> {
+    let reader = db.stores.rkv.read()?;
+    for lifetime in [Lifetime::User, Lifetime::Ping, Lifetime::Application].iter() {
+        if db
+            .get_store(*lifetime)
+            .iter_start(&reader)?
+            .next()
+            .is_some()
+        {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+fn set_schema_version(db: &Database, version: u64) -> Result<()> {
+    db.write_with_store(Lifetime::User, |mut writer, store| {
+        put_schema_version(&mut writer, store, version)?;
+        writer.commit()?;
+        Ok(())
+    })
+}
+
+/// Bring the stored data up to date with the given list of migrations.
+///
+/// A new, empty database is marked as up to date without running any migration.
+/// Data written by a newer version of Glean is left untouched.
+///
+/// If a migration fails, the remaining migrations are skipped and retried the next time
+/// the database is opened.
+pub(super) fn run_migrations(db: &Database, migrations: &[Migration]) -> Result<()> {
+    let latest = migrations.last().map(|m| m.version).unwrap_or(0);
+    let stored = match schema_version(db)? {
+        Some(version) => version,
+        None if is_empty(db)? => return set_schema_version(db, latest),
+        None => 0,
+    };
+
+    if stored > latest {
+        log::warn!(
+            "Database schema version {} is newer than the supported version {}. Not migrating.",
+            stored,
+            latest
+        );
+        return Ok(());
+    }
+
+    for migration in migrations.iter().filter(|m| m.version > stored) {
+        log::info!(
+            "Migrating database to schema version {}: {}",
+            migration.version,
+            migration.description
+        );
+        if let Err(e) = (migration.migrate)(db) {
+            log::error!(
+                "Failed to migrate database to schema version {}: {:?}",
+                migration.version,
+                e
+            );
+            return Ok(());
+        }
+        set_schema_version(db, migration.version)?;
+    }
+
+    Ok(())
+}
+
+/// Version 1: remove values that can no longer be decoded.
+///
+/// Before the storage format was versioned, encoding changes could leave behind values
+/// that fail to decode (e.g. experiments stored with the old `RecordedExperimentData` layout).
+/// These were silently skipped on every read, but never removed.
+fn remove_undecodable_values(db: &Database) -> Result<()> {
+    for lifetime in [Lifetime::User, Lifetime::Ping, Lifetime::Application].iter() {
+        db.write_with_store(*lifetime, |mut writer, store| {
+            let mut to_delete = Vec::new();
+            {
+                let mut iter = store.iter_start(&writer)?;
+                while let Some(Ok((key, value))) = iter.next() {
+                    // Values that can't be decrypted are kept,
+                    // the key might become available again.
+                    let undecodable = match value {
+                        Some(kv::Value::Blob(blob)) => match db.stores.encryption.decrypt(blob) {
+                            Ok(decrypted) => bincode::deserialize::<Metric>(&decrypted).is_err(),
+                            Err(_) => false,
+                        },
+                        _ => false,
+                    };
+                    if undecodable {
+                        to_delete.push(key.to_vec());
+                    }
+                }
+            }
+
+            for key in to_delete {
+                log::info!(
+                    "Removing undecodable value '{}'",
+                    String::from_utf8_lossy(&key)
+                );
+                store.delete(&mut writer, key)?;
+            }
+            writer.commit()?;
+            Ok(())
+        })?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::database::kv::StoreOptions;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tempfile::tempdir;
+
+    /// A value stored by a previous version of Glean.
+    struct Fixture {
+        lifetime: Lifetime,
+        key: &'static str,
+        value: &'static [u8],
+    }
+
+    /// Write fixtures as raw values, the way a previous version of Glean stored them,
+    /// then open the database.
+    fn database_from_fixtures(
+        data_path: &str,
+        version: Option<u64>,
+        fixtures: &[Fixture],
+    ) -> Database {
+        {
+            let rkv = Database::open_rkv(data_path).unwrap();
+            // Stores need to be opened before starting the write transaction.
+            let stores: Vec<_> = [Lifetime::User, Lifetime::Ping, Lifetime::Application]
+                .iter()
+                .map(|lifetime| {
+                    let store = rkv
+                        .open_single(lifetime.as_str(), StoreOptions::create())
+                        .unwrap();
+                    (*lifetime, store)
+                })
+                .collect();
+            let store = |lifetime| &stores.iter().find(|(l, _)| *l == lifetime).unwrap().1;
+
+            let mut writer = rkv.write().unwrap();
+            for fixture in fixtures {
+                store(fixture.lifetime)
+                    .put(&mut writer, fixture.key, &kv::Value::Blob(fixture.value))
+                    .unwrap();
+            }
+            if let Some(version) = version {
+                put_schema_version(&mut writer, store(Lifetime::User), version).unwrap();
+            }
+            writer.commit().unwrap();
+        }
+
+        Database::new(data_path, false).unwrap()
+    }
+
+    #[rustfmt::skip] // Let's not add newlines unnecessary
+    fn glean_v31_fixtures() -> Vec<Fixture> {
+        vec![
+            // `Metric::Counter(3)`
+            Fixture { lifetime: Lifetime::Ping, key: "store1#telemetry.counter", value: &[1, 0, 0, 0, 3, 0, 0, 0] },
+            // `Metric::String("glean")`
+            Fixture { lifetime: Lifetime::User, key: "store1#telemetry.string", value: &[7, 0, 0, 0, 5, 0, 0, 0, 0, 0, 0, 0, 103, 108, 101, 97, 110] },
+            // `Metric::Experiment` with the `RecordedExperimentData` layout
+            // from before Glean commit ac27fceb7c0d5a7288d7d569e8c5c5399a53afb2.
+            Fixture { lifetime: Lifetime::Application, key: "glean_internal_info#experiment", value: &[5, 0, 0, 0, 6, 0, 0, 0, 0, 0, 0, 0, 98, 114, 97, 110, 99, 104] },
+        ]
+    }
+
+    #[test]
+    fn new_databases_are_at_the_current_version() {
+        let dir = tempdir().unwrap();
+        let db = Database::new(&dir.path().display().to_string(), false).unwrap();
+
+        assert_eq!(Some(current_version()), schema_version(&db).unwrap());
+    }
+
+    #[test]
+    fn unversioned_databases_are_migrated() {
+        let dir = tempdir().unwrap();
+        let db = database_from_fixtures(
+            &dir.path().display().to_string(),
+            None,
+            &glean_v31_fixtures(),
+        );
+
+        assert_eq!(Some(current_version()), schema_version(&db).unwrap());
+        assert_eq!(
+            vec![("store1#telemetry.counter".to_string(), Metric::Counter(3))],
+            db.export_lifetime(Lifetime::Ping)
+        );
+        assert_eq!(
+            vec![(
+                "store1#telemetry.string".to_string(),
+                Metric::String("glean".into())
+            )],
+            db.export_lifetime(Lifetime::User)
+        );
+
+        let reader = db.stores.rkv.read().unwrap();
+        assert!(db
+            .get_store(Lifetime::Application)
+            .get(&reader, "glean_internal_info#experiment")
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn databases_from_newer_versions_are_not_migrated() {
+        let dir = tempdir().unwrap();
+        let newer = current_version() + 1;
+        let db = database_from_fixtures(
+            &dir.path().display().to_string(),
+            Some(newer),
+            &glean_v31_fixtures(),
+        );
+
+        assert_eq!(Some(newer), schema_version(&db).unwrap());
+        let reader = db.stores.rkv.read().unwrap();
+        assert!(db
+            .get_store(Lifetime::Application)
+            .get(&reader, "glean_internal_info#experiment")
+            .unwrap()
+            .is_some());
+    }
+
+    #[test]
+    fn only_pending_migrations_run_in_order() {
+        static RUN: AtomicUsize = AtomicUsize::new(0);
+
+        fn first(_db: &Database) -> Result<()> {
+            assert_eq!(0, RUN.fetch_add(1, Ordering::SeqCst));
+            Ok(())
+        }
+        fn second(_db: &Database) -> Result<()> {
+            assert_eq!(1, RUN.fetch_add(1, Ordering::SeqCst));
+            Ok(())
+        }
+        fn third(_db: &Database) -> Result<()> {
+            Err(crate::ErrorKind::InvalidConfig.into())
+        }
+
+        let migrations = [
+            Migration {
+                version: 1,
+                description: "already applied",
+                migrate: |_| panic!("Migration ran twice"),
+            },
+            Migration {
+                version: 2,
+                description: "first",
+                migrate: first,
+            },
+            Migration {
+                version: 3,
+                description: "second",
+                migrate: second,
+            },
+            Migration {
+                version: 4,
+                description: "failing",
+                migrate: third,
+            },
+        ];
+
+        let dir = tempdir().unwrap();
+        let db = database_from_fixtures(&dir.path().display().to_string(), Some(1), &[]);
+        run_migrations(&db, &migrations).unwrap();
+
+        assert_eq!(2, RUN.load(Ordering::SeqCst));
+        assert_eq!(Some(3), schema_version(&db).unwrap());
+    }
+
+    #[test]
+    fn clearing_the_database_keeps_the_version() {
+        let dir = tempdir().unwrap();
+        let db = Database::new(&dir.path().display().to_string(), false).unwrap();
+
+        db.clear_all();
+        assert_eq!(Some(current_version()), schema_version(&db).unwrap());
+    }
+}
@@ -2,21 +2,120 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
+use std::borrow::Cow;
 use std::collections::btree_map::Entry;
 use std::collections::BTreeMap;
-use std::fs;
 use std::str;
-use std::sync::RwLock;
+#[cfg(not(feature = "wasm"))]
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex, MutexGuard, RwLock};
+#[cfg(not(feature = "wasm"))]
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
 
-use rkv::{Rkv, SingleStore, StoreOptions};
+#[cfg(feature = "wasm")]
+use instant::Instant;
+#[cfg(not(feature = "wasm"))]
+use std::time::Instant;
 
+use self::kv::{Rkv, SingleStore, StoreOptions};
+
+use crate::encryption::Encryption;
 use crate::metrics::Metric;
 use crate::CommonMetricData;
 use crate::Glean;
 use crate::Lifetime;
 use crate::Result;
 
-pub struct Database {
+#[cfg(feature = "wasm")]
+pub(crate) mod memory;
+mod migrations;
+
+// The key-value store backing the database.
+#[cfg(feature = "wasm")]
+pub(crate) use self::memory as kv;
+#[cfg(not(feature = "wasm"))]
+pub(crate) use rkv as kv;
+
+/// How metrics are written to disk when they are recorded.
+///
+/// This applies to metrics of all lifetimes, except for ping-lifetime metrics
+/// while `delay_ping_lifetime_io` is set, which are only persisted on demand.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StorageDurability {
+    /// Every recording is committed to disk in its own transaction before returning.
+    ///
+    /// Nothing recorded is lost if the process crashes.
+    Immediate,
+    /// Recordings are buffered in memory and committed to disk together in a single transaction.
+    ///
+    /// Buffered recordings are written:
+    ///
+    /// * by a background thread, once `flush_interval_ms` have passed since the last write;
+    /// * when a ping is submitted, or data is otherwise read or cleared in bulk;
+    /// * on `Glean::flush`;
+    /// * when Glean is dropped.
+    ///
+    /// Recordings not yet written are lost if the process crashes,
+    /// i.e. at most the last `flush_interval_ms` of data.
+    ///
+    /// With the `wasm` feature there are no threads. Buffered recordings are then written
+    /// with the first recording after `flush_interval_ms` have passed, or one of the other events.
+    Coalesced {
+        /// The minimum time between two writes, in milliseconds.
+        flush_interval_ms: u64,
+    },
+}
+
+impl Default for StorageDurability {
+    fn default() -> Self {
+        StorageDurability::Immediate
+    }
+}
+
+/// Recordings buffered in memory, if writes are coalesced.
+#[derive(Debug)]
+struct PendingWrites {
+    flush_interval: Duration,
+    last_flush: Instant,
+    /// The buffered metrics per lifetime, by their full storage key.
+    user: BTreeMap<String, Metric>,
+    ping: BTreeMap<String, Metric>,
+    application: BTreeMap<String, Metric>,
+}
+
+impl PendingWrites {
+    fn new(flush_interval: Duration) -> Self {
+        Self {
+            flush_interval,
+            last_flush: Instant::now(),
+            user: BTreeMap::new(),
+            ping: BTreeMap::new(),
+            application: BTreeMap::new(),
+        }
+    }
+
+    fn lifetime_mut(&mut self, lifetime: Lifetime) -> &mut BTreeMap<String, Metric> {
+        match lifetime {
+            Lifetime::User => &mut self.user,
+            Lifetime::Ping => &mut self.ping,
+            Lifetime::Application => &mut self.application,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.user.is_empty() && self.ping.is_empty() && self.application.is_empty()
+    }
+
+    fn is_due(&self) -> bool {
+        self.last_flush.elapsed() >= self.flush_interval
+    }
+}
+
+/// The database environment and the "lifetime" stores in it.
+///
+/// Shared with the thread writing coalesced recordings.
+struct Stores {
     /// Handle to the database environment.
     rkv: Rkv,
 
@@ -24,24 +123,153 @@ pub struct Database {
     ///
     /// A "store" is a handle to the underlying database.
     /// We keep them open for fast and frequent access.
-    user_store: SingleStore,
-    ping_store: SingleStore,
-    application_store: SingleStore,
+    user: SingleStore,
+    ping: SingleStore,
+    application: SingleStore,
+
+    /// Encrypts values before they are written to disk, if configured.
+    encryption: Encryption,
+}
+
+impl std::fmt::Debug for Stores {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        fmt.debug_struct("Stores")
+            .field("rkv", &self.rkv)
+            .field("user", &"SingleStore")
+            .field("ping", &"SingleStore")
+            .field("application", &"SingleStore")
+            .field("encryption", &self.encryption)
+            .finish()
+    }
+}
+
+impl Stores {
+    fn get(&self, lifetime: Lifetime) -> &SingleStore {
+        match lifetime {
+            Lifetime::User => &self.user,
+            Lifetime::Ping => &self.ping,
+            Lifetime::Application => &self.application,
+        }
+    }
+
+    /// Encode a metric for storage, encrypting it if configured.
+    ///
+    /// ## Return value
+    ///
+    /// Returns an error if the metric cannot be encrypted,
+    /// e.g. because no encryption key is available.
+    fn encode(&self, metric: &Metric) -> Result<Vec<u8>> {
+        let encoded = bincode::serialize(metric).expect("IMPOSSIBLE: Serializing metric failed");
+        Ok(self.encryption.encrypt(&encoded)?.into_owned())
+    }
+
+    /// Write all buffered recordings to rkv in a single transaction.
+    ///
+    /// Recordings that cannot be encoded are logged and discarded.
+    /// If the transaction fails, the recordings are kept to be written later.
+    fn write_pending(&self, pending: &mut PendingWrites) -> Result<()> {
+        if !pending.is_empty() {
+            let mut writer = self.rkv.write()?;
+            for &lifetime in &[Lifetime::User, Lifetime::Ping, Lifetime::Application] {
+                let store = self.get(lifetime);
+                for (key, metric) in pending.lifetime_mut(lifetime).iter() {
+                    match self.encode(metric) {
+                        Ok(encoded) => store.put(&mut writer, key, &kv::Value::Blob(&encoded))?,
+                        Err(e) => log::error!("Failed to encode '{}': {:?}", key, e),
+                    }
+                }
+            }
+            writer.commit()?;
+
+            pending.user.clear();
+            pending.ping.clear();
+            pending.application.clear();
+        }
+
+        pending.last_flush = Instant::now();
+        Ok(())
+    }
+}
+
+/// Writes coalesced recordings once they are due, on a background thread.
+///
+/// The thread is stopped when the flusher is dropped.
+#[cfg(not(feature = "wasm"))]
+#[derive(Debug)]
+struct Flusher {
+    /// The flush thread and the channel that is dropped to stop it.
+    ///
+    /// Kept behind a mutex, so that Glean stays unwind safe.
+    thread: Mutex<Option<(Sender<()>, JoinHandle<()>)>>,
+}
+
+#[cfg(not(feature = "wasm"))]
+impl Flusher {
+    fn start(stores: Arc<Stores>, pending: Arc<Mutex<PendingWrites>>) -> Result<Self> {
+        let (stop, stopped) = mpsc::channel();
+        let thread = thread::Builder::new()
+            .name("glean.database.flush".to_string())
+            .spawn(move || loop {
+                let wait = {
+                    let pending = pending.lock().expect("Can't access pending writes");
+                    pending
+                        .flush_interval
+                        .checked_sub(pending.last_flush.elapsed())
+                        .unwrap_or_default()
+                };
+                // Nothing is ever sent, the channel is disconnected when the flusher is dropped.
+                if let Err(RecvTimeoutError::Disconnected) = stopped.recv_timeout(wait) {
+                    return;
+                }
+
+                let mut pending = pending.lock().expect("Can't access pending writes");
+                if pending.is_due() {
+                    if let Err(e) = stores.write_pending(&mut pending) {
+                        log::error!("Failed to write pending metrics: {:?}", e);
+                    }
+                }
+            })?;
+
+        Ok(Self {
+            thread: Mutex::new(Some((stop, thread))),
+        })
+    }
+}
+
+#[cfg(not(feature = "wasm"))]
+impl Drop for Flusher {
+    fn drop(&mut self) {
+        if let Some((stop, thread)) = self.thread.lock().unwrap().take() {
+            drop(stop);
+            let _ = thread.join();
+        }
+    }
+}
+
+pub struct Database {
+    /// The database environment and the "lifetime" stores.
+    stores: Arc<Stores>,
 
     /// If the `delay_ping_lifetime_io` Glean config option is `true`,
     /// we will save metrics with 'ping' lifetime data in a map temporarily
     /// so as to persist them to disk using rkv in bulk on demand.
     ping_lifetime_data: Option<RwLock<BTreeMap<String, Metric>>>,
+
+    /// If writes are coalesced (see `StorageDurability`), the recordings
+    /// not yet written to disk.
+    pending_writes: Option<Arc<Mutex<PendingWrites>>>,
+
+    /// Writes the coalesced recordings in the background, once they are due.
+    #[cfg(not(feature = "wasm"))]
+    flusher: Option<Flusher>,
 }
 
 impl std::fmt::Debug for Database {
     fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
         fmt.debug_struct("Database")
-            .field("rkv", &self.rkv)
-            .field("user_store", &"SingleStore")
-            .field("ping_store", &"SingleStore")
-            .field("application_store", &"SingleStore")
+            .field("stores", &self.stores)
             .field("ping_lifetime_data", &self.ping_lifetime_data)
+            .field("pending_writes", &self.pending_writes)
             .finish()
     }
 }
@@ -52,9 +280,30 @@ impl Database {
     /// This opens the underlying rkv store and creates
     /// the underlying directory structure.
     ///
+    /// Data stored by previous versions of Glean is migrated
+    /// to the current storage format.
+    ///
     /// It also loads any Lifetime::Ping data that might be
     /// persisted, in case `delay_ping_lifetime_io` is set.
     pub fn new(data_path: &str, delay_ping_lifetime_io: bool) -> Result<Self> {
+        Self::with_options(
+            data_path,
+            delay_ping_lifetime_io,
+            StorageDurability::default(),
+            Encryption::default(),
+        )
+    }
+
+    /// Initialize the data store with the given durability,
+    /// encrypting all values with the given encryption.
+    ///
+    /// See [`new`](#method.new).
+    pub(crate) fn with_options(
+        data_path: &str,
+        delay_ping_lifetime_io: bool,
+        durability: StorageDurability,
+        encryption: Encryption,
+    ) -> Result<Self> {
         let rkv = Self::open_rkv(data_path)?;
         let user_store = rkv.open_single(Lifetime::User.as_str(), StoreOptions::create())?;
         let ping_store = rkv.open_single(Lifetime::Ping.as_str(), StoreOptions::create())?;
@@ -65,33 +314,55 @@ impl Database {
         } else {
             None
         };
+        let stores = Arc::new(Stores {
+            rkv,
+            user: user_store,
+            ping: ping_store,
+            application: application_store,
+            encryption,
+        });
+        let pending_writes = match durability {
+            StorageDurability::Immediate => None,
+            StorageDurability::Coalesced { flush_interval_ms } => Some(Arc::new(Mutex::new(
+                PendingWrites::new(Duration::from_millis(flush_interval_ms)),
+            ))),
+        };
+        // Without an interval every recording is written right away, there is nothing to wait for.
+        #[cfg(not(feature = "wasm"))]
+        let flusher = match &pending_writes {
+            Some(pending) if pending.lock().unwrap().flush_interval == Duration::from_millis(0) => {
+                None
+            }
+            Some(pending) => Some(Flusher::start(stores.clone(), pending.clone())?),
+            None => None,
+        };
 
         let db = Self {
-            rkv,
-            user_store,
-            ping_store,
-            application_store,
+            stores,
             ping_lifetime_data,
+            pending_writes,
+            #[cfg(not(feature = "wasm"))]
+            flusher,
         };
 
+        migrations::run_migrations(&db, migrations::MIGRATIONS)?;
         db.load_ping_lifetime_data();
 
         Ok(db)
     }
 
     fn get_store(&self, lifetime: Lifetime) -> &SingleStore {
-        match lifetime {
-            Lifetime::User => &self.user_store,
-            Lifetime::Ping => &self.ping_store,
-            Lifetime::Application => &self.application_store,
-        }
+        self.stores.get(lifetime)
     }
 
     /// Creates the storage directories and inits rkv.
+    ///
+    /// With the `wasm` feature, opens the in-memory store for the path instead.
     fn open_rkv(path: &str) -> Result<Rkv> {
         let path = std::path::Path::new(path).join("db");
         log::debug!("Database path: {:?}", path.display());
-        fs::create_dir_all(&path)?;
+        #[cfg(not(feature = "wasm"))]
+        std::fs::create_dir_all(&path)?;
 
         let rkv = Rkv::new(&path)?;
         log::info!("Database initialized");
@@ -118,6 +389,72 @@ impl Database {
         }
     }
 
+    /// Decode a stored metric.
+    ///
+    /// ## Return value
+    ///
+    /// Returns an error if the value cannot be decrypted or deserialized.
+    /// Decryption errors are logged.
+    fn decode(&self, blob: &[u8]) -> Result<Metric> {
+        Ok(bincode::deserialize(&self.decrypt(blob)?)?)
+    }
+
+    /// Decrypt a stored value, logging any error.
+    fn decrypt<'a>(&self, blob: &'a [u8]) -> Result<Cow<'a, [u8]>> {
+        self.stores.encryption.decrypt(blob).map_err(|e| {
+            log::error!("Failed to decrypt stored value: {}", e);
+            e
+        })
+    }
+
+    /// Read a single metric from rkv, ignoring any buffered recordings.
+    ///
+    /// ## Return value
+    ///
+    /// Returns an error if the metric is stored but cannot be decrypted,
+    /// so that it is never mistaken for a missing metric and overwritten.
+    /// A value that cannot be deserialized is treated as missing.
+    fn read_metric(
+        &self,
+        reader: &impl kv::Readable,
+        lifetime: Lifetime,
+        key: &str,
+    ) -> Result<Option<Metric>> {
+        match self.get_store(lifetime).get(reader, key)? {
+            Some(kv::Value::Blob(blob)) => Ok(bincode::deserialize(&self.decrypt(blob)?).ok()),
+            _ => Ok(None),
+        }
+    }
+
+    /// Lock the buffered recordings, if writes are coalesced.
+    fn lock_pending_writes(&self) -> Option<MutexGuard<'_, PendingWrites>> {
+        self.pending_writes
+            .as_ref()
+            .map(|pending| pending.lock().expect("Can't access pending writes"))
+    }
+
+    /// Write all buffered recordings to disk, if writes are coalesced.
+    ///
+    /// All recordings are committed in a single transaction.
+    /// Does nothing if writes are not coalesced or nothing is buffered.
+    ///
+    /// ## Panics
+    ///
+    /// * This function will **not** panic on database errors.
+    pub fn flush_pending(&self) -> Result<()> {
+        match self.lock_pending_writes() {
+            Some(mut pending) => self.stores.write_pending(&mut pending),
+            None => Ok(()),
+        }
+    }
+
+    /// Log a failure to write the buffered recordings before they are read or modified in bulk.
+    fn flush_pending_or_log(&self) {
+        if let Err(e) = self.flush_pending() {
+            log::error!("Failed to write pending metrics: {:?}", e);
+        }
+    }
+
     /// Loads Lifetime::Ping data from rkv to memory,
     /// if `delay_ping_lifetime_io` is set to true.
     ///
@@ -128,7 +465,7 @@ impl Database {
                 .write()
                 .expect("Can't read ping lifetime data");
 
-            let reader = unwrap_or!(self.rkv.read(), return);
+            let reader = unwrap_or!(self.stores.rkv.read(), return);
             let store = self.get_store(Lifetime::Ping);
             let mut iter = unwrap_or!(store.iter_start(&reader), return);
 
@@ -138,7 +475,7 @@ impl Database {
                     _ => continue,
                 };
                 let metric: Metric = match value.expect("Value missing in iteration") {
-                    rkv::Value::Blob(blob) => unwrap_or!(bincode::deserialize(blob), continue),
+                    kv::Value::Blob(blob) => unwrap_or!(self.decode(blob), continue),
                     _ => continue,
                 };
 
@@ -198,7 +535,9 @@ impl Database {
             }
         }
 
-        let reader = unwrap_or!(self.rkv.read(), return);
+        self.flush_pending_or_log();
+
+        let reader = unwrap_or!(self.stores.rkv.read(), return);
         let mut iter = unwrap_or!(
             self.get_store(lifetime).iter_from(&reader, &iter_start),
             return
@@ -211,7 +550,7 @@ impl Database {
 
             let metric_id = &metric_id[len..];
             let metric: Metric = match value.expect("Value missing in iteration") {
-                rkv::Value::Blob(blob) => unwrap_or!(bincode::deserialize(blob), continue),
+                kv::Value::Blob(blob) => unwrap_or!(self.decode(blob), continue),
                 _ => continue,
             };
             transaction_fn(metric_id, &metric);
@@ -250,13 +589,66 @@ impl Database {
             }
         }
 
-        let reader = unwrap_or!(self.rkv.read(), return false);
+        if let Some(mut pending) = self.lock_pending_writes() {
+            if pending.lifetime_mut(lifetime).contains_key(&key) {
+                return true;
+            }
+        }
+
+        let reader = unwrap_or!(self.stores.rkv.read(), return false);
         self.get_store(lifetime)
             .get(&reader, &key)
             .unwrap_or(None)
             .is_some()
     }
 
+    /// Read a single metric from the storage.
+    ///
+    /// This is a direct lookup of the metric's key, without iterating over the store.
+    ///
+    /// ## Arguments
+    ///
+    /// * `lifetime`: The lifetime of the metric.
+    /// * `storage_name`: The storage name to look in.
+    /// * `metric_identifier`: The metric identifier.
+    ///
+    /// ## Return value
+    ///
+    /// Returns the decoded metric or `None` if no data is found
+    /// or the data cannot be read or decoded.
+    ///
+    /// ## Panics
+    ///
+    /// This function will **not** panic on database errors.
+    pub fn get_metric(
+        &self,
+        lifetime: Lifetime,
+        storage_name: &str,
+        metric_identifier: &str,
+    ) -> Option<Metric> {
+        let key = Self::get_storage_key(storage_name, Some(metric_identifier));
+
+        // Lifetime::Ping data is not persisted to disk if
+        // Glean has `delay_ping_lifetime_io` set to true
+        if lifetime == Lifetime::Ping {
+            if let Some(ping_lifetime_data) = &self.ping_lifetime_data {
+                return ping_lifetime_data
+                    .read()
+                    .ok()
+                    .and_then(|data| data.get(&key).cloned());
+            }
+        }
+
+        if let Some(mut pending) = self.lock_pending_writes() {
+            if let Some(metric) = pending.lifetime_mut(lifetime).get(&key) {
+                return Some(metric.clone());
+            }
+        }
+
+        let reader = unwrap_or!(self.stores.rkv.read(), return None);
+        self.read_metric(&reader, lifetime, &key).ok().flatten()
+    }
+
     /// Write to the specified storage with the provided transaction function.
     ///
     /// If the storage is unavailable, it will return an error.
@@ -266,9 +658,9 @@ impl Database {
     /// * This function will **not** panic on database errors.
     pub fn write_with_store<F>(&self, store_name: Lifetime, mut transaction_fn: F) -> Result<()>
     where
-        F: FnMut(rkv::Writer, &SingleStore) -> Result<()>,
+        F: FnMut(kv::Writer, &SingleStore) -> Result<()>,
     {
-        let writer = self.rkv.write().unwrap();
+        let writer = self.stores.rkv.write().unwrap();
         let store = self.get_store(store_name);
         transaction_fn(writer, store)
     }
@@ -316,10 +708,20 @@ impl Database {
             }
         }
 
-        let encoded = bincode::serialize(&metric).expect("IMPOSSIBLE: Serializing metric failed");
-        let value = rkv::Value::Blob(&encoded);
+        if let Some(mut pending) = self.lock_pending_writes() {
+            pending
+                .lifetime_mut(lifetime)
+                .insert(final_key, metric.clone());
+            if pending.is_due() {
+                self.stores.write_pending(&mut pending)?;
+            }
+            return Ok(());
+        }
+
+        let encoded = self.stores.encode(metric)?;
+        let value = kv::Value::Blob(&encoded);
 
-        let mut writer = self.rkv.write()?;
+        let mut writer = self.stores.rkv.write()?;
         self.get_store(lifetime)
             .put(&mut writer, final_key, &value)?;
         writer.commit()?;
@@ -387,24 +789,30 @@ impl Database {
             }
         }
 
-        let mut writer = self.rkv.write()?;
-        let store = self.get_store(lifetime);
-        let new_value: Metric = {
-            let old_value = store.get(&writer, &final_key)?;
-
-            match old_value {
-                Some(rkv::Value::Blob(blob)) => {
-                    let old_value = bincode::deserialize(blob).ok();
-                    transform(old_value)
+        if let Some(mut pending) = self.lock_pending_writes() {
+            let old_value = match pending.lifetime_mut(lifetime).get(&final_key) {
+                Some(metric) => Some(metric.clone()),
+                None => {
+                    let reader = self.stores.rkv.read()?;
+                    self.read_metric(&reader, lifetime, &final_key)?
                 }
-                _ => transform(None),
+            };
+            let new_value = transform(old_value);
+            pending.lifetime_mut(lifetime).insert(final_key, new_value);
+            if pending.is_due() {
+                self.stores.write_pending(&mut pending)?;
             }
-        };
+            return Ok(());
+        }
+
+        let mut writer = self.stores.rkv.write()?;
+        // A value that can't be decrypted is kept rather than replaced.
+        let new_value = transform(self.read_metric(&writer, lifetime, &final_key)?);
 
-        let encoded =
-            bincode::serialize(&new_value).expect("IMPOSSIBLE: Serializing metric failed");
-        let value = rkv::Value::Blob(&encoded);
-        store.put(&mut writer, final_key, &value)?;
+        let encoded = self.stores.encode(&new_value)?;
+        let value = kv::Value::Blob(&encoded);
+        self.get_store(lifetime)
+            .put(&mut writer, final_key, &value)?;
         writer.commit()?;
         Ok(())
     }
@@ -432,6 +840,15 @@ impl Database {
                 .clear();
         }
 
+        if let Some(mut pending) = self.lock_pending_writes() {
+            let prefix = Self::get_storage_key(storage_name, None);
+            let buffered = std::mem::take(&mut pending.ping);
+            pending.ping = buffered
+                .into_iter()
+                .filter(|(key, _)| !key.starts_with(&prefix))
+                .collect();
+        }
+
         self.write_with_store(Lifetime::Ping, |mut writer, store| {
             let mut metrics = Vec::new();
             {
@@ -496,11 +913,16 @@ impl Database {
             }
         }
 
+        let mut was_pending = false;
+        if let Some(mut pending) = self.lock_pending_writes() {
+            was_pending = pending.lifetime_mut(lifetime).remove(&final_key).is_some();
+        }
+
         self.write_with_store(lifetime, |mut writer, store| {
             if let Err(e) = store.delete(&mut writer, final_key.clone()) {
-                if self.ping_lifetime_data.is_some() {
-                    // If ping_lifetime_data exists, it might be
-                    // that data is in memory, but not yet in rkv.
+                if self.ping_lifetime_data.is_some() || was_pending {
+                    // If ping_lifetime_data exists or writes are coalesced,
+                    // it might be that data is in memory, but not yet in rkv.
                     return Ok(());
                 }
                 return Err(e.into());
@@ -512,14 +934,23 @@ impl Database {
 
     /// Clears all the metrics in the database, for the provided lifetime.
     ///
+    /// The storage format version is kept.
+    ///
     /// Errors are logged.
     ///
     /// ## Panics
     ///
     /// * This function will **not** panic on database errors.
     pub fn clear_lifetime(&self, lifetime: Lifetime) {
+        if let Some(mut pending) = self.lock_pending_writes() {
+            pending.lifetime_mut(lifetime).clear();
+        }
+
         let res = self.write_with_store(lifetime, |mut writer, store| {
             store.clear(&mut writer)?;
+            if lifetime == Lifetime::User {
+                migrations::put_schema_version(&mut writer, store, migrations::current_version())?;
+            }
             writer.commit()?;
             Ok(())
         });
@@ -548,6 +979,131 @@ impl Database {
         }
     }
 
+    /// Get all metrics stored for the given lifetime.
+    ///
+    /// Entries that cannot be read or deserialized are skipped.
+    ///
+    /// ## Return value
+    ///
+    /// Returns the metrics together with their full storage key,
+    /// in the form `storage_name#metric_id`.
+    pub fn export_lifetime(&self, lifetime: Lifetime) -> Vec<(String, Metric)> {
+        // Lifetime::Ping data is kept in memory in full if
+        // Glean has `delay_ping_lifetime_io` set to true
+        if lifetime == Lifetime::Ping {
+            if let Some(ping_lifetime_data) = &self.ping_lifetime_data {
+                let data = ping_lifetime_data
+                    .read()
+                    .expect("Can't read ping lifetime data");
+                return data
+                    .iter()
+                    .map(|(key, metric)| (key.clone(), metric.clone()))
+                    .collect();
+            }
+        }
+
+        self.flush_pending_or_log();
+
+        let mut metrics = Vec::new();
+        let reader = unwrap_or!(self.stores.rkv.read(), return metrics);
+        let mut iter = unwrap_or!(self.get_store(lifetime).iter_start(&reader), return metrics);
+
+        while let Some(Ok((key, value))) = iter.next() {
+            let key = match str::from_utf8(key) {
+                Ok(key) => key.to_string(),
+                _ => continue,
+            };
+            let metric: Metric = match value {
+                Some(kv::Value::Blob(blob)) => unwrap_or!(self.decode(blob), continue),
+                _ => continue,
+            };
+            metrics.push((key, metric));
+        }
+
+        metrics
+    }
+
+    /// Write metrics into the store for the given lifetime, replacing existing values.
+    ///
+    /// All metrics are written in a single transaction.
+    ///
+    /// ## Arguments
+    ///
+    /// * `lifetime` - the lifetime of the store to write to.
+    /// * `metrics` - the metrics with their full storage key, as returned by `export_lifetime`.
+    ///
+    /// ## Panics
+    ///
+    /// * This function will **not** panic on database errors.
+    pub fn import_lifetime(&self, lifetime: Lifetime, metrics: &[(String, Metric)]) -> Result<()> {
+        // Lifetime::Ping data is not persisted to disk if
+        // Glean has `delay_ping_lifetime_io` set to true
+        if lifetime == Lifetime::Ping {
+            if let Some(ping_lifetime_data) = &self.ping_lifetime_data {
+                let mut data = ping_lifetime_data
+                    .write()
+                    .expect("Can't access ping lifetime data as writable");
+                for (key, metric) in metrics {
+                    data.insert(key.clone(), metric.clone());
+                }
+                return Ok(());
+            }
+        }
+
+        // Buffered recordings would otherwise overwrite the imported values.
+        self.flush_pending()?;
+
+        self.write_with_store(lifetime, |mut writer, store| {
+            for (key, metric) in metrics {
+                let encoded = self.stores.encode(metric)?;
+                store.put(&mut writer, key, &kv::Value::Blob(&encoded))?;
+            }
+            writer.commit()?;
+            Ok(())
+        })
+    }
+
+    /// Re-encrypt all stored values with the current encryption key.
+    ///
+    /// Values that cannot be decrypted are left untouched.
+    ///
+    /// ## Panics
+    ///
+    /// * This function will **not** panic on database errors.
+    pub(crate) fn reencrypt(&self) -> Result<()> {
+        self.flush_pending()?;
+
+        for lifetime in [Lifetime::User, Lifetime::Ping, Lifetime::Application].iter() {
+            self.write_with_store(*lifetime, |mut writer, store| {
+                let mut updated = Vec::new();
+                {
+                    let mut iter = store.iter_start(&writer)?;
+                    while let Some(Ok((key, value))) = iter.next() {
+                        if let Some(kv::Value::Blob(blob)) = value {
+                            match self.stores.encryption.reencrypt(blob) {
+                                Ok(Some(encrypted)) => updated.push((key.to_vec(), encrypted)),
+                                Ok(None) => {}
+                                Err(e) => log::error!(
+                                    "Failed to re-encrypt '{}': {}",
+                                    String::from_utf8_lossy(key),
+                                    e
+                                ),
+                            }
+                        }
+                    }
+                }
+
+                for (key, encrypted) in updated {
+                    store.put(&mut writer, key, &kv::Value::Blob(&encrypted))?;
+                }
+                writer.commit()?;
+                Ok(())
+            })?;
+        }
+
+        Ok(())
+    }
+
     /// Persist ping_lifetime_data to disk.
     ///
     /// Does nothing in case there is nothing to persist.
@@ -563,12 +1119,11 @@ impl Database {
 
             self.write_with_store(Lifetime::Ping, |mut writer, store| {
                 for (key, value) in data.iter() {
-                    let encoded =
-                        bincode::serialize(&value).expect("IMPOSSIBLE: Serializing metric failed");
+                    let encoded = self.stores.encode(value)?;
                     // There is no need for `get_storage_key` here because
                     // the key is already formatted from when it was saved
                     // to ping_lifetime_data.
-                    store.put(&mut writer, &key, &rkv::Value::Blob(&encoded))?;
+                    store.put(&mut writer, key, &kv::Value::Blob(&encoded))?;
                 }
                 writer.commit()?;
                 Ok(())
@@ -578,13 +1133,24 @@ impl Database {
     }
 }
 
+impl Drop for Database {
+    fn drop(&mut self) {
+        // Stop the flush thread first, so the final write doesn't race with it.
+        #[cfg(not(feature = "wasm"))]
+        drop(self.flusher.take());
+        self.flush_pending_or_log();
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
     use std::collections::HashMap;
     use tempfile::tempdir;
 
+    // The in-memory store can be opened at any path.
     #[test]
+    #[cfg(not(feature = "wasm"))]
     fn test_panicks_if_fails_dir_creation() {
         assert!(Database::new("/!#\"'@#°ç", false).is_err());
     }
@@ -636,6 +1202,159 @@ mod test {
         assert_eq!(1, found_metrics, "We only expect 1 Lifetime.Ping metric.");
     }
 
+    #[test]
+    fn test_get_metric_reads_single_metrics() {
+        for &delay_ping_lifetime_io in &[false, true] {
+            // Init the database in a temporary directory.
+            let dir = tempdir().unwrap();
+            let str_dir = dir.path().display().to_string();
+            let db = Database::new(&str_dir, delay_ping_lifetime_io).unwrap();
+
+            let test_storage = "test-storage";
+            for &lifetime in &[Lifetime::Ping, Lifetime::Application, Lifetime::User] {
+                for i in 0..10 {
+                    db.record_per_lifetime(
+                        lifetime,
+                        test_storage,
+                        &format!("telemetry_test.{}_{}", lifetime.as_str(), i),
+                        &Metric::Counter(i),
+                    )
+                    .unwrap();
+                }
+            }
+
+            for &lifetime in &[Lifetime::Ping, Lifetime::Application, Lifetime::User] {
+                let metric_id = format!("telemetry_test.{}_5", lifetime.as_str());
+                match db.get_metric(lifetime, test_storage, &metric_id) {
+                    Some(Metric::Counter(5)) => {}
+                    metric => panic!("Unexpected data found: {:?}", metric),
+                }
+                // Metrics are only found in their own lifetime and storage.
+                assert!(db
+                    .get_metric(lifetime, "other-storage", &metric_id)
+                    .is_none());
+                assert!(db
+                    .get_metric(lifetime, test_storage, "telemetry_test.missing")
+                    .is_none());
+            }
+            assert!(db
+                .get_metric(Lifetime::User, test_storage, "telemetry_test.ping_5")
+                .is_none());
+        }
+    }
+
+    #[test]
+    fn test_coalesced_writes_are_buffered_until_flushed() {
+        // Init the database in a temporary directory.
+        let dir = tempdir().unwrap();
+        let str_dir = dir.path().display().to_string();
+        let durability = StorageDurability::Coalesced {
+            flush_interval_ms: 60_000,
+        };
+        let db =
+            Database::with_options(&str_dir, false, durability, Encryption::default()).unwrap();
+        let on_disk = |db: &Database, key: &str| {
+            let reader = db.stores.rkv.read().unwrap();
+            db.read_metric(&reader, Lifetime::User, key).unwrap()
+        };
+
+        let test_storage = "test-storage";
+        let metric_id = "telemetry_test.counter";
+        let key = Database::get_storage_key(test_storage, Some(metric_id));
+        db.record_per_lifetime(Lifetime::User, test_storage, metric_id, &Metric::Counter(1))
+            .unwrap();
+        db.record_per_lifetime_with(Lifetime::User, test_storage, metric_id, |old| match old {
+            Some(Metric::Counter(old)) => Metric::Counter(old + 1),
+            _ => Metric::Counter(1),
+        })
+        .unwrap();
+
+        // The recordings are visible, but not written yet.
+        assert!(db.has_metric(Lifetime::User, test_storage, metric_id));
+        assert_eq!(
+            Some(Metric::Counter(2)),
+            db.get_metric(Lifetime::User, test_storage, metric_id)
+        );
+        assert_eq!(None, on_disk(&db, &key));
+
+        db.flush_pending().unwrap();
+        assert_eq!(Some(Metric::Counter(2)), on_disk(&db, &key));
+
+        // Cleared recordings are not written.
+        db.record_per_lifetime(
+            Lifetime::User,
+            test_storage,
+            "telemetry_test.cleared",
+            &Metric::Counter(1),
+        )
+        .unwrap();
+        db.clear_lifetime(Lifetime::User);
+        db.flush_pending().unwrap();
+        assert!(!db.has_metric(Lifetime::User, test_storage, "telemetry_test.cleared"));
+
+        // Recordings still buffered are written when the database is dropped.
+        db.record_per_lifetime(Lifetime::User, test_storage, metric_id, &Metric::Counter(3))
+            .unwrap();
+        drop(db);
+        let db = Database::new(&str_dir, false).unwrap();
+        assert_eq!(Some(Metric::Counter(3)), on_disk(&db, &key));
+    }
+
+    #[test]
+    fn test_coalesced_writes_are_flushed_after_the_interval() {
+        // Init the database in a temporary directory.
+        let dir = tempdir().unwrap();
+        let str_dir = dir.path().display().to_string();
+        let durability = StorageDurability::Coalesced {
+            flush_interval_ms: 0,
+        };
+        let db =
+            Database::with_options(&str_dir, false, durability, Encryption::default()).unwrap();
+
+        let test_storage = "test-storage";
+        let metric_id = "telemetry_test.counter";
+        db.record_per_lifetime(Lifetime::Ping, test_storage, metric_id, &Metric::Counter(1))
+            .unwrap();
+
+        let reader = db.stores.rkv.read().unwrap();
+        let key = Database::get_storage_key(test_storage, Some(metric_id));
+        assert_eq!(
+            Some(Metric::Counter(1)),
+            db.read_metric(&reader, Lifetime::Ping, &key).unwrap()
+        );
+    }
+
+    #[test]
+    #[cfg(not(feature = "wasm"))]
+    fn test_coalesced_writes_are_flushed_in_the_background() {
+        // Init the database in a temporary directory.
+        let dir = tempdir().unwrap();
+        let str_dir = dir.path().display().to_string();
+        let durability = StorageDurability::Coalesced {
+            flush_interval_ms: 50,
+        };
+        let db =
+            Database::with_options(&str_dir, false, durability, Encryption::default()).unwrap();
+
+        let test_storage = "test-storage";
+        let metric_id = "telemetry_test.counter";
+        db.record_per_lifetime(Lifetime::Ping, test_storage, metric_id, &Metric::Counter(1))
+            .unwrap();
+
+        // No further recording, the flush thread writes the buffered one.
+        let key = Database::get_storage_key(test_storage, Some(metric_id));
+        let mut stored = None;
+        for _ in 0..100 {
+            std::thread::sleep(Duration::from_millis(20));
+            let reader = db.stores.rkv.read().unwrap();
+            stored = db.read_metric(&reader, Lifetime::Ping, &key).unwrap();
+            if stored.is_some() {
+                break;
+            }
+        }
+        assert_eq!(Some(Metric::Counter(1)), stored);
+    }
+
     #[test]
     fn test_application_lifetime_metric_recorded() {
         // Init the database in a temporary directory.
@@ -842,6 +1561,46 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_export_and_import_lifetime() {
+        let dir = tempdir().unwrap();
+        let str_dir = dir.path().display().to_string();
+        let db = Database::new(&str_dir, false).unwrap();
+
+        let test_storage = "test-storage";
+        for lifetime in [Lifetime::User, Lifetime::Ping, Lifetime::Application].iter() {
+            db.record_per_lifetime(
+                *lifetime,
+                test_storage,
+                "telemetry_test.test_name",
+                &Metric::String(lifetime.as_str().to_string()),
+            )
+            .unwrap();
+        }
+
+        let exported = db.export_lifetime(Lifetime::User);
+        assert_eq!(
+            vec![(
+                "test-storage#telemetry_test.test_name".to_string(),
+                Metric::String("user".into())
+            )],
+            exported
+        );
+
+        let other_dir = tempdir().unwrap();
+        let other_str_dir = other_dir.path().display().to_string();
+        let other_db = Database::new(&other_str_dir, true).unwrap();
+        for lifetime in [Lifetime::User, Lifetime::Ping, Lifetime::Application].iter() {
+            other_db
+                .import_lifetime(*lifetime, &db.export_lifetime(*lifetime))
+                .unwrap();
+            assert_eq!(
+                db.export_lifetime(*lifetime),
+                other_db.export_lifetime(*lifetime)
+            );
+        }
+    }
+
     #[test]
     fn test_delayed_ping_lifetime_persistence() {
         // Init the database in a temporary directory.
@@ -882,10 +1641,11 @@ mod test {
             // since it was recorded before calling `persist_ping_lifetime_data`,
             // and `test_value2` to be only in memory, since it was recorded after.
             let store: SingleStore = db
+                .stores
                 .rkv
                 .open_single(Lifetime::Ping.as_str(), StoreOptions::create())
                 .unwrap();
-            let reader = db.rkv.read().unwrap();
+            let reader = db.stores.rkv.read().unwrap();
 
             // Verify that test_value1 is in rkv.
             assert!(store
@@ -921,10 +1681,11 @@ mod test {
             // be persisted, since both were created before a call to
             // `persist_ping_lifetime_data`.
             let store: SingleStore = db
+                .stores
                 .rkv
                 .open_single(Lifetime::Ping.as_str(), StoreOptions::create())
                 .unwrap();
-            let reader = db.rkv.read().unwrap();
+            let reader = db.stores.rkv.read().unwrap();
 
             // Verify that test_value1 is in rkv.
             assert!(store
@@ -990,10 +1751,11 @@ mod test {
 
             // Verify that test_value is now in rkv.
             let store: SingleStore = db
+                .stores
                 .rkv
                 .open_single(Lifetime::Ping.as_str(), StoreOptions::create())
                 .unwrap();
-            let reader = db.rkv.read().unwrap();
+            let reader = db.stores.rkv.read().unwrap();
             assert!(store
                 .get(&reader, format!("{}#{}", test_storage, test_metric_id))
                 .unwrap_or(None)
@@ -1017,10 +1779,11 @@ mod test {
 
             // Verify that test_value is also in rkv.
             let store: SingleStore = db
+                .stores
                 .rkv
                 .open_single(Lifetime::Ping.as_str(), StoreOptions::create())
                 .unwrap();
-            let reader = db.rkv.read().unwrap();
+            let reader = db.stores.rkv.read().unwrap();
             assert!(store
                 .get(&reader, format!("{}#{}", test_storage, test_metric_id))
                 .unwrap_or(None)
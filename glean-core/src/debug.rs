@@ -23,14 +23,45 @@
 //!         This may be set by calling glean.set_source_tags(value: Vec<String>)
 //!         or by setting the environment variable GLEAN_SOURCE_TAGS=<some, tags>;
 //!
+//! The debug view tag and the source tags may also be set with [`DebugTagLimits`](struct.DebugTagLimits.html),
+//! to only apply them for some time, to a number of pings or to some pings only.
+//! Tags set with limits are persisted, so that they are restored when Glean is restarted
+//! until they expire.
+//!
+//! All three options may also be changed at runtime through a `debug.json` file in the data path,
+//! once [`Glean::watch_debug_file`](../struct.Glean.html#method.watch_debug_file) is called.
+//! The file is polled for changes, and the options it contains take precedence over the others
+//! until they are removed from it, e.g.:
+//!
+//! ```json
+//! { "log_pings": true, "debug_view_tag": "my-tag", "source_tags": ["daemon", "staging"] }
+//! ```
+//!
 //! Bindings may implement other debugging features, e.g. sending pings on demand.
 
+use std::convert::TryFrom;
 use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use chrono::{DateTime, FixedOffset};
+use serde::Deserialize;
+
+use crate::metrics::{
+    DatetimeMetric, Metric, MetricType, QuantityMetric, StringListMetric, TimeUnit, MAX_LIST_LENGTH,
+};
+use crate::{CommonMetricData, Glean, Lifetime, INTERNAL_STORAGE};
 
 const GLEAN_LOG_PINGS: &str = "GLEAN_LOG_PINGS";
 const GLEAN_DEBUG_VIEW_TAG: &str = "GLEAN_DEBUG_VIEW_TAG";
 const GLEAN_SOURCE_TAGS: &str = "GLEAN_SOURCE_TAGS";
 const GLEAN_MAX_SOURCE_TAGS: usize = 5;
+/// The name of the file in the data path debug options are read from at runtime.
+pub(crate) const DEBUG_FILE_NAME: &str = "debug.json";
 
 /// A representation of all of Glean's debug options.
 pub struct DebugOptions {
@@ -41,6 +72,12 @@ pub struct DebugOptions {
     /// Option to add the X-Source-Tags header to ping requests. This will allow the data
     /// consumers to classify data depending on the applied tags.
     pub source_tags: DebugOption<Vec<String>>,
+    /// The limits of the debug view tag, if it was set with limits.
+    pub debug_view_tag_scope: Mutex<Option<TagScope>>,
+    /// The limits of the source tags, if they were set with limits.
+    pub source_tags_scope: Mutex<Option<TagScope>>,
+    /// The watcher of the debug options file, if it is watched.
+    pub file_watcher: Option<DebugFileWatcher>,
 }
 
 impl std::fmt::Debug for DebugOptions {
@@ -49,6 +86,9 @@ impl std::fmt::Debug for DebugOptions {
             .field("log_pings", &self.log_pings.get())
             .field("debug_view_tag", &self.debug_view_tag.get())
             .field("source_tags", &self.source_tags.get())
+            .field("debug_view_tag_scope", &self.debug_view_tag_scope)
+            .field("source_tags_scope", &self.source_tags_scope)
+            .field("file_options", &self.file_options())
             .finish()
     }
 }
@@ -63,6 +103,423 @@ impl DebugOptions {
                 tokenize_string,
                 Some(validate_source_tags),
             ),
+            debug_view_tag_scope: Mutex::new(None),
+            source_tags_scope: Mutex::new(None),
+            file_watcher: None,
+        }
+    }
+
+    /// The debug options currently set through the debug options file.
+    ///
+    /// Returns the default, empty options if the file isn't watched.
+    pub(crate) fn file_options(&self) -> DebugFileOptions {
+        match &self.file_watcher {
+            Some(watcher) => watcher.options.lock().unwrap().clone(),
+            None => DebugFileOptions::default(),
+        }
+    }
+
+    /// The limits of the given tag, if it was set with limits.
+    pub(crate) fn scope(&self, tag: DebugTag) -> &Mutex<Option<TagScope>> {
+        match tag {
+            DebugTag::DebugViewTag => &self.debug_view_tag_scope,
+            DebugTag::SourceTags => &self.source_tags_scope,
+        }
+    }
+
+    /// Whether the given tag applies to a ping, according to its limits.
+    ///
+    /// Tags set without limits apply to all pings.
+    pub(crate) fn tag_applies(
+        &self,
+        tag: DebugTag,
+        ping_name: &str,
+        now: DateTime<FixedOffset>,
+    ) -> bool {
+        match &*self.scope(tag).lock().unwrap() {
+            Some(scope) => scope.applies_to(ping_name, now),
+            None => true,
+        }
+    }
+}
+
+/// The debug tags that can be set with limits.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum DebugTag {
+    /// The debug view tag, sent in the `X-Debug-ID` header.
+    DebugViewTag,
+    /// The source tags, sent in the `X-Source-Tags` header.
+    SourceTags,
+}
+
+impl DebugTag {
+    /// The name the tag is persisted under.
+    fn storage_name(self) -> &'static str {
+        match self {
+            DebugTag::DebugViewTag => "debug_view_tag",
+            DebugTag::SourceTags => "source_tags",
+        }
+    }
+}
+
+/// Limits on how long and to which pings a debug tag is applied.
+///
+/// A tag is applied until any of the limits is reached.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DebugTagLimits {
+    /// How long the tag is applied, from the time it is set.
+    pub expires_after: Option<Duration>,
+    /// The number of pings the tag is applied to.
+    pub max_pings: Option<u32>,
+    /// The names of the pings the tag is applied to, at most 20.
+    /// If `None`, the tag is applied to all pings.
+    pub pings: Option<Vec<String>>,
+}
+
+impl DebugTagLimits {
+    /// Whether the limits can be applied.
+    ///
+    /// The ping filter must name between 1 and 20 pings.
+    pub(crate) fn is_valid(&self) -> bool {
+        match &self.pings {
+            Some(pings) if pings.is_empty() || pings.len() > MAX_LIST_LENGTH => {
+                log::error!(
+                    "The ping filter of a debug tag must name between 1 and {} pings.",
+                    MAX_LIST_LENGTH
+                );
+                false
+            }
+            _ => true,
+        }
+    }
+}
+
+/// The limits of a debug tag, as of the time it was set.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TagScope {
+    /// When the tag expires.
+    pub expires_at: Option<DateTime<FixedOffset>>,
+    /// The number of pings the tag is still applied to.
+    pub remaining_pings: Option<u32>,
+    /// The names of the pings the tag is applied to, if it isn't applied to all pings.
+    pub pings: Option<Vec<String>>,
+}
+
+impl TagScope {
+    /// Create the scope of a tag set at `now` with the given limits.
+    pub(crate) fn new(limits: DebugTagLimits, now: DateTime<FixedOffset>) -> Self {
+        Self {
+            expires_at: limits
+                .expires_after
+                .and_then(|duration| chrono::Duration::from_std(duration).ok())
+                .and_then(|duration| now.checked_add_signed(duration)),
+            remaining_pings: limits.max_pings,
+            pings: limits.pings,
+        }
+    }
+
+    /// Whether the tag is neither expired nor used up.
+    pub(crate) fn is_active(&self, now: DateTime<FixedOffset>) -> bool {
+        let expired = self
+            .expires_at
+            .map_or(false, |expires_at| now >= expires_at);
+        !expired && self.remaining_pings != Some(0)
+    }
+
+    /// Whether the tag is applied to the given ping.
+    pub(crate) fn applies_to(&self, ping_name: &str, now: DateTime<FixedOffset>) -> bool {
+        let included = match &self.pings {
+            Some(pings) => pings.iter().any(|name| name == ping_name),
+            None => true,
+        };
+        included && self.is_active(now)
+    }
+}
+
+/// The internal metrics a tag set with limits is persisted in.
+struct PersistedTag {
+    value: StringListMetric,
+    expires_at: DatetimeMetric,
+    remaining_pings: QuantityMetric,
+    pings: StringListMetric,
+}
+
+impl PersistedTag {
+    fn new(tag: DebugTag) -> Self {
+        let meta = |field: &str| CommonMetricData {
+            name: format!("{}#{}", tag.storage_name(), field),
+            // We don't need a category, the name is already unique
+            category: "".into(),
+            send_in_pings: vec![INTERNAL_STORAGE.into()],
+            lifetime: Lifetime::User,
+            ..Default::default()
+        };
+
+        Self {
+            value: StringListMetric::new(meta("value")),
+            expires_at: DatetimeMetric::new(meta("expires_at"), TimeUnit::Millisecond),
+            remaining_pings: QuantityMetric::new(meta("remaining_pings")),
+            pings: StringListMetric::new(meta("pings")),
+        }
+    }
+}
+
+fn get_persisted(glean: &Glean, metric: &dyn MetricType) -> Option<Metric> {
+    glean.storage().get_metric(
+        Lifetime::User,
+        INTERNAL_STORAGE,
+        &metric.meta().identifier(glean),
+    )
+}
+
+fn remove_persisted(glean: &Glean, metric: &dyn MetricType) {
+    // Nothing to do if the metric was never persisted.
+    let _ = glean.storage().remove_single_metric(
+        Lifetime::User,
+        INTERNAL_STORAGE,
+        &metric.meta().identifier(glean),
+    );
+}
+
+/// Persist a tag and its limits, or remove the persisted tag if it has no limits.
+///
+/// ## Arguments
+///
+/// * `glean` - The Glean instance to persist the tag in.
+/// * `tag` - Which tag to persist.
+/// * `value` - The value of the tag, or the list of source tags.
+/// * `scope` - The limits of the tag.
+pub(crate) fn persist_tag(
+    glean: &Glean,
+    tag: DebugTag,
+    value: &[String],
+    scope: Option<&TagScope>,
+) {
+    let persisted = PersistedTag::new(tag);
+    let scope = match scope {
+        Some(scope) => scope,
+        None => {
+            remove_persisted(glean, &persisted.value);
+            remove_persisted(glean, &persisted.expires_at);
+            remove_persisted(glean, &persisted.remaining_pings);
+            remove_persisted(glean, &persisted.pings);
+            return;
+        }
+    };
+
+    persisted.value.set(glean, value.to_vec());
+    match scope.expires_at {
+        Some(expires_at) => persisted.expires_at.set(glean, Some(expires_at)),
+        None => remove_persisted(glean, &persisted.expires_at),
+    }
+    match scope.remaining_pings {
+        Some(remaining) => persisted.remaining_pings.set(glean, i64::from(remaining)),
+        None => remove_persisted(glean, &persisted.remaining_pings),
+    }
+    match &scope.pings {
+        Some(pings) => persisted.pings.set(glean, pings.clone()),
+        None => remove_persisted(glean, &persisted.pings),
+    }
+}
+
+/// Persist the number of pings a tag is still applied to.
+pub(crate) fn persist_remaining_pings(glean: &Glean, tag: DebugTag, remaining: u32) {
+    PersistedTag::new(tag)
+        .remaining_pings
+        .set(glean, i64::from(remaining));
+}
+
+/// Load a tag persisted with its limits.
+///
+/// ## Return value
+///
+/// Returns the value of the tag and its limits, or `None` if no tag was persisted.
+pub(crate) fn load_tag(glean: &Glean, tag: DebugTag) -> Option<(Vec<String>, TagScope)> {
+    let persisted = PersistedTag::new(tag);
+    let value = match get_persisted(glean, &persisted.value) {
+        Some(Metric::StringList(value)) => value,
+        _ => return None,
+    };
+    let expires_at = match get_persisted(glean, &persisted.expires_at) {
+        Some(Metric::Datetime(expires_at, _)) => Some(expires_at),
+        _ => None,
+    };
+    let remaining_pings = match get_persisted(glean, &persisted.remaining_pings) {
+        Some(Metric::Quantity(remaining)) => u32::try_from(remaining).ok(),
+        _ => None,
+    };
+    let pings = match get_persisted(glean, &persisted.pings) {
+        Some(Metric::StringList(pings)) => Some(pings),
+        _ => None,
+    };
+
+    Some((
+        value,
+        TagScope {
+            expires_at,
+            remaining_pings,
+            pings,
+        },
+    ))
+}
+
+/// The debug options set through the debug options file.
+///
+/// Options missing from the file or with an invalid value are `None`.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub(crate) struct DebugFileOptions {
+    pub log_pings: Option<bool>,
+    pub debug_view_tag: Option<String>,
+    pub source_tags: Option<Vec<String>>,
+}
+
+impl DebugFileOptions {
+    /// Parse and validate the contents of the debug options file.
+    ///
+    /// Invalid values are logged and dropped.
+    ///
+    /// ## Return value
+    ///
+    /// Returns `None` if the contents are not a valid JSON object.
+    fn parse(contents: &str) -> Option<Self> {
+        let mut options: Self = match serde_json::from_str(contents) {
+            Ok(options) => options,
+            Err(e) => {
+                log::error!("Unable to parse {}: {}. Ignoring.", DEBUG_FILE_NAME, e);
+                return None;
+            }
+        };
+
+        if let Some(tag) = &options.debug_view_tag {
+            if !validate_tag(tag) {
+                log::error!("Invalid debug view tag in {}. Ignoring.", DEBUG_FILE_NAME);
+                options.debug_view_tag = None;
+            }
+        }
+        if let Some(tags) = &options.source_tags {
+            if !validate_source_tags(tags) {
+                log::error!("Invalid source tags in {}. Ignoring.", DEBUG_FILE_NAME);
+                options.source_tags = None;
+            }
+        }
+
+        Some(options)
+    }
+
+    /// Log the options that differ from the previous ones.
+    fn log_changes(&self, previous: &Self) {
+        if self.log_pings != previous.log_pings {
+            log::info!(
+                "Debug option log_pings changed to {:?} through {}.",
+                self.log_pings,
+                DEBUG_FILE_NAME
+            );
+        }
+        if self.debug_view_tag != previous.debug_view_tag {
+            log::info!(
+                "Debug option debug_view_tag changed to {:?} through {}.",
+                self.debug_view_tag,
+                DEBUG_FILE_NAME
+            );
+        }
+        if self.source_tags != previous.source_tags {
+            log::info!(
+                "Debug option source_tags changed to {:?} through {}.",
+                self.source_tags,
+                DEBUG_FILE_NAME
+            );
+        }
+    }
+}
+
+/// Watches the debug options file for changes.
+///
+/// The file is polled on a background thread, which is stopped when the watcher is dropped.
+#[derive(Debug)]
+pub struct DebugFileWatcher {
+    /// The options currently set through the file.
+    options: Arc<Mutex<DebugFileOptions>>,
+    /// The polling thread and the channel that is dropped to stop it.
+    ///
+    /// Kept behind a mutex, so that Glean stays unwind safe.
+    thread: Mutex<Option<(Sender<()>, JoinHandle<()>)>>,
+}
+
+impl DebugFileWatcher {
+    /// Start watching the debug options file in the given directory.
+    ///
+    /// The file is read once before this returns, so that its options apply right away.
+    ///
+    /// ## Arguments
+    ///
+    /// * `data_path` - The directory containing the debug options file.
+    /// * `poll_interval` - How often the file is checked for changes.
+    pub(crate) fn start(data_path: &Path, poll_interval: Duration) -> crate::Result<Self> {
+        let path = data_path.join(DEBUG_FILE_NAME);
+        let options = Arc::new(Mutex::new(DebugFileOptions::default()));
+        let mut poller = DebugFilePoller {
+            path,
+            last_contents: None,
+            options: options.clone(),
+        };
+        poller.poll();
+
+        let (stop, stopped) = mpsc::channel();
+        let thread = thread::Builder::new()
+            .name("glean.debug_file_watcher".to_string())
+            .spawn(move || {
+                // Nothing is ever sent, the channel is disconnected when the watcher is dropped.
+                while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(poll_interval) {
+                    poller.poll();
+                }
+            })?;
+
+        Ok(Self {
+            options,
+            thread: Mutex::new(Some((stop, thread))),
+        })
+    }
+}
+
+impl Drop for DebugFileWatcher {
+    fn drop(&mut self) {
+        if let Some((stop, thread)) = self.thread.lock().unwrap().take() {
+            drop(stop);
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Reads the debug options file on behalf of the watcher.
+struct DebugFilePoller {
+    path: PathBuf,
+    /// The contents of the file the last time it was read, `None` if it didn't exist.
+    last_contents: Option<String>,
+    options: Arc<Mutex<DebugFileOptions>>,
+}
+
+impl DebugFilePoller {
+    /// Apply the options from the file if it changed since the last poll.
+    ///
+    /// A removed file resets the options.
+    /// If the file can't be parsed, the previous options are kept.
+    fn poll(&mut self) {
+        let contents = fs::read_to_string(&self.path).ok();
+        if contents == self.last_contents {
+            return;
+        }
+
+        let options = match &contents {
+            Some(contents) => DebugFileOptions::parse(contents),
+            None => Some(DebugFileOptions::default()),
+        };
+        self.last_contents = contents;
+
+        if let Some(options) = options {
+            let mut current = self.options.lock().unwrap();
+            options.log_changes(&current);
+            *current = options;
         }
     }
 }
@@ -293,6 +750,30 @@ mod test {
         assert!(!validate_tag(&"".to_string()));
     }
 
+    #[test]
+    fn debug_file_options_are_validated() {
+        let options = DebugFileOptions::parse(
+            r#"{"log_pings": true, "debug_view_tag": "invalid tag", "source_tags": ["a", "b"]}"#,
+        )
+        .unwrap();
+        assert_eq!(Some(true), options.log_pings);
+        assert_eq!(None, options.debug_view_tag);
+        assert_eq!(
+            Some(vec!["a".to_string(), "b".to_string()]),
+            options.source_tags
+        );
+
+        // Missing options are unset.
+        assert_eq!(
+            Some(DebugFileOptions::default()),
+            DebugFileOptions::parse("{}")
+        );
+
+        // Invalid JSON or values of the wrong type are rejected as a whole.
+        assert_eq!(None, DebugFileOptions::parse("{"));
+        assert_eq!(None, DebugFileOptions::parse(r#"{"log_pings": "yes"}"#));
+    }
+
     #[test]
     fn validates_source_tags_correctly() {
         // Empty tags.
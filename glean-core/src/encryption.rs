@@ -0,0 +1,319 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Encryption of the data Glean stores on disk.
+//!
+//! If a [`KeyProvider`](trait.KeyProvider.html) is passed in the configuration,
+//! metric values in the database, recorded events and pending pings are encrypted
+//! with AES-256-GCM before they are written to disk.
+//!
+//! Every encrypted value is stored in an envelope that carries the ID of the key used
+//! to encrypt it:
+//!
+//! ```text
+//! "GLNE" | format version (1 byte) | key ID (u32, little endian) | nonce (12 bytes) | ciphertext
+//! ```
+//!
+//! New data is always encrypted with the provider's current key.
+//! Data encrypted with a retired key can still be read as long as the provider
+//! returns that key, which allows keys to be rotated.
+//! [`Glean::reencrypt_storage`](../struct.Glean.html#method.reencrypt_storage)
+//! rewrites everything with the current key, after which retired keys can be dropped.
+//!
+//! Unencrypted data (e.g. written before encryption was enabled) is read as-is
+//! and encrypted the next time it is written.
+
+use std::borrow::Cow;
+use std::convert::TryInto;
+use std::fmt;
+use std::panic::RefUnwindSafe;
+use std::sync::Arc;
+
+use aes_gcm::aead::generic_array::GenericArray;
+use aes_gcm::aead::{Aead, NewAead, Payload};
+use aes_gcm::Aes256Gcm;
+
+use crate::error::ErrorKind;
+use crate::Result;
+
+/// The marker every encrypted value starts with.
+const ENVELOPE_MAGIC: &[u8; 4] = b"GLNE";
+
+/// The version of the envelope format.
+const ENVELOPE_VERSION: u8 = 1;
+
+/// The length of the envelope header: marker, version and key ID.
+const HEADER_LENGTH: usize = 4 + 1 + 4;
+
+/// The length of the AES-GCM nonce.
+const NONCE_LENGTH: usize = 12;
+
+/// A 256-bit encryption key.
+pub type EncryptionKey = [u8; 32];
+
+/// Provides the keys to encrypt the data Glean stores on disk.
+///
+/// Keys are identified by a numeric ID, which is stored alongside the encrypted data.
+/// To rotate keys, return the new key from `current_key`
+/// and keep returning retired keys from `key` until all data has been re-encrypted.
+///
+/// The provider is called from any thread Glean runs on, including across the FFI boundary.
+pub trait KeyProvider: fmt::Debug + Send + Sync + RefUnwindSafe {
+    /// The ID and the key new data is encrypted with.
+    ///
+    /// If no key is available, nothing is written to disk.
+    fn current_key(&self) -> Option<(u32, EncryptionKey)>;
+
+    /// Look up a key by its ID to decrypt data.
+    ///
+    /// This needs to return the current key as well as any retired key
+    /// that might still be in use.
+    fn key(&self, key_id: u32) -> Option<EncryptionKey>;
+}
+
+/// Encrypts and decrypts data using the configured key provider.
+///
+/// Without a key provider data is passed through unchanged.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Encryption {
+    provider: Option<Arc<dyn KeyProvider>>,
+}
+
+impl Encryption {
+    /// Create a new encryption object.
+    ///
+    /// ## Arguments
+    ///
+    /// * `provider` - The key provider. If `None`, data is not encrypted.
+    pub fn new(provider: Option<Arc<dyn KeyProvider>>) -> Self {
+        Self { provider }
+    }
+
+    /// Whether the given data is an encrypted envelope.
+    pub fn is_encrypted(data: &[u8]) -> bool {
+        data.starts_with(ENVELOPE_MAGIC)
+    }
+
+    /// Encrypt data with the current key.
+    ///
+    /// ## Return value
+    ///
+    /// Returns the data unchanged if no key provider is configured.
+    /// Returns an error if the provider has no current key.
+    pub fn encrypt<'a>(&self, plaintext: &'a [u8]) -> Result<Cow<'a, [u8]>> {
+        let provider = match &self.provider {
+            Some(provider) => provider,
+            None => return Ok(Cow::Borrowed(plaintext)),
+        };
+        let (key_id, key) = provider
+            .current_key()
+            .ok_or(ErrorKind::MissingEncryptionKey(None))?;
+
+        let mut envelope = Vec::with_capacity(HEADER_LENGTH + NONCE_LENGTH + plaintext.len() + 16);
+        envelope.extend_from_slice(ENVELOPE_MAGIC);
+        envelope.push(ENVELOPE_VERSION);
+        envelope.extend_from_slice(&key_id.to_le_bytes());
+
+        let mut nonce = [0u8; NONCE_LENGTH];
+        getrandom::getrandom(&mut nonce).map_err(|_| ErrorKind::Encryption)?;
+        envelope.extend_from_slice(&nonce);
+
+        let cipher = Aes256Gcm::new(GenericArray::from_slice(&key));
+        let ciphertext = cipher
+            .encrypt(
+                GenericArray::from_slice(&nonce),
+                Payload {
+                    msg: plaintext,
+                    aad: &envelope[..HEADER_LENGTH],
+                },
+            )
+            .map_err(|_| ErrorKind::Encryption)?;
+        envelope.extend_from_slice(&ciphertext);
+
+        Ok(Cow::Owned(envelope))
+    }
+
+    /// Decrypt data.
+    ///
+    /// ## Return value
+    ///
+    /// Returns unencrypted data unchanged.
+    /// Returns an error if the key the data was encrypted with is not available
+    /// or the data cannot be decrypted with it.
+    pub fn decrypt<'a>(&self, data: &'a [u8]) -> Result<Cow<'a, [u8]>> {
+        if !Self::is_encrypted(data) {
+            return Ok(Cow::Borrowed(data));
+        }
+        if data.len() < HEADER_LENGTH + NONCE_LENGTH || data[4] != ENVELOPE_VERSION {
+            return Err(ErrorKind::Encryption.into());
+        }
+
+        let key_id = u32::from_le_bytes(data[5..HEADER_LENGTH].try_into().unwrap()); // safe unwrap, length checked above
+        let key = self
+            .provider
+            .as_ref()
+            .and_then(|provider| provider.key(key_id))
+            .ok_or(ErrorKind::MissingEncryptionKey(Some(key_id)))?;
+
+        let cipher = Aes256Gcm::new(GenericArray::from_slice(&key));
+        let nonce = &data[HEADER_LENGTH..HEADER_LENGTH + NONCE_LENGTH];
+        let plaintext = cipher
+            .decrypt(
+                GenericArray::from_slice(nonce),
+                Payload {
+                    msg: &data[HEADER_LENGTH + NONCE_LENGTH..],
+                    aad: &data[..HEADER_LENGTH],
+                },
+            )
+            .map_err(|_| ErrorKind::Encryption)?;
+
+        Ok(Cow::Owned(plaintext))
+    }
+
+    /// Encrypt a single line of text.
+    ///
+    /// Encrypted lines are base64-encoded, so they never contain a line break.
+    pub fn encrypt_line(&self, line: &str) -> Result<String> {
+        match self.encrypt(line.as_bytes())? {
+            Cow::Borrowed(_) => Ok(line.to_string()),
+            Cow::Owned(envelope) => Ok(base64::encode(&envelope)),
+        }
+    }
+
+    /// Decrypt a single line of text, as written by `encrypt_line`.
+    ///
+    /// Unencrypted lines are returned unchanged.
+    pub fn decrypt_line(&self, line: &str) -> Result<String> {
+        // Unencrypted lines are JSON objects, encrypted lines are never.
+        if line.starts_with('{') {
+            return Ok(line.to_string());
+        }
+        let envelope = base64::decode(line).map_err(|_| ErrorKind::Encryption)?;
+        let plaintext = self.decrypt(&envelope)?;
+        String::from_utf8(plaintext.into_owned()).map_err(|_| ErrorKind::Encryption.into())
+    }
+
+    /// Re-encrypt data with the current key.
+    ///
+    /// ## Return value
+    ///
+    /// Returns `None` if the data is already encrypted with the current key
+    /// or if no key provider is configured.
+    pub fn reencrypt(&self, data: &[u8]) -> Result<Option<Vec<u8>>> {
+        let provider = match &self.provider {
+            Some(provider) => provider,
+            None => return Ok(None),
+        };
+        if let Some((current_key_id, _)) = provider.current_key() {
+            if Self::is_encrypted(data)
+                && data.len() >= HEADER_LENGTH
+                && data[5..HEADER_LENGTH] == current_key_id.to_le_bytes()
+            {
+                return Ok(None);
+            }
+        }
+
+        let plaintext = self.decrypt(data)?;
+        Ok(Some(self.encrypt(&plaintext)?.into_owned()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::HashMap;
+    use std::sync::RwLock;
+
+    #[derive(Debug, Default)]
+    struct TestKeys {
+        current: RwLock<Option<u32>>,
+        keys: RwLock<HashMap<u32, EncryptionKey>>,
+    }
+
+    impl TestKeys {
+        fn rotate(&self, key_id: u32) {
+            self.keys
+                .write()
+                .unwrap()
+                .insert(key_id, [key_id as u8; 32]);
+            *self.current.write().unwrap() = Some(key_id);
+        }
+    }
+
+    impl KeyProvider for TestKeys {
+        fn current_key(&self) -> Option<(u32, EncryptionKey)> {
+            let current = (*self.current.read().unwrap())?;
+            self.key(current).map(|key| (current, key))
+        }
+
+        fn key(&self, key_id: u32) -> Option<EncryptionKey> {
+            self.keys.read().unwrap().get(&key_id).copied()
+        }
+    }
+
+    #[test]
+    fn data_is_passed_through_without_a_provider() {
+        let encryption = Encryption::default();
+        assert_eq!(b"data", &*encryption.encrypt(b"data").unwrap());
+        assert_eq!(b"data", &*encryption.decrypt(b"data").unwrap());
+        assert_eq!("{}", encryption.encrypt_line("{}").unwrap());
+    }
+
+    #[test]
+    fn data_round_trips() {
+        let keys = Arc::new(TestKeys::default());
+        keys.rotate(1);
+        let encryption = Encryption::new(Some(keys));
+
+        let encrypted = encryption.encrypt(b"some data").unwrap();
+        assert!(Encryption::is_encrypted(&encrypted));
+        assert_ne!(b"some data", &encrypted[HEADER_LENGTH + NONCE_LENGTH..]);
+        assert_eq!(b"some data", &*encryption.decrypt(&encrypted).unwrap());
+
+        let line = encryption.encrypt_line(r#"{"a":1}"#).unwrap();
+        assert!(!line.contains('\n'));
+        assert_eq!(r#"{"a":1}"#, encryption.decrypt_line(&line).unwrap());
+
+        // Unencrypted data can still be read.
+        assert_eq!(b"plain", &*encryption.decrypt(b"plain").unwrap());
+    }
+
+    #[test]
+    fn retired_keys_are_used_until_data_is_reencrypted() {
+        let keys = Arc::new(TestKeys::default());
+        keys.rotate(1);
+        let encryption = Encryption::new(Some(keys.clone()));
+        let old = encryption.encrypt(b"some data").unwrap().into_owned();
+        assert!(encryption.reencrypt(&old).unwrap().is_none());
+
+        keys.rotate(2);
+        assert_eq!(b"some data", &*encryption.decrypt(&old).unwrap());
+        let new = encryption.reencrypt(&old).unwrap().unwrap();
+
+        keys.keys.write().unwrap().remove(&1);
+        assert!(encryption.decrypt(&old).is_err());
+        assert_eq!(b"some data", &*encryption.decrypt(&new).unwrap());
+    }
+
+    #[test]
+    fn wrong_or_missing_keys_fail_cleanly() {
+        let keys = Arc::new(TestKeys::default());
+        let encryption = Encryption::new(Some(keys.clone()));
+        assert!(encryption.encrypt(b"some data").is_err());
+
+        keys.rotate(1);
+        let mut encrypted = encryption.encrypt(b"some data").unwrap().into_owned();
+
+        assert!(Encryption::default().decrypt(&encrypted).is_err());
+
+        keys.keys.write().unwrap().insert(1, [0xff; 32]);
+        assert!(encryption.decrypt(&encrypted).is_err());
+
+        keys.rotate(1);
+        let last = encrypted.len() - 1;
+        encrypted[last] ^= 0xff;
+        assert!(encryption.decrypt(&encrypted).is_err());
+        assert!(encryption.decrypt(&encrypted[..8]).is_err());
+    }
+}
@@ -5,7 +5,7 @@ use std::result;
 
 use ffi_support::{handle_map::HandleError, ExternError};
 
-use rkv::error::StoreError;
+use crate::database::kv::error::StoreError;
 
 /// A specialized [`Result`] type for this crate's operations.
 ///
@@ -40,6 +40,9 @@ pub enum ErrorKind {
     /// JSON error
     Json(serde_json::error::Error),
 
+    /// Bincode error
+    Bincode(bincode::Error),
+
     /// TimeUnit conversion failed
     TimeUnit(i32),
 
@@ -60,6 +63,44 @@ pub enum ErrorKind {
 
     /// Glean not initialized
     NotInitialized,
+
+    /// A state archive could not be recognized
+    InvalidStateArchive,
+
+    /// A state archive was written with an unsupported format version
+    IncompatibleStateArchive(u32),
+
+    /// Data could not be encrypted or decrypted
+    Encryption,
+
+    /// The key to encrypt or decrypt data is not available
+    MissingEncryptionKey(Option<u32>),
+
+    /// A public key to encrypt pings with is invalid or unsupported
+    InvalidJwk(String),
+
+    /// The path template to upload pings to is invalid
+    InvalidPathTemplate(String),
+
+    /// The server endpoint to upload pings to is invalid
+    InvalidServerEndpoint(String),
+
+    /// ErrorType conversion failed
+    ErrorType(i32),
+
+    /// A metric has no stored value
+    NoValue,
+
+    /// A null pointer was passed where a value is required
+    NullPointer,
+
+    /// A configuration was passed over FFI by a caller built for a different ABI
+    IncompatibleAbi {
+        /// The ABI version and configuration size of the caller
+        found: (u32, u32),
+        /// The ABI version and configuration size of the library
+        expected: (u32, u32),
+    },
 }
 
 /// A specialized [`Error`] type for this crate's operations.
@@ -86,6 +127,24 @@ impl Error {
             kind: ErrorKind::NotInitialized,
         }
     }
+
+    /// Indicates that a metric has no stored value
+    ///
+    /// This is exposed in order to report reads of missing values on the FFI layer.
+    pub fn no_value() -> Error {
+        Error {
+            kind: ErrorKind::NoValue,
+        }
+    }
+
+    /// Indicates that a required pointer was null
+    ///
+    /// This is exposed in order to report invalid arguments on the FFI layer.
+    pub fn null_pointer() -> Error {
+        Error {
+            kind: ErrorKind::NullPointer,
+        }
+    }
 }
 
 impl std::error::Error for Error {}
@@ -99,6 +158,7 @@ impl Display for Error {
             IoError(e) => write!(f, "An I/O error occurred: {}", e),
             Rkv(e) => write!(f, "An Rkv error occurred: {}", e),
             Json(e) => write!(f, "A JSON error occurred: {}", e),
+            Bincode(e) => write!(f, "A bincode error occurred: {}", e),
             TimeUnit(t) => write!(f, "TimeUnit conversion from {} failed", t),
             MemoryUnit(m) => write!(f, "MemoryUnit conversion from {} failed", m),
             HistogramType(h) => write!(f, "HistogramType conversion from {} failed", h),
@@ -106,6 +166,24 @@ impl Display for Error {
             Utf8Error => write!(f, "Invalid UTF-8 byte sequence in string"),
             InvalidConfig => write!(f, "Invalid Glean configuration provided"),
             NotInitialized => write!(f, "Global Glean object missing"),
+            InvalidStateArchive => write!(f, "Invalid Glean state archive"),
+            IncompatibleStateArchive(v) => {
+                write!(f, "Unsupported Glean state archive version {}", v)
+            }
+            Encryption => write!(f, "Data could not be encrypted or decrypted"),
+            MissingEncryptionKey(Some(id)) => write!(f, "Encryption key {} is not available", id),
+            MissingEncryptionKey(None) => write!(f, "No current encryption key available"),
+            InvalidJwk(msg) => write!(f, "Invalid JWK: {}", msg),
+            InvalidPathTemplate(msg) => write!(f, "Invalid path template: {}", msg),
+            InvalidServerEndpoint(e) => write!(f, "Invalid server endpoint '{}'", e),
+            ErrorType(e) => write!(f, "ErrorType conversion from {} failed", e),
+            NoValue => write!(f, "No value stored for the metric"),
+            NullPointer => write!(f, "Unexpected null pointer"),
+            IncompatibleAbi { found, expected } => write!(
+                f,
+                "Incompatible ABI: configuration of ABI version {} with {} bytes, expected ABI version {} with {} bytes",
+                found.0, found.1, expected.0, expected.1
+            ),
         }
     }
 }
@@ -154,6 +232,14 @@ impl From<serde_json::error::Error> for Error {
     }
 }
 
+impl From<bincode::Error> for Error {
+    fn from(error: bincode::Error) -> Error {
+        Error {
+            kind: ErrorKind::Bincode(error),
+        }
+    }
+}
+
 impl From<OsString> for Error {
     fn from(error: OsString) -> Error {
         Error {
@@ -59,7 +59,7 @@ impl TryFrom<i32> for ErrorType {
             1 => Ok(ErrorType::InvalidLabel),
             2 => Ok(ErrorType::InvalidState),
             3 => Ok(ErrorType::InvalidOverflow),
-            e => Err(ErrorKind::Lifetime(e).into()),
+            e => Err(ErrorKind::ErrorType(e).into()),
         }
     }
 }
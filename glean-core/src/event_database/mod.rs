@@ -3,11 +3,6 @@
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
 use std::collections::HashMap;
-use std::fs;
-use std::fs::{create_dir_all, File, OpenOptions};
-use std::io::BufRead;
-use std::io::BufReader;
-use std::io::Write;
 use std::iter::FromIterator;
 use std::path::{Path, PathBuf};
 use std::sync::RwLock;
@@ -15,6 +10,8 @@ use std::sync::RwLock;
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value as JsonValue};
 
+use crate::encryption::Encryption;
+use crate::fs::{self, create_dir_all};
 use crate::CommonMetricData;
 use crate::Glean;
 use crate::Result;
@@ -76,6 +73,8 @@ pub struct EventDatabase {
     event_stores: RwLock<HashMap<String, Vec<RecordedEvent>>>,
     /// A lock to be held when doing operations on the filesystem
     file_lock: RwLock<()>,
+    /// Encrypts events before they are written to disk, if configured
+    encryption: Encryption,
 }
 
 impl EventDatabase {
@@ -86,6 +85,13 @@ impl EventDatabase {
     /// * `data_path` - The directory to store events in. A new directory
     ///   `events` will be created inside of this directory.
     pub fn new(data_path: &str) -> Result<Self> {
+        Self::with_encryption(data_path, Encryption::default())
+    }
+
+    /// Create a new event database, encrypting all events stored on disk.
+    ///
+    /// See [`new`](#method.new).
+    pub(crate) fn with_encryption(data_path: &str, encryption: Encryption) -> Result<Self> {
         let path = Path::new(data_path).join("events");
         create_dir_all(&path)?;
 
@@ -93,9 +99,28 @@ impl EventDatabase {
             path,
             event_stores: RwLock::new(HashMap::new()),
             file_lock: RwLock::new(()),
+            encryption,
         })
     }
 
+    /// Read all events of a single store file.
+    ///
+    /// Lines that cannot be decrypted are logged and skipped.
+    ///
+    /// # Returns
+    ///
+    /// The single-line JSON-encoded events.
+    fn read_store_file(&self, path: &Path) -> Result<Vec<String>> {
+        let mut lines = Vec::new();
+        for line in fs::read_to_string(path)?.lines() {
+            match self.encryption.decrypt_line(line) {
+                Ok(line) => lines.push(line),
+                Err(e) => log::error!("Failed to decrypt event in '{}': {}", path.display(), e),
+            }
+        }
+        Ok(lines)
+    }
+
     /// Initialize events storage after Glean is fully initialized and ready to
     /// send pings. This must be called once on application startup, e.g. from
     /// [Glean.initialize], but after we are ready to send pings, since this
@@ -129,19 +154,15 @@ impl EventDatabase {
     fn load_events_from_disk(&self) -> Result<()> {
         let _lock = self.file_lock.read().unwrap(); // safe unwrap, only error case is poisoning
         let mut db = self.event_stores.write().unwrap(); // safe unwrap, only error case is poisoning
-        for entry in fs::read_dir(&self.path)? {
-            let entry = entry?;
-            if entry.file_type()?.is_file() {
-                let store_name = entry.file_name().into_string()?;
-                let file = BufReader::new(File::open(entry.path())?);
-                db.insert(
-                    store_name,
-                    file.lines()
-                        .filter_map(|line| line.ok())
-                        .filter_map(|line| serde_json::from_str::<RecordedEvent>(&line).ok())
-                        .collect(),
-                );
-            }
+        for entry in fs::list_files(&self.path)? {
+            let store_name = entry.file_name().into_string()?;
+            db.insert(
+                store_name,
+                self.read_store_file(&entry.path())?
+                    .iter()
+                    .filter_map(|line| serde_json::from_str::<RecordedEvent>(line).ok())
+                    .collect(),
+            );
         }
         Ok(())
     }
@@ -186,6 +207,12 @@ impl EventDatabase {
         timestamp: u64,
         extra: Option<HashMap<String, String>>,
     ) {
+        // Child processes only buffer the event, it's stored once it reaches the main process.
+        if let Some(buffer) = glean.ipc_buffer() {
+            buffer.record_event(meta, timestamp, extra);
+            return;
+        }
+
         // Create RecordedEvent object, and its JSON form for serialization
         // on disk.
         let event = RecordedEvent {
@@ -231,13 +258,20 @@ impl EventDatabase {
     /// * `store_name` - The name of the store.
     /// * `event_json` - The event content, as a single-line JSON-encoded string.
     fn write_event_to_disk(&self, store_name: &str, event_json: &str) {
+        let event_json = match self.encryption.encrypt_line(event_json) {
+            Ok(line) => line,
+            Err(err) => {
+                log::error!(
+                    "Failed to encrypt event for store '{}': {}",
+                    store_name,
+                    err
+                );
+                return;
+            }
+        };
+
         let _lock = self.file_lock.write().unwrap(); // safe unwrap, only error case is poisoning
-        if let Err(err) = OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(self.path.join(store_name))
-            .and_then(|mut file| writeln!(file, "{}", event_json))
-        {
+        if let Err(err) = fs::append(self.path.join(store_name), format!("{}\n", event_json)) {
             log::error!("IO error writing event to store '{}': {}", store_name, err);
         }
     }
@@ -302,12 +336,95 @@ impl EventDatabase {
 
         // safe unwrap, only error case is poisoning
         let _lock = self.file_lock.write().unwrap();
-        std::fs::remove_dir_all(&self.path)?;
+        fs::remove_dir_all(&self.path)?;
         create_dir_all(&self.path)?;
 
         Ok(())
     }
 
+    /// Get the stored events of all stores, as they are persisted on disk.
+    ///
+    /// # Returns
+    ///
+    /// A map from store name to the single-line JSON-encoded events of that store.
+    pub fn export_stores(&self) -> Result<HashMap<String, Vec<String>>> {
+        let _lock = self.file_lock.read().unwrap(); // safe unwrap, only error case is poisoning
+        let mut stores = HashMap::new();
+        for entry in fs::list_files(&self.path)? {
+            let store_name = entry.file_name().into_string()?;
+            stores.insert(store_name, self.read_store_file(&entry.path())?);
+        }
+        Ok(stores)
+    }
+
+    /// Append events to the given stores, both in memory and on disk.
+    ///
+    /// Events that cannot be decoded are skipped.
+    ///
+    /// # Arguments
+    ///
+    /// * `stores` - A map from store name to single-line JSON-encoded events,
+    ///   as returned by `export_stores`.
+    pub fn import_stores(&self, stores: &HashMap<String, Vec<String>>) -> Result<()> {
+        let _lock = self.file_lock.write().unwrap(); // safe unwrap, only error case is poisoning
+        let mut db = self.event_stores.write().unwrap(); // safe unwrap, only error case is poisoning
+        for (store_name, lines) in stores {
+            let events: Vec<RecordedEvent> = lines
+                .iter()
+                .filter_map(|line| serde_json::from_str(line).ok())
+                .collect();
+            if events.is_empty() {
+                continue;
+            }
+
+            let mut content = String::new();
+            for event in &events {
+                let line = self
+                    .encryption
+                    .encrypt_line(&serde_json::to_string(event)?)?;
+                content.push_str(&line);
+                content.push('\n');
+            }
+            fs::append(self.path.join(store_name), content)?;
+
+            db.entry(store_name.to_string()).or_default().extend(events);
+        }
+        Ok(())
+    }
+
+    /// Re-encrypt all events stored on disk with the current encryption key.
+    ///
+    /// Events that cannot be decrypted are kept as they are.
+    pub(crate) fn reencrypt(&self) -> Result<()> {
+        let _lock = self.file_lock.write().unwrap(); // safe unwrap, only error case is poisoning
+        for entry in fs::list_files(&self.path)? {
+            let mut content = String::new();
+            for line in fs::read_to_string(entry.path())?.lines() {
+                match self.encryption.decrypt_line(line) {
+                    Ok(line) => content.push_str(&self.encryption.encrypt_line(&line)?),
+                    Err(e) => {
+                        log::error!(
+                            "Failed to re-encrypt event in '{}': {}",
+                            entry.path().display(),
+                            e
+                        );
+                        content.push_str(line);
+                    }
+                }
+                content.push('\n');
+            }
+
+            // Write to a temporary location and then move when done,
+            // so a failure never loses the existing events.
+            let tmp_dir = self.path.with_file_name("tmp");
+            create_dir_all(&tmp_dir)?;
+            let tmp_path = tmp_dir.join(entry.file_name());
+            fs::write(&tmp_path, content)?;
+            fs::rename(&tmp_path, entry.path())?;
+        }
+        Ok(())
+    }
+
     /// **Test-only API (exported for FFI purposes).**
     ///
     /// Return whether there are any events currently stored for the given even
@@ -0,0 +1,341 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Filesystem access for the pending pings, the stored events, state archives and the file sink.
+//!
+//! By default this is a thin layer over `std::fs`.
+//! With the `wasm` feature, files are kept in memory for the lifetime of the process instead,
+//! as there is no filesystem to write to.
+//! The in-memory filesystem behaves like a real one where Glean relies on it:
+//! files can only be created in existing directories and missing files are reported as `NotFound`.
+
+use std::ffi::OsString;
+use std::io;
+use std::path::{Path, PathBuf};
+
+pub(crate) use self::imp::*;
+
+/// A file in a directory, as listed by `list_files`.
+#[derive(Debug)]
+pub(crate) struct DirEntry {
+    path: PathBuf,
+    len: u64,
+    modified: Option<Modified>,
+}
+
+impl DirEntry {
+    /// The full path of the file.
+    pub fn path(&self) -> PathBuf {
+        self.path.clone()
+    }
+
+    /// The file name, without the directory.
+    pub fn file_name(&self) -> OsString {
+        self.path.file_name().unwrap_or_default().to_os_string()
+    }
+
+    /// The size of the file, in bytes.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// When the file was last written, if known.
+    ///
+    /// Only meant to order files by age.
+    pub fn modified(&self) -> Option<Modified> {
+        self.modified
+    }
+}
+
+/// Read a file into a string.
+pub(crate) fn read_to_string<P: AsRef<Path>>(path: P) -> io::Result<String> {
+    String::from_utf8(read(path)?).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+#[cfg(not(feature = "wasm"))]
+mod imp {
+    use std::fs::{self, File, OpenOptions};
+    use std::io::{self, Write};
+    use std::path::{Path, PathBuf};
+    use std::time::SystemTime;
+
+    use super::DirEntry;
+
+    pub(crate) use std::fs::{create_dir_all, read, remove_dir_all, remove_file, rename, write};
+
+    /// When a file was last written.
+    pub(crate) type Modified = SystemTime;
+
+    /// Whether a file or directory exists at the given path.
+    pub(crate) fn exists<P: AsRef<Path>>(path: P) -> bool {
+        path.as_ref().exists()
+    }
+
+    /// Append to a file, creating it if it doesn't exist.
+    pub(crate) fn append<P: AsRef<Path>, C: AsRef<[u8]>>(path: P, contents: C) -> io::Result<()> {
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?
+            .write_all(contents.as_ref())
+    }
+
+    /// Write a file like `write`, returning once the contents reached the disk.
+    pub(crate) fn write_synced<P: AsRef<Path>, C: AsRef<[u8]>>(
+        path: P,
+        contents: C,
+    ) -> io::Result<()> {
+        let mut file = File::create(path)?;
+        file.write_all(contents.as_ref())?;
+        file.sync_all()
+    }
+
+    /// Append to a file like `append`, returning once the contents reached the disk.
+    pub(crate) fn append_synced<P: AsRef<Path>, C: AsRef<[u8]>>(
+        path: P,
+        contents: C,
+    ) -> io::Result<()> {
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        file.write_all(contents.as_ref())?;
+        file.sync_all()
+    }
+
+    /// The size of a file, in bytes.
+    pub(crate) fn file_len<P: AsRef<Path>>(path: P) -> io::Result<u64> {
+        Ok(fs::metadata(path)?.len())
+    }
+
+    /// List the subdirectories of a directory.
+    ///
+    /// Entries that can't be read are skipped.
+    pub(crate) fn list_dirs<P: AsRef<Path>>(dir: P) -> io::Result<Vec<PathBuf>> {
+        Ok(fs::read_dir(dir)?
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| {
+                entry
+                    .file_type()
+                    .map_or(false, |file_type| file_type.is_dir())
+            })
+            .map(|entry| entry.path())
+            .collect())
+    }
+
+    /// List the files in a directory.
+    ///
+    /// Subdirectories and entries that can't be read are skipped.
+    pub(crate) fn list_files<P: AsRef<Path>>(dir: P) -> io::Result<Vec<DirEntry>> {
+        let mut files = Vec::new();
+        for entry in fs::read_dir(dir)?.filter_map(|entry| entry.ok()) {
+            let metadata = match entry.metadata() {
+                Ok(metadata) if metadata.is_file() => metadata,
+                _ => continue,
+            };
+            files.push(DirEntry {
+                path: entry.path(),
+                len: metadata.len(),
+                modified: metadata.modified().ok(),
+            });
+        }
+        Ok(files)
+    }
+}
+
+#[cfg(feature = "wasm")]
+mod imp {
+    use std::collections::{BTreeMap, BTreeSet};
+    use std::io;
+    use std::path::{Path, PathBuf};
+    use std::sync::{Mutex, MutexGuard};
+
+    use once_cell::sync::Lazy;
+
+    use super::DirEntry;
+
+    /// When a file was last written, as a sequence number of all writes.
+    pub(crate) type Modified = u64;
+
+    #[derive(Debug, Default)]
+    struct File {
+        contents: Vec<u8>,
+        modified: Modified,
+    }
+
+    #[derive(Debug, Default)]
+    struct MemoryFs {
+        dirs: BTreeSet<PathBuf>,
+        files: BTreeMap<PathBuf, File>,
+        writes: Modified,
+    }
+
+    impl MemoryFs {
+        fn check_parent(&self, path: &Path) -> io::Result<()> {
+            match path.parent() {
+                Some(parent) if self.dirs.contains(parent) => Ok(()),
+                _ => Err(not_found(path)),
+            }
+        }
+
+        fn file_mut(&mut self, path: &Path) -> io::Result<&mut File> {
+            self.check_parent(path)?;
+            self.writes += 1;
+            let file = self.files.entry(path.to_path_buf()).or_default();
+            file.modified = self.writes;
+            Ok(file)
+        }
+    }
+
+    static FS: Lazy<Mutex<MemoryFs>> = Lazy::new(|| Mutex::new(MemoryFs::default()));
+
+    fn lock() -> MutexGuard<'static, MemoryFs> {
+        // The filesystem is never left in an inconsistent state, so a poisoned lock is fine.
+        FS.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn not_found(path: &Path) -> io::Error {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("No such file or directory: {}", path.display()),
+        )
+    }
+
+    /// Create a directory and all its missing parents.
+    pub(crate) fn create_dir_all<P: AsRef<Path>>(path: P) -> io::Result<()> {
+        let mut fs = lock();
+        for dir in path.as_ref().ancestors() {
+            fs.dirs.insert(dir.to_path_buf());
+        }
+        Ok(())
+    }
+
+    /// Whether a file or directory exists at the given path.
+    pub(crate) fn exists<P: AsRef<Path>>(path: P) -> bool {
+        let fs = lock();
+        fs.files.contains_key(path.as_ref()) || fs.dirs.contains(path.as_ref())
+    }
+
+    /// Read a file.
+    pub(crate) fn read<P: AsRef<Path>>(path: P) -> io::Result<Vec<u8>> {
+        let path = path.as_ref();
+        lock()
+            .files
+            .get(path)
+            .map(|file| file.contents.clone())
+            .ok_or_else(|| not_found(path))
+    }
+
+    /// Write a file, replacing its contents if it exists.
+    pub(crate) fn write<P: AsRef<Path>, C: AsRef<[u8]>>(path: P, contents: C) -> io::Result<()> {
+        lock().file_mut(path.as_ref())?.contents = contents.as_ref().to_vec();
+        Ok(())
+    }
+
+    /// Append to a file, creating it if it doesn't exist.
+    pub(crate) fn append<P: AsRef<Path>, C: AsRef<[u8]>>(path: P, contents: C) -> io::Result<()> {
+        lock()
+            .file_mut(path.as_ref())?
+            .contents
+            .extend_from_slice(contents.as_ref());
+        Ok(())
+    }
+
+    /// Write a file like `write`. There is no disk to wait for.
+    pub(crate) fn write_synced<P: AsRef<Path>, C: AsRef<[u8]>>(
+        path: P,
+        contents: C,
+    ) -> io::Result<()> {
+        write(path, contents)
+    }
+
+    /// Append to a file like `append`. There is no disk to wait for.
+    pub(crate) fn append_synced<P: AsRef<Path>, C: AsRef<[u8]>>(
+        path: P,
+        contents: C,
+    ) -> io::Result<()> {
+        append(path, contents)
+    }
+
+    /// The size of a file, in bytes.
+    pub(crate) fn file_len<P: AsRef<Path>>(path: P) -> io::Result<u64> {
+        let path = path.as_ref();
+        lock()
+            .files
+            .get(path)
+            .map(|file| file.contents.len() as u64)
+            .ok_or_else(|| not_found(path))
+    }
+
+    /// Move a file, replacing the destination if it exists.
+    pub(crate) fn rename<P: AsRef<Path>, Q: AsRef<Path>>(from: P, to: Q) -> io::Result<()> {
+        let (from, to) = (from.as_ref(), to.as_ref());
+        let mut fs = lock();
+        fs.check_parent(to)?;
+        let file = fs.files.remove(from).ok_or_else(|| not_found(from))?;
+        fs.files.insert(to.to_path_buf(), file);
+        Ok(())
+    }
+
+    /// Remove a file.
+    pub(crate) fn remove_file<P: AsRef<Path>>(path: P) -> io::Result<()> {
+        let path = path.as_ref();
+        lock()
+            .files
+            .remove(path)
+            .map(|_| ())
+            .ok_or_else(|| not_found(path))
+    }
+
+    /// Remove a directory with everything in it.
+    pub(crate) fn remove_dir_all<P: AsRef<Path>>(path: P) -> io::Result<()> {
+        let path = path.as_ref();
+        let mut fs = lock();
+        if !fs.dirs.contains(path) {
+            return Err(not_found(path));
+        }
+        let dirs = std::mem::take(&mut fs.dirs);
+        fs.dirs = dirs
+            .into_iter()
+            .filter(|dir| !dir.starts_with(path))
+            .collect();
+        let files = std::mem::take(&mut fs.files);
+        fs.files = files
+            .into_iter()
+            .filter(|(file, _)| !file.starts_with(path))
+            .collect();
+        Ok(())
+    }
+
+    /// List the subdirectories of a directory.
+    pub(crate) fn list_dirs<P: AsRef<Path>>(dir: P) -> io::Result<Vec<PathBuf>> {
+        let dir = dir.as_ref();
+        let fs = lock();
+        if !fs.dirs.contains(dir) {
+            return Err(not_found(dir));
+        }
+        Ok(fs
+            .dirs
+            .iter()
+            .filter(|path| path.parent() == Some(dir))
+            .cloned()
+            .collect())
+    }
+
+    /// List the files in a directory.
+    pub(crate) fn list_files<P: AsRef<Path>>(dir: P) -> io::Result<Vec<DirEntry>> {
+        let dir = dir.as_ref();
+        let fs = lock();
+        if !fs.dirs.contains(dir) {
+            return Err(not_found(dir));
+        }
+        Ok(fs
+            .files
+            .iter()
+            .filter(|(path, _)| path.parent() == Some(dir))
+            .map(|(path, file)| DirEntry {
+                path: path.clone(),
+                len: file.contents.len() as u64,
+                modified: Some(file.modified),
+            })
+            .collect())
+    }
+}
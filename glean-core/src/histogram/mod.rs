@@ -99,6 +99,18 @@ impl<B: Bucketing> Histogram<B> {
         self.count += 1;
     }
 
+    /// Merge the samples of another histogram into this one.
+    ///
+    /// Both histograms need to use the same bucketing.
+    pub fn merge(&mut self, other: &Histogram<B>) {
+        for (&bucket_min, &count) in other.values.iter() {
+            let entry = self.values.entry(bucket_min).or_insert(0);
+            *entry += count;
+        }
+        self.sum = self.sum.saturating_add(other.sum);
+        self.count += other.count;
+    }
+
     /// Get the total sum of values recorded in this histogram.
     pub fn sum(&self) -> u64 {
         self.sum
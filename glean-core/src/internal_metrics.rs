@@ -10,6 +10,14 @@ pub struct CoreMetrics {
     pub first_run_date: DatetimeMetric,
     pub os: StringMetric,
     pub ping_upload_failure: LabeledMetric<CounterMetric>,
+    pub ping_compression: LabeledMetric<CounterMetric>,
+    pub compression_ratio: CustomDistributionMetric,
+    pub network_wait_time: TimingDistributionMetric,
+    pub pending_pings: QuantityMetric,
+    pub pending_pings_directory_size: MemoryDistributionMetric,
+    pub time_to_upload: TimingDistributionMetric,
+    pub throttled: CounterMetric,
+    pub uploaded_bytes: LabeledMetric<CounterMetric>,
 }
 
 impl CoreMetrics {
@@ -62,6 +70,99 @@ impl CoreMetrics {
                     "recoverable".into(),
                 ]),
             ),
+
+            ping_compression: LabeledMetric::new(
+                CounterMetric::new(CommonMetricData {
+                    name: "ping_compression".into(),
+                    category: "glean.upload".into(),
+                    send_in_pings: vec!["metrics".into()],
+                    lifetime: Lifetime::Ping,
+                    disabled: false,
+                    dynamic_label: None,
+                }),
+                Some(vec!["none".into(), "gzip".into(), "zstd".into()]),
+            ),
+
+            compression_ratio: CustomDistributionMetric::new(
+                CommonMetricData {
+                    name: "compression_ratio".into(),
+                    category: "glean.upload".into(),
+                    send_in_pings: vec!["metrics".into()],
+                    lifetime: Lifetime::Ping,
+                    disabled: false,
+                    dynamic_label: None,
+                },
+                0,
+                100,
+                50,
+                HistogramType::Linear,
+            ),
+
+            network_wait_time: TimingDistributionMetric::new(
+                CommonMetricData {
+                    name: "network_wait_time".into(),
+                    category: "glean.upload".into(),
+                    send_in_pings: vec!["metrics".into()],
+                    lifetime: Lifetime::Ping,
+                    disabled: false,
+                    dynamic_label: None,
+                },
+                TimeUnit::Millisecond,
+            ),
+
+            pending_pings: QuantityMetric::new(CommonMetricData {
+                name: "pending_pings".into(),
+                category: "glean.upload".into(),
+                send_in_pings: vec!["metrics".into()],
+                lifetime: Lifetime::Ping,
+                disabled: false,
+                dynamic_label: None,
+            }),
+
+            pending_pings_directory_size: MemoryDistributionMetric::new(
+                CommonMetricData {
+                    name: "pending_pings_directory_size".into(),
+                    category: "glean.upload".into(),
+                    send_in_pings: vec!["metrics".into()],
+                    lifetime: Lifetime::Ping,
+                    disabled: false,
+                    dynamic_label: None,
+                },
+                MemoryUnit::Kilobyte,
+            ),
+
+            time_to_upload: TimingDistributionMetric::new(
+                CommonMetricData {
+                    name: "time_to_upload".into(),
+                    category: "glean.upload".into(),
+                    send_in_pings: vec!["metrics".into()],
+                    lifetime: Lifetime::Ping,
+                    disabled: false,
+                    dynamic_label: None,
+                },
+                TimeUnit::Millisecond,
+            ),
+
+            throttled: CounterMetric::new(CommonMetricData {
+                name: "throttled".into(),
+                category: "glean.upload".into(),
+                send_in_pings: vec!["metrics".into()],
+                lifetime: Lifetime::Ping,
+                disabled: false,
+                dynamic_label: None,
+            }),
+
+            uploaded_bytes: LabeledMetric::new(
+                CounterMetric::new(CommonMetricData {
+                    name: "uploaded_bytes".into(),
+                    category: "glean.upload".into(),
+                    send_in_pings: vec!["metrics".into()],
+                    lifetime: Lifetime::Ping,
+                    disabled: false,
+                    dynamic_label: None,
+                }),
+                None,
+            ),
         }
     }
 }
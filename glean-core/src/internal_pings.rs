@@ -10,10 +10,15 @@ use super::metrics::PingType;
 /// This might get auto-generated when the Rust API lands ([Bug 1579146](https://bugzilla.mozilla.org/show_bug.cgi?id=1579146)).
 ///
 /// They are parsed and registered by the platform-specific wrappers, but might be used Glean-internal directly.
+/// Only the `deletion-request` ping is used internally, so there are no others in WebAssembly builds,
+/// which have no such wrappers.
 #[derive(Debug)]
 pub struct InternalPings {
+    #[cfg(not(feature = "wasm"))]
     pub baseline: PingType,
+    #[cfg(not(feature = "wasm"))]
     pub metrics: PingType,
+    #[cfg(not(feature = "wasm"))]
     pub events: PingType,
     pub deletion_request: PingType,
 }
@@ -21,7 +26,9 @@ pub struct InternalPings {
 impl InternalPings {
     pub fn new() -> InternalPings {
         InternalPings {
+            #[cfg(not(feature = "wasm"))]
             baseline: PingType::new("baseline", true, false, vec![]),
+            #[cfg(not(feature = "wasm"))]
             metrics: PingType::new(
                 "metrics",
                 true,
@@ -34,6 +41,7 @@ impl InternalPings {
                     "upgrade".to_string(),
                 ],
             ),
+            #[cfg(not(feature = "wasm"))]
             events: PingType::new("events", true, false, vec![]),
             deletion_request: PingType::new("deletion-request", true, true, vec![]),
         }
@@ -0,0 +1,329 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Recording from child processes.
+//!
+//! A Glean object created with [`Glean::new_for_ipc_child`](../struct.Glean.html#method.new_for_ipc_child)
+//! never opens the database.
+//! Instead all recorded data is kept in an in-memory buffer of metric deltas:
+//! counter increments, accumulated histogram samples, events and plain values.
+//!
+//! The buffer is serialized with bincode and handed to the caller,
+//! which is responsible for transporting it to the main process.
+//! There the payload is merged into the main storage using
+//! [`Glean::apply_ipc_payload`](../struct.Glean.html#method.apply_ipc_payload).
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+
+use crate::metrics::{combine_base_identifier_and_label, Metric, MAX_LIST_LENGTH};
+use crate::CommonMetricData;
+use crate::Glean;
+use crate::Result;
+
+/// How a buffered value needs to be applied to the main process storage.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+enum Operation {
+    /// The value replaces whatever is stored.
+    Set,
+    /// The value is a delta that is merged with what is stored.
+    Accumulate,
+}
+
+/// A single buffered metric value.
+#[derive(Debug, Serialize, Deserialize)]
+struct BufferedMetric {
+    meta: CommonMetricData,
+    operation: Operation,
+    value: Metric,
+}
+
+/// A single buffered event.
+#[derive(Debug, Serialize, Deserialize)]
+struct BufferedEvent {
+    meta: CommonMetricData,
+    timestamp: u64,
+    extra: Option<HashMap<String, String>>,
+}
+
+/// The data recorded in a child process since the last payload was taken.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct IpcPayload {
+    /// Buffered metrics, keyed by their identifier (including any unvalidated dynamic label).
+    metrics: HashMap<String, BufferedMetric>,
+    /// Buffered events, in recording order.
+    events: Vec<BufferedEvent>,
+}
+
+impl IpcPayload {
+    fn is_empty(&self) -> bool {
+        self.metrics.is_empty() && self.events.is_empty()
+    }
+}
+
+/// The in-memory buffer a child process records into.
+#[derive(Debug, Default)]
+pub(crate) struct IpcBuffer {
+    payload: Mutex<IpcPayload>,
+}
+
+/// The buffer key for a metric.
+///
+/// Dynamic labels can only be validated against the main process storage,
+/// so they are kept as-is and resolved when the payload is applied.
+fn buffer_key(meta: &CommonMetricData) -> String {
+    let base_identifier = meta.base_identifier();
+    match &meta.dynamic_label {
+        Some(label) => combine_base_identifier_and_label(&base_identifier, label),
+        None => base_identifier,
+    }
+}
+
+/// Merge a buffered delta into a value from the main process storage.
+///
+/// Counters are summed up, distributions merge their buckets and string lists are extended
+/// up to their maximum length.
+/// Any other metric type is replaced by the delta.
+fn merge(old_value: Option<Metric>, delta: Metric) -> Metric {
+    match (old_value, delta) {
+        (Some(Metric::Counter(old)), Metric::Counter(delta)) => {
+            Metric::Counter(old.saturating_add(delta))
+        }
+        (Some(Metric::TimingDistribution(mut old)), Metric::TimingDistribution(delta)) => {
+            old.merge(&delta);
+            Metric::TimingDistribution(old)
+        }
+        (Some(Metric::MemoryDistribution(mut old)), Metric::MemoryDistribution(delta)) => {
+            old.merge(&delta);
+            Metric::MemoryDistribution(old)
+        }
+        (
+            Some(Metric::CustomDistributionExponential(mut old)),
+            Metric::CustomDistributionExponential(delta),
+        ) => {
+            old.merge(&delta);
+            Metric::CustomDistributionExponential(old)
+        }
+        (
+            Some(Metric::CustomDistributionLinear(mut old)),
+            Metric::CustomDistributionLinear(delta),
+        ) => {
+            old.merge(&delta);
+            Metric::CustomDistributionLinear(old)
+        }
+        (Some(Metric::StringList(mut old)), Metric::StringList(delta)) => {
+            old.extend(delta);
+            old.truncate(MAX_LIST_LENGTH);
+            Metric::StringList(old)
+        }
+        (_, delta) => delta,
+    }
+}
+
+impl IpcBuffer {
+    /// Buffer a value that replaces any previously recorded value.
+    pub fn record(&self, meta: &CommonMetricData, value: &Metric) {
+        let mut payload = self.payload.lock().unwrap(); // safe unwrap, only error case is poisoning
+        payload.metrics.insert(
+            buffer_key(meta),
+            BufferedMetric {
+                meta: meta.clone(),
+                operation: Operation::Set,
+                value: value.clone(),
+            },
+        );
+    }
+
+    /// Buffer a delta, applying the transformation function to the delta buffered so far.
+    ///
+    /// If a value was `Set` before, the result keeps replacing the stored value.
+    pub fn record_with<F>(&self, meta: &CommonMetricData, mut transform: F)
+    where
+        F: FnMut(Option<Metric>) -> Metric,
+    {
+        let mut payload = self.payload.lock().unwrap(); // safe unwrap, only error case is poisoning
+        let key = buffer_key(meta);
+        let (operation, old_value) = match payload.metrics.remove(&key) {
+            Some(buffered) => (buffered.operation, Some(buffered.value)),
+            None => (Operation::Accumulate, None),
+        };
+        payload.metrics.insert(
+            key,
+            BufferedMetric {
+                meta: meta.clone(),
+                operation,
+                value: transform(old_value),
+            },
+        );
+    }
+
+    /// Buffer an event.
+    pub fn record_event(
+        &self,
+        meta: &CommonMetricData,
+        timestamp: u64,
+        extra: Option<HashMap<String, String>>,
+    ) {
+        let mut payload = self.payload.lock().unwrap(); // safe unwrap, only error case is poisoning
+        payload.events.push(BufferedEvent {
+            meta: meta.clone(),
+            timestamp,
+            extra,
+        });
+    }
+
+    /// Drop all buffered data.
+    pub fn clear(&self) {
+        let mut payload = self.payload.lock().unwrap(); // safe unwrap, only error case is poisoning
+        *payload = IpcPayload::default();
+    }
+
+    /// Serialize the buffered data and clear the buffer.
+    ///
+    /// ## Return value
+    ///
+    /// Returns `None` if nothing was recorded since the last call.
+    pub fn take_payload(&self) -> Result<Option<Vec<u8>>> {
+        let mut payload = self.payload.lock().unwrap(); // safe unwrap, only error case is poisoning
+        if payload.is_empty() {
+            return Ok(None);
+        }
+
+        let encoded = bincode::serialize(&*payload)?;
+        *payload = IpcPayload::default();
+        Ok(Some(encoded))
+    }
+}
+
+/// Merge a serialized payload from a child process into the storage of the given Glean object.
+///
+/// Metrics are applied first, then the events in the order they were recorded.
+pub(crate) fn apply_payload(glean: &Glean, buf: &[u8]) -> Result<()> {
+    let payload: IpcPayload = bincode::deserialize(buf)?;
+
+    for buffered in payload.metrics.values() {
+        if !buffered.meta.should_record() {
+            continue;
+        }
+
+        match buffered.operation {
+            Operation::Set => glean
+                .storage()
+                .record(glean, &buffered.meta, &buffered.value),
+            Operation::Accumulate => {
+                glean
+                    .storage()
+                    .record_with(glean, &buffered.meta, |old_value| {
+                        merge(old_value, buffered.value.clone())
+                    })
+            }
+        }
+    }
+
+    for event in payload.events {
+        glean
+            .event_storage()
+            .record(glean, &event.meta, event.timestamp, event.extra);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::histogram::{Functional, Histogram};
+
+    #[test]
+    fn deltas_are_accumulated_in_the_buffer() {
+        let buffer = IpcBuffer::default();
+        let meta = CommonMetricData::new("telemetry", "counter", "store1");
+
+        for _ in 0..3 {
+            buffer.record_with(&meta, |old_value| match old_value {
+                Some(Metric::Counter(old)) => Metric::Counter(old + 2),
+                _ => Metric::Counter(2),
+            });
+        }
+
+        let payload = buffer.payload.lock().unwrap();
+        let buffered = &payload.metrics["telemetry.counter"];
+        assert_eq!(Operation::Accumulate, buffered.operation);
+        assert_eq!(Metric::Counter(6), buffered.value);
+    }
+
+    #[test]
+    fn set_values_replace_previous_deltas() {
+        let buffer = IpcBuffer::default();
+        let meta = CommonMetricData::new("telemetry", "list", "store1");
+
+        buffer.record_with(&meta, |_| Metric::StringList(vec!["a".into()]));
+        buffer.record(&meta, &Metric::StringList(vec!["b".into()]));
+        buffer.record_with(&meta, |old_value| match old_value {
+            Some(Metric::StringList(mut old)) => {
+                old.push("c".into());
+                Metric::StringList(old)
+            }
+            _ => panic!("Expected the previously set value"),
+        });
+
+        let payload = buffer.payload.lock().unwrap();
+        let buffered = &payload.metrics["telemetry.list"];
+        assert_eq!(Operation::Set, buffered.operation);
+        assert_eq!(
+            Metric::StringList(vec!["b".into(), "c".into()]),
+            buffered.value
+        );
+    }
+
+    #[test]
+    fn dynamic_labels_are_kept_unresolved() {
+        let buffer = IpcBuffer::default();
+        let meta = CommonMetricData {
+            dynamic_label: Some("some_label".into()),
+            ..CommonMetricData::new("telemetry", "labeled", "store1")
+        };
+
+        buffer.record_with(&meta, |_| Metric::Counter(1));
+
+        let payload = buffer.payload.lock().unwrap();
+        assert!(payload.metrics.contains_key("telemetry.labeled/some_label"));
+    }
+
+    #[test]
+    fn taking_the_payload_clears_the_buffer() {
+        let buffer = IpcBuffer::default();
+        let meta = CommonMetricData::new("telemetry", "counter", "store1");
+
+        assert!(buffer.take_payload().unwrap().is_none());
+
+        buffer.record_with(&meta, |_| Metric::Counter(1));
+        buffer.record_event(&meta, 1000, None);
+
+        assert!(buffer.take_payload().unwrap().is_some());
+        assert!(buffer.take_payload().unwrap().is_none());
+    }
+
+    #[test]
+    fn distributions_are_merged() {
+        let mut old: Histogram<Functional> = Histogram::functional(2.0, 8.0);
+        old.accumulate(10);
+        let mut delta: Histogram<Functional> = Histogram::functional(2.0, 8.0);
+        delta.accumulate(10);
+        delta.accumulate(1000);
+
+        match merge(
+            Some(Metric::TimingDistribution(old)),
+            Metric::TimingDistribution(delta),
+        ) {
+            Metric::TimingDistribution(hist) => {
+                assert_eq!(3, hist.count());
+                assert_eq!(1020, hist.sum());
+            }
+            _ => panic!("Wrong metric type"),
+        }
+    }
+}
@@ -13,14 +13,21 @@
 //! ## [The Glean SDK Book](https://mozilla.github.io/glean)
 
 use std::collections::HashMap;
+use std::convert::TryFrom;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
 
 use chrono::{DateTime, FixedOffset};
 use once_cell::sync::Lazy;
 use once_cell::sync::OnceCell;
+use serde_json::Value as JsonValue;
 use std::sync::Mutex;
 use uuid::Uuid;
 
+#[cfg(all(target_arch = "wasm32", not(feature = "wasm")))]
+compile_error!("Building for WebAssembly requires the `wasm` feature.");
+
 // This needs to be included first, and the space below prevents rustfmt from
 // alphabetizing it.
 mod macros;
@@ -28,14 +35,18 @@ mod macros;
 mod common_metric_data;
 mod database;
 mod debug;
+mod encryption;
 mod error;
 mod error_recording;
 mod event_database;
+mod fs;
 mod histogram;
 mod internal_metrics;
 mod internal_pings;
+mod ipc;
 pub mod metrics;
 pub mod ping;
+mod state_archive;
 pub mod storage;
 mod system;
 pub mod upload;
@@ -43,16 +54,26 @@ mod util;
 
 pub use crate::common_metric_data::{CommonMetricData, Lifetime};
 use crate::database::Database;
-use crate::debug::DebugOptions;
+pub use crate::database::StorageDurability;
+pub use crate::debug::DebugTagLimits;
+use crate::debug::{DebugFileWatcher, DebugOptions, DebugTag, TagScope};
+use crate::encryption::Encryption;
+pub use crate::encryption::{EncryptionKey, KeyProvider};
 pub use crate::error::{Error, ErrorKind, Result};
 pub use crate::error_recording::{test_get_num_recorded_errors, ErrorType};
 use crate::event_database::EventDatabase;
 use crate::internal_metrics::CoreMetrics;
 use crate::internal_pings::InternalPings;
+use crate::ipc::IpcBuffer;
 use crate::metrics::{Metric, MetricType, PingType};
 use crate::ping::PingMaker;
+pub use crate::ping::{PingObserver, PingStage};
+pub use crate::state_archive::ImportPolicy;
 use crate::storage::StorageManager;
-use crate::upload::{PingUploadManager, PingUploadTask, UploadResult};
+use crate::upload::{
+    strip_reserved_headers, validate_server_endpoint, BatchLimits, HeaderMap, PathTemplate,
+    PathValues, PingCompression, PingRequest, PingUploadManager, PingUploadTask, UploadResult,
+};
 use crate::util::{local_now_with_offset, sanitize_application_id};
 
 const GLEAN_VERSION: &str = env!("CARGO_PKG_VERSION");
@@ -125,6 +146,27 @@ pub struct Configuration {
     pub max_events: Option<usize>,
     /// Whether Glean should delay persistence of data from metrics with ping lifetime.
     pub delay_ping_lifetime_io: bool,
+    /// Provides the keys to encrypt all data stored on disk.
+    /// If `None`, data is stored unencrypted.
+    pub key_provider: Option<Arc<dyn KeyProvider>>,
+    /// The limits for batching multiple pings into a single upload task.
+    /// If `None`, every upload task carries a single ping.
+    pub upload_batch_limits: Option<BatchLimits>,
+    /// The compression applied to ping bodies before upload.
+    pub ping_compression: PingCompression,
+    /// The base URL of the server to upload pings to, e.g. `https://collector.example.com`.
+    /// If `None`, the uploader uses its own default.
+    pub server_endpoint: Option<String>,
+    /// The template for the URL path of pings, with the placeholders `{app_id}`, `{ping}`,
+    /// `{schema_version}`, `{doc_id}` and `{channel}`.
+    /// Channels with characters other than ASCII letters, digits, `.`, `_` and `-` are
+    /// filled in as `unknown`.
+    /// If `None`, the path of the Mozilla ingestion pipeline is used:
+    /// `/submit/{app_id}/{ping}/{schema_version}/{doc_id}`.
+    pub path_template: Option<String>,
+    /// When recorded metrics are written to disk.
+    /// By default every recording is written immediately, see `StorageDurability`.
+    pub storage_durability: StorageDurability,
 }
 
 /// The object holding meta information about a Glean instance.
@@ -143,6 +185,12 @@ pub struct Configuration {
 ///     upload_enabled: true,
 ///     max_events: None,
 ///     delay_ping_lifetime_io: false,
+///     key_provider: None,
+///     upload_batch_limits: None,
+///     ping_compression: Default::default(),
+///     server_endpoint: None,
+///     path_template: None,
+///     storage_durability: Default::default(),
 /// };
 /// let mut glean = Glean::new(cfg).unwrap();
 /// let ping = PingType::new("sample", true, false, vec![]);
@@ -179,6 +227,12 @@ pub struct Glean {
     is_first_run: bool,
     upload_manager: PingUploadManager,
     debug: DebugOptions,
+    ipc_buffer: Option<IpcBuffer>,
+    encryption: Encryption,
+    upload_headers: HeaderMap,
+    server_endpoint: Option<String>,
+    path_template: PathTemplate,
+    ping_observers: Vec<Box<dyn PingObserver>>,
 }
 
 impl Glean {
@@ -192,18 +246,40 @@ impl Glean {
         if application_id.is_empty() {
             return Err(ErrorKind::InvalidConfig.into());
         }
+        let server_endpoint = cfg
+            .server_endpoint
+            .as_deref()
+            .map(validate_server_endpoint)
+            .transpose()?;
+        let path_template = match &cfg.path_template {
+            Some(template) => PathTemplate::parse(template)?,
+            None => PathTemplate::default(),
+        };
+
+        let encryption = Encryption::new(cfg.key_provider.clone());
 
         // Creating the data store creates the necessary path as well.
         // If that fails we bail out and don't initialize further.
-        let data_store = Some(Database::new(&cfg.data_path, cfg.delay_ping_lifetime_io)?);
-        let event_data_store = EventDatabase::new(&cfg.data_path)?;
+        let data_store = Some(Database::with_options(
+            &cfg.data_path,
+            cfg.delay_ping_lifetime_io,
+            cfg.storage_durability,
+            encryption.clone(),
+        )?);
+        let event_data_store = EventDatabase::with_encryption(&cfg.data_path, encryption.clone())?;
 
         // Create an upload manager with rate limiting of 10 pings every 60 seconds.
-        let mut upload_manager =
-            PingUploadManager::new(&cfg.data_path, &cfg.language_binding_name, false);
+        let mut upload_manager = PingUploadManager::with_settings(
+            &cfg.data_path,
+            &cfg.language_binding_name,
+            false,
+            encryption.clone(),
+            cfg.ping_compression,
+        );
         upload_manager.set_rate_limiter(
             /* seconds per interval */ 60, /* max tasks per interval */ 10,
         );
+        upload_manager.set_batch_limits(cfg.upload_batch_limits);
 
         Ok(Self {
             upload_enabled: cfg.upload_enabled,
@@ -219,6 +295,66 @@ impl Glean {
             max_events: cfg.max_events.unwrap_or(DEFAULT_MAX_EVENTS),
             is_first_run: false,
             debug: DebugOptions::new(),
+            ipc_buffer: None,
+            upload_headers: HeaderMap::new(),
+            server_endpoint,
+            path_template,
+            ping_observers: Vec::new(),
+            encryption,
+        })
+    }
+
+    /// Create and initialize a new Glean object for use in a child process.
+    ///
+    /// Unlike [`new_for_subprocess`](#method.new_for_subprocess), this does not open the database.
+    /// All data is recorded into an in-memory buffer instead,
+    /// which needs to be taken with [`take_ipc_payload`](#method.take_ipc_payload),
+    /// sent to the main process and applied there with
+    /// [`apply_ipc_payload`](#method.apply_ipc_payload).
+    ///
+    /// A Glean object in a child process never submits or uploads any pings.
+    /// Its test APIs are not available, as there is no storage to query.
+    pub fn new_for_ipc_child(cfg: &Configuration) -> Result<Self> {
+        log::info!("Creating new Glean v{} for a child process", GLEAN_VERSION);
+
+        let application_id = sanitize_application_id(&cfg.application_id);
+        if application_id.is_empty() {
+            return Err(ErrorKind::InvalidConfig.into());
+        }
+        let server_endpoint = cfg
+            .server_endpoint
+            .as_deref()
+            .map(validate_server_endpoint)
+            .transpose()?;
+        let path_template = match &cfg.path_template {
+            Some(template) => PathTemplate::parse(template)?,
+            None => PathTemplate::default(),
+        };
+
+        let encryption = Encryption::new(cfg.key_provider.clone());
+        let event_data_store = EventDatabase::with_encryption(&cfg.data_path, encryption.clone())?;
+        let upload_manager = PingUploadManager::no_scan(&cfg.data_path, &cfg.language_binding_name);
+
+        Ok(Self {
+            upload_enabled: cfg.upload_enabled,
+            data_store: None,
+            event_data_store,
+            core_metrics: CoreMetrics::new(),
+            internal_pings: InternalPings::new(),
+            upload_manager,
+            data_path: PathBuf::from(&cfg.data_path),
+            application_id,
+            ping_registry: HashMap::new(),
+            start_time: local_now_with_offset(),
+            max_events: cfg.max_events.unwrap_or(DEFAULT_MAX_EVENTS),
+            is_first_run: false,
+            debug: DebugOptions::new(),
+            ipc_buffer: Some(IpcBuffer::default()),
+            upload_headers: HeaderMap::new(),
+            server_endpoint,
+            path_template,
+            ping_observers: Vec::new(),
+            encryption,
         })
     }
 
@@ -260,6 +396,8 @@ impl Glean {
             }
         }
 
+        glean.restore_debug_tags();
+
         Ok(glean)
     }
 
@@ -277,6 +415,12 @@ impl Glean {
             upload_enabled,
             max_events: None,
             delay_ping_lifetime_io: false,
+            key_provider: None,
+            upload_batch_limits: None,
+            ping_compression: Default::default(),
+            server_endpoint: None,
+            path_template: None,
+            storage_durability: Default::default(),
         };
 
         Self::new(cfg)
@@ -327,6 +471,10 @@ impl Glean {
     ///
     /// `true` if at least one ping was generated, `false` otherwise.
     pub fn on_ready_to_submit_pings(&self) -> bool {
+        if self.ipc_buffer.is_some() {
+            return false;
+        }
+
         self.event_data_store.flush_pending_events_on_startup(&self)
     }
 
@@ -352,6 +500,17 @@ impl Glean {
     pub fn set_upload_enabled(&mut self, flag: bool) -> bool {
         log::info!("Upload enabled: {:?}", flag);
 
+        // A child process has no storage of its own to clear or reinstate.
+        // The main process handles the state change on its own.
+        if let Some(buffer) = &self.ipc_buffer {
+            if !flag {
+                buffer.clear();
+            }
+            let changed = self.upload_enabled != flag;
+            self.upload_enabled = flag;
+            return changed;
+        }
+
         if self.upload_enabled != flag {
             if flag {
                 self.on_upload_enabled();
@@ -479,6 +638,158 @@ impl Glean {
         &self.event_data_store
     }
 
+    /// Get the encryption used for all data stored on disk.
+    pub(crate) fn encryption(&self) -> &Encryption {
+        &self.encryption
+    }
+
+    /// Re-encrypt all data stored on disk with the current encryption key.
+    ///
+    /// This rewrites the stored metrics, events and pending pings.
+    /// Afterwards, keys retired in the [`KeyProvider`](trait.KeyProvider.html)
+    /// are no longer needed.
+    /// Data that cannot be decrypted with any available key is left as-is.
+    ///
+    /// Does nothing if no key provider is configured.
+    ///
+    /// ## Return value
+    ///
+    /// Returns an error if stored data cannot be rewritten.
+    pub fn reencrypt_storage(&self) -> Result<()> {
+        if let Some(data) = &self.data_store {
+            data.reencrypt()?;
+            self.event_data_store.reencrypt()?;
+            self.upload_manager.reencrypt_pending_pings()?;
+        }
+        Ok(())
+    }
+
+    /// Get the IPC buffer if this is a Glean object for a child process.
+    pub(crate) fn ipc_buffer(&self) -> Option<&IpcBuffer> {
+        self.ipc_buffer.as_ref()
+    }
+
+    /// Records a metric.
+    ///
+    /// In a child process the value is buffered, otherwise it is written to the database.
+    pub(crate) fn record_metric(&self, meta: &CommonMetricData, value: &Metric) {
+        match &self.ipc_buffer {
+            Some(buffer) => buffer.record(meta, value),
+            None => self.storage().record(self, meta, value),
+        }
+    }
+
+    /// Records a metric after applying the transformation function to the current value.
+    ///
+    /// In a child process the transformation is applied to the buffered delta,
+    /// otherwise to the value in the database.
+    pub(crate) fn record_metric_with<F>(&self, meta: &CommonMetricData, transform: F)
+    where
+        F: FnMut(Option<Metric>) -> Metric,
+    {
+        match &self.ipc_buffer {
+            Some(buffer) => buffer.record_with(meta, transform),
+            None => self.storage().record_with(self, meta, transform),
+        }
+    }
+
+    /// Take the data recorded in a child process since the last call.
+    ///
+    /// The returned payload needs to be sent to the main process by the caller,
+    /// e.g. over a pipe or any other byte channel,
+    /// and applied there with [`apply_ipc_payload`](#method.apply_ipc_payload).
+    ///
+    /// ## Return value
+    ///
+    /// Returns `None` if this is not a Glean object for a child process
+    /// or nothing was recorded since the last call.
+    /// Returns an error if serializing the data failed.
+    pub fn take_ipc_payload(&self) -> Result<Option<Vec<u8>>> {
+        match &self.ipc_buffer {
+            Some(buffer) => buffer.take_payload(),
+            None => Ok(None),
+        }
+    }
+
+    /// Merge a payload of data recorded in a child process into this Glean object's storage.
+    ///
+    /// Counters are incremented, distributions receive the child's samples,
+    /// events are appended and all other metrics take the child's value.
+    /// Dynamic labels are validated against this storage.
+    ///
+    /// If upload is disabled, the payload is discarded.
+    ///
+    /// ## Arguments
+    ///
+    /// * `buf` - A payload as returned from [`take_ipc_payload`](#method.take_ipc_payload).
+    ///
+    /// ## Return value
+    ///
+    /// Returns an error if the payload cannot be decoded.
+    /// No data is recorded in that case.
+    pub fn apply_ipc_payload(&self, buf: &[u8]) -> Result<()> {
+        if self.ipc_buffer.is_some() {
+            log::error!("IPC payloads can only be applied in the main process.");
+            return Ok(());
+        }
+
+        if !self.is_upload_enabled() {
+            log::info!("Glean disabled: discarding IPC payload.");
+            return Ok(());
+        }
+
+        ipc::apply_payload(self, buf)
+    }
+
+    /// Export the complete state of this instance to a file.
+    ///
+    /// The archive contains all stored metrics, including the client ID,
+    /// the first run date and the active experiments, all stored events
+    /// and all pings pending upload.
+    ///
+    /// ## Arguments
+    ///
+    /// * `path` - The file to write the archive to. An existing file is replaced.
+    ///
+    /// ## Return value
+    ///
+    /// Returns an error if the state cannot be read or the archive cannot be written.
+    pub fn export_state<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        if self.data_store.is_none() {
+            log::error!("State can only be exported in the main process.");
+            return Err(Error::not_initialized());
+        }
+
+        state_archive::export_state(self, path.as_ref())
+    }
+
+    /// Import a state archive created by [`export_state`](#method.export_state).
+    ///
+    /// If upload is disabled, nothing is imported.
+    ///
+    /// ## Arguments
+    ///
+    /// * `path` - The archive file.
+    /// * `policy` - Whether to merge the archive into the existing state or to replace it.
+    ///
+    /// ## Return value
+    ///
+    /// Returns an error if the archive is invalid or was written by an incompatible
+    /// version of Glean. Nothing is imported in that case.
+    pub fn import_state<P: AsRef<Path>>(&self, path: P, policy: ImportPolicy) -> Result<()> {
+        if self.data_store.is_none() {
+            log::error!("State can only be imported in the main process.");
+            return Err(Error::not_initialized());
+        }
+
+        if !self.is_upload_enabled() {
+            log::info!("Glean disabled: not importing state archive.");
+            return Ok(());
+        }
+
+        state_archive::import_state(self, path.as_ref(), policy)
+    }
+
     /// Get the maximum number of events to store before sending a ping.
     pub fn get_max_events(&self) -> usize {
         self.max_events
@@ -490,13 +801,86 @@ impl Glean {
     ///
     /// * `Wait` - which means the requester should ask again later;
     /// * `Upload(PingRequest)` - which means there is a ping to upload. This wraps the actual request object;
+    /// * `UploadBatch(Vec<PingRequest>)` - which means there are multiple pings to upload at once,
+    ///   if batching is enabled;
     /// * `Done` - which means there are no more pings queued right now.
     ///
     /// # Return value
     ///
     /// `PingUploadTask` - an enum representing the possible tasks.
     pub fn get_upload_task(&self) -> PingUploadTask {
-        self.upload_manager.get_upload_task(self.log_pings())
+        let task = self.upload_manager.get_upload_task(self.log_pings());
+        self.record_upload_queue_state();
+        let requests = match &task {
+            PingUploadTask::Upload(request) => std::slice::from_ref(request),
+            PingUploadTask::UploadBatch(requests) => &requests[..],
+            _ => return task,
+        };
+        for request in requests {
+            self.record_compression(request);
+            // Decompressing and parsing the body is only worth it if someone is listening.
+            if !self.ping_observers.is_empty() {
+                self.notify_ping_observers(
+                    PingStage::Uploading,
+                    request.ping_name.as_deref().unwrap_or_default(),
+                    &request.document_id,
+                    &request.path,
+                    &request.payload().unwrap_or(JsonValue::Null),
+                    &request.headers,
+                );
+            }
+        }
+
+        if let Some(wait_time) = self.upload_manager.take_network_wait_time() {
+            self.core_metrics
+                .network_wait_time
+                .accumulate_samples_signed(self, vec![wait_time.as_millis() as i64]);
+        }
+        task
+    }
+
+    /// Set the network conditions pings are uploaded under.
+    ///
+    /// While offline, `get_upload_task` returns `PingUploadTask::WaitFor` instead of pings.
+    /// While on a metered network, only pings that allow metered networks are handed out
+    /// (see `PingType::with_network_policy`).
+    /// The time pings are held back is recorded in the `glean.upload.network_wait_time` metric.
+    ///
+    /// ## Arguments
+    ///
+    /// * `online` - Whether the device is online.
+    /// * `metered` - Whether the device is on a metered network.
+    pub fn set_network_state(&self, online: bool, metered: bool) {
+        self.upload_manager.set_network_state(online, metered);
+    }
+
+    /// Record the state of the pending pings queue, as far as it changed since last recorded.
+    fn record_upload_queue_state(&self) {
+        let metrics = &self.core_metrics;
+        if let Some(stats) = self.upload_manager.take_scan_stats() {
+            metrics.pending_pings.set(self, stats.pending_pings as i64);
+            metrics
+                .pending_pings_directory_size
+                .accumulate(self, stats.directory_size / 1024);
+        }
+
+        let throttled = self.upload_manager.take_throttled_count();
+        if throttled > 0 {
+            metrics
+                .throttled
+                .add(self, i32::try_from(throttled).unwrap_or(i32::MAX));
+        }
+    }
+
+    /// Record the compression of a ping handed out for upload.
+    fn record_compression(&self, request: &PingRequest) {
+        let label = request.content_encoding().unwrap_or("none");
+        self.core_metrics.ping_compression.get(label).add(self, 1);
+        if let Some(percentage) = request.compressed_size_percentage() {
+            self.core_metrics
+                .compression_ratio
+                .accumulate_samples_signed(self, vec![percentage as i64]);
+        }
     }
 
     /// Processes the response from an attempt to upload a ping.
@@ -511,8 +895,48 @@ impl Glean {
             metric.add(self, 1);
         }
 
-        self.upload_manager
-            .process_ping_upload_response(uuid, status);
+        let sent = match self.upload_manager.process_upload_response(uuid, status) {
+            Some(sent) => sent,
+            None => return,
+        };
+
+        if let Some(ping_name) = &sent.ping_name {
+            self.core_metrics
+                .uploaded_bytes
+                .get(ping_name)
+                .add(self, i32::try_from(sent.body_size).unwrap_or(i32::MAX));
+        }
+        if let Some(submitted_at) = sent.submitted_at {
+            let elapsed = chrono::Utc::now().timestamp_millis() - submitted_at;
+            // Skip samples from clocks that went backwards.
+            if elapsed >= 0 {
+                self.core_metrics
+                    .time_to_upload
+                    .accumulate_samples_signed(self, vec![elapsed]);
+            }
+        }
+    }
+
+    /// Processes the responses from an attempt to upload a batch of pings.
+    ///
+    /// Only the pings that failed with a recoverable error are re-enqueued.
+    ///
+    /// # Arguments
+    ///
+    /// * `results` - The UUID of each ping in the batch and its upload result.
+    pub fn process_ping_upload_batch_response(&self, results: Vec<(String, UploadResult)>) {
+        for (uuid, status) in results {
+            self.process_ping_upload_response(&uuid, status);
+        }
+    }
+
+    /// Enables or disables batching of multiple pings into a single upload task.
+    ///
+    /// # Arguments
+    ///
+    /// * `limits` - The limits for each batch. If `None`, every upload task carries a single ping.
+    pub fn set_upload_batch_limits(&mut self, limits: Option<BatchLimits>) {
+        self.upload_manager.set_batch_limits(limits);
     }
 
     /// Take a snapshot for the given store and optionally clear it.
@@ -533,13 +957,36 @@ impl Glean {
     }
 
     fn make_path(&self, ping_name: &str, doc_id: &str) -> String {
-        format!(
-            "/submit/{}/{}/{}/{}",
-            self.get_application_id(),
-            ping_name,
-            GLEAN_SCHEMA_VERSION,
-            doc_id
-        )
+        self.path_template.render(&PathValues {
+            app_id: self.get_application_id(),
+            ping: ping_name,
+            schema_version: GLEAN_SCHEMA_VERSION,
+            doc_id,
+            channel: &self.app_channel(),
+        })
+    }
+
+    /// The release channel of the application, as recorded in the client info.
+    ///
+    /// Returns `unknown` if no channel was set.
+    fn app_channel(&self) -> String {
+        let channel = match self.data_store.as_ref() {
+            Some(data_store) => {
+                StorageManager.snapshot_metric(data_store, "glean_client_info", "app_channel")
+            }
+            None => None,
+        };
+        match channel {
+            Some(Metric::String(channel)) => channel,
+            _ => "unknown".into(),
+        }
+    }
+
+    /// Return the server endpoint to upload pings to, if one was configured.
+    ///
+    /// Pings are uploaded to this endpoint joined with the path of each `PingRequest`.
+    pub fn server_endpoint(&self) -> Option<&str> {
+        self.server_endpoint.as_deref()
     }
 
     /// Collect and submit a ping for eventual uploading.
@@ -562,6 +1009,22 @@ impl Glean {
             return Ok(false);
         }
 
+        if self.ipc_buffer.is_some() {
+            log::error!("Pings can't be submitted from a child process.");
+            return Ok(false);
+        }
+
+        // Write buffered recordings first, so that they end up in this ping
+        // and are not lost if the process dies after the ping was sent.
+        if let Some(data) = self.data_store.as_ref() {
+            if let Err(e) = data.flush_pending() {
+                log::error!(
+                    "Failed to write pending metrics before submitting a ping: {:?}",
+                    e
+                );
+            }
+        }
+
         let ping_maker = PingMaker::new();
         let doc_id = Uuid::new_v4().to_string();
         let url_path = self.make_path(&ping.name, &doc_id);
@@ -574,19 +1037,35 @@ impl Glean {
                 Ok(false)
             }
             Some(content) => {
+                // Computed once, before the ping counts against the limits of the debug tags,
+                // so that the stored ping and the observers see the same headers.
+                let headers = ping_maker.get_headers(self, ping);
                 if let Err(e) = ping_maker.store_ping(
                     self,
                     &doc_id,
-                    &ping.name,
+                    ping,
                     &self.get_data_path(),
                     &url_path,
                     &content,
+                    &headers,
                 ) {
-                    log::warn!("IO error while writing ping to file: {}", e);
-                    return Err(e.into());
+                    log::warn!("Error while writing ping to file: {}", e);
+                    return Err(e);
                 }
 
                 self.upload_manager.enqueue_ping_from_file(&doc_id);
+                self.count_tagged_ping(&ping.name);
+
+                if !self.ping_observers.is_empty() {
+                    self.notify_ping_observers(
+                        PingStage::Submitted,
+                        &ping.name,
+                        &doc_id,
+                        &url_path,
+                        &content,
+                        &headers,
+                    );
+                }
 
                 log::info!(
                     "The ping '{}' was submitted and will be sent as soon as possible",
@@ -670,6 +1149,11 @@ impl Glean {
     ///
     /// * `experiment_id` - The id of the active experiment to deactivate (maximum 30 bytes).
     pub fn set_experiment_inactive(&self, experiment_id: String) {
+        if self.ipc_buffer.is_some() {
+            log::error!("Experiments can't be deactivated from a child process.");
+            return;
+        }
+
         let metric = metrics::ExperimentMetric::new(&self, experiment_id);
         metric.set_inactive(&self);
     }
@@ -687,6 +1171,21 @@ impl Glean {
         Ok(())
     }
 
+    /// Write all data held in memory to disk.
+    ///
+    /// This writes recordings buffered because of `StorageDurability::Coalesced`
+    /// as well as ping lifetime data held in memory because of `delay_ping_lifetime_io`.
+    ///
+    /// If there is no data to write, this function does nothing.
+    pub fn flush(&self) -> Result<()> {
+        if let Some(data) = self.data_store.as_ref() {
+            data.flush_pending()?;
+            data.persist_ping_lifetime_data()?;
+        }
+
+        Ok(())
+    }
+
     /// Set internally-handled application lifetime metrics.
     fn set_application_lifetime_core_metrics(&self) {
         self.core_metrics.os.set(self, system::OS);
@@ -721,15 +1220,51 @@ impl Glean {
     ///
     /// * `value` - A valid HTTP header value. Must match the regex: "[a-zA-Z0-9-]{1,20}".
     pub fn set_debug_view_tag(&mut self, value: &str) -> bool {
-        self.debug.debug_view_tag.set(value.into())
+        if !self.debug.debug_view_tag.set(value.into()) {
+            return false;
+        }
+        self.set_debug_tag_scope(DebugTag::DebugViewTag, &[value.into()], None);
+        true
     }
 
-    /// Return the value for the debug view tag or `None` if it hasn't been set.
+    /// Set a debug view tag that is only applied within the given limits.
+    ///
+    /// Unlike a tag set with [`set_debug_view_tag`](#method.set_debug_view_tag),
+    /// the tag and its limits are persisted and restored when Glean is restarted,
+    /// until any of the limits is reached.
+    ///
+    /// This will return `false` in case `value` is not a valid tag or the limits are invalid.
+    ///
+    /// ## Arguments
     ///
-    /// The debug_view_tag may be set from an environment variable (GLEAN_DEBUG_VIEW_TAG)
-    /// or through the `set_debug_view_tag` function.
-    pub(crate) fn debug_view_tag(&self) -> Option<&String> {
-        self.debug.debug_view_tag.get()
+    /// * `value` - A valid HTTP header value. Must match the regex: "[a-zA-Z0-9-]{1,20}".
+    /// * `limits` - How long and to which pings the tag is applied.
+    pub fn set_debug_view_tag_with_limits(&mut self, value: &str, limits: DebugTagLimits) -> bool {
+        if !limits.is_valid() || !self.debug.debug_view_tag.set(value.into()) {
+            return false;
+        }
+        let scope = TagScope::new(limits, local_now_with_offset());
+        self.set_debug_tag_scope(DebugTag::DebugViewTag, &[value.into()], Some(scope));
+        true
+    }
+
+    /// Return the debug view tag to apply to the given ping, or `None` if it hasn't been set
+    /// or the ping is outside its limits.
+    ///
+    /// The debug view tag may be set from an environment variable (GLEAN_DEBUG_VIEW_TAG)
+    /// or through the `set_debug_view_tag` functions.
+    pub(crate) fn debug_view_tag_for(&self, ping_name: &str) -> Option<String> {
+        if let Some(tag) = self.debug.file_options().debug_view_tag {
+            return Some(tag);
+        }
+        self.debug
+            .debug_view_tag
+            .get()
+            .filter(|_| {
+                self.debug
+                    .tag_applies(DebugTag::DebugViewTag, ping_name, local_now_with_offset())
+            })
+            .cloned()
     }
 
     /// Set source tags.
@@ -742,15 +1277,167 @@ impl Glean {
     ///
     /// * `value` - A vector of at most 5 valid HTTP header values. Individual tags must match the regex: "[a-zA-Z0-9-]{1,20}".
     pub fn set_source_tags(&mut self, value: Vec<String>) -> bool {
-        self.debug.source_tags.set(value)
+        if !self.debug.source_tags.set(value.clone()) {
+            return false;
+        }
+        self.set_debug_tag_scope(DebugTag::SourceTags, &value, None);
+        true
     }
 
-    /// Return the value for the source tags or `None` if it hasn't been set.
+    /// Set source tags that are only applied within the given limits.
     ///
-    /// The source_tags may be set from an environment variable (GLEAN_SOURCE_TAGS)
-    /// or through the `set_source_tags` function.
-    pub(crate) fn source_tags(&self) -> Option<&Vec<String>> {
-        self.debug.source_tags.get()
+    /// Unlike tags set with [`set_source_tags`](#method.set_source_tags),
+    /// the tags and their limits are persisted and restored when Glean is restarted,
+    /// until any of the limits is reached.
+    ///
+    /// This will return `false` in case `value` contains invalid tags or the limits are invalid.
+    ///
+    /// ## Arguments
+    ///
+    /// * `value` - A vector of at most 5 valid HTTP header values. Individual tags must match the regex: "[a-zA-Z0-9-]{1,20}".
+    /// * `limits` - How long and to which pings the tags are applied.
+    pub fn set_source_tags_with_limits(
+        &mut self,
+        value: Vec<String>,
+        limits: DebugTagLimits,
+    ) -> bool {
+        if !limits.is_valid() || !self.debug.source_tags.set(value.clone()) {
+            return false;
+        }
+        let scope = TagScope::new(limits, local_now_with_offset());
+        self.set_debug_tag_scope(DebugTag::SourceTags, &value, Some(scope));
+        true
+    }
+
+    /// Return the source tags to apply to the given ping, or `None` if it hasn't been set
+    /// or the ping is outside its limits.
+    ///
+    /// The source tags may be set from an environment variable (GLEAN_SOURCE_TAGS)
+    /// or through the `set_source_tags` functions.
+    pub(crate) fn source_tags_for(&self, ping_name: &str) -> Option<Vec<String>> {
+        if let Some(tags) = self.debug.file_options().source_tags {
+            return Some(tags);
+        }
+        self.debug
+            .source_tags
+            .get()
+            .filter(|_| {
+                self.debug
+                    .tag_applies(DebugTag::SourceTags, ping_name, local_now_with_offset())
+            })
+            .cloned()
+    }
+
+    /// Replace the limits of a debug tag and persist them.
+    fn set_debug_tag_scope(&self, tag: DebugTag, value: &[String], scope: Option<TagScope>) {
+        debug::persist_tag(self, tag, value, scope.as_ref());
+        *self.debug.scope(tag).lock().unwrap() = scope;
+    }
+
+    /// Count a submitted ping against the limits of the debug tags applied to it.
+    fn count_tagged_ping(&self, ping_name: &str) {
+        let now = local_now_with_offset();
+        for &tag in &[DebugTag::DebugViewTag, DebugTag::SourceTags] {
+            let mut scope = self.debug.scope(tag).lock().unwrap();
+            if let Some(scope) = scope.as_mut() {
+                if !scope.applies_to(ping_name, now) {
+                    continue;
+                }
+                if let Some(remaining) = scope.remaining_pings.as_mut() {
+                    *remaining -= 1;
+                    debug::persist_remaining_pings(self, tag, *remaining);
+                }
+            }
+        }
+    }
+
+    /// Restore the debug tags persisted with their limits in a previous run.
+    ///
+    /// Tags set from the environment take precedence.
+    /// Persisted tags that are overridden, expired or used up are removed.
+    fn restore_debug_tags(&mut self) {
+        let now = local_now_with_offset();
+        for &tag in &[DebugTag::DebugViewTag, DebugTag::SourceTags] {
+            let (value, scope) = match debug::load_tag(self, tag) {
+                Some(persisted) => persisted,
+                None => continue,
+            };
+
+            let restored = scope.is_active(now)
+                && match tag {
+                    DebugTag::DebugViewTag => {
+                        self.debug.debug_view_tag.get().is_none()
+                            && value.len() == 1
+                            && self.debug.debug_view_tag.set(value[0].clone())
+                    }
+                    DebugTag::SourceTags => {
+                        self.debug.source_tags.get().is_none() && self.debug.source_tags.set(value)
+                    }
+                };
+
+            if restored {
+                log::info!("Restored the {:?} set with limits in a previous run.", tag);
+                *self.debug.scope(tag).lock().unwrap() = Some(scope);
+            } else {
+                debug::persist_tag(self, tag, &[], None);
+            }
+        }
+    }
+
+    /// Set custom HTTP headers to send with all pings submitted from now on.
+    ///
+    /// The headers are persisted with each ping, so they are also sent for pings
+    /// uploaded after a restart. Headers of a ping type (see `PingType::with_headers`)
+    /// take precedence over these.
+    ///
+    /// This will return `false` in case `headers` contains headers set by Glean itself
+    /// (e.g. `Content-Type` or `X-Debug-ID`), names that are not valid HTTP header names
+    /// or values containing CR, LF or NUL characters.
+    /// These are ignored, all other headers are set.
+    ///
+    /// ## Arguments
+    ///
+    /// * `headers` - A map of header names to values, replacing any previously set headers.
+    pub fn set_upload_headers(&mut self, mut headers: HeaderMap) -> bool {
+        let valid = strip_reserved_headers(&mut headers);
+        self.upload_headers = headers;
+        valid
+    }
+
+    /// Return the custom HTTP headers to send with all pings.
+    pub(crate) fn upload_headers(&self) -> &HeaderMap {
+        &self.upload_headers
+    }
+
+    /// Register an observer to be notified of every ping submitted and uploaded.
+    ///
+    /// See `PingObserver` for details.
+    ///
+    /// ## Arguments
+    ///
+    /// * `observer` - The observer to add to the registered observers.
+    pub fn add_ping_observer(&mut self, observer: Box<dyn PingObserver>) {
+        self.ping_observers.push(observer);
+    }
+
+    /// Remove all registered ping observers.
+    pub fn clear_ping_observers(&mut self) {
+        self.ping_observers.clear();
+    }
+
+    /// Notify all registered ping observers of a ping.
+    fn notify_ping_observers(
+        &self,
+        stage: PingStage,
+        ping_name: &str,
+        document_id: &str,
+        path: &str,
+        payload: &JsonValue,
+        headers: &HeaderMap,
+    ) {
+        for observer in &self.ping_observers {
+            observer.on_ping(stage, ping_name, document_id, path, payload, headers);
+        }
     }
 
     /// Set the log pings debug option.
@@ -772,7 +1459,38 @@ impl Glean {
     /// The log_pings option may be set from an environment variable (GLEAN_LOG_PINGS)
     /// or through the `set_log_pings` function.
     pub(crate) fn log_pings(&self) -> bool {
-        self.debug.log_pings.get().copied().unwrap_or(false)
+        self.debug
+            .file_options()
+            .log_pings
+            .or_else(|| self.debug.log_pings.get().copied())
+            .unwrap_or(false)
+    }
+
+    /// Start watching the `debug.json` file in the data path for debug options.
+    ///
+    /// The file is polled for changes on a background thread.
+    /// The `log_pings`, `debug_view_tag` and `source_tags` options it contains are validated
+    /// like the ones set through the `set_*` functions, and take precedence over them
+    /// and over the environment variables until they are removed from the file.
+    /// Tags set through the file are applied to all pings.
+    ///
+    /// Calling this again restarts the watcher with the new interval.
+    ///
+    /// ## Arguments
+    ///
+    /// * `poll_interval` - How often the file is checked for changes.
+    pub fn watch_debug_file(&mut self, poll_interval: Duration) -> Result<()> {
+        // Stop a previous watcher before reading the file again.
+        self.debug.file_watcher = None;
+        self.debug.file_watcher = Some(DebugFileWatcher::start(&self.data_path, poll_interval)?);
+        Ok(())
+    }
+
+    /// Stop watching the `debug.json` file.
+    ///
+    /// The options set through the file no longer apply.
+    pub fn stop_watching_debug_file(&mut self) {
+        self.debug.file_watcher = None;
     }
 
     fn get_dirty_bit_metric(&self) -> metrics::BooleanMetric {
@@ -864,6 +1582,9 @@ impl Glean {
         }
         // We don't care about this failing, maybe the data does just not exist.
         let _ = self.event_data_store.clear_all();
+        if let Some(buffer) = &self.ipc_buffer {
+            buffer.clear();
+        }
     }
 }
 
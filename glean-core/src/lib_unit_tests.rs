@@ -6,11 +6,14 @@
 // the lib.rs file.
 
 use std::collections::HashSet;
+use std::fs;
 use std::iter::FromIterator;
 
 use super::*;
 use crate::metrics::RecordedExperimentData;
-use crate::metrics::{StringMetric, TimeUnit, TimespanMetric, TimingDistributionMetric};
+use crate::metrics::{
+    CounterMetric, StringMetric, TimeUnit, TimespanMetric, TimingDistributionMetric,
+};
 
 const GLOBAL_APPLICATION_ID: &str = "org.mozilla.glean.test.app";
 pub fn new_glean(tempdir: Option<tempfile::TempDir>) -> (Glean, tempfile::TempDir) {
@@ -33,6 +36,84 @@ fn path_is_constructed_from_data() {
     );
 }
 
+#[test]
+fn path_is_constructed_from_a_custom_template() {
+    let dir = tempfile::tempdir().unwrap();
+    let cfg = Configuration {
+        data_path: dir.path().display().to_string(),
+        application_id: GLOBAL_APPLICATION_ID.into(),
+        language_binding_name: "Rust".into(),
+        upload_enabled: true,
+        max_events: None,
+        delay_ping_lifetime_io: false,
+        key_provider: None,
+        upload_batch_limits: None,
+        ping_compression: Default::default(),
+        server_endpoint: Some("https://collector.example.com/".into()),
+        path_template: Some("/ingest/{channel}/{app_id}/{ping}/{doc_id}".into()),
+        storage_durability: Default::default(),
+    };
+    let glean = Glean::new(cfg).unwrap();
+
+    assert_eq!(
+        Some("https://collector.example.com"),
+        glean.server_endpoint()
+    );
+    assert_eq!(
+        "/ingest/unknown/org-mozilla-glean-test-app/baseline/this-is-a-docid",
+        glean.make_path("baseline", "this-is-a-docid")
+    );
+
+    StringMetric::new(CommonMetricData {
+        name: "app_channel".into(),
+        category: "".into(),
+        send_in_pings: vec!["glean_client_info".into()],
+        lifetime: Lifetime::Application,
+        ..Default::default()
+    })
+    .set(&glean, "nightly");
+    assert_eq!(
+        "/ingest/nightly/org-mozilla-glean-test-app/baseline/this-is-a-docid",
+        glean.make_path("baseline", "this-is-a-docid")
+    );
+
+    // Channels that would change the path are not used.
+    StringMetric::new(CommonMetricData {
+        name: "app_channel".into(),
+        category: "".into(),
+        send_in_pings: vec!["glean_client_info".into()],
+        lifetime: Lifetime::Application,
+        ..Default::default()
+    })
+    .set(&glean, "beta 2/x?y");
+    assert_eq!(
+        "/ingest/unknown/org-mozilla-glean-test-app/baseline/this-is-a-docid",
+        glean.make_path("baseline", "this-is-a-docid")
+    );
+}
+
+#[test]
+fn invalid_upload_urls_are_rejected_at_init() {
+    let dir = tempfile::tempdir().unwrap();
+    let cfg = |server_endpoint: Option<&str>, path_template: Option<&str>| Configuration {
+        data_path: dir.path().display().to_string(),
+        application_id: GLOBAL_APPLICATION_ID.into(),
+        language_binding_name: "Rust".into(),
+        upload_enabled: true,
+        max_events: None,
+        delay_ping_lifetime_io: false,
+        key_provider: None,
+        upload_batch_limits: None,
+        ping_compression: Default::default(),
+        server_endpoint: server_endpoint.map(String::from),
+        path_template: path_template.map(String::from),
+        storage_durability: Default::default(),
+    };
+
+    assert!(Glean::new(cfg(None, Some("/submit/{app_id}/{ping}"))).is_err());
+    assert!(Glean::new(cfg(Some("collector.example.com"), None)).is_err());
+}
+
 // Experiment's API tests: the next two tests come from glean-ac's
 // ExperimentsStorageEngineTest.kt.
 #[test]
@@ -735,7 +816,7 @@ fn timing_distribution_truncation_accumulate() {
         TimeUnit::Microsecond,
         TimeUnit::Millisecond,
     ] {
-        let mut dist = TimingDistributionMetric::new(
+        let dist = TimingDistributionMetric::new(
             CommonMetricData {
                 name: format!("local_metric_{:?}", unit),
                 category: "local".into(),
@@ -774,11 +855,91 @@ fn test_setting_debug_view_tag() {
 
     let valid_tag = "valid-tag";
     assert_eq!(true, glean.set_debug_view_tag(valid_tag));
-    assert_eq!(valid_tag, glean.debug_view_tag().unwrap());
+    assert_eq!(valid_tag, glean.debug_view_tag_for("baseline").unwrap());
 
     let invalid_tag = "invalid tag";
     assert_eq!(false, glean.set_debug_view_tag(invalid_tag));
-    assert_eq!(valid_tag, glean.debug_view_tag().unwrap());
+    assert_eq!(valid_tag, glean.debug_view_tag_for("baseline").unwrap());
+}
+
+#[test]
+fn debug_tags_are_only_applied_within_their_limits() {
+    let (mut glean, _t) = new_glean(None);
+    let custom = PingType::new("custom", true, true, vec![]);
+    let other = PingType::new("other", true, true, vec![]);
+    glean.register_ping_type(&custom);
+    glean.register_ping_type(&other);
+    let headers = |glean: &Glean, ping: &PingType| PingMaker::new().get_headers(glean, ping);
+
+    // An empty ping filter is rejected.
+    let limits = DebugTagLimits {
+        pings: Some(vec![]),
+        ..Default::default()
+    };
+    assert!(!glean.set_debug_view_tag_with_limits("tag", limits));
+
+    let limits = DebugTagLimits {
+        max_pings: Some(2),
+        pings: Some(vec!["custom".into()]),
+        ..Default::default()
+    };
+    assert!(glean.set_debug_view_tag_with_limits("tag", limits));
+    assert_eq!("tag", headers(&glean, &custom)["X-Debug-ID"]);
+    assert!(!headers(&glean, &other).contains_key("X-Debug-ID"));
+
+    // Only tagged pings count against the limit.
+    assert!(other.submit(&glean, None).unwrap());
+    assert!(custom.submit(&glean, None).unwrap());
+    assert_eq!("tag", headers(&glean, &custom)["X-Debug-ID"]);
+    assert!(custom.submit(&glean, None).unwrap());
+    assert!(!headers(&glean, &custom).contains_key("X-Debug-ID"));
+
+    // Expired tags are not applied.
+    let limits = DebugTagLimits {
+        expires_after: Some(std::time::Duration::from_secs(0)),
+        ..Default::default()
+    };
+    assert!(glean.set_source_tags_with_limits(vec!["source".into()], limits));
+    assert!(!headers(&glean, &custom).contains_key("X-Source-Tags"));
+
+    // Setting a tag without limits lifts them.
+    assert!(glean.set_debug_view_tag("tag"));
+    assert_eq!("tag", headers(&glean, &other)["X-Debug-ID"]);
+}
+
+#[test]
+fn debug_tags_set_with_limits_are_restored_after_restart() {
+    let (mut glean, dir) = new_glean(None);
+    let custom = PingType::new("custom", true, true, vec![]);
+    glean.register_ping_type(&custom);
+
+    let limits = DebugTagLimits {
+        expires_after: Some(std::time::Duration::from_secs(3600)),
+        max_pings: Some(3),
+        ..Default::default()
+    };
+    assert!(glean.set_debug_view_tag_with_limits("tag", limits));
+    let limits = DebugTagLimits {
+        expires_after: Some(std::time::Duration::from_secs(0)),
+        ..Default::default()
+    };
+    assert!(glean.set_source_tags_with_limits(vec!["source".into()], limits));
+    assert!(custom.submit(&glean, None).unwrap());
+    drop(glean);
+
+    // The debug view tag is restored with the pings left, the expired source tags are dropped.
+    let (mut glean, dir) = new_glean(Some(dir));
+    assert_eq!(Some("tag"), glean.debug_view_tag_for("custom").as_deref());
+    let scope = glean.debug.debug_view_tag_scope.lock().unwrap().clone();
+    assert_eq!(Some(2), scope.unwrap().remaining_pings);
+    assert_eq!(None, glean.source_tags_for("custom"));
+    assert!(debug::load_tag(&glean, DebugTag::SourceTags).is_none());
+
+    // Tags set without limits are not persisted.
+    assert!(glean.set_debug_view_tag("other-tag"));
+    drop(glean);
+    let (glean, _t) = new_glean(Some(dir));
+    assert_eq!(None, glean.debug_view_tag_for("custom"));
 }
 
 #[test]
@@ -795,6 +956,64 @@ fn test_setting_log_pings() {
     assert!(!glean.log_pings());
 }
 
+#[test]
+fn debug_options_are_applied_from_the_watched_debug_file() {
+    let (mut glean, dir) = new_glean(None);
+    let custom = PingType::new("custom", true, true, vec![]);
+    glean.register_ping_type(&custom);
+    let headers = |glean: &Glean| PingMaker::new().get_headers(glean, &custom);
+    let debug_file = dir.path().join("debug.json");
+    let wait_until = |condition: &dyn Fn() -> bool| {
+        for _ in 0..500 {
+            if condition() {
+                return;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        panic!("The debug options file was not applied in time");
+    };
+
+    assert!(glean.set_debug_view_tag("set-tag"));
+
+    // A file present when the watcher starts is applied right away.
+    fs::write(
+        &debug_file,
+        r#"{"log_pings": true, "debug_view_tag": "file-tag"}"#,
+    )
+    .unwrap();
+    glean
+        .watch_debug_file(std::time::Duration::from_millis(10))
+        .unwrap();
+    assert!(glean.log_pings());
+    assert_eq!("file-tag", headers(&glean)["X-Debug-ID"]);
+
+    // Invalid values are ignored, the other options still apply.
+    fs::write(
+        &debug_file,
+        r#"{"debug_view_tag": "invalid tag", "source_tags": ["daemon"]}"#,
+    )
+    .unwrap();
+    wait_until(&|| headers(&glean).contains_key("X-Source-Tags"));
+    assert_eq!("daemon", headers(&glean)["X-Source-Tags"]);
+    assert_eq!("set-tag", headers(&glean)["X-Debug-ID"]);
+    assert!(!glean.log_pings());
+
+    // A file that can't be parsed keeps the previous options.
+    fs::write(&debug_file, "{ not json").unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(50));
+    assert_eq!("daemon", headers(&glean)["X-Source-Tags"]);
+
+    // Removing the file resets the options.
+    fs::remove_file(&debug_file).unwrap();
+    wait_until(&|| !headers(&glean).contains_key("X-Source-Tags"));
+
+    // Once the watcher is stopped, changes to the file are no longer applied.
+    glean.stop_watching_debug_file();
+    fs::write(&debug_file, r#"{"log_pings": true}"#).unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(50));
+    assert!(!glean.log_pings());
+}
+
 #[test]
 #[should_panic]
 fn test_empty_application_id() {
@@ -805,3 +1024,304 @@ fn test_empty_application_id() {
     // Check that this is indeed the first run.
     assert!(glean.is_first_run());
 }
+
+#[test]
+fn compression_of_uploaded_pings_is_recorded() {
+    let (mut glean, _t) = new_glean(None);
+    let ping = PingType::new("custom", true, true, vec![]);
+    glean.register_ping_type(&ping);
+    assert!(ping.submit(&glean, None).unwrap());
+
+    loop {
+        match glean.get_upload_task() {
+            PingUploadTask::Upload(request) => {
+                assert_eq!(Some("gzip"), request.content_encoding());
+                break;
+            }
+            PingUploadTask::Wait => std::thread::sleep(std::time::Duration::from_millis(10)),
+            _ => panic!("Expected a ping to upload"),
+        }
+    }
+
+    let metrics = &glean.core_metrics;
+    assert_eq!(
+        Some(1),
+        metrics
+            .ping_compression
+            .get("gzip")
+            .test_get_value(&glean, "metrics")
+    );
+    assert_eq!(
+        None,
+        metrics
+            .ping_compression
+            .get("none")
+            .test_get_value(&glean, "metrics")
+    );
+    let ratio = metrics
+        .compression_ratio
+        .test_get_value(&glean, "metrics")
+        .unwrap();
+    assert_eq!(1, ratio.values.values().sum::<u64>());
+}
+
+#[test]
+fn network_policy_is_persisted_and_wait_time_recorded() {
+    let (mut glean, dir) = new_glean(None);
+    let ping = PingType::new("custom", true, true, vec![])
+        .with_network_policy(upload::NetworkPolicy::Unmetered);
+    glean.register_ping_type(&ping);
+    assert!(ping.submit(&glean, None).unwrap());
+
+    // The policy is read back from disk after a restart.
+    drop(glean);
+    let (glean, _t) = new_glean(Some(dir));
+    glean.set_network_state(true, true);
+    loop {
+        match glean.get_upload_task() {
+            PingUploadTask::WaitFor(reason, _) => {
+                assert_eq!(upload::WaitReason::Metered, reason);
+                break;
+            }
+            PingUploadTask::Wait => std::thread::sleep(std::time::Duration::from_millis(10)),
+            _ => panic!("Expected the ping to be held back"),
+        }
+    }
+
+    glean.set_network_state(true, false);
+    match glean.get_upload_task() {
+        PingUploadTask::Upload(request) => assert!(request.path.contains("/custom/")),
+        _ => panic!("Expected a ping to upload"),
+    }
+
+    let wait_time = glean
+        .core_metrics
+        .network_wait_time
+        .test_get_value(&glean, "metrics")
+        .unwrap();
+    assert_eq!(1, wait_time.values.values().sum::<u64>());
+}
+
+#[test]
+fn upload_queue_and_health_is_recorded() {
+    let (mut glean, dir) = new_glean(None);
+    // An empty queue is recorded without any error.
+    while glean.get_upload_task() == PingUploadTask::Wait {
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+    let metrics = &glean.core_metrics;
+    assert_eq!(
+        Some(0),
+        metrics.pending_pings.test_get_value(&glean, "metrics")
+    );
+    assert!(test_get_num_recorded_errors(
+        &glean,
+        metrics.pending_pings.meta(),
+        ErrorType::InvalidValue,
+        None
+    )
+    .is_err());
+
+    let ping = PingType::new("custom", true, true, vec![]);
+    glean.register_ping_type(&ping);
+    assert!(ping.submit(&glean, None).unwrap());
+
+    // The pending pings are counted by the directory scan at startup.
+    drop(glean);
+    let (glean, _t) = new_glean(Some(dir));
+    let request = loop {
+        match glean.get_upload_task() {
+            PingUploadTask::Upload(request) => break request,
+            PingUploadTask::Wait => std::thread::sleep(std::time::Duration::from_millis(10)),
+            _ => panic!("Expected a ping to upload"),
+        }
+    };
+
+    let metrics = &glean.core_metrics;
+    assert_eq!(
+        Some(1),
+        metrics.pending_pings.test_get_value(&glean, "metrics")
+    );
+    let directory_size = metrics
+        .pending_pings_directory_size
+        .test_get_value(&glean, "metrics")
+        .unwrap();
+    // One sample for each startup.
+    assert_eq!(2, directory_size.values.values().sum::<u64>());
+
+    glean.process_ping_upload_response(&request.document_id, UploadResult::HttpStatus(200));
+    assert_eq!(
+        Some(request.body.len() as i32),
+        metrics
+            .uploaded_bytes
+            .get("custom")
+            .test_get_value(&glean, "metrics")
+    );
+    let time_to_upload = metrics
+        .time_to_upload
+        .test_get_value(&glean, "metrics")
+        .unwrap();
+    assert_eq!(1, time_to_upload.values.values().sum::<u64>());
+}
+
+type ObservedPing = (PingStage, String, String, JsonValue, HeaderMap);
+
+#[derive(Debug, Default)]
+struct RecordingObserver {
+    pings: std::sync::Arc<std::sync::Mutex<Vec<ObservedPing>>>,
+}
+
+impl PingObserver for RecordingObserver {
+    fn on_ping(
+        &self,
+        stage: PingStage,
+        ping_name: &str,
+        document_id: &str,
+        _path: &str,
+        payload: &JsonValue,
+        headers: &HeaderMap,
+    ) {
+        self.pings.lock().unwrap().push((
+            stage,
+            ping_name.to_string(),
+            document_id.to_string(),
+            payload.clone(),
+            headers.clone(),
+        ));
+    }
+}
+
+#[test]
+fn ping_observers_are_notified_on_submission_and_upload() {
+    let (mut glean, _t) = new_glean(None);
+    let observer = RecordingObserver::default();
+    let pings = observer.pings.clone();
+    glean.add_ping_observer(Box::new(observer));
+
+    let mut headers = HeaderMap::new();
+    headers.insert("X-Tenant".to_string(), "tenant".to_string());
+    glean.set_upload_headers(headers);
+
+    let ping = PingType::new("custom", true, true, vec![]);
+    glean.register_ping_type(&ping);
+    assert!(ping.submit(&glean, None).unwrap());
+
+    match glean.get_upload_task() {
+        PingUploadTask::Upload(_) => {}
+        _ => panic!("Expected a ping to upload"),
+    }
+
+    let pings = pings.lock().unwrap();
+    assert_eq!(2, pings.len());
+    let (stage, name, doc_id, payload, headers) = &pings[0];
+    assert_eq!(PingStage::Submitted, *stage);
+    assert_eq!("custom", name);
+    assert_eq!(0, payload["ping_info"]["seq"]);
+    assert_eq!("tenant", headers["X-Tenant"]);
+
+    let (stage, name, upload_doc_id, payload, headers) = &pings[1];
+    assert_eq!(PingStage::Uploading, *stage);
+    assert_eq!("custom", name);
+    assert_eq!(doc_id, upload_doc_id);
+    assert_eq!(0, payload["ping_info"]["seq"]);
+    assert_eq!("tenant", headers["X-Tenant"]);
+    assert!(headers.contains_key("User-Agent"));
+
+    glean.clear_ping_observers();
+    assert!(ping.submit(&glean, None).unwrap());
+    assert_eq!(2, pings.len());
+}
+
+#[test]
+fn ping_observers_see_the_debug_tags_of_the_last_tagged_ping() {
+    let (mut glean, _t) = new_glean(None);
+    let observer = RecordingObserver::default();
+    let pings = observer.pings.clone();
+    glean.add_ping_observer(Box::new(observer));
+
+    let ping = PingType::new("custom", true, true, vec![]);
+    glean.register_ping_type(&ping);
+    let limits = DebugTagLimits {
+        max_pings: Some(1),
+        ..Default::default()
+    };
+    assert!(glean.set_debug_view_tag_with_limits("tag", limits.clone()));
+    assert!(glean.set_source_tags_with_limits(vec!["source".into()], limits));
+    assert!(ping.submit(&glean, None).unwrap());
+    assert!(ping.submit(&glean, None).unwrap());
+
+    let pings = pings.lock().unwrap();
+    assert_eq!(2, pings.len());
+    let (stage, _, _, _, headers) = &pings[0];
+    assert_eq!(PingStage::Submitted, *stage);
+    assert_eq!("tag", headers["X-Debug-ID"]);
+    assert_eq!("source", headers["X-Source-Tags"]);
+
+    // The tags are used up by the first ping.
+    let (_, _, _, _, headers) = &pings[1];
+    assert!(!headers.contains_key("X-Debug-ID"));
+    assert!(!headers.contains_key("X-Source-Tags"));
+}
+
+#[test]
+fn coalesced_recordings_are_submitted_and_flushed() {
+    let dir = tempfile::tempdir().unwrap();
+    let cfg = Configuration {
+        data_path: dir.path().display().to_string(),
+        application_id: GLOBAL_APPLICATION_ID.into(),
+        language_binding_name: "Rust".into(),
+        upload_enabled: true,
+        max_events: None,
+        delay_ping_lifetime_io: false,
+        key_provider: None,
+        upload_batch_limits: None,
+        ping_compression: Default::default(),
+        server_endpoint: None,
+        path_template: None,
+        storage_durability: StorageDurability::Coalesced {
+            flush_interval_ms: 60_000,
+        },
+    };
+    let mut glean = Glean::new(cfg).unwrap();
+
+    let ping = PingType::new("custom", true, false, vec![]);
+    glean.register_ping_type(&ping);
+    let counter = CounterMetric::new(CommonMetricData {
+        name: "counter".into(),
+        category: "local".into(),
+        send_in_pings: vec!["custom".into()],
+        ..Default::default()
+    });
+    let string = StringMetric::new(CommonMetricData {
+        name: "string".into(),
+        category: "local".into(),
+        send_in_pings: vec!["store".into()],
+        lifetime: Lifetime::User,
+        ..Default::default()
+    });
+
+    // Buffered recordings end up in the submitted ping.
+    counter.add(&glean, 2);
+    assert!(ping.submit(&glean, None).unwrap());
+    let request = loop {
+        match glean.get_upload_task() {
+            PingUploadTask::Upload(request) => break request,
+            PingUploadTask::Wait => std::thread::sleep(std::time::Duration::from_millis(10)),
+            _ => panic!("Expected a ping to upload"),
+        }
+    };
+    assert_eq!(
+        2,
+        request.payload().unwrap()["metrics"]["counter"]["local.counter"]
+    );
+
+    // Flushed recordings survive a restart.
+    string.set(&glean, "flushed");
+    glean.flush().unwrap();
+    drop(glean);
+    let (glean, _t) = new_glean(Some(dir));
+    assert_eq!(
+        Some("flushed".to_string()),
+        string.test_get_value(&glean, "store")
+    );
+}
@@ -0,0 +1,645 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Concurrent recording of counters, quantities and distributions.
+//!
+//! Recording a metric requires access to the [`Glean`](../struct.Glean.html) object,
+//! which is usually behind a single lock, so that every recording from every thread
+//! is serialized and written to the database.
+//! An [`Accumulator`](struct.Accumulator.html) instead collects recordings in memory,
+//! sharded by thread, without access to Glean.
+//! They are replayed into Glean in bulk with
+//! [`Accumulator::merge_into`](struct.Accumulator.html#method.merge_into).
+//!
+//! Replaying uses the regular metric APIs, so that `should_record`
+//! and error recording behave exactly as if the metrics were recorded directly,
+//! at the time they are merged.
+//! Merging before every other operation on Glean (e.g. submitting a ping or
+//! changing the upload state) therefore preserves the semantics of recording directly.
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use crate::metrics::{
+    CounterMetric, CustomDistributionMetric, MemoryDistributionMetric, MetricType, QuantityMetric,
+    TimingDistributionMetric,
+};
+use crate::Glean;
+
+/// The number of shards of an accumulator created with `Accumulator::new`.
+const DEFAULT_SHARD_COUNT: usize = 16;
+
+/// The number of buffered samples and invalid recordings after which
+/// the accumulator should be merged, to bound its memory use.
+const MAX_BUFFERED_RECORDINGS: usize = 10_000;
+
+/// The source of thread indices, which pick the shard a thread records into.
+static NEXT_THREAD_INDEX: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    static THREAD_INDEX: usize = NEXT_THREAD_INDEX.fetch_add(1, Ordering::Relaxed);
+}
+
+/// The pending recordings of a counter.
+#[derive(Debug)]
+struct PendingCounter {
+    metric: CounterMetric,
+    /// The saturated sum of all valid amounts.
+    amount: i32,
+    /// Invalid amounts, replayed one by one to record their errors.
+    invalid: Vec<i32>,
+}
+
+/// The pending recordings of a quantity.
+#[derive(Debug)]
+struct PendingQuantity {
+    metric: QuantityMetric,
+    /// The last valid value with its sequence number, to order values set on different threads.
+    value: Option<(u64, i64)>,
+    /// Invalid values, replayed one by one to record their errors.
+    invalid: Vec<i64>,
+}
+
+/// The pending samples of a distribution.
+#[derive(Debug)]
+struct PendingSamples<M> {
+    metric: M,
+    samples: Vec<i64>,
+}
+
+/// The pending samples of a memory distribution.
+///
+/// Single samples are only recorded if the metric should be recorded,
+/// unlike samples accumulated in bulk, so they are kept apart.
+#[derive(Debug)]
+struct PendingMemorySamples {
+    metric: MemoryDistributionMetric,
+    samples: Vec<i64>,
+    signed_samples: Vec<i64>,
+}
+
+/// The pending recordings of one shard, by metric ID.
+#[derive(Debug, Default)]
+struct Recordings {
+    /// The number of samples and invalid recordings buffered in the vectors below.
+    buffered: usize,
+    counters: HashMap<u64, PendingCounter>,
+    quantities: HashMap<u64, PendingQuantity>,
+    custom_distributions: HashMap<u64, PendingSamples<CustomDistributionMetric>>,
+    memory_distributions: HashMap<u64, PendingMemorySamples>,
+    timing_distributions: HashMap<u64, PendingSamples<TimingDistributionMetric>>,
+}
+
+impl Recordings {
+    /// Add the recordings of another shard.
+    fn absorb(&mut self, other: Recordings) {
+        for (id, counter) in other.counters {
+            match self.counters.get_mut(&id) {
+                Some(pending) => {
+                    pending.amount = pending.amount.saturating_add(counter.amount);
+                    pending.invalid.extend(counter.invalid);
+                }
+                None => {
+                    self.counters.insert(id, counter);
+                }
+            }
+        }
+
+        for (id, quantity) in other.quantities {
+            match self.quantities.get_mut(&id) {
+                Some(pending) => {
+                    if quantity.value > pending.value {
+                        pending.value = quantity.value;
+                    }
+                    pending.invalid.extend(quantity.invalid);
+                }
+                None => {
+                    self.quantities.insert(id, quantity);
+                }
+            }
+        }
+
+        for (id, distribution) in other.custom_distributions {
+            match self.custom_distributions.get_mut(&id) {
+                Some(pending) => pending.samples.extend(distribution.samples),
+                None => {
+                    self.custom_distributions.insert(id, distribution);
+                }
+            }
+        }
+
+        for (id, distribution) in other.memory_distributions {
+            match self.memory_distributions.get_mut(&id) {
+                Some(pending) => {
+                    pending.samples.extend(distribution.samples);
+                    pending.signed_samples.extend(distribution.signed_samples);
+                }
+                None => {
+                    self.memory_distributions.insert(id, distribution);
+                }
+            }
+        }
+
+        for (id, distribution) in other.timing_distributions {
+            match self.timing_distributions.get_mut(&id) {
+                Some(pending) => pending.samples.extend(distribution.samples),
+                None => {
+                    self.timing_distributions.insert(id, distribution);
+                }
+            }
+        }
+    }
+
+    /// Record everything into Glean, through the regular metric APIs.
+    fn replay(self, glean: &Glean) {
+        for counter in self.counters.values() {
+            for &amount in &counter.invalid {
+                counter.metric.add(glean, amount);
+            }
+            if counter.amount > 0 {
+                counter.metric.add(glean, counter.amount);
+            }
+        }
+
+        for quantity in self.quantities.values() {
+            for &value in &quantity.invalid {
+                quantity.metric.set(glean, value);
+            }
+            if let Some((_, value)) = quantity.value {
+                quantity.metric.set(glean, value);
+            }
+        }
+
+        for (_, distribution) in self.custom_distributions {
+            distribution
+                .metric
+                .accumulate_samples_signed(glean, distribution.samples);
+        }
+
+        for (_, distribution) in self.memory_distributions {
+            if !distribution.samples.is_empty() && distribution.metric.should_record(glean) {
+                distribution
+                    .metric
+                    .accumulate_samples_signed(glean, distribution.samples);
+            }
+            if !distribution.signed_samples.is_empty() {
+                distribution
+                    .metric
+                    .accumulate_samples_signed(glean, distribution.signed_samples);
+            }
+        }
+
+        for (_, distribution) in self.timing_distributions {
+            distribution
+                .metric
+                .accumulate_samples_signed(glean, distribution.samples);
+        }
+    }
+}
+
+/// A shard of the accumulator.
+///
+/// Aligned to a cache line, so that threads recording into different shards don't contend.
+#[derive(Debug, Default)]
+#[repr(align(64))]
+struct Shard {
+    /// Whether there are recordings to merge, to skip locking empty shards.
+    dirty: AtomicBool,
+    recordings: Mutex<Recordings>,
+}
+
+/// Collects recordings of counters, quantities and distributions
+/// from many threads without access to Glean.
+///
+/// Each thread records into one of a fixed number of shards, each behind its own lock,
+/// so that threads rarely contend.
+/// Recordings are kept in memory, aggregated where possible (e.g. counter increments are summed),
+/// until they are merged with [`merge_into`](#method.merge_into).
+/// Samples of distributions and invalid values can't be aggregated without Glean,
+/// so callers must merge whenever [`should_merge`](#method.should_merge) says so
+/// to bound the memory used.
+///
+/// Metrics are identified by an ID chosen by the caller, e.g. an FFI handle.
+/// The same ID must always be used with the same metric.
+#[derive(Debug)]
+pub struct Accumulator {
+    shards: Vec<Shard>,
+    /// Orders the values of quantities set on different threads.
+    sequence: AtomicU64,
+    /// The number of samples and invalid recordings buffered in all shards.
+    buffered: AtomicUsize,
+}
+
+impl Default for Accumulator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Accumulator {
+    /// Create a new accumulator with the default number of shards.
+    pub fn new() -> Self {
+        Self::with_shards(DEFAULT_SHARD_COUNT)
+    }
+
+    /// Create a new accumulator.
+    ///
+    /// ## Arguments
+    ///
+    /// * `count` - The number of shards. Threads are spread over the shards round-robin.
+    pub fn with_shards(count: usize) -> Self {
+        Self {
+            shards: (0..count.max(1)).map(|_| Shard::default()).collect(),
+            sequence: AtomicU64::new(0),
+            buffered: AtomicUsize::new(0),
+        }
+    }
+
+    /// Record into the shard of the current thread.
+    ///
+    /// The transaction function returns the number of samples and invalid recordings it buffered.
+    fn record<F>(&self, transaction_fn: F)
+    where
+        F: FnOnce(&mut Recordings) -> usize,
+    {
+        let index = THREAD_INDEX.with(|index| *index) % self.shards.len();
+        let shard = &self.shards[index];
+        let mut recordings = shard.recordings.lock().unwrap();
+        let buffered = transaction_fn(&mut recordings);
+        recordings.buffered += buffered;
+        self.buffered.fetch_add(buffered, Ordering::Relaxed);
+        shard.dirty.store(true, Ordering::Release);
+    }
+
+    /// Whether the accumulator should be merged to bound its memory use.
+    ///
+    /// Counter increments and quantities are aggregated as they are recorded,
+    /// but distribution samples and invalid values are buffered one by one until merged.
+    pub fn should_merge(&self) -> bool {
+        self.buffered.load(Ordering::Relaxed) >= MAX_BUFFERED_RECORDINGS
+    }
+
+    /// Increase a counter by `amount`.
+    ///
+    /// See [`CounterMetric::add`](struct.CounterMetric.html#method.add).
+    ///
+    /// ## Arguments
+    ///
+    /// * `id` - The ID of the metric.
+    /// * `metric` - The metric to record to.
+    /// * `amount` - The amount to increase by. Should be positive.
+    pub fn add_to_counter(&self, id: u64, metric: &CounterMetric, amount: i32) {
+        if !metric.meta().should_record() {
+            return;
+        }
+
+        self.record(|recordings| {
+            let pending = recordings
+                .counters
+                .entry(id)
+                .or_insert_with(|| PendingCounter {
+                    metric: metric.clone(),
+                    amount: 0,
+                    invalid: Vec::new(),
+                });
+            if amount > 0 {
+                pending.amount = pending.amount.saturating_add(amount);
+                0
+            } else {
+                pending.invalid.push(amount);
+                1
+            }
+        });
+    }
+
+    /// Set a quantity.
+    ///
+    /// See [`QuantityMetric::set`](struct.QuantityMetric.html#method.set).
+    ///
+    /// ## Arguments
+    ///
+    /// * `id` - The ID of the metric.
+    /// * `metric` - The metric to record to.
+    /// * `value` - The value. Must be non-negative.
+    pub fn set_quantity(&self, id: u64, metric: &QuantityMetric, value: i64) {
+        if !metric.meta().should_record() {
+            return;
+        }
+
+        let sequence = self.sequence.fetch_add(1, Ordering::Relaxed);
+        self.record(|recordings| {
+            let pending = recordings
+                .quantities
+                .entry(id)
+                .or_insert_with(|| PendingQuantity {
+                    metric: metric.clone(),
+                    value: None,
+                    invalid: Vec::new(),
+                });
+            if value >= 0 {
+                pending.value = Some((sequence, value));
+                0
+            } else {
+                pending.invalid.push(value);
+                1
+            }
+        });
+    }
+
+    /// Accumulate samples in a custom distribution.
+    ///
+    /// See [`CustomDistributionMetric::accumulate_samples_signed`](struct.CustomDistributionMetric.html#method.accumulate_samples_signed).
+    ///
+    /// ## Arguments
+    ///
+    /// * `id` - The ID of the metric.
+    /// * `metric` - The metric to record to.
+    /// * `samples` - The samples to accumulate.
+    pub fn accumulate_custom_distribution_samples(
+        &self,
+        id: u64,
+        metric: &CustomDistributionMetric,
+        samples: Vec<i64>,
+    ) {
+        let count = samples.len();
+        self.record(|recordings| {
+            recordings
+                .custom_distributions
+                .entry(id)
+                .or_insert_with(|| PendingSamples {
+                    metric: metric.clone(),
+                    samples: Vec::new(),
+                })
+                .samples
+                .extend(samples);
+            count
+        });
+    }
+
+    /// Accumulate a single sample in a memory distribution.
+    ///
+    /// See [`MemoryDistributionMetric::accumulate`](struct.MemoryDistributionMetric.html#method.accumulate).
+    ///
+    /// ## Arguments
+    ///
+    /// * `id` - The ID of the metric.
+    /// * `metric` - The metric to record to.
+    /// * `sample` - The sample, in the memory unit of the metric.
+    pub fn accumulate_memory_distribution(
+        &self,
+        id: u64,
+        metric: &MemoryDistributionMetric,
+        sample: u64,
+    ) {
+        if !metric.meta().should_record() {
+            return;
+        }
+
+        // Samples this large are truncated either way.
+        let sample = i64::try_from(sample).unwrap_or(std::i64::MAX);
+        self.record(|recordings| {
+            recordings
+                .memory_distributions
+                .entry(id)
+                .or_insert_with(|| PendingMemorySamples {
+                    metric: metric.clone(),
+                    samples: Vec::new(),
+                    signed_samples: Vec::new(),
+                })
+                .samples
+                .push(sample);
+            1
+        });
+    }
+
+    /// Accumulate samples in a memory distribution.
+    ///
+    /// See [`MemoryDistributionMetric::accumulate_samples_signed`](struct.MemoryDistributionMetric.html#method.accumulate_samples_signed).
+    ///
+    /// ## Arguments
+    ///
+    /// * `id` - The ID of the metric.
+    /// * `metric` - The metric to record to.
+    /// * `samples` - The samples to accumulate, in the memory unit of the metric.
+    pub fn accumulate_memory_distribution_samples(
+        &self,
+        id: u64,
+        metric: &MemoryDistributionMetric,
+        samples: Vec<i64>,
+    ) {
+        let count = samples.len();
+        self.record(|recordings| {
+            recordings
+                .memory_distributions
+                .entry(id)
+                .or_insert_with(|| PendingMemorySamples {
+                    metric: metric.clone(),
+                    samples: Vec::new(),
+                    signed_samples: Vec::new(),
+                })
+                .signed_samples
+                .extend(samples);
+            count
+        });
+    }
+
+    /// Accumulate samples in a timing distribution.
+    ///
+    /// See [`TimingDistributionMetric::accumulate_samples_signed`](struct.TimingDistributionMetric.html#method.accumulate_samples_signed).
+    ///
+    /// ## Arguments
+    ///
+    /// * `id` - The ID of the metric.
+    /// * `metric` - The metric to record to.
+    /// * `samples` - The samples to accumulate, in the time unit of the metric.
+    pub fn accumulate_timing_distribution_samples(
+        &self,
+        id: u64,
+        metric: &TimingDistributionMetric,
+        samples: Vec<i64>,
+    ) {
+        let count = samples.len();
+        self.record(|recordings| {
+            recordings
+                .timing_distributions
+                .entry(id)
+                .or_insert_with(|| PendingSamples {
+                    metric: metric.clone(),
+                    samples: Vec::new(),
+                })
+                .samples
+                .extend(samples);
+            count
+        });
+    }
+
+    /// Record all pending recordings into Glean and clear them.
+    ///
+    /// Recordings made by other threads while merging are either merged
+    /// or kept for the next merge.
+    ///
+    /// ## Arguments
+    ///
+    /// * `glean` - The Glean instance to record to.
+    pub fn merge_into(&self, glean: &Glean) {
+        let mut merged = Recordings::default();
+        for shard in &self.shards {
+            // Only swap the flag if it's set, so that merging an empty accumulator
+            // doesn't write to the shards.
+            if shard.dirty.load(Ordering::Acquire) && shard.dirty.swap(false, Ordering::AcqRel) {
+                let recordings = std::mem::take(&mut *shard.recordings.lock().unwrap());
+                self.buffered
+                    .fetch_sub(recordings.buffered, Ordering::Relaxed);
+                merged.absorb(recordings);
+            }
+        }
+        merged.replay(glean);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::metrics::MemoryUnit;
+    use crate::tests::new_glean;
+    use crate::{CommonMetricData, ErrorType, Lifetime};
+    use std::sync::Arc;
+
+    fn meta(name: &str) -> CommonMetricData {
+        CommonMetricData {
+            name: name.into(),
+            category: "telemetry".into(),
+            send_in_pings: vec!["store1".into()],
+            lifetime: Lifetime::Application,
+            disabled: false,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn recordings_from_many_threads_are_merged() {
+        let (glean, _t) = new_glean(None);
+        let accumulator = Arc::new(Accumulator::with_shards(4));
+        let counter = CounterMetric::new(meta("counter"));
+        let distribution = MemoryDistributionMetric::new(meta("memory"), MemoryUnit::Byte);
+
+        let threads: Vec<_> = (0..8)
+            .map(|_| {
+                let accumulator = Arc::clone(&accumulator);
+                let counter = counter.clone();
+                let distribution = distribution.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..100 {
+                        accumulator.add_to_counter(1, &counter, 2);
+                        accumulator.accumulate_memory_distribution(2, &distribution, 10);
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        // Nothing is recorded until merged.
+        assert_eq!(None, counter.test_get_value(&glean, "store1"));
+
+        accumulator.merge_into(&glean);
+        assert_eq!(Some(1600), counter.test_get_value(&glean, "store1"));
+        let snapshot = distribution.test_get_value(&glean, "store1").unwrap();
+        assert_eq!(8 * 100 * 10, snapshot.sum);
+
+        // Merged recordings are cleared.
+        accumulator.merge_into(&glean);
+        assert_eq!(Some(1600), counter.test_get_value(&glean, "store1"));
+    }
+
+    #[test]
+    fn buffered_samples_ask_for_a_merge() {
+        let (glean, _t) = new_glean(None);
+        let accumulator = Accumulator::with_shards(2);
+        let counter = CounterMetric::new(meta("counter"));
+        let distribution = MemoryDistributionMetric::new(meta("memory"), MemoryUnit::Byte);
+
+        // Valid counter increments are summed, they don't need to be merged.
+        for _ in 0..MAX_BUFFERED_RECORDINGS {
+            accumulator.add_to_counter(1, &counter, 1);
+        }
+        assert!(!accumulator.should_merge());
+
+        accumulator.accumulate_memory_distribution_samples(
+            2,
+            &distribution,
+            vec![1; MAX_BUFFERED_RECORDINGS - 1],
+        );
+        assert!(!accumulator.should_merge());
+        accumulator.accumulate_memory_distribution(2, &distribution, 1);
+        assert!(accumulator.should_merge());
+
+        accumulator.merge_into(&glean);
+        assert!(!accumulator.should_merge());
+        let snapshot = distribution.test_get_value(&glean, "store1").unwrap();
+        assert_eq!(MAX_BUFFERED_RECORDINGS as u64, snapshot.sum);
+    }
+
+    #[test]
+    fn invalid_recordings_record_errors_when_merged() {
+        let (glean, _t) = new_glean(None);
+        let accumulator = Accumulator::new();
+        let counter = CounterMetric::new(meta("counter"));
+        let quantity = QuantityMetric::new(meta("quantity"));
+
+        accumulator.add_to_counter(1, &counter, 1);
+        accumulator.add_to_counter(1, &counter, 0);
+        accumulator.add_to_counter(1, &counter, -1);
+        accumulator.set_quantity(2, &quantity, 5);
+        accumulator.set_quantity(2, &quantity, -5);
+        accumulator.set_quantity(2, &quantity, 7);
+        accumulator.merge_into(&glean);
+
+        assert_eq!(Some(1), counter.test_get_value(&glean, "store1"));
+        assert_eq!(
+            Ok(2),
+            crate::test_get_num_recorded_errors(
+                &glean,
+                counter.meta(),
+                ErrorType::InvalidValue,
+                Some("store1")
+            )
+        );
+        assert_eq!(Some(7), quantity.test_get_value(&glean, "store1"));
+        assert_eq!(
+            Ok(1),
+            crate::test_get_num_recorded_errors(
+                &glean,
+                quantity.meta(),
+                ErrorType::InvalidValue,
+                Some("store1")
+            )
+        );
+    }
+
+    #[test]
+    fn recordings_are_dropped_if_they_should_not_be_recorded() {
+        let (mut glean, _t) = new_glean(None);
+        let accumulator = Accumulator::new();
+        let disabled = CounterMetric::new(CommonMetricData {
+            disabled: true,
+            ..meta("disabled")
+        });
+        let counter = CounterMetric::new(meta("counter"));
+
+        accumulator.add_to_counter(1, &disabled, 1);
+        accumulator.merge_into(&glean);
+        assert_eq!(None, disabled.test_get_value(&glean, "store1"));
+
+        // Whether upload is enabled is checked when merging.
+        accumulator.add_to_counter(2, &counter, 1);
+        glean.set_upload_enabled(false);
+        accumulator.merge_into(&glean);
+        glean.set_upload_enabled(true);
+        assert_eq!(None, counter.test_get_value(&glean, "store1"));
+    }
+}
@@ -44,7 +44,7 @@ impl BooleanMetric {
         }
 
         let value = Metric::Boolean(value);
-        glean.storage().record(glean, &self.meta, &value)
+        glean.record_metric(&self.meta, &value)
     }
 
     /// **Test-only API (exported for FFI purposes).**
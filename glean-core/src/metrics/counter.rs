@@ -60,14 +60,10 @@ impl CounterMetric {
             return;
         }
 
-        glean
-            .storage()
-            .record_with(glean, &self.meta, |old_value| match old_value {
-                Some(Metric::Counter(old_value)) => {
-                    Metric::Counter(old_value.saturating_add(amount))
-                }
-                _ => Metric::Counter(amount),
-            })
+        glean.record_metric_with(&self.meta, |old_value| match old_value {
+            Some(Metric::Counter(old_value)) => Metric::Counter(old_value.saturating_add(amount)),
+            _ => Metric::Counter(amount),
+        })
     }
 
     /// **Test-only API (exported for FFI purposes).**
@@ -12,7 +12,7 @@ use crate::Glean;
 /// A custom distribution metric.
 ///
 /// Memory distributions are used to accumulate and store memory sizes.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct CustomDistributionMetric {
     meta: CommonMetricData,
     range_min: u64,
@@ -99,7 +99,7 @@ impl CustomDistributionMetric {
             (num_negative_samples, metric(hist))
         }
 
-        glean.storage().record_with(glean, &self.meta, |old_value| {
+        glean.record_metric_with(&self.meta, |old_value| {
             let (num_negative, hist) = match self.histogram_type {
                 HistogramType::Linear => {
                     let hist = if let Some(Metric::CustomDistributionLinear(hist)) = old_value {
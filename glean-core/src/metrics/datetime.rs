@@ -113,7 +113,7 @@ impl DatetimeMetric {
 
         let value = value.unwrap_or_else(local_now_with_offset);
         let value = Metric::Datetime(value, self.time_unit);
-        glean.storage().record(glean, &self.meta, &value)
+        glean.record_metric(&self.meta, &value)
     }
 
     /// Get the stored datetime value.
@@ -190,7 +190,7 @@ impl ExperimentMetric {
             branch: truncated_branch,
             extra: truncated_extras,
         });
-        glean.storage().record(glean, &self.meta, &value)
+        glean.record_metric(&self.meta, &value)
     }
 
     /// Record an experiment as inactive.
@@ -5,13 +5,26 @@
 use std::fmt;
 use std::str::FromStr;
 
+use aes_gcm::aead::generic_array::GenericArray;
+use aes_gcm::aead::{Aead, NewAead, Payload};
+use aes_gcm::Aes256Gcm;
+use num_bigint::BigUint;
+use p256::ecdh::EphemeralSecret;
+use p256::elliptic_curve::sec1::FromEncodedPoint;
+use p256::{AffinePoint, EncodedPoint};
+use rand_core::OsRng;
 use serde::Serialize;
+use serde_json::{json, Value as JsonValue};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
 
+use crate::error::ErrorKind;
 use crate::error_recording::{record_error, ErrorType};
 use crate::metrics::{Metric, MetricType};
 use crate::storage::StorageManager;
 use crate::CommonMetricData;
 use crate::Glean;
+use crate::Result as GleanResult;
 
 const DEFAULT_MAX_CHARS_PER_VARIABLE_SIZE_ELEMENT: usize = 1024;
 
@@ -202,6 +215,345 @@ impl fmt::Display for Jwe {
     }
 }
 
+/// The `enc` algorithm of JWEs produced by Glean.
+const CONTENT_ENCRYPTION: &str = "A256GCM";
+
+/// The length of the AES-GCM initialization vector.
+const INIT_VECTOR_LENGTH: usize = 12;
+
+/// The length of the AES-256 content encryption key.
+const CONTENT_KEY_LENGTH: usize = 32;
+
+/// The minimum size of RSA keys, as required by [RFC 7518](https://tools.ietf.org/html/rfc7518#section-4.2).
+const MIN_RSA_KEY_BITS: usize = 2048;
+
+fn base64url(data: &[u8]) -> String {
+    base64::encode_config(data, base64::URL_SAFE_NO_PAD)
+}
+
+/// The key encryption algorithms for RSA keys.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum RsaOaep {
+    /// `RSA-OAEP`, using SHA-1.
+    Sha1,
+    /// `RSA-OAEP-256`, using SHA-256.
+    Sha256,
+}
+
+impl RsaOaep {
+    fn name(self) -> &'static str {
+        match self {
+            RsaOaep::Sha1 => "RSA-OAEP",
+            RsaOaep::Sha256 => "RSA-OAEP-256",
+        }
+    }
+}
+
+/// The public key of a JWK.
+#[derive(Clone, Debug, PartialEq)]
+enum PublicKey {
+    /// A point on the P-256 curve, for direct key agreement.
+    Ec(EncodedPoint),
+    /// An RSA key, to encrypt a random content encryption key with.
+    Rsa {
+        modulus: BigUint,
+        exponent: BigUint,
+        algorithm: RsaOaep,
+    },
+}
+
+/// A public key in the [JWK](https://tools.ietf.org/html/rfc7517) format,
+/// used to encrypt data into a JWE.
+///
+/// Data is always encrypted with `A256GCM`. The content encryption key is either
+///
+/// * derived by direct key agreement (`ECDH-ES`), for elliptic curve keys on the P-256 curve, or
+/// * random and encrypted with `RSA-OAEP-256` (or `RSA-OAEP`, if the JWK's `alg` asks for it),
+///   for RSA keys of at least 2048 bits.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Jwk {
+    /// The key ID, included in the JWE header if present.
+    kid: Option<String>,
+    /// The public key.
+    key: PublicKey,
+}
+
+impl Jwk {
+    /// Parse a public key from its JSON representation.
+    ///
+    /// ## Arguments
+    ///
+    /// * `json` - The JWK, e.g. `{"kty":"EC","crv":"P-256","x":"...","y":"..."}`
+    ///   or `{"kty":"RSA","n":"...","e":"AQAB"}`.
+    ///
+    /// ## Return value
+    ///
+    /// Returns an error if the JWK is malformed, not a public key of a supported type
+    /// or not meant for encryption.
+    pub fn from_json(json: &str) -> GleanResult<Self> {
+        let invalid = |msg: &str| ErrorKind::InvalidJwk(msg.to_string());
+
+        let jwk: JsonValue =
+            serde_json::from_str(json).map_err(|_| invalid("not a JSON object"))?;
+        let field = |name| jwk.get(name).and_then(JsonValue::as_str);
+
+        if field("use").map_or(false, |key_use| key_use != "enc") {
+            return Err(invalid("key is not meant for encryption").into());
+        }
+        if jwk.get("d").is_some() {
+            return Err(invalid("private keys must not be shipped").into());
+        }
+
+        let key = match field("kty") {
+            Some("EC") => Self::ec_key(&jwk)?,
+            Some("RSA") => Self::rsa_key(&jwk)?,
+            Some(kty) => return Err(invalid(&format!("unsupported key type '{}'", kty)).into()),
+            None => return Err(invalid("missing key type").into()),
+        };
+
+        Ok(Self {
+            kid: field("kid").map(String::from),
+            key,
+        })
+    }
+
+    fn ec_key(jwk: &JsonValue) -> Result<PublicKey, ErrorKind> {
+        let invalid = |msg: &str| ErrorKind::InvalidJwk(msg.to_string());
+        let field = |name| jwk.get(name).and_then(JsonValue::as_str);
+
+        if field("crv") != Some("P-256") {
+            return Err(invalid("only the P-256 curve is supported"));
+        }
+
+        let coordinate = |name| {
+            field(name)
+                .and_then(|value| base64::decode_config(value, base64::URL_SAFE_NO_PAD).ok())
+                .filter(|value| value.len() == 32)
+                .ok_or_else(|| invalid(&format!("invalid '{}' coordinate", name)))
+        };
+        let point = EncodedPoint::from_affine_coordinates(
+            GenericArray::from_slice(&coordinate("x")?),
+            GenericArray::from_slice(&coordinate("y")?),
+            false,
+        );
+        if !bool::from(AffinePoint::from_encoded_point(&point).is_some()) {
+            return Err(invalid("not a point on the curve"));
+        }
+
+        Ok(PublicKey::Ec(point))
+    }
+
+    fn rsa_key(jwk: &JsonValue) -> Result<PublicKey, ErrorKind> {
+        let invalid = |msg: &str| ErrorKind::InvalidJwk(msg.to_string());
+        let field = |name| jwk.get(name).and_then(JsonValue::as_str);
+
+        let algorithm = match field("alg") {
+            None | Some("RSA-OAEP-256") => RsaOaep::Sha256,
+            Some("RSA-OAEP") => RsaOaep::Sha1,
+            Some(alg) => return Err(invalid(&format!("unsupported algorithm '{}'", alg))),
+        };
+
+        let number = |name| {
+            field(name)
+                .and_then(|value| base64::decode_config(value, base64::URL_SAFE_NO_PAD).ok())
+                .filter(|value| !value.is_empty())
+                .map(|value| BigUint::from_bytes_be(&value))
+                .ok_or_else(|| invalid(&format!("invalid '{}' parameter", name)))
+        };
+        let modulus = number("n")?;
+        let exponent = number("e")?;
+        if modulus.bits() < MIN_RSA_KEY_BITS {
+            return Err(invalid("RSA keys must be at least 2048 bits"));
+        }
+        if exponent <= BigUint::from(1u32)
+            || exponent >= modulus
+            || &exponent % 2u32 == BigUint::from(0u32)
+        {
+            return Err(invalid("invalid 'e' parameter"));
+        }
+
+        Ok(PublicKey::Rsa {
+            modulus,
+            exponent,
+            algorithm,
+        })
+    }
+}
+
+/// Derive the content encryption key from a shared secret,
+/// using the [Concat KDF](https://tools.ietf.org/html/rfc7518#section-4.6.2).
+///
+/// As no `apu` and `apv` header parameters are used, the party info is empty.
+fn concat_kdf(shared_secret: &[u8]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    // A 256-bit key requires a single round.
+    hasher.update(1u32.to_be_bytes());
+    hasher.update(shared_secret);
+    hasher.update((CONTENT_ENCRYPTION.len() as u32).to_be_bytes());
+    hasher.update(CONTENT_ENCRYPTION.as_bytes());
+    hasher.update(0u32.to_be_bytes());
+    hasher.update(0u32.to_be_bytes());
+    hasher.update(256u32.to_be_bytes());
+
+    let mut key = [0u8; 32];
+    key.copy_from_slice(&hasher.finalize());
+    key
+}
+
+/// Apply a mask to the data, byte by byte.
+fn xor(data: &mut [u8], mask: &[u8]) {
+    for (byte, mask) in data.iter_mut().zip(mask) {
+        *byte ^= mask;
+    }
+}
+
+/// The mask generation function [MGF1](https://tools.ietf.org/html/rfc8017#appendix-B.2.1).
+fn mgf1<D: Digest>(seed: &[u8], len: usize) -> Vec<u8> {
+    let mut mask = Vec::with_capacity(len + D::output_size());
+    let mut counter = 0u32;
+    while mask.len() < len {
+        let mut hasher = D::new();
+        hasher.update(seed);
+        hasher.update(counter.to_be_bytes());
+        mask.extend_from_slice(&hasher.finalize());
+        counter += 1;
+    }
+    mask.truncate(len);
+    mask
+}
+
+/// Encrypt a message with [RSAES-OAEP](https://tools.ietf.org/html/rfc8017#section-7.1.1),
+/// using an empty label.
+///
+/// The result has the length of the modulus.
+fn rsa_oaep_encrypt<D: Digest>(
+    modulus: &BigUint,
+    exponent: &BigUint,
+    message: &[u8],
+) -> GleanResult<Vec<u8>> {
+    let len = (modulus.bits() + 7) / 8;
+    let hash_len = D::output_size();
+    if message.len() + 2 * hash_len + 2 > len {
+        return Err(ErrorKind::Encryption.into());
+    }
+
+    // The data block is the hash of the label, zero padding, a 0x01 separator and the message.
+    let mut data_block = D::digest(&[]).to_vec();
+    data_block.resize(len - message.len() - hash_len - 2, 0);
+    data_block.push(1);
+    data_block.extend_from_slice(message);
+
+    let mut seed = vec![0u8; hash_len];
+    getrandom::getrandom(&mut seed).map_err(|_| ErrorKind::Encryption)?;
+    let data_block_mask = mgf1::<D>(&seed, data_block.len());
+    xor(&mut data_block, &data_block_mask);
+    xor(&mut seed, &mgf1::<D>(&data_block, hash_len));
+
+    let mut encoded = Vec::with_capacity(len);
+    encoded.push(0);
+    encoded.extend(seed);
+    encoded.extend(data_block);
+
+    let cipher_text = BigUint::from_bytes_be(&encoded)
+        .modpow(exponent, modulus)
+        .to_bytes_be();
+    let mut result = vec![0u8; len - cipher_text.len()];
+    result.extend(cipher_text);
+    Ok(result)
+}
+
+impl Jwe {
+    /// Encrypt data for the owner of the given public key.
+    ///
+    /// For EC keys, the resulting JWE has an empty encrypted key, as the content encryption key
+    /// is derived from an ephemeral key agreement.
+    /// It is not subject to the size limits of the JWE metric.
+    pub(crate) fn encrypt(key: &Jwk, plaintext: &[u8]) -> GleanResult<Self> {
+        let (mut header, content_key, encrypted_key) = match &key.key {
+            PublicKey::Ec(point) => {
+                let ephemeral = EphemeralSecret::random(&mut OsRng);
+                let ephemeral_public = ephemeral.public_key();
+                let shared_secret = ephemeral
+                    .diffie_hellman(point)
+                    .map_err(|_| ErrorKind::Encryption)?;
+
+                let header = json!({
+                    "alg": "ECDH-ES",
+                    "enc": CONTENT_ENCRYPTION,
+                    "epk": {
+                        "kty": "EC",
+                        "crv": "P-256",
+                        "x": base64url(ephemeral_public.x()),
+                        // safe unwrap, the public key is never compressed
+                        "y": base64url(ephemeral_public.y().unwrap()),
+                    },
+                });
+                (header, concat_kdf(shared_secret.as_bytes()), Vec::new())
+            }
+            PublicKey::Rsa {
+                modulus,
+                exponent,
+                algorithm,
+            } => {
+                let mut content_key = [0u8; CONTENT_KEY_LENGTH];
+                getrandom::getrandom(&mut content_key).map_err(|_| ErrorKind::Encryption)?;
+                let encrypted_key = match algorithm {
+                    RsaOaep::Sha1 => rsa_oaep_encrypt::<Sha1>(modulus, exponent, &content_key)?,
+                    RsaOaep::Sha256 => rsa_oaep_encrypt::<Sha256>(modulus, exponent, &content_key)?,
+                };
+
+                let header = json!({
+                    "alg": algorithm.name(),
+                    "enc": CONTENT_ENCRYPTION,
+                });
+                (header, content_key, encrypted_key)
+            }
+        };
+        if let Some(kid) = &key.kid {
+            // safe unwrap, we created the object above
+            header
+                .as_object_mut()
+                .unwrap()
+                .insert("kid".into(), JsonValue::String(kid.clone()));
+        }
+        let header = base64url(serde_json::to_string(&header)?.as_bytes());
+
+        let mut init_vector = [0u8; INIT_VECTOR_LENGTH];
+        getrandom::getrandom(&mut init_vector).map_err(|_| ErrorKind::Encryption)?;
+
+        let cipher = Aes256Gcm::new(GenericArray::from_slice(&content_key));
+        let mut cipher_text = cipher
+            .encrypt(
+                GenericArray::from_slice(&init_vector),
+                Payload {
+                    msg: plaintext,
+                    aad: header.as_bytes(),
+                },
+            )
+            .map_err(|_| ErrorKind::Encryption)?;
+        // The authentication tag is appended to the cipher text.
+        let auth_tag = cipher_text.split_off(cipher_text.len() - 16);
+
+        Ok(Self {
+            header,
+            key: base64url(&encrypted_key),
+            init_vector: base64url(&init_vector),
+            cipher_text: base64url(&cipher_text),
+            auth_tag: base64url(&auth_tag),
+        })
+    }
+}
+
+/// Encrypt data into a JWE for the owner of the given public key.
+///
+/// ## Return value
+///
+/// Returns the [`compact representation`](https://tools.ietf.org/html/rfc7516#appendix-A.2.7)
+/// of the JWE.
+pub(crate) fn encrypt_to_compact(key: &Jwk, plaintext: &[u8]) -> GleanResult<String> {
+    Ok(Jwe::encrypt(key, plaintext)?.to_string())
+}
+
 /// A JWE metric.
 ///
 /// This metric will be work as a "transport" for JWE encrypted data.
@@ -242,9 +594,7 @@ impl JweMetric {
 
         let value = value.into();
         match Jwe::from_str(&value) {
-            Ok(_) => glean
-                .storage()
-                .record(glean, &self.meta, &Metric::Jwe(value)),
+            Ok(_) => glean.record_metric(&self.meta, &Metric::Jwe(value)),
             Err((error_type, msg)) => record_error(glean, &self.meta, error_type, msg, None),
         };
     }
@@ -273,9 +623,7 @@ impl JweMetric {
         }
 
         match Jwe::new(header, key, init_vector, cipher_text, auth_tag) {
-            Ok(jwe) => glean
-                .storage()
-                .record(glean, &self.meta, &Metric::Jwe(jwe.to_string())),
+            Ok(jwe) => glean.record_metric(&self.meta, &Metric::Jwe(jwe.to_string())),
             Err((error_type, msg)) => record_error(glean, &self.meta, error_type, msg, None),
         };
     }
@@ -316,9 +664,235 @@ impl JweMetric {
 }
 
 #[cfg(test)]
-mod test {
+pub(crate) mod test {
     use super::*;
 
+    // A 2048-bit RSA key, generated with `openssl genpkey -algorithm RSA`.
+    const RSA_MODULUS: &str = "vOShFDsCqXxAEMBnGD9j6oizW2bn1gj46ZMRR14Ormk-sIgY3Me3A2y-DrKJcLyYemEP99zzTZFkgX0RdWgZf7u_pANsn62ebwy4ic70wQ96l9tN8C0GoQ31PDWvY3MBv3ScsvKejoJEq1sucGdOmDD14NRG7NoG1CDUZZIB3TV4IIrMUxwVA0Bv_w91TxkXaJyotpWO05FNyC3QCXYnq73DsQuWlOMN5uoz20D2sgHiL4Rfojl9V4S3p0LaJhvm83H2ttRSUqB8RixEXlrgUAMokG6z8Bmvl0c7he3uEw6xAGDjcL-mVzDCzoMSDxzCN4osqM7nuyfshPtDh-w6Tw";
+    const RSA_PRIVATE_EXPONENT: &str = "Dxww4Hfp-hnU3CIv_QoRGZFO26hrMj1sf-7CSpW1EfGcUK3KY1rcgnsf5vgUM376qYt7JgY4NkPDqPISw_QMWiHwLhQSC-HtExAO8QMrpAfzdBNYdDZ92cNOkz7uJUPtZ13SLSRoV5kHGjgEinxRFq6P9Ki2dbrpIe3wX6Gi4YmCqk7YnV2uyvZybHfLM9f8F01wazEutwXY1NJp7wHKIHhj6JIAuE6LVql1grZMiHZ96Z_AGV2D-RKT8rmoNCjnmAarHQVy1WXTMK26ZKgqk83KfIO6iUq4pvFbEx6SsagzDWXY_xrl1hQuNbH07WQILulWPlnZ0Lbof0n7qL4dBQ";
+
+    /// The private key to decrypt data with in tests.
+    pub(crate) enum PrivateKey {
+        Ec(EphemeralSecret),
+        Rsa { modulus: BigUint, exponent: BigUint },
+    }
+
+    /// Generate a key pair to encrypt data for in tests.
+    pub(crate) fn key_pair() -> (PrivateKey, Jwk) {
+        let secret = EphemeralSecret::random(&mut OsRng);
+        let public = secret.public_key();
+        let jwk = json!({
+            "kty": "EC",
+            "crv": "P-256",
+            "kid": "test-key",
+            "x": base64url(public.x()),
+            "y": base64url(public.y().unwrap()),
+        });
+        (
+            PrivateKey::Ec(secret),
+            Jwk::from_json(&jwk.to_string()).unwrap(),
+        )
+    }
+
+    /// Get the test RSA key pair, using the given algorithm, if any.
+    pub(crate) fn rsa_key_pair(alg: Option<&str>) -> (PrivateKey, Jwk) {
+        let mut jwk = json!({
+            "kty": "RSA",
+            "kid": "test-rsa-key",
+            "n": RSA_MODULUS,
+            "e": "AQAB",
+        });
+        if let Some(alg) = alg {
+            jwk["alg"] = json!(alg);
+        }
+        let number = |value| {
+            BigUint::from_bytes_be(&base64::decode_config(value, base64::URL_SAFE_NO_PAD).unwrap())
+        };
+        let secret = PrivateKey::Rsa {
+            modulus: number(RSA_MODULUS),
+            exponent: number(RSA_PRIVATE_EXPONENT),
+        };
+        (secret, Jwk::from_json(&jwk.to_string()).unwrap())
+    }
+
+    /// Decrypt an encrypted key produced by `rsa_oaep_encrypt`.
+    fn rsa_oaep_decrypt<D: Digest>(
+        modulus: &BigUint,
+        exponent: &BigUint,
+        encrypted_key: &[u8],
+    ) -> Option<Vec<u8>> {
+        let len = (modulus.bits() + 7) / 8;
+        let hash_len = D::output_size();
+        let decrypted = BigUint::from_bytes_be(encrypted_key)
+            .modpow(exponent, modulus)
+            .to_bytes_be();
+        let mut encoded = vec![0u8; len - decrypted.len()];
+        encoded.extend(decrypted);
+
+        let (seed, data_block) = encoded[1..].split_at_mut(hash_len);
+        xor(seed, &mgf1::<D>(data_block, hash_len));
+        xor(data_block, &mgf1::<D>(seed, data_block.len()));
+        if encoded[0] != 0 || encoded[1 + hash_len..1 + 2 * hash_len] != D::digest(&[])[..] {
+            return None;
+        }
+        let separator = encoded[1 + 2 * hash_len..].iter().position(|&b| b != 0)?;
+        let message = &encoded[1 + 2 * hash_len + separator..];
+        if message[0] != 1 {
+            return None;
+        }
+        Some(message[1..].to_vec())
+    }
+
+    /// Decrypt a compact JWE produced by `Jwe::encrypt`.
+    pub(crate) fn decrypt(secret: &PrivateKey, compact: &str) -> Option<Vec<u8>> {
+        // Not parsed with `Jwe::from_str`, payloads exceed the size limits of the metric.
+        let elements: Vec<&str> = compact.split('.').collect();
+        if elements.len() != 5 {
+            return None;
+        }
+        let jwe = Jwe {
+            header: elements[0].into(),
+            key: elements[1].into(),
+            init_vector: elements[2].into(),
+            cipher_text: elements[3].into(),
+            auth_tag: elements[4].into(),
+        };
+        let decode = |value: &str| base64::decode_config(value, base64::URL_SAFE_NO_PAD).ok();
+
+        let header: JsonValue = serde_json::from_slice(&decode(&jwe.header)?).ok()?;
+        assert_eq!("A256GCM", header["enc"]);
+        let content_key = match secret {
+            PrivateKey::Ec(secret) => {
+                assert_eq!("ECDH-ES", header["alg"]);
+                assert_eq!("", jwe.key);
+                let epk = EncodedPoint::from_affine_coordinates(
+                    GenericArray::from_slice(&decode(header["epk"]["x"].as_str()?)?),
+                    GenericArray::from_slice(&decode(header["epk"]["y"].as_str()?)?),
+                    false,
+                );
+                let shared_secret = secret.diffie_hellman(&epk).ok()?;
+                concat_kdf(shared_secret.as_bytes()).to_vec()
+            }
+            PrivateKey::Rsa { modulus, exponent } => {
+                let encrypted_key = decode(&jwe.key)?;
+                match header["alg"].as_str()? {
+                    "RSA-OAEP" => rsa_oaep_decrypt::<Sha1>(modulus, exponent, &encrypted_key)?,
+                    "RSA-OAEP-256" => {
+                        rsa_oaep_decrypt::<Sha256>(modulus, exponent, &encrypted_key)?
+                    }
+                    alg => panic!("Unexpected algorithm {}", alg),
+                }
+            }
+        };
+
+        let mut cipher_text = decode(&jwe.cipher_text)?;
+        cipher_text.extend(decode(&jwe.auth_tag)?);
+        Aes256Gcm::new(GenericArray::from_slice(&content_key))
+            .decrypt(
+                GenericArray::from_slice(&decode(&jwe.init_vector)?),
+                Payload {
+                    msg: &cipher_text,
+                    aad: jwe.header.as_bytes(),
+                },
+            )
+            .ok()
+    }
+
+    #[test]
+    fn encrypted_data_round_trips() {
+        let (secret, jwk) = key_pair();
+        let compact = encrypt_to_compact(&jwk, b"some data").unwrap();
+
+        assert!(!compact.contains("some data"));
+        assert_eq!(b"some data".to_vec(), decrypt(&secret, &compact).unwrap());
+
+        let (other_secret, _) = key_pair();
+        assert!(decrypt(&other_secret, &compact).is_none());
+    }
+
+    #[test]
+    fn rsa_encrypted_data_round_trips() {
+        for &(alg, expected) in &[
+            (None, "RSA-OAEP-256"),
+            (Some("RSA-OAEP-256"), "RSA-OAEP-256"),
+            (Some("RSA-OAEP"), "RSA-OAEP"),
+        ] {
+            let (secret, jwk) = rsa_key_pair(alg);
+            let compact = encrypt_to_compact(&jwk, b"some data").unwrap();
+            assert!(!compact.contains("some data"));
+
+            let elements: Vec<&str> = compact.split('.').collect();
+            let header = base64::decode_config(elements[0], base64::URL_SAFE_NO_PAD).unwrap();
+            let header: JsonValue = serde_json::from_slice(&header).unwrap();
+            assert_eq!(expected, header["alg"]);
+            assert_eq!("test-rsa-key", header["kid"]);
+            // The encrypted key has the size of the 2048-bit modulus.
+            assert_eq!(
+                256,
+                base64::decode_config(elements[1], base64::URL_SAFE_NO_PAD)
+                    .unwrap()
+                    .len()
+            );
+
+            assert_eq!(b"some data".to_vec(), decrypt(&secret, &compact).unwrap());
+        }
+
+        // The content encryption key is random.
+        let (_, jwk) = rsa_key_pair(None);
+        let first = encrypt_to_compact(&jwk, b"some data").unwrap();
+        let second = encrypt_to_compact(&jwk, b"some data").unwrap();
+        assert_ne!(first.split('.').nth(1), second.split('.').nth(1));
+    }
+
+    #[test]
+    fn jwks_are_validated() {
+        let (_, jwk) = key_pair();
+        let point = match jwk.key {
+            PublicKey::Ec(point) => point,
+            _ => unreachable!(),
+        };
+        let x = base64url(point.x());
+        let y = base64url(point.y().unwrap());
+        let ec_key = |crv: &str, x: &str, y: &str| {
+            format!(r#"{{"kty":"EC","crv":"{}","x":"{}","y":"{}"}}"#, crv, x, y)
+        };
+
+        let parsed = Jwk::from_json(&ec_key("P-256", &x, &y)).unwrap();
+        assert_eq!(PublicKey::Ec(point), parsed.key);
+        assert_eq!(None, parsed.kid);
+
+        assert!(Jwk::from_json("not json").is_err());
+        assert!(Jwk::from_json(r#"{"kty":"oct","k":"AQAB"}"#).is_err());
+        assert!(Jwk::from_json(&ec_key("P-384", &x, &y)).is_err());
+        assert!(Jwk::from_json(&ec_key("P-256", &x, "AAAA")).is_err());
+        // A point that is not on the curve.
+        assert!(Jwk::from_json(&ec_key("P-256", &x, &x)).is_err());
+        assert!(Jwk::from_json(&format!(
+            r#"{{"kty":"EC","crv":"P-256","use":"sig","x":"{}","y":"{}"}}"#,
+            x, y
+        ))
+        .is_err());
+
+        let rsa_key = |fields: &str| {
+            Jwk::from_json(&format!(
+                r#"{{"kty":"RSA","n":"{}","e":"AQAB"{}}}"#,
+                RSA_MODULUS, fields
+            ))
+        };
+        assert!(rsa_key("").is_ok());
+        assert!(rsa_key(r#","alg":"RSA-OAEP""#).is_ok());
+        assert!(rsa_key(r#","alg":"RSA1_5""#).is_err());
+        assert!(rsa_key(r#","use":"sig""#).is_err());
+        assert!(rsa_key(&format!(r#","d":"{}""#, RSA_PRIVATE_EXPONENT)).is_err());
+        let short = Jwk::from_json(r#"{"kty":"RSA","n":"AQAB","e":"AQAB"}"#).unwrap_err();
+        assert!(short.to_string().contains("at least 2048 bits"));
+        assert!(Jwk::from_json(&format!(
+            r#"{{"kty":"RSA","n":"{}","e":"AQ"}}"#,
+            RSA_MODULUS
+        ))
+        .is_err());
+    }
+
     const HEADER: &str = "eyJhbGciOiJSU0EtT0FFUCIsImVuYyI6IkEyNTZHQ00ifQ";
     const KEY: &str = "OKOawDo13gRp2ojaHV7LFpZcgV7T6DVZKTyKOMTYUmKoTCVJRgckCL9kiMT03JGeipsEdY3mx_etLbbWSrFr05kLzcSr4qKAq7YN7e9jwQRb23nfa6c9d-StnImGyFDbSv04uVuxIp5Zms1gNxKKK2Da14B8S4rzVRltdYwam_lDp5XnZAYpQdb76FdIKLaVmqgfwX7XWRxv2322i-vDxRfqNzo_tETKzpVLzfiwQyeyPGLBIO56YJ7eObdv0je81860ppamavo35UgoRdbYaBcoh9QcfylQr66oc6vFWXRcZ_ZT2LawVCWTIy3brGPi6UklfCpIMfIjf7iGdXKHzg";
     const INIT_VECTOR: &str = "48V1_ALb6US04U3b";
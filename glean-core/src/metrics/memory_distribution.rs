@@ -23,7 +23,7 @@ const MAX_BYTES: u64 = 1 << 40;
 /// A memory distribution metric.
 ///
 /// Memory distributions are used to accumulate and store memory sizes.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct MemoryDistributionMetric {
     meta: CommonMetricData,
     memory_unit: MemoryUnit,
@@ -81,19 +81,17 @@ impl MemoryDistributionMetric {
             sample = MAX_BYTES;
         }
 
-        glean
-            .storage()
-            .record_with(glean, &self.meta, |old_value| match old_value {
-                Some(Metric::MemoryDistribution(mut hist)) => {
-                    hist.accumulate(sample);
-                    Metric::MemoryDistribution(hist)
-                }
-                _ => {
-                    let mut hist = Histogram::functional(LOG_BASE, BUCKETS_PER_MAGNITUDE);
-                    hist.accumulate(sample);
-                    Metric::MemoryDistribution(hist)
-                }
-            });
+        glean.record_metric_with(&self.meta, |old_value| match old_value {
+            Some(Metric::MemoryDistribution(mut hist)) => {
+                hist.accumulate(sample);
+                Metric::MemoryDistribution(hist)
+            }
+            _ => {
+                let mut hist = Histogram::functional(LOG_BASE, BUCKETS_PER_MAGNITUDE);
+                hist.accumulate(sample);
+                Metric::MemoryDistribution(hist)
+            }
+        });
     }
 
     /// Accumulates the provided signed samples in the metric.
@@ -123,7 +121,7 @@ impl MemoryDistributionMetric {
         let mut num_negative_samples = 0;
         let mut num_too_log_samples = 0;
 
-        glean.storage().record_with(glean, &self.meta, |old_value| {
+        glean.record_metric_with(&self.meta, |old_value| {
             let mut hist = match old_value {
                 Some(Metric::MemoryDistribution(hist)) => hist,
                 _ => Histogram::functional(LOG_BASE, BUCKETS_PER_MAGNITUDE),
@@ -10,13 +10,14 @@ use chrono::{DateTime, FixedOffset};
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value as JsonValue};
 
+mod accumulator;
 mod boolean;
 mod counter;
 mod custom_distribution;
 mod datetime;
 mod event;
 mod experiment;
-mod jwe;
+pub(crate) mod jwe;
 mod labeled;
 mod memory_distribution;
 mod memory_unit;
@@ -36,6 +37,7 @@ use crate::util::get_iso_time_string;
 use crate::CommonMetricData;
 use crate::Glean;
 
+pub use self::accumulator::Accumulator;
 pub use self::boolean::BooleanMetric;
 pub use self::counter::CounterMetric;
 pub use self::datetime::DatetimeMetric;
@@ -47,16 +49,18 @@ pub use crate::histogram::HistogramType;
 pub use self::custom_distribution::CustomDistributionMetric;
 #[cfg(test)]
 pub(crate) use self::experiment::RecordedExperimentData;
-pub use self::jwe::JweMetric;
+pub(crate) use self::jwe::encrypt_to_compact;
+pub use self::jwe::{JweMetric, Jwk};
 pub use self::labeled::{
     combine_base_identifier_and_label, dynamic_label, strip_label, LabeledMetric,
 };
 pub use self::memory_distribution::MemoryDistributionMetric;
 pub use self::memory_unit::MemoryUnit;
-pub use self::ping::PingType;
+pub use self::ping::{PingEncryption, PingType};
 pub use self::quantity::QuantityMetric;
 pub use self::string::StringMetric;
 pub use self::string_list::StringListMetric;
+pub(crate) use self::string_list::MAX_LIST_LENGTH;
 pub use self::time_unit::TimeUnit;
 pub use self::timespan::TimespanMetric;
 pub use self::timing_distribution::TimerId;
@@ -3,8 +3,23 @@
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
 use crate::error::Result;
+use crate::metrics::Jwk;
+use crate::upload::{strip_reserved_headers, HeaderMap, NetworkPolicy};
 use crate::Glean;
 
+/// How the payload of a ping is encrypted before it is stored for upload.
+#[derive(Clone, Debug)]
+pub struct PingEncryption {
+    /// The public key to encrypt with.
+    pub key: Jwk,
+    /// The identifiers of the metrics to encrypt, e.g. `category.name`.
+    ///
+    /// Each of these metrics is moved into the `jwe` section of the payload,
+    /// under its own identifier.
+    /// If `None`, the whole payload is encrypted.
+    pub metrics: Option<Vec<String>>,
+}
+
 /// Stores information about a ping.
 ///
 /// This is required so that given metric data queued on disk we can send
@@ -19,6 +34,14 @@ pub struct PingType {
     pub send_if_empty: bool,
     /// The "reason" codes that this ping can send
     pub reason_codes: Vec<String>,
+    /// How the payload is encrypted, if at all
+    pub encryption: Option<PingEncryption>,
+    /// The upload priority. Pings with a higher priority are uploaded first.
+    pub priority: u8,
+    /// Custom HTTP headers sent with this ping, in addition to the global upload headers.
+    pub headers: HeaderMap,
+    /// The network conditions under which this ping is uploaded.
+    pub network_policy: NetworkPolicy,
 }
 
 impl PingType {
@@ -42,9 +65,69 @@ impl PingType {
             include_client_id,
             send_if_empty,
             reason_codes,
+            encryption: None,
+            priority: 0,
+            headers: HeaderMap::new(),
+            network_policy: NetworkPolicy::default(),
         }
     }
 
+    /// Set the upload priority of this ping.
+    ///
+    /// Pending pings with a higher priority are uploaded before those with a lower one.
+    /// `deletion-request` pings are always uploaded first, independent of their priority.
+    ///
+    /// ## Arguments
+    ///
+    /// * `priority` - The upload priority. Defaults to `0`, the lowest priority.
+    pub fn with_priority(mut self, priority: u8) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Set custom HTTP headers to send with this ping.
+    ///
+    /// These take precedence over the global headers set with `Glean::set_upload_headers`.
+    /// Headers set by Glean itself (e.g. `Content-Type` or `X-Debug-ID`) can't be overridden
+    /// and are ignored, as are malformed headers (see `Glean::set_upload_headers`).
+    ///
+    /// ## Arguments
+    ///
+    /// * `headers` - A map of header names to values.
+    pub fn with_headers(mut self, mut headers: HeaderMap) -> Self {
+        strip_reserved_headers(&mut headers);
+        self.headers = headers;
+        self
+    }
+
+    /// Set the network conditions under which this ping is uploaded.
+    ///
+    /// The policy is persisted with the ping, so it also applies to pings uploaded after a restart.
+    /// See `Glean::set_network_state`.
+    ///
+    /// ## Arguments
+    ///
+    /// * `network_policy` - The network policy. Defaults to `NetworkPolicy::Any`.
+    pub fn with_network_policy(mut self, network_policy: NetworkPolicy) -> Self {
+        self.network_policy = network_policy;
+        self
+    }
+
+    /// Encrypt the payload of this ping with the given public key.
+    ///
+    /// ## Arguments
+    ///
+    /// * `key` - The public key of the recipient.
+    /// * `encrypted_metrics` - The identifiers of the metrics to encrypt.
+    ///   If `None`, the whole payload is encrypted.
+    pub fn with_encryption(mut self, key: Jwk, encrypted_metrics: Option<Vec<String>>) -> Self {
+        self.encryption = Some(PingEncryption {
+            key,
+            metrics: encrypted_metrics,
+        });
+        self
+    }
+
     /// Submit the ping for eventual uploading
     ///
     /// ## Arguments
@@ -59,9 +59,7 @@ impl QuantityMetric {
             return;
         }
 
-        glean
-            .storage()
-            .record(glean, &self.meta, &Metric::Quantity(value))
+        glean.record_metric(&self.meta, &Metric::Quantity(value))
     }
 
     /// **Test-only API (exported for FFI purposes).**
@@ -54,7 +54,7 @@ impl StringMetric {
         let s = truncate_string_at_boundary_with_error(glean, &self.meta, value, MAX_LENGTH_VALUE);
 
         let value = Metric::String(s);
-        glean.storage().record(glean, &self.meta, &value)
+        glean.record_metric(&self.meta, &value)
     }
 
     /// **Test-only API (exported for FFI purposes).**
@@ -11,7 +11,7 @@ use crate::CommonMetricData;
 use crate::Glean;
 
 // Maximum length of any list
-const MAX_LIST_LENGTH: usize = 20;
+pub(crate) const MAX_LIST_LENGTH: usize = 20;
 // Maximum length of any string in the list
 const MAX_STRING_LENGTH: usize = 50;
 
@@ -57,24 +57,22 @@ impl StringListMetric {
         let value =
             truncate_string_at_boundary_with_error(glean, &self.meta, value, MAX_STRING_LENGTH);
         let mut error = None;
-        glean
-            .storage()
-            .record_with(glean, &self.meta, |old_value| match old_value {
-                Some(Metric::StringList(mut old_value)) => {
-                    if old_value.len() == MAX_LIST_LENGTH {
-                        let msg = format!(
-                            "String list length of {} exceeds maximum of {}",
-                            old_value.len() + 1,
-                            MAX_LIST_LENGTH
-                        );
-                        error = Some(msg);
-                    } else {
-                        old_value.push(value.clone());
-                    }
-                    Metric::StringList(old_value)
+        glean.record_metric_with(&self.meta, |old_value| match old_value {
+            Some(Metric::StringList(mut old_value)) => {
+                if old_value.len() == MAX_LIST_LENGTH {
+                    let msg = format!(
+                        "String list length of {} exceeds maximum of {}",
+                        old_value.len() + 1,
+                        MAX_LIST_LENGTH
+                    );
+                    error = Some(msg);
+                } else {
+                    old_value.push(value.clone());
                 }
-                _ => Metric::StringList(vec![value.clone()]),
-            });
+                Metric::StringList(old_value)
+            }
+            _ => Metric::StringList(vec![value.clone()]),
+        });
 
         if let Some(msg) = error {
             record_error(glean, &self.meta, ErrorType::InvalidValue, msg, None);
@@ -118,7 +116,7 @@ impl StringListMetric {
             .collect();
 
         let value = Metric::StringList(value);
-        glean.storage().record(glean, &self.meta, &value);
+        glean.record_metric(&self.meta, &value);
     }
 
     /// **Test-only API (exported for FFI purposes).**
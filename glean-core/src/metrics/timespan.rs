@@ -128,7 +128,7 @@ impl TimespanMetric {
         }
 
         let mut report_value_exists: bool = false;
-        glean.storage().record_with(glean, &self.meta, |old_value| {
+        glean.record_metric_with(&self.meta, |old_value| {
             if overwrite {
                 Metric::Timespan(elapsed, self.time_unit)
             } else {
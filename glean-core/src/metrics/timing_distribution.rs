@@ -93,7 +93,7 @@ impl Timings {
 /// A timing distribution metric.
 ///
 /// Timing distributions are used to accumulate and store time measurement, for analyzing distributions of the timing data.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct TimingDistributionMetric {
     meta: CommonMetricData,
     time_unit: TimeUnit,
@@ -191,19 +191,17 @@ impl TimingDistributionMetric {
             return;
         }
 
-        glean
-            .storage()
-            .record_with(glean, &self.meta, |old_value| match old_value {
-                Some(Metric::TimingDistribution(mut hist)) => {
-                    hist.accumulate(duration);
-                    Metric::TimingDistribution(hist)
-                }
-                _ => {
-                    let mut hist = Histogram::functional(LOG_BASE, BUCKETS_PER_MAGNITUDE);
-                    hist.accumulate(duration);
-                    Metric::TimingDistribution(hist)
-                }
-            });
+        glean.record_metric_with(&self.meta, |old_value| match old_value {
+            Some(Metric::TimingDistribution(mut hist)) => {
+                hist.accumulate(duration);
+                Metric::TimingDistribution(hist)
+            }
+            _ => {
+                let mut hist = Histogram::functional(LOG_BASE, BUCKETS_PER_MAGNITUDE);
+                hist.accumulate(duration);
+                Metric::TimingDistribution(hist)
+            }
+        });
     }
 
     /// Abort a previous `set_start` call. No error is recorded if no `set_start`
@@ -240,12 +238,12 @@ impl TimingDistributionMetric {
     /// Discards any negative value in `samples` and report an `ErrorType::InvalidValue`
     /// for each of them. Reports an `ErrorType::InvalidOverflow` error for samples that
     /// are longer than `MAX_SAMPLE_TIME`.
-    pub fn accumulate_samples_signed(&mut self, glean: &Glean, samples: Vec<i64>) {
+    pub fn accumulate_samples_signed(&self, glean: &Glean, samples: Vec<i64>) {
         let mut num_negative_samples = 0;
         let mut num_too_long_samples = 0;
         let max_sample_time = self.time_unit.as_nanos(MAX_SAMPLE_TIME);
 
-        glean.storage().record_with(glean, &self.meta, |old_value| {
+        glean.record_metric_with(&self.meta, |old_value| {
             let mut hist = match old_value {
                 Some(Metric::TimingDistribution(hist)) => hist,
                 _ => Histogram::functional(LOG_BASE, BUCKETS_PER_MAGNITUDE),
@@ -47,7 +47,7 @@ impl UuidMetric {
 
         let s = value.to_string();
         let value = Metric::Uuid(s);
-        glean.storage().record(glean, &self.meta, &value)
+        glean.record_metric(&self.meta, &value)
     }
 
     /// Generate a new random UUID and set the metric to it.
@@ -4,24 +4,96 @@
 
 //! Ping collection, assembly & submission.
 
-use std::fs::{create_dir_all, File};
 use std::io::Write;
 use std::path::{Path, PathBuf};
 
+use chrono::Utc;
 use log::info;
 use serde_json::{json, Value as JsonValue};
 
 use crate::common_metric_data::{CommonMetricData, Lifetime};
-use crate::metrics::{CounterMetric, DatetimeMetric, Metric, MetricType, PingType, TimeUnit};
+use crate::fs::{self, create_dir_all};
+use crate::metrics::{
+    encrypt_to_compact, CounterMetric, DatetimeMetric, Metric, MetricType, PingEncryption,
+    PingType, TimeUnit,
+};
 use crate::storage::StorageManager;
+use crate::upload::{HeaderMap, NetworkPolicy};
 use crate::util::{get_iso_time_string, local_now_with_offset};
 use crate::{
     Glean, Result, DELETION_REQUEST_PINGS_DIRECTORY, INTERNAL_STORAGE, PENDING_PINGS_DIRECTORY,
 };
 
+mod observer;
+
+pub use observer::{PingObserver, PingStage};
+
+/// The content type of ping payloads that are encrypted as a whole.
+const JWE_CONTENT_TYPE: &str = "application/jose";
+
 /// Collect a ping's data, assemble it into its full payload and store it on disk.
 pub struct PingMaker;
 
+/// Whether the whole payload of the given ping is encrypted.
+fn is_payload_encrypted(ping: &PingType) -> bool {
+    ping.encryption
+        .as_ref()
+        .map_or(false, |encryption| encryption.metrics.is_none())
+}
+
+/// Move the given metrics into the `jwe` section of the payload, encrypting each of them.
+///
+/// The plaintext of each JWE has the same layout as the `metrics` section,
+/// e.g. `{"string": {"category.name": "value"}}`.
+fn encrypt_metrics(
+    content: &mut JsonValue,
+    encryption: &PingEncryption,
+    identifiers: &[String],
+) -> Result<()> {
+    let metrics = match content
+        .get_mut("metrics")
+        .and_then(JsonValue::as_object_mut)
+    {
+        Some(metrics) => metrics,
+        None => return Ok(()),
+    };
+
+    let mut encrypted = Vec::new();
+    for (metric_type, values) in metrics.iter_mut() {
+        if metric_type == "jwe" {
+            continue;
+        }
+        // safe unwrap, every metric type section is an object
+        let values = values.as_object_mut().unwrap();
+        for identifier in identifiers {
+            if let Some(value) = values.remove(identifier) {
+                let plaintext = json!({ metric_type.as_str(): { identifier.as_str(): value } });
+                let jwe = encrypt_to_compact(&encryption.key, plaintext.to_string().as_bytes())?;
+                encrypted.push((identifier.clone(), JsonValue::String(jwe)));
+            }
+        }
+    }
+    let emptied: Vec<_> = metrics
+        .iter()
+        .filter(|(_, values)| values.as_object().map_or(false, |v| v.is_empty()))
+        .map(|(metric_type, _)| metric_type.clone())
+        .collect();
+    for metric_type in emptied {
+        metrics.remove(&metric_type);
+    }
+
+    if !encrypted.is_empty() {
+        let jwe_section = metrics
+            .entry("jwe")
+            .or_insert_with(|| json!({}))
+            .as_object_mut()
+            .unwrap(); // safe unwrap, the section is an object
+        jwe_section.extend(encrypted);
+    }
+
+    Ok(())
+}
+
 fn merge(a: &mut JsonValue, b: &JsonValue) {
     match (a, b) {
         (&mut JsonValue::Object(ref mut a), &JsonValue::Object(ref b)) => {
@@ -159,13 +231,44 @@ impl PingMaker {
         json!(map)
     }
 
+    /// Build the additional headers to be persisted with a ping.
+    ///
+    /// These are the custom upload headers, the `Content-Type` of encrypted payloads
+    /// and the `X-Debug-ID` and `X-Source-Tags` headers, if set.
+    /// Headers of the ping type take precedence over the global ones.
+    pub(crate) fn get_headers(&self, glean: &Glean, ping: &PingType) -> HeaderMap {
+        // Custom headers go first, so they can't override the headers set by Glean below.
+        let mut headers: HeaderMap = glean
+            .upload_headers()
+            .iter()
+            .chain(ping.headers.iter())
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect();
+
+        if is_payload_encrypted(ping) {
+            headers.insert("Content-Type".to_string(), JWE_CONTENT_TYPE.to_string());
+        }
+        if let Some(debug_view_tag) = glean.debug_view_tag_for(&ping.name) {
+            headers.insert("X-Debug-ID".to_string(), debug_view_tag);
+        }
+        if let Some(source_tags) = glean.source_tags_for(&ping.name) {
+            headers.insert("X-Source-Tags".to_string(), source_tags.join(","));
+        }
+
+        headers
+    }
+
     /// Build the metadata JSON to be persisted with a ping.
     ///
-    /// Currently the only type of metadata we need to persist is the value of the `X-Debug-ID` header.
+    /// Currently we need to persist the name of the ping and the time it was submitted,
+    /// additional headers (the `X-Debug-ID` and `X-Source-Tags` headers,
+    /// the `Content-Type` of encrypted payloads and any custom upload headers),
+    /// and the upload priority and network policy of the ping, if they are not the default.
     ///
     /// ## Arguments
     ///
-    /// * `glean` - the Glean instance to collect metadata from.
+    /// * `ping` - the ping the metadata is persisted with.
+    /// * `headers` - the headers to send the ping with, see `get_headers`.
     ///
     /// ## Return value
     ///
@@ -175,42 +278,34 @@ impl PingMaker {
     ///
     /// ```json
     /// {
+    ///     "ping": "metrics",
+    ///     "submitted_at": 1595000000000,
     ///     "headers": {
-    ///         "X-Debug-ID": "test-tag"
-    ///     }
+    ///         "X-Debug-ID": "test-tag",
+    ///         "X-Tenant": "tenant-id"
+    ///     },
+    ///     "priority": 10,
+    ///     "network_policy": "unmetered"
     /// }
     /// ```
-    fn get_metadata(&self, glean: &Glean) -> Option<JsonValue> {
-        let mut headers_map = json!({});
-
-        if let Some(debug_view_tag) = glean.debug_view_tag() {
-            headers_map
-                .as_object_mut()
-                .unwrap() // safe unwrap, we created the object above
-                .insert(
-                    "X-Debug-ID".to_string(),
-                    JsonValue::String(debug_view_tag.to_string()),
-                );
+    fn get_metadata(&self, ping: &PingType, headers: &HeaderMap) -> JsonValue {
+        let mut metadata = json!({
+            "ping": ping.name,
+            "submitted_at": Utc::now().timestamp_millis(),
+        });
+        // safe unwraps, we created the objects above
+        let metadata_map = metadata.as_object_mut().unwrap();
+        if !headers.is_empty() {
+            metadata_map.insert("headers".to_string(), json!(headers));
         }
-
-        if let Some(source_tags) = glean.source_tags() {
-            headers_map
-                .as_object_mut()
-                .unwrap() // safe unwrap, we created the object above
-                .insert(
-                    "X-Source-Tags".to_string(),
-                    JsonValue::String(source_tags.join(",")),
-                );
+        if ping.priority > 0 {
+            metadata_map.insert("priority".to_string(), json!(ping.priority));
         }
-
-        // safe unwrap, we created the object above
-        if !headers_map.as_object().unwrap().is_empty() {
-            Some(json!({
-                "headers": headers_map,
-            }))
-        } else {
-            None
+        if ping.network_policy != NetworkPolicy::default() {
+            metadata_map.insert("network_policy".to_string(), json!(ping.network_policy));
         }
+
+        metadata
     }
 
     /// Collect a snapshot for the given ping from storage and attach required meta information.
@@ -312,17 +407,47 @@ impl PingMaker {
         Ok(pings_dir)
     }
 
+    /// Serialize the payload of a ping, encrypting it as configured for the ping.
+    ///
+    /// ## Return value
+    ///
+    /// Returns the payload as JSON, or as the compact representation of a JWE
+    /// if the whole payload is encrypted.
+    fn serialize_payload(&self, ping: &PingType, ping_content: &JsonValue) -> Result<String> {
+        let encryption = match &ping.encryption {
+            Some(encryption) => encryption,
+            None => return Ok(::serde_json::to_string(ping_content)?),
+        };
+
+        match &encryption.metrics {
+            Some(identifiers) => {
+                let mut content = ping_content.clone();
+                encrypt_metrics(&mut content, encryption, identifiers)?;
+                Ok(::serde_json::to_string(&content)?)
+            }
+            None => {
+                let payload = ::serde_json::to_string(ping_content)?;
+                encrypt_to_compact(&encryption.key, payload.as_bytes())
+            }
+        }
+    }
+
     /// Store a ping to disk in the pings directory.
+    ///
+    /// If the ping is configured to be encrypted, its payload is encrypted before storing.
+    /// The `headers` are persisted with the ping and used when uploading it.
+    #[allow(clippy::too_many_arguments)]
     pub fn store_ping(
         &self,
         glean: &Glean,
         doc_id: &str,
-        ping_name: &str,
+        ping: &PingType,
         data_path: &Path,
         url_path: &str,
         ping_content: &JsonValue,
-    ) -> std::io::Result<()> {
-        let pings_dir = self.get_pings_dir(data_path, Some(ping_name))?;
+        headers: &HeaderMap,
+    ) -> Result<()> {
+        let pings_dir = self.get_pings_dir(data_path, Some(&ping.name))?;
         let temp_dir = self.get_tmp_dir(data_path)?;
 
         // Write to a temporary location and then move when done,
@@ -333,23 +458,26 @@ impl PingMaker {
         log::debug!("Storing ping '{}' at '{}'", doc_id, ping_path.display());
 
         {
-            let mut file = File::create(&temp_ping_path)?;
-            file.write_all(url_path.as_bytes())?;
-            file.write_all(b"\n")?;
-            file.write_all(::serde_json::to_string(ping_content)?.as_bytes())?;
-            if let Some(metadata) = self.get_metadata(glean) {
-                file.write_all(b"\n")?;
-                file.write_all(::serde_json::to_string(&metadata)?.as_bytes())?;
-            }
+            let mut content = Vec::new();
+            content.write_all(url_path.as_bytes())?;
+            content.write_all(b"\n")?;
+            content.write_all(self.serialize_payload(ping, ping_content)?.as_bytes())?;
+            content.write_all(b"\n")?;
+            content.write_all(
+                ::serde_json::to_string(&self.get_metadata(ping, headers))?.as_bytes(),
+            )?;
+
+            let content = glean.encryption().encrypt(&content)?;
+            fs::write(&temp_ping_path, content)?;
         }
 
-        if let Err(e) = std::fs::rename(&temp_ping_path, &ping_path) {
+        if let Err(e) = fs::rename(&temp_ping_path, &ping_path) {
             log::warn!(
                 "Unable to move '{}' to '{}",
                 temp_ping_path.display(),
                 ping_path.display()
             );
-            return Err(e);
+            return Err(e.into());
         }
 
         Ok(())
@@ -359,7 +487,7 @@ impl PingMaker {
     pub fn clear_pending_pings(&self, data_path: &Path) -> Result<()> {
         let pings_dir = self.get_pings_dir(data_path, None)?;
 
-        std::fs::remove_dir_all(&pings_dir)?;
+        fs::remove_dir_all(&pings_dir)?;
         create_dir_all(&pings_dir)?;
 
         log::debug!("All pending pings deleted");
@@ -371,7 +499,35 @@ impl PingMaker {
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::metrics::jwe::test::{decrypt, key_pair, rsa_key_pair};
+    use crate::metrics::StringMetric;
     use crate::tests::new_glean;
+    use crate::upload::{HeaderMap, PingRequest, PingUploadTask};
+
+    fn secret_metric(name: &str) -> StringMetric {
+        StringMetric::new(CommonMetricData {
+            name: name.into(),
+            category: "telemetry".into(),
+            send_in_pings: vec!["secret".into()],
+            lifetime: Lifetime::Ping,
+            ..Default::default()
+        })
+    }
+
+    fn submit_and_upload(glean: &mut Glean, ping: &PingType) -> PingRequest {
+        glean.register_ping_type(ping);
+        secret_metric("public").set(glean, "public value");
+        secret_metric("private").set(glean, "private value");
+        assert!(ping.submit(glean, None).unwrap());
+
+        loop {
+            match glean.get_upload_task() {
+                PingUploadTask::Upload(request) => return request,
+                PingUploadTask::Wait => std::thread::sleep(std::time::Duration::from_millis(10)),
+                _ => panic!("Expected a ping to upload"),
+            }
+        }
+    }
 
     #[test]
     fn sequence_numbers_should_be_reset_when_toggling_uploading() {
@@ -389,4 +545,93 @@ mod test {
         assert_eq!(0, ping_maker.get_ping_seq(&glean, "custom"));
         assert_eq!(1, ping_maker.get_ping_seq(&glean, "custom"));
     }
+
+    #[test]
+    fn whole_payloads_can_be_encrypted() {
+        for (secret, jwk) in vec![key_pair(), rsa_key_pair(None)] {
+            let (mut glean, _) = new_glean(None);
+            let ping = PingType::new("secret", true, false, vec![]).with_encryption(jwk, None);
+
+            let request = submit_and_upload(&mut glean, &ping);
+            assert_eq!(JWE_CONTENT_TYPE, request.headers["Content-Type"]);
+
+            let mut body = String::new();
+            std::io::Read::read_to_string(
+                &mut flate2::read::GzDecoder::new(&request.body[..]),
+                &mut body,
+            )
+            .unwrap();
+            assert!(!body.contains("value"));
+
+            let payload: JsonValue =
+                serde_json::from_slice(&decrypt(&secret, &body).unwrap()).unwrap();
+            assert_eq!(
+                "private value",
+                payload["metrics"]["string"]["telemetry.private"]
+            );
+        }
+    }
+
+    #[test]
+    fn designated_metrics_can_be_encrypted() {
+        let (mut glean, _) = new_glean(None);
+        let (secret, jwk) = key_pair();
+        let ping = PingType::new("secret", true, false, vec![])
+            .with_encryption(jwk, Some(vec!["telemetry.private".into()]));
+
+        let request = submit_and_upload(&mut glean, &ping);
+        assert_ne!(JWE_CONTENT_TYPE, request.headers["Content-Type"]);
+
+        let payload: JsonValue = serde_json::from_str(&request.pretty_body().unwrap()).unwrap();
+        let metrics = &payload["metrics"];
+        assert_eq!("public value", metrics["string"]["telemetry.public"]);
+        assert!(metrics["string"].get("telemetry.private").is_none());
+
+        let jwe = metrics["jwe"]["telemetry.private"].as_str().unwrap();
+        let decrypted: JsonValue = serde_json::from_slice(&decrypt(&secret, jwe).unwrap()).unwrap();
+        assert_eq!(
+            json!({"string": {"telemetry.private": "private value"}}),
+            decrypted
+        );
+    }
+
+    #[test]
+    fn custom_headers_are_persisted_and_cannot_override_reserved_ones() {
+        let (mut glean, dir) = new_glean(None);
+        glean.set_debug_view_tag("debug-tag");
+
+        let mut global = HeaderMap::new();
+        global.insert("Authorization".into(), "Bearer token".into());
+        global.insert("X-Tenant".into(), "global".into());
+        global.insert("X-Debug-ID".into(), "other-tag".into());
+        assert!(!glean.set_upload_headers(global));
+
+        let mut headers = HeaderMap::new();
+        headers.insert("X-Tenant".into(), "tenant".into());
+        headers.insert("content-type".into(), "text/plain".into());
+        let ping = PingType::new("secret", true, false, vec![]).with_headers(headers);
+        glean.register_ping_type(&ping);
+        secret_metric("public").set(&glean, "public value");
+        assert!(ping.submit(&glean, None).unwrap());
+
+        // The headers are read back from disk after a restart.
+        drop(glean);
+        let (glean, _) = new_glean(Some(dir));
+        let request = loop {
+            match glean.get_upload_task() {
+                PingUploadTask::Upload(request) => break request,
+                PingUploadTask::Wait => std::thread::sleep(std::time::Duration::from_millis(10)),
+                _ => panic!("Expected a ping to upload"),
+            }
+        };
+
+        assert_eq!("Bearer token", request.headers["Authorization"]);
+        assert_eq!("tenant", request.headers["X-Tenant"]);
+        assert_eq!("debug-tag", request.headers["X-Debug-ID"]);
+        assert_eq!(
+            "application/json; charset=utf-8",
+            request.headers["Content-Type"]
+        );
+        assert!(!request.headers.contains_key("content-type"));
+    }
 }
@@ -0,0 +1,52 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::fmt;
+use std::panic::RefUnwindSafe;
+
+use serde_json::Value as JsonValue;
+
+use crate::upload::HeaderMap;
+
+/// The point in the life of a ping at which observers are notified.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[repr(u8)]
+pub enum PingStage {
+    /// The ping was assembled and stored for upload.
+    Submitted = 1,
+    /// The ping was handed out for upload by `Glean::get_upload_task`.
+    Uploading = 2,
+}
+
+/// Receives the pings Glean submits and uploads.
+///
+/// This is a structured alternative to the `log_pings` debug option,
+/// e.g. to inspect pings in tests or CI.
+/// Observers are called from any thread Glean runs on, including across the FFI boundary.
+pub trait PingObserver: fmt::Debug + Send + Sync + RefUnwindSafe {
+    /// Called when a ping is submitted and again when it is handed out for upload.
+    ///
+    /// ## Arguments
+    ///
+    /// * `stage` - Whether the ping was just submitted or is about to be uploaded.
+    /// * `ping_name` - The name of the ping.
+    /// * `document_id` - The UUID of the ping.
+    /// * `path` - The URL path the ping is uploaded to.
+    /// * `payload` - The ping payload.
+    /// * `headers` - The headers of the ping.
+    ///
+    /// On submission, `payload` is the payload before encryption and `headers` are
+    /// only the headers persisted with the ping.
+    /// On upload, `payload` is the decompressed body, which is `Null` if it isn't JSON
+    /// (e.g. for encrypted pings), and `headers` are all headers of the request.
+    fn on_ping(
+        &self,
+        stage: PingStage,
+        ping_name: &str,
+        document_id: &str,
+        path: &str,
+        payload: &JsonValue,
+        headers: &HeaderMap,
+    );
+}
@@ -0,0 +1,273 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Exporting and importing the complete Glean state.
+//!
+//! A state archive contains the metrics of all lifetimes (including the client ID,
+//! the first run date and the active experiments), the stored events and all pings
+//! that are pending upload.
+//! It can be used to move a profile from one data directory to another,
+//! e.g. when an application migrates its storage location or device.
+//!
+//! The archive starts with a magic marker and a format version,
+//! followed by the bincode-encoded state.
+//! Archives with an unknown format version are refused.
+//!
+//! The archive itself is not encrypted. If encryption at rest is configured,
+//! the data is decrypted on export and encrypted with the current key on import.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::encryption::Encryption;
+use crate::error::ErrorKind;
+use crate::fs;
+use crate::metrics::Metric;
+use crate::ping::PingMaker;
+use crate::Glean;
+use crate::Lifetime;
+use crate::Result;
+use crate::{DELETION_REQUEST_PINGS_DIRECTORY, GLEAN_VERSION, PENDING_PINGS_DIRECTORY};
+
+/// The marker every state archive starts with.
+const ARCHIVE_MAGIC: &[u8; 8] = b"GLEANSTA";
+
+/// The version of the archive format.
+///
+/// This needs to be bumped whenever the layout of `StateArchive` changes.
+pub(crate) const ARCHIVE_VERSION: u32 = 1;
+
+/// How imported state is combined with the state already stored.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ImportPolicy {
+    /// Keep the existing state. Metrics from the archive replace existing values
+    /// of the same metric, events are appended and pending pings are added.
+    Merge,
+    /// Replace the existing state with the archive.
+    /// Pending `deletion-request` pings are never dropped.
+    Overwrite,
+}
+
+/// The complete Glean state.
+#[derive(Debug, Serialize, Deserialize)]
+struct StateArchive {
+    /// The version of Glean that created the archive.
+    glean_version: String,
+    /// The metrics of each lifetime, with their full storage key.
+    user_metrics: Vec<(String, Metric)>,
+    application_metrics: Vec<(String, Metric)>,
+    ping_metrics: Vec<(String, Metric)>,
+    /// The stored events, by store name.
+    events: HashMap<String, Vec<String>>,
+    /// The ping files pending upload, by document ID.
+    pending_pings: HashMap<String, String>,
+    /// The `deletion-request` ping files pending upload, by document ID.
+    deletion_request_pings: HashMap<String, String>,
+}
+
+/// Whether a name from an archive can safely be used as a file name in a Glean directory.
+fn is_valid_file_name(name: &str) -> bool {
+    !name.is_empty() && name != "." && name != ".." && !name.contains(&['/', '\\'][..])
+}
+
+/// Read all ping files in the given directory, by document ID.
+///
+/// Files that are not named after a UUID are not pings and are skipped.
+/// Encrypted files are decrypted, files that cannot be decrypted are skipped.
+fn read_pings(dir: &Path, encryption: &Encryption) -> Result<HashMap<String, String>> {
+    let mut pings = HashMap::new();
+    if !fs::exists(dir) {
+        return Ok(pings);
+    }
+
+    for entry in fs::list_files(dir)? {
+        let document_id = entry.file_name().into_string()?;
+        if Uuid::parse_str(&document_id).is_err() {
+            continue;
+        }
+        let content = match encryption.decrypt(&fs::read(entry.path())?) {
+            Ok(content) => String::from_utf8_lossy(&content).into_owned(),
+            Err(e) => {
+                log::warn!("Skipping ping '{}': {}", document_id, e);
+                continue;
+            }
+        };
+        pings.insert(document_id, content);
+    }
+
+    Ok(pings)
+}
+
+/// Write ping files into the given directory, skipping any that already exist.
+///
+/// ## Return value
+///
+/// Returns the document IDs of the written pings.
+fn write_pings(
+    encryption: &Encryption,
+    data_path: &Path,
+    dir: &Path,
+    pings: &HashMap<String, String>,
+) -> Result<Vec<String>> {
+    let tmp_dir = data_path.join("tmp");
+    fs::create_dir_all(dir)?;
+    fs::create_dir_all(&tmp_dir)?;
+
+    let mut written = Vec::new();
+    for (document_id, content) in pings {
+        if Uuid::parse_str(document_id).is_err() {
+            log::warn!("Skipping ping with invalid document ID '{}'", document_id);
+            continue;
+        }
+
+        let ping_path = dir.join(document_id);
+        if fs::exists(&ping_path) {
+            continue;
+        }
+
+        // Write to a temporary location and then move when done,
+        // for transactional writes.
+        let temp_ping_path = tmp_dir.join(document_id);
+        fs::write(&temp_ping_path, encryption.encrypt(content.as_bytes())?)?;
+        fs::rename(&temp_ping_path, &ping_path)?;
+        written.push(document_id.clone());
+    }
+
+    Ok(written)
+}
+
+/// Write the complete state of the given Glean object to `path`.
+pub(crate) fn export_state(glean: &Glean, path: &Path) -> Result<()> {
+    let data = glean.storage();
+    let data_path = glean.get_data_path();
+
+    let archive = StateArchive {
+        glean_version: GLEAN_VERSION.into(),
+        user_metrics: data.export_lifetime(Lifetime::User),
+        application_metrics: data.export_lifetime(Lifetime::Application),
+        ping_metrics: data.export_lifetime(Lifetime::Ping),
+        events: glean.event_storage().export_stores()?,
+        pending_pings: read_pings(&data_path.join(PENDING_PINGS_DIRECTORY), glean.encryption())?,
+        deletion_request_pings: read_pings(
+            &data_path.join(DELETION_REQUEST_PINGS_DIRECTORY),
+            glean.encryption(),
+        )?,
+    };
+
+    let mut contents = ARCHIVE_MAGIC.to_vec();
+    contents.extend_from_slice(&ARCHIVE_VERSION.to_le_bytes());
+    bincode::serialize_into(&mut contents, &archive)?;
+    fs::write_synced(path, contents)?;
+
+    Ok(())
+}
+
+/// Read a state archive from `path`, validating its marker and version.
+fn read_archive(path: &Path) -> Result<StateArchive> {
+    let contents = fs::read(path)?;
+
+    let header_len = ARCHIVE_MAGIC.len() + 4;
+    if contents.len() < header_len || !contents.starts_with(ARCHIVE_MAGIC) {
+        return Err(ErrorKind::InvalidStateArchive.into());
+    }
+
+    let mut version = [0u8; 4];
+    version.copy_from_slice(&contents[ARCHIVE_MAGIC.len()..header_len]);
+    let version = u32::from_le_bytes(version);
+    if version != ARCHIVE_VERSION {
+        return Err(ErrorKind::IncompatibleStateArchive(version).into());
+    }
+
+    Ok(bincode::deserialize(&contents[header_len..])?)
+}
+
+/// Import the state archive at `path` into the given Glean object.
+///
+/// The archive is fully read and validated before any stored data is touched.
+pub(crate) fn import_state(glean: &Glean, path: &Path, policy: ImportPolicy) -> Result<()> {
+    let archive = read_archive(path)?;
+    log::info!(
+        "Importing state archive created by Glean v{}",
+        archive.glean_version
+    );
+
+    let data = glean.storage();
+    let data_path = glean.get_data_path();
+    let upload_manager = &glean.upload_manager;
+
+    if policy == ImportPolicy::Overwrite {
+        data.clear_all();
+        glean.event_storage().clear_all()?;
+
+        // Only pending pings are dropped. Local deletion-request pings must still be sent.
+        let _queue = upload_manager.clear_ping_queue();
+        PingMaker::new().clear_pending_pings(data_path)?;
+    }
+
+    data.import_lifetime(Lifetime::User, &archive.user_metrics)?;
+    data.import_lifetime(Lifetime::Application, &archive.application_metrics)?;
+    data.import_lifetime(Lifetime::Ping, &archive.ping_metrics)?;
+
+    let events = archive
+        .events
+        .into_iter()
+        .filter(|(store_name, _)| {
+            let valid = is_valid_file_name(store_name);
+            if !valid {
+                log::warn!("Skipping events for invalid store '{}'", store_name);
+            }
+            valid
+        })
+        .collect();
+    glean.event_storage().import_stores(&events)?;
+
+    let mut imported = write_pings(
+        glean.encryption(),
+        data_path,
+        &data_path.join(DELETION_REQUEST_PINGS_DIRECTORY),
+        &archive.deletion_request_pings,
+    )?;
+    imported.extend(write_pings(
+        glean.encryption(),
+        data_path,
+        &data_path.join(PENDING_PINGS_DIRECTORY),
+        &archive.pending_pings,
+    )?);
+    for document_id in imported {
+        upload_manager.enqueue_ping_from_file(&document_id);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn file_names_from_archives_are_validated() {
+        assert!(is_valid_file_name("metrics"));
+        assert!(is_valid_file_name("store-1"));
+        assert!(!is_valid_file_name(""));
+        assert!(!is_valid_file_name(".."));
+        assert!(!is_valid_file_name("../metrics"));
+        assert!(!is_valid_file_name("a\\b"));
+    }
+
+    #[test]
+    fn archives_with_bad_markers_are_refused() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path()).unwrap();
+        let path = dir.path().join("archive");
+
+        fs::write(&path, b"NOTGLEAN\x01\x00\x00\x00").unwrap();
+        assert!(read_archive(&path).is_err());
+
+        fs::write(&path, b"GLEAN").unwrap();
+        assert!(read_archive(&path).is_err());
+    }
+}
@@ -130,20 +130,11 @@ impl StorageManager {
         store_name: &str,
         metric_id: &str,
     ) -> Option<Metric> {
-        let mut snapshot: Option<Metric> = None;
-
-        let mut snapshotter = |id: &[u8], metric: &Metric| {
-            let id = String::from_utf8_lossy(id).into_owned();
-            if id == metric_id {
-                snapshot = Some(metric.clone())
-            }
-        };
-
-        storage.iter_store_from(Lifetime::Ping, &store_name, None, &mut snapshotter);
-        storage.iter_store_from(Lifetime::Application, &store_name, None, &mut snapshotter);
-        storage.iter_store_from(Lifetime::User, &store_name, None, &mut snapshotter);
-
-        snapshot
+        // A metric is only stored in one lifetime. Should it still be found in several,
+        // the longest lifetime takes precedence.
+        [Lifetime::User, Lifetime::Application, Lifetime::Ping]
+            .iter()
+            .find_map(|&lifetime| storage.get_metric(lifetime, store_name, metric_id))
     }
 
     ///  Snapshot the experiments.
@@ -5,19 +5,41 @@
 //! Pings directory processing utilities.
 
 use std::cmp::Ordering;
-use std::fs::{self, File};
-use std::io::{BufRead, BufReader};
 use std::path::{Path, PathBuf};
 
 use serde::Deserialize;
 use uuid::Uuid;
 
-use super::request::HeaderMap;
+use super::request::{HeaderMap, NetworkPolicy};
+use crate::encryption::Encryption;
+use crate::fs::{self, create_dir_all, DirEntry};
+use crate::Result;
 use crate::{DELETION_REQUEST_PINGS_DIRECTORY, PENDING_PINGS_DIRECTORY};
 
+/// The metadata persisted with a ping, in the optional third line of the ping file.
+#[derive(Debug, Default, Deserialize)]
+pub(crate) struct PingMetadata {
+    /// The name of the ping.
+    #[serde(default)]
+    pub ping: Option<String>,
+    /// When the ping was submitted, in milliseconds since the UNIX epoch.
+    #[serde(default)]
+    pub submitted_at: Option<i64>,
+    /// Additional headers to be added to the ping request.
+    #[serde(default)]
+    pub headers: Option<HeaderMap>,
+    /// The upload priority of the ping.
+    #[serde(default)]
+    pub priority: u8,
+    /// The network conditions under which the ping may be uploaded.
+    #[serde(default)]
+    pub network_policy: NetworkPolicy,
+}
+
 /// A representation of the data extracted from a ping file,
-/// this will contain the document_id, path, JSON encoded body of a ping and the persisted headers.
-type PingPayload = (String, String, String, Option<HeaderMap>);
+/// this will contain the document_id, path, JSON encoded body of a ping,
+/// the persisted metadata and whether it is a `deletion-request` ping.
+type PingPayload = (String, String, String, PingMetadata, bool);
 
 /// Get the file name from a path as a &str.
 ///
@@ -43,21 +65,16 @@ fn get_file_name_as_str(path: &Path) -> Option<&str> {
 /// Process a ping's metadata.
 ///
 /// The metadata is an optional third line in the ping file,
-/// currently it contains only additonal headers to be added to each ping request.
+/// currently it contains the name and submission time of the ping,
+/// additonal headers to be added to each ping request,
+/// and the upload priority and the network policy of the ping.
 /// Therefore, we will process the contents of this line
-/// and return a HeaderMap of the persisted headers.
-fn process_metadata(path: &str, metadata: &str) -> Option<HeaderMap> {
-    #[derive(Deserialize)]
-    struct PingMetadata {
-        pub headers: HeaderMap,
-    }
-
-    if let Ok(metadata) = serde_json::from_str::<PingMetadata>(metadata) {
-        return Some(metadata.headers);
-    } else {
+/// and return the persisted metadata.
+fn process_metadata(path: &str, metadata: &str) -> PingMetadata {
+    serde_json::from_str::<PingMetadata>(metadata).unwrap_or_else(|_| {
         log::warn!("Error while parsing ping metadata: {}", path);
-    }
-    None
+        PingMetadata::default()
+    })
 }
 
 /// Manages the pending pings directories.
@@ -65,6 +82,8 @@ fn process_metadata(path: &str, metadata: &str) -> Option<HeaderMap> {
 pub struct PingDirectoryManager {
     /// Paths to the pings directories.
     pings_dirs: [PathBuf; 2],
+    /// Decrypts the ping files, if configured.
+    encryption: Encryption,
 }
 
 impl PingDirectoryManager {
@@ -74,12 +93,23 @@ impl PingDirectoryManager {
     ///
     /// * `data_path` - Path to the pending pings directory.
     pub fn new<P: Into<PathBuf>>(data_path: P) -> Self {
+        Self::with_encryption(data_path, Encryption::default())
+    }
+
+    /// Creates a new directory manager for encrypted ping files.
+    ///
+    /// # Arguments
+    ///
+    /// * `data_path` - Path to the pending pings directory.
+    /// * `encryption` - Decrypts the ping files.
+    pub(crate) fn with_encryption<P: Into<PathBuf>>(data_path: P, encryption: Encryption) -> Self {
         let data_path = data_path.into();
         Self {
             pings_dirs: [
                 data_path.join(PENDING_PINGS_DIRECTORY),
                 data_path.join(DELETION_REQUEST_PINGS_DIRECTORY),
             ],
+            encryption,
         }
     }
 
@@ -109,6 +139,7 @@ impl PingDirectoryManager {
     /// Reads a ping file and returns the data from it.
     ///
     /// If the file is not properly formatted, it will be deleted and `None` will be returned.
+    /// If the file cannot be decrypted, it is kept and `None` will be returned.
     ///
     /// ## Arguments
     ///
@@ -121,8 +152,8 @@ impl PingDirectoryManager {
                 return None;
             }
         };
-        let file = match File::open(&path) {
-            Ok(file) => file,
+        let content = match fs::read(&path) {
+            Ok(content) => content,
             Err(e) => {
                 log::error!("Error reading ping file {}. {}", path.display(), e);
                 return None;
@@ -131,16 +162,36 @@ impl PingDirectoryManager {
 
         log::info!("Processing ping at: {}", path.display());
 
+        // The ping name is not at a fixed position in custom URL paths,
+        // so `deletion-request` pings are told apart by their directory.
+        let deletion_request = path.starts_with(&self.pings_dirs[1]);
+
+        // Keep files we can't decrypt, the key might become available again.
+        let content = match self.encryption.decrypt(&content) {
+            Ok(content) => content,
+            Err(e) => {
+                log::error!("Error decrypting ping file {}. {}", path.display(), e);
+                return None;
+            }
+        };
+
         // The way the ping file is structured:
         // first line should always have the path,
         // second line should have the body with the ping contents in JSON format
         // and third line might contain ping metadata e.g. additional headers.
-        let mut lines = BufReader::new(file).lines();
-        if let (Some(Ok(path)), Some(Ok(body)), Ok(metadata)) =
-            (lines.next(), lines.next(), lines.next().transpose())
-        {
-            let headers = metadata.map(|m| process_metadata(&path, &m)).flatten();
-            return Some((document_id.into(), path, body, headers));
+        let mut lines = std::str::from_utf8(&content).unwrap_or_default().lines();
+        if let (Some(path), Some(body)) = (lines.next(), lines.next()) {
+            let metadata = lines
+                .next()
+                .map(|m| process_metadata(path, m))
+                .unwrap_or_default();
+            return Some((
+                document_id.into(),
+                path.into(),
+                body.into(),
+                metadata,
+                deletion_request,
+            ));
         } else {
             log::warn!(
                 "Error processing ping file: {}. Ping file is not formatted as expected.",
@@ -162,8 +213,8 @@ impl PingDirectoryManager {
     ///
     /// # Return value
     ///
-    /// `Vec<(String, String, JsonValue, HeaderMap)>` -
-    ///     a vector of tuples containing the document_id, path, body and headers of each request.
+    /// `Vec<PingPayload>` - a vector of tuples containing the document_id, path, body, metadata
+    ///     and whether it is a `deletion-request` ping, for each request.
     pub fn process_dir(&self) -> Vec<PingPayload> {
         log::info!("Processing persisted pings.");
 
@@ -187,8 +238,7 @@ impl PingDirectoryManager {
                     if let Some(data) = self.process_file(file_name) {
                         // Get the modified date of the file, which will later be used
                         // for sorting the resulting vector.
-                        let modified_date = fs::metadata(&path).and_then(|data| data.modified());
-                        return Some((modified_date, data));
+                        return Some((entry.modified(), data));
                     }
                 };
                 None
@@ -199,7 +249,7 @@ impl PingDirectoryManager {
         pending_pings.sort_by(|(a, _), (b, _)| {
             // We might not be able to get the modified date for a given file,
             // in which case we just put it at the end.
-            if let (Ok(a), Ok(b)) = (a, b) {
+            if let (Some(a), Some(b)) = (a, b) {
                 a.cmp(b)
             } else {
                 Ordering::Less
@@ -210,12 +260,45 @@ impl PingDirectoryManager {
         pending_pings.into_iter().map(|(_, data)| data).collect()
     }
 
+    /// Re-encrypt all ping files with the current encryption key.
+    ///
+    /// Files that cannot be decrypted are left untouched.
+    pub fn reencrypt(&self) -> Result<()> {
+        let tmp_dir = self.pings_dirs[0].with_file_name("tmp");
+        create_dir_all(&tmp_dir)?;
+
+        for entry in self.get_ping_entries() {
+            let path = entry.path();
+            let encrypted = match self.encryption.reencrypt(&fs::read(&path)?) {
+                Ok(Some(encrypted)) => encrypted,
+                Ok(None) => continue,
+                Err(e) => {
+                    log::error!("Failed to re-encrypt ping file {}. {}", path.display(), e);
+                    continue;
+                }
+            };
+
+            // Write to a temporary location and then move when done,
+            // for transactional writes.
+            let tmp_path = tmp_dir.join(entry.file_name());
+            fs::write(&tmp_path, encrypted)?;
+            fs::rename(&tmp_path, &path)?;
+        }
+
+        Ok(())
+    }
+
+    /// Get the total size of all ping files, in bytes.
+    pub fn directory_size(&self) -> u64 {
+        self.get_ping_entries().iter().map(DirEntry::len).sum()
+    }
+
     /// Get all the ping entries in all ping directories.
-    fn get_ping_entries(&self) -> Vec<fs::DirEntry> {
+    fn get_ping_entries(&self) -> Vec<DirEntry> {
         let mut result = Vec::new();
         for dir in &self.pings_dirs {
-            if let Ok(entries) = dir.read_dir() {
-                result.extend(entries.filter_map(|entry| entry.ok()))
+            if let Ok(entries) = fs::list_files(dir) {
+                result.extend(entries)
             };
         }
         result
@@ -228,7 +311,7 @@ impl PingDirectoryManager {
     fn get_file_path(&self, document_id: &str) -> Option<PathBuf> {
         for dir in &self.pings_dirs {
             let path = dir.join(document_id);
-            if path.exists() {
+            if fs::exists(&path) {
                 return Some(path);
             }
         }
@@ -238,8 +321,6 @@ impl PingDirectoryManager {
 
 #[cfg(test)]
 mod test {
-    use std::fs::File;
-
     use super::*;
     use crate::metrics::PingType;
     use crate::tests::new_glean;
@@ -294,7 +375,7 @@ mod test {
             .path()
             .join(PENDING_PINGS_DIRECTORY)
             .join("not-uuid-file-name.txt");
-        File::create(&not_uuid_path).unwrap();
+        fs::write(&not_uuid_path, "").unwrap();
 
         // Try and process the pings folder
         let data = directory_manager.process_dir();
@@ -307,7 +388,7 @@ mod test {
         assert_eq!(request_ping_type, "test");
 
         // Verify that file was indeed deleted
-        assert!(!not_uuid_path.exists());
+        assert!(!fs::exists(&not_uuid_path));
     }
 
     #[test]
@@ -327,7 +408,7 @@ mod test {
             .path()
             .join(PENDING_PINGS_DIRECTORY)
             .join(Uuid::new_v4().to_string());
-        File::create(&wrong_contents_file_path).unwrap();
+        fs::write(&wrong_contents_file_path, "").unwrap();
 
         // Try and process the pings folder
         let data = directory_manager.process_dir();
@@ -340,7 +421,7 @@ mod test {
         assert_eq!(request_ping_type, "test");
 
         // Verify that file was indeed deleted
-        assert!(!wrong_contents_file_path.exists());
+        assert!(!fs::exists(&wrong_contents_file_path));
     }
 
     #[test]
@@ -364,5 +445,6 @@ mod test {
         // Verify request was returned for the "deletion-request" ping
         let request_ping_type = data[0].1.split('/').nth(3).unwrap();
         assert_eq!(request_ping_type, "deletion-request");
+        assert!(data[0].4);
     }
 }
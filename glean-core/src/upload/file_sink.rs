@@ -0,0 +1,407 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! An uploader writing pings to a local directory instead of sending them over the network.
+//!
+//! This is meant for debugging and for deployments without network access,
+//! where the output directory is swept by some other process.
+
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+use serde_json::{json, Value as JsonValue};
+
+use super::{PingRequest, PingUploadTask, UploadResult};
+use crate::fs;
+use crate::{Glean, Result};
+
+/// The default maximum size of an output segment, in bytes.
+pub const DEFAULT_MAX_SEGMENT_SIZE: u64 = 10 * 1024 * 1024;
+
+/// How often to ask again for an upload task when told to wait, before giving up.
+const MAX_WAIT_ATTEMPTS: u32 = 3;
+
+/// How many failed writes to tolerate before giving up.
+const MAX_RETRIES: u32 = 3;
+
+/// The format pings are written in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FileSinkFormat {
+    /// Every ping is written to its own `<document_id>.body` file, exactly as it would be uploaded
+    /// (i.e. compressed), with the path and headers in a `<document_id>.json` sidecar file.
+    ///
+    /// Segments are directories named `segment-<n>`.
+    Raw,
+    /// Every ping is written as a line of JSON with the document ID, path, headers
+    /// and decompressed payload.
+    /// A body that isn't JSON, e.g. the JWE of an encrypted ping, is written as a `body` string instead,
+    /// encoded in base64 with an `"encoding": "base64"` field if it isn't valid UTF-8.
+    ///
+    /// Segments are files named `pings-<n>.ndjson`.
+    Ndjson,
+}
+
+impl FileSinkFormat {
+    fn segment_prefix(self) -> &'static str {
+        match self {
+            FileSinkFormat::Raw => "segment-",
+            FileSinkFormat::Ndjson => "pings-",
+        }
+    }
+
+    fn segment_name(self, index: u32) -> String {
+        match self {
+            FileSinkFormat::Raw => format!("segment-{:05}", index),
+            FileSinkFormat::Ndjson => format!("pings-{:05}.ndjson", index),
+        }
+    }
+
+    fn segment_index(self, name: &str) -> Option<u32> {
+        let suffix = match self {
+            FileSinkFormat::Raw => "",
+            FileSinkFormat::Ndjson => ".ndjson",
+        };
+        let prefix = self.segment_prefix();
+        if !name.starts_with(prefix) || !name.ends_with(suffix) {
+            return None;
+        }
+        name.get(prefix.len()..name.len() - suffix.len())?
+            .parse()
+            .ok()
+    }
+}
+
+/// Writes ping requests to an output directory, rotating the output by size.
+///
+/// Writing a ping is acknowledged like a successful upload, with `HttpStatus(200)`.
+/// Failed writes are reported as recoverable failures, so the ping is retried later.
+#[derive(Debug)]
+pub struct FileSinkUploader {
+    output_dir: PathBuf,
+    format: FileSinkFormat,
+    max_segment_size: u64,
+    /// The index of the segment currently written to.
+    segment: u32,
+    /// The number of bytes written to the current segment.
+    segment_size: u64,
+}
+
+impl FileSinkUploader {
+    /// Creates a new file sink.
+    ///
+    /// Writing continues in the latest segment found in the output directory, if any.
+    ///
+    /// ## Arguments
+    ///
+    /// * `output_dir` - The directory to write pings to. It is created if necessary.
+    /// * `format` - The format to write pings in.
+    /// * `max_segment_size` - The size in bytes after which to start a new segment.
+    ///
+    /// A single ping larger than `max_segment_size` is written to a segment of its own.
+    pub fn new<P: Into<PathBuf>>(
+        output_dir: P,
+        format: FileSinkFormat,
+        max_segment_size: u64,
+    ) -> Result<Self> {
+        let output_dir = output_dir.into();
+        fs::create_dir_all(&output_dir)?;
+
+        let segments = match format {
+            FileSinkFormat::Raw => fs::list_dirs(&output_dir)?,
+            FileSinkFormat::Ndjson => fs::list_files(&output_dir)?
+                .iter()
+                .map(fs::DirEntry::path)
+                .collect(),
+        };
+        let mut segment = 0;
+        for path in segments {
+            if let Some(index) = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| format.segment_index(name))
+            {
+                segment = segment.max(index);
+            }
+        }
+
+        let mut sink = Self {
+            output_dir,
+            format,
+            max_segment_size,
+            segment,
+            segment_size: 0,
+        };
+        sink.segment_size = sink.size_on_disk();
+        Ok(sink)
+    }
+
+    /// The path of the current segment.
+    fn segment_path(&self) -> PathBuf {
+        self.output_dir.join(self.format.segment_name(self.segment))
+    }
+
+    /// The size of the current segment, in bytes, or 0 if it doesn't exist yet.
+    fn size_on_disk(&self) -> u64 {
+        let path = self.segment_path();
+        match self.format {
+            FileSinkFormat::Raw => fs::list_files(&path)
+                .map(|files| files.iter().map(fs::DirEntry::len).sum())
+                .unwrap_or(0),
+            FileSinkFormat::Ndjson => fs::file_len(&path).unwrap_or(0),
+        }
+    }
+
+    /// Moves on to the next segment, if writing `size` bytes would exceed the current one.
+    fn rotate_for(&mut self, size: u64) {
+        if self.segment_size > 0 && self.segment_size + size > self.max_segment_size {
+            self.segment += 1;
+            self.segment_size = 0;
+        }
+    }
+
+    /// Writes a single ping request to the output directory.
+    ///
+    /// ## Return value
+    ///
+    /// `HttpStatus(200)` if the ping was written and `RecoverableFailure` otherwise.
+    pub fn upload(&mut self, request: &PingRequest) -> UploadResult {
+        let written = match self.format {
+            FileSinkFormat::Raw => self.write_raw(request),
+            FileSinkFormat::Ndjson => self.write_ndjson(request),
+        };
+
+        match written {
+            Ok(size) => {
+                self.segment_size += size;
+                UploadResult::HttpStatus(200)
+            }
+            Err(e) => {
+                log::warn!(
+                    "Failed to write ping {} to {}: {}",
+                    request.document_id,
+                    self.output_dir.display(),
+                    e
+                );
+                UploadResult::RecoverableFailure
+            }
+        }
+    }
+
+    fn write_raw(&mut self, request: &PingRequest) -> std::io::Result<u64> {
+        let sidecar = serde_json::to_vec(&json!({
+            "document_id": request.document_id,
+            "path": request.path,
+            "headers": request.headers,
+        }))?;
+        let size = (request.body.len() + sidecar.len()) as u64;
+        self.rotate_for(size);
+
+        let dir = self.segment_path();
+        fs::create_dir_all(&dir)?;
+        // The sidecar is written last, so a complete sidecar means a complete ping.
+        write_atomically(
+            &dir.join(format!("{}.body", request.document_id)),
+            &request.body,
+        )?;
+        write_atomically(&dir.join(format!("{}.json", request.document_id)), &sidecar)?;
+        Ok(size)
+    }
+
+    fn write_ndjson(&mut self, request: &PingRequest) -> std::io::Result<u64> {
+        let mut entry = json!({
+            "document_id": request.document_id,
+            "path": request.path,
+            "headers": request.headers,
+        });
+        let body = request.decompressed_body();
+        match serde_json::from_slice::<JsonValue>(&body) {
+            Ok(payload) => entry["payload"] = payload,
+            Err(_) => match std::str::from_utf8(&body) {
+                Ok(body) => entry["body"] = body.into(),
+                Err(_) => {
+                    entry["body"] = base64::encode(&body).into();
+                    entry["encoding"] = "base64".into();
+                }
+            },
+        }
+        let mut line = serde_json::to_vec(&entry)?;
+        line.push(b'\n');
+        let size = line.len() as u64;
+        self.rotate_for(size);
+
+        fs::append_synced(self.segment_path(), &line)?;
+        Ok(size)
+    }
+
+    /// Writes all pings currently queued for upload.
+    ///
+    /// Like the upload workers of the language bindings, this gives up after being asked
+    /// to wait a few times or after a few failed writes, and stops if pings are held back
+    /// because of the network state.
+    ///
+    /// ## Return value
+    ///
+    /// The number of pings written.
+    pub fn drain(&mut self, glean: &Glean) -> usize {
+        let mut written = 0;
+        let mut wait_attempts = 0;
+        let mut failures = 0;
+
+        while failures < MAX_RETRIES {
+            match glean.get_upload_task() {
+                PingUploadTask::Upload(request) => {
+                    let result = self.upload(&request);
+                    match result {
+                        UploadResult::HttpStatus(_) => written += 1,
+                        _ => failures += 1,
+                    }
+                    glean.process_ping_upload_response(&request.document_id, result);
+                }
+                PingUploadTask::UploadBatch(requests) => {
+                    let results = requests
+                        .iter()
+                        .map(|request| {
+                            let result = self.upload(request);
+                            match result {
+                                UploadResult::HttpStatus(_) => written += 1,
+                                _ => failures += 1,
+                            }
+                            (request.document_id.clone(), result)
+                        })
+                        .collect();
+                    glean.process_ping_upload_batch_response(results);
+                }
+                PingUploadTask::Wait => {
+                    if wait_attempts >= MAX_WAIT_ATTEMPTS {
+                        break;
+                    }
+                    wait_attempts += 1;
+                    thread::sleep(Duration::from_secs(1));
+                }
+                PingUploadTask::WaitFor(..) | PingUploadTask::Done => break,
+            }
+        }
+
+        written
+    }
+}
+
+/// Writes a file through a temporary file, so the output directory never contains partial files.
+fn write_atomically(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let tmp_path = path.with_extension("tmp");
+    fs::write_synced(&tmp_path, contents)?;
+    fs::rename(tmp_path, path)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn request(document_id: &str) -> PingRequest {
+        PingRequest::builder("Test")
+            .document_id(document_id)
+            .path(format!("/submit/app/custom/1/{}", document_id))
+            .body(r#"{"ping_info":{"seq":0}}"#)
+            .build()
+    }
+
+    #[test]
+    fn raw_pings_are_written_with_a_sidecar() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut sink = FileSinkUploader::new(dir.path(), FileSinkFormat::Raw, 1024).unwrap();
+
+        let request = request("doc-1");
+        match sink.upload(&request) {
+            UploadResult::HttpStatus(200) => {}
+            result => panic!("Unexpected result: {:?}", result),
+        }
+
+        let segment = dir.path().join("segment-00000");
+        assert_eq!(request.body, fs::read(segment.join("doc-1.body")).unwrap());
+        let sidecar: serde_json::Value =
+            serde_json::from_slice(&fs::read(segment.join("doc-1.json")).unwrap()).unwrap();
+        assert_eq!(request.path, sidecar["path"]);
+        assert_eq!("gzip", sidecar["headers"]["Content-Encoding"]);
+    }
+
+    #[test]
+    fn ndjson_output_is_rotated_by_size() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut sink = FileSinkUploader::new(dir.path(), FileSinkFormat::Ndjson, 1).unwrap();
+
+        sink.upload(&request("doc-1"));
+        sink.upload(&request("doc-2"));
+
+        for (segment, doc_id) in &[(0, "doc-1"), (1, "doc-2")] {
+            let contents =
+                fs::read_to_string(dir.path().join(format!("pings-{:05}.ndjson", segment)))
+                    .unwrap();
+            let lines: Vec<_> = contents.lines().collect();
+            assert_eq!(1, lines.len());
+            let line: serde_json::Value = serde_json::from_str(lines[0]).unwrap();
+            assert_eq!(*doc_id, line["document_id"]);
+            assert_eq!(0, line["payload"]["ping_info"]["seq"]);
+        }
+
+        // Writing continues in the latest segment after a restart.
+        let sink = FileSinkUploader::new(dir.path(), FileSinkFormat::Ndjson, 1).unwrap();
+        assert_eq!(1, sink.segment);
+        assert!(sink.segment_size > 0);
+    }
+
+    #[test]
+    fn bodies_that_are_not_json_are_written_as_is() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut sink = FileSinkUploader::new(dir.path(), FileSinkFormat::Ndjson, 1024).unwrap();
+
+        let jwe = "header.key.iv.ciphertext.tag";
+        let mut encrypted = request("doc-1");
+        encrypted.body = PingRequest::builder("Test")
+            .document_id("doc-1")
+            .path("/submit/app/custom/1/doc-1")
+            .body(jwe)
+            .build()
+            .body;
+        let mut binary = request("doc-2");
+        binary.body = vec![0xff, 0xfe];
+        binary.headers.remove("Content-Encoding");
+
+        for request in &[encrypted, binary] {
+            match sink.upload(request) {
+                UploadResult::HttpStatus(200) => {}
+                result => panic!("Unexpected result: {:?}", result),
+            }
+        }
+
+        let contents = fs::read_to_string(dir.path().join("pings-00000.ndjson")).unwrap();
+        let lines: Vec<serde_json::Value> = contents
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(jwe, lines[0]["body"]);
+        assert!(lines[0].get("encoding").is_none());
+        assert_eq!("//4=", lines[1]["body"]);
+        assert_eq!("base64", lines[1]["encoding"]);
+    }
+
+    #[test]
+    fn queued_pings_are_drained_to_the_sink() {
+        let (mut glean, _t) = crate::tests::new_glean(None);
+        let ping = crate::metrics::PingType::new("custom", true, true, vec![]);
+        glean.register_ping_type(&ping);
+        assert!(ping.submit(&glean, None).unwrap());
+        assert!(ping.submit(&glean, None).unwrap());
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut sink =
+            FileSinkUploader::new(dir.path(), FileSinkFormat::Ndjson, DEFAULT_MAX_SEGMENT_SIZE)
+                .unwrap();
+        assert_eq!(2, sink.drain(&glean));
+        assert_eq!(PingUploadTask::Done, glean.get_upload_task());
+
+        let contents = fs::read_to_string(dir.path().join("pings-00000.ndjson")).unwrap();
+        assert_eq!(2, contents.lines().count());
+    }
+}
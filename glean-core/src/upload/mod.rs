@@ -7,20 +7,36 @@
 //! * Keeps track of pending pings, loading any unsent ping from disk on startup;
 //! * Exposes `get_upload_task` API for the platform layer to request next upload task;
 //! * Exposes `process_ping_upload_response` API to check the HTTP response from the ping upload
-//!   and either delete the corresponding ping from disk or re-enqueue it for sending.
+//!   and either delete the corresponding ping from disk or re-enqueue it for sending;
+//! * Optionally batches multiple pings into a single upload task,
+//!   see [`BatchLimits`](struct.BatchLimits.html);
+//! * Holds back pings while the device is offline or on a metered network,
+//!   see [`set_network_state`](struct.PingUploadManager.html#method.set_network_state).
 
-use std::collections::VecDeque;
+use std::collections::{HashMap, VecDeque};
 use std::path::PathBuf;
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::{Arc, RwLock, RwLockWriteGuard};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex, RwLock, RwLockWriteGuard};
 use std::thread;
-use std::time::{Duration, Instant};
-
-use directory::PingDirectoryManager;
-pub use request::{HeaderMap, PingRequest};
+use std::time::Duration;
+
+#[cfg(feature = "wasm")]
+use instant::Instant;
+#[cfg(not(feature = "wasm"))]
+use std::time::Instant;
+
+use crate::encryption::Encryption;
+use crate::Result;
+use directory::{PingDirectoryManager, PingMetadata};
+pub use file_sink::{FileSinkFormat, FileSinkUploader, DEFAULT_MAX_SEGMENT_SIZE};
+pub(crate) use path_template::{validate_server_endpoint, PathTemplate, PathValues};
+pub(crate) use request::strip_reserved_headers;
+pub use request::{HeaderMap, NetworkPolicy, PingCompression, PingRequest};
 pub use result::{ffi_upload_result, UploadResult};
 
 mod directory;
+mod file_sink;
+mod path_template;
 mod request;
 mod result;
 
@@ -79,24 +95,64 @@ impl RateLimiter {
         false
     }
 
-    /// Tries to increment the internal counter
-    /// and returns the current state of the RateLimiter.
+    /// Returns the current state of the RateLimiter.
+    ///
+    /// The internal counter is incremented separately, with `increment`.
     pub fn get_state(&mut self) -> RateLimiterState {
         if self.should_reset() {
             self.reset();
         }
 
-        if self.count == self.max_count {
+        if self.count >= self.max_count {
             return RateLimiterState::Throttled;
         }
 
-        self.count += 1;
         RateLimiterState::Incrementing
     }
+
+    /// The number of pings that can still be uploaded in the current interval.
+    fn remaining(&self) -> u32 {
+        self.max_count.saturating_sub(self.count)
+    }
+
+    /// Increments the internal counter by the number of pings handed out.
+    fn increment(&mut self, count: u32) {
+        self.count += count;
+    }
+}
+
+/// Limits for batching multiple pings into a single upload task.
+///
+/// If batching is enabled, `get_upload_task` hands out as many queued pings as fit
+/// into these limits as a `PingUploadTask::UploadBatch`.
+/// A single ping exceeding `max_bytes` is still handed out on its own.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BatchLimits {
+    /// The maximum number of pings in a batch.
+    pub max_count: usize,
+    /// The maximum total size of the (compressed) ping bodies in a batch, in bytes.
+    pub max_bytes: usize,
+}
+
+/// The delay suggested to the uploader while waiting for network conditions to change,
+/// in milliseconds.
+const NETWORK_WAIT_DELAY_MS: u64 = 60_000;
+
+/// The reason pending pings are held back from upload.
+///
+/// The values are part of the FFI, see `glean-core/ffi/src/upload.rs`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[repr(u8)]
+pub enum WaitReason {
+    /// The device is offline.
+    Offline = 1,
+    /// The device is on a metered network and all pending pings
+    /// may only be uploaded on unmetered networks.
+    Metered = 2,
 }
 
 /// When asking for the next ping request to upload,
-/// the requester may receive one out of three possible tasks.
+/// the requester may receive one out of five possible tasks.
 ///
 /// If new variants are added, this should be reflected in `glean-core/ffi/src/upload.rs` as well.
 #[derive(PartialEq, Debug)]
@@ -109,12 +165,44 @@ pub enum PingUploadTask {
     Wait,
     /// A flag signaling that the pending pings queue is empty and requester is done.
     Done,
+    /// Multiple PingRequests popped from the front of the queue, in upload order.
+    ///
+    /// This is only returned if batching is enabled and more than one ping is pending.
+    /// The result for each ping needs to be reported individually.
+    UploadBatch(Vec<PingRequest>),
+    /// A flag signaling that pings are pending, but can't be uploaded
+    /// under the current network conditions (see [`WaitReason`](enum.WaitReason.html)).
+    ///
+    /// The requester should come back after the suggested delay, in milliseconds,
+    /// or as soon as the network state changes.
+    WaitFor(WaitReason, u64),
+}
+
+/// The state of the pending pings queue after processing the pending pings directories.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct ScanStats {
+    /// The number of pings enqueued.
+    pub pending_pings: usize,
+    /// The total size of the pending ping files, in bytes.
+    pub directory_size: u64,
+}
+
+/// A ping handed out for upload, kept until its upload result is known.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct InFlightPing {
+    /// The name of the ping, if known.
+    pub ping_name: Option<String>,
+    /// The size of the uploaded (compressed) body, in bytes.
+    pub body_size: usize,
+    /// When the ping was submitted, in milliseconds since the UNIX epoch, if known.
+    pub submitted_at: Option<i64>,
 }
 
 /// Manages the pending pings queue and directory.
 #[derive(Debug)]
 pub struct PingUploadManager {
-    /// A FIFO queue storing a `PingRequest` for each pending ping.
+    /// A queue storing a `PingRequest` for each pending ping,
+    /// ordered by priority and FIFO within the same priority.
     queue: Arc<RwLock<VecDeque<PingRequest>>>,
     /// A manager for the pending pings directories.
     directory_manager: PingDirectoryManager,
@@ -129,6 +217,23 @@ pub struct PingUploadManager {
     ///
     /// This will be used to build the value User-Agent header for each ping request.
     language_binding_name: String,
+    /// The limits for batching pings into a single upload task, if batching is enabled.
+    batch_limits: Option<BatchLimits>,
+    /// The compression applied to ping bodies.
+    compression: PingCompression,
+    /// Whether the device is online.
+    online: AtomicBool,
+    /// Whether the device is on a metered network.
+    metered: AtomicBool,
+    /// The instant we started holding back pings because of the network conditions, if we are.
+    waiting_since: Mutex<Option<Instant>>,
+    /// The state of the queue after processing the pending pings directories,
+    /// until it is taken to be recorded.
+    scan_stats: Arc<Mutex<Option<ScanStats>>>,
+    /// The number of upload tasks denied by the rate limiter, since last taken.
+    throttled_count: AtomicU32,
+    /// The pings handed out for upload, by document ID.
+    in_flight: RwLock<HashMap<String, InFlightPing>>,
 }
 
 impl PingUploadManager {
@@ -136,6 +241,8 @@ impl PingUploadManager {
     ///
     /// Spawns a new thread and processes the pending pings directory,
     /// filling up the queue with whatever pings are in there.
+    /// With the `wasm` feature, the directory is always processed
+    /// synchronously on the calling thread instead.
     ///
     /// # Arguments
     ///
@@ -149,42 +256,85 @@ impl PingUploadManager {
         data_path: P,
         language_binding_name: &str,
         sync_scan: bool,
+    ) -> Self {
+        Self::with_settings(
+            data_path,
+            language_binding_name,
+            sync_scan,
+            Encryption::default(),
+            PingCompression::default(),
+        )
+    }
+
+    /// Create a new PingUploadManager for encrypted ping files,
+    /// compressing ping bodies as configured.
+    ///
+    /// See [`new`](#method.new).
+    ///
+    /// # Arguments
+    ///
+    /// * `encryption` - Decrypts the ping files.
+    /// * `compression` - The compression applied to ping bodies.
+    pub(crate) fn with_settings<P: Into<PathBuf>>(
+        data_path: P,
+        language_binding_name: &str,
+        sync_scan: bool,
+        encryption: Encryption,
+        compression: PingCompression,
     ) -> Self {
         let queue = Arc::new(RwLock::new(VecDeque::new()));
-        let directory_manager = PingDirectoryManager::new(data_path);
+        let directory_manager = PingDirectoryManager::with_encryption(data_path, encryption);
         let processed_pending_pings = Arc::new(AtomicBool::new(false));
 
         let local_queue = queue.clone();
         let local_flag = processed_pending_pings.clone();
         let local_manager = directory_manager.clone();
         let local_language_binding_name = language_binding_name.to_string();
-        let ping_scanning_thread = thread::Builder::new()
-            .name("glean.ping_directory_manager.process_dir".to_string())
-            .spawn(move || {
-                let mut local_queue = local_queue
-                    .write()
-                    .expect("Can't write to pending pings queue.");
-                for (document_id, path, body, headers) in local_manager.process_dir() {
-                    if Self::is_enqueued(&local_queue, &document_id) {
-                        continue;
-                    }
-                    let mut request = PingRequest::builder(&local_language_binding_name)
-                        .document_id(document_id)
-                        .path(path)
-                        .body(body);
-                    if let Some(headers) = headers {
-                        request = request.headers(headers);
-                    }
-                    local_queue.push_back(request.build());
+        let scan_stats = Arc::new(Mutex::new(None));
+        let local_scan_stats = scan_stats.clone();
+        let scan = move || {
+            let mut local_queue = local_queue
+                .write()
+                .expect("Can't write to pending pings queue.");
+            for (document_id, path, body, metadata, deletion_request) in local_manager.process_dir()
+            {
+                if Self::is_enqueued(&local_queue, &document_id) {
+                    continue;
                 }
-                local_flag.store(true, Ordering::SeqCst);
-            })
-            .expect("Unable to spawn thread to process pings directories.");
+                let request = Self::build_request(
+                    &local_language_binding_name,
+                    compression,
+                    &document_id,
+                    &path,
+                    &body,
+                    metadata,
+                    deletion_request,
+                );
+                Self::insert_by_priority(&mut local_queue, request);
+            }
+            *local_scan_stats
+                .lock()
+                .expect("Can't lock the scan statistics.") = Some(ScanStats {
+                pending_pings: local_queue.len(),
+                directory_size: local_manager.directory_size(),
+            });
+            local_flag.store(true, Ordering::SeqCst);
+        };
 
-        if sync_scan {
-            ping_scanning_thread
-                .join()
-                .expect("Unable to wait for startup ping processing to finish.");
+        // There are no threads in WebAssembly.
+        if cfg!(feature = "wasm") {
+            scan();
+        } else {
+            let ping_scanning_thread = thread::Builder::new()
+                .name("glean.ping_directory_manager.process_dir".to_string())
+                .spawn(scan)
+                .expect("Unable to spawn thread to process pings directories.");
+
+            if sync_scan {
+                ping_scanning_thread
+                    .join()
+                    .expect("Unable to wait for startup ping processing to finish.");
+            }
         }
 
         Self {
@@ -193,9 +343,47 @@ impl PingUploadManager {
             directory_manager,
             rate_limiter: None,
             language_binding_name: language_binding_name.into(),
+            batch_limits: None,
+            compression,
+            online: AtomicBool::new(true),
+            metered: AtomicBool::new(false),
+            waiting_since: Mutex::new(None),
+            scan_stats,
+            throttled_count: AtomicU32::new(0),
+            in_flight: RwLock::new(HashMap::new()),
         }
     }
 
+    /// Create a new PingUploadManager that never processes the pending pings directories.
+    ///
+    /// This is used in child processes, which don't upload any pings themselves.
+    ///
+    /// # Arguments
+    ///
+    /// * `data_path` - Path to the pending pings directory.
+    pub(crate) fn no_scan<P: Into<PathBuf>>(data_path: P, language_binding_name: &str) -> Self {
+        Self {
+            queue: Arc::new(RwLock::new(VecDeque::new())),
+            processed_pending_pings: Arc::new(AtomicBool::new(true)),
+            directory_manager: PingDirectoryManager::new(data_path),
+            rate_limiter: None,
+            language_binding_name: language_binding_name.into(),
+            batch_limits: None,
+            compression: PingCompression::default(),
+            online: AtomicBool::new(true),
+            metered: AtomicBool::new(false),
+            waiting_since: Mutex::new(None),
+            scan_stats: Arc::new(Mutex::new(None)),
+            throttled_count: AtomicU32::new(0),
+            in_flight: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Re-encrypt all pending ping files with the current encryption key.
+    pub(crate) fn reencrypt_pending_pings(&self) -> Result<()> {
+        self.directory_manager.reencrypt()
+    }
+
     fn has_processed_pings_dir(&self) -> bool {
         self.processed_pending_pings.load(Ordering::SeqCst)
     }
@@ -207,6 +395,18 @@ impl PingUploadManager {
             .any(|request| request.document_id == document_id)
     }
 
+    /// Inserts a request behind all requests that need to be uploaded before it.
+    ///
+    /// This keeps `deletion-request` pings at the front and requests of the same priority
+    /// in the order they were enqueued.
+    fn insert_by_priority(queue: &mut VecDeque<PingRequest>, request: PingRequest) {
+        let position = queue
+            .iter()
+            .position(|queued| request.precedes(queued))
+            .unwrap_or(queue.len());
+        queue.insert(position, request);
+    }
+
     /// Adds rate limiting capability to this upload manager. The rate limiter
     /// will limit the amount of calls to `get_upload_task` per interval.
     ///
@@ -225,7 +425,76 @@ impl PingUploadManager {
         )));
     }
 
-    fn enqueue_ping(&self, document_id: &str, path: &str, body: &str, headers: Option<HeaderMap>) {
+    /// Enables or disables batching of multiple pings into a single upload task.
+    ///
+    /// ## Arguments
+    ///
+    /// * `limits` - the limits for each batch. If `None`, every upload task carries a single ping.
+    pub fn set_batch_limits(&mut self, limits: Option<BatchLimits>) {
+        self.batch_limits = limits;
+    }
+
+    /// Updates the network conditions pings are uploaded under.
+    ///
+    /// While offline, no pings are handed out for upload.
+    /// While on a metered network, only pings with the `NetworkPolicy::Any` policy are.
+    ///
+    /// ## Arguments
+    ///
+    /// * `online` - Whether the device is online.
+    /// * `metered` - Whether the device is on a metered network.
+    pub fn set_network_state(&self, online: bool, metered: bool) {
+        self.online.store(online, Ordering::SeqCst);
+        self.metered.store(metered, Ordering::SeqCst);
+    }
+
+    /// Returns the time pings were held back because of the network conditions,
+    /// if they were until the last upload task was handed out.
+    pub(crate) fn take_network_wait_time(&self) -> Option<Duration> {
+        let mut waiting_since = self
+            .waiting_since
+            .lock()
+            .expect("Can't lock the network wait time.");
+        waiting_since.take().map(|since| since.elapsed())
+    }
+
+    /// Returns the state of the queue after processing the pending pings directories,
+    /// once it is done and if it was not taken before.
+    pub(crate) fn take_scan_stats(&self) -> Option<ScanStats> {
+        self.scan_stats
+            .lock()
+            .expect("Can't lock the scan statistics.")
+            .take()
+    }
+
+    /// Returns the number of upload tasks denied by the rate limiter since the last call.
+    pub(crate) fn take_throttled_count(&self) -> u32 {
+        self.throttled_count.swap(0, Ordering::SeqCst)
+    }
+
+    /// Returns a task to wait for the network conditions to change,
+    /// starting to track the time pings are held back, unless already tracking.
+    fn wait_for(&self, reason: WaitReason) -> PingUploadTask {
+        log::info!(
+            "Tried getting an upload task, but the network conditions don't allow it: {:?}",
+            reason
+        );
+        let mut waiting_since = self
+            .waiting_since
+            .lock()
+            .expect("Can't lock the network wait time.");
+        waiting_since.get_or_insert_with(Instant::now);
+        PingUploadTask::WaitFor(reason, NETWORK_WAIT_DELAY_MS)
+    }
+
+    fn enqueue_ping(
+        &self,
+        document_id: &str,
+        path: &str,
+        body: &str,
+        metadata: PingMetadata,
+        deletion_request: bool,
+    ) {
         let mut queue = self
             .queue
             .write()
@@ -241,16 +510,54 @@ impl PingUploadManager {
             return;
         }
 
+        // A ping handed out earlier without a response is no longer in flight.
+        self.in_flight
+            .write()
+            .expect("Can't write to the in-flight pings.")
+            .remove(document_id);
+
         log::trace!("Enqueuing ping {} at {}", document_id, path);
-        let mut request = PingRequest::builder(&self.language_binding_name)
+        let request = Self::build_request(
+            &self.language_binding_name,
+            self.compression,
+            document_id,
+            path,
+            body,
+            metadata,
+            deletion_request,
+        );
+
+        Self::insert_by_priority(&mut queue, request);
+    }
+
+    /// Builds the request for a ping from its persisted contents.
+    fn build_request(
+        language_binding_name: &str,
+        compression: PingCompression,
+        document_id: &str,
+        path: &str,
+        body: &str,
+        metadata: PingMetadata,
+        deletion_request: bool,
+    ) -> PingRequest {
+        let mut request = PingRequest::builder(language_binding_name)
             .document_id(document_id)
             .path(path)
-            .body(body);
-        if let Some(headers) = headers {
+            .compression(compression)
+            .body(body)
+            .priority(metadata.priority)
+            .network_policy(metadata.network_policy)
+            .deletion_request(deletion_request);
+        if let Some(headers) = metadata.headers {
             request = request.headers(headers);
         }
-
-        queue.push_back(request.build());
+        if let Some(ping) = metadata.ping {
+            request = request.ping_name(ping);
+        }
+        if let Some(submitted_at) = metadata.submitted_at {
+            request = request.submitted_at(submitted_at);
+        }
+        request.build()
     }
 
     /// Reads a ping file, creates a `PingRequest` and adds it to the queue.
@@ -261,20 +568,26 @@ impl PingUploadManager {
     ///
     /// * `document_id` - The UUID of the ping in question.
     pub fn enqueue_ping_from_file(&self, document_id: &str) {
-        if let Some((doc_id, path, body, headers)) =
+        if let Some((doc_id, path, body, metadata, deletion_request)) =
             self.directory_manager.process_file(document_id)
         {
-            self.enqueue_ping(&doc_id, &path, &body, headers)
+            self.enqueue_ping(&doc_id, &path, &body, metadata, deletion_request)
         }
     }
 
     /// Clears the pending pings queue, leaves the deletion-request pings.
+    ///
+    /// The pings handed out for upload are no longer tracked either.
     pub fn clear_ping_queue(&self) -> RwLockWriteGuard<'_, VecDeque<PingRequest>> {
         log::trace!("Clearing ping queue");
         let mut queue = self
             .queue
             .write()
             .expect("Can't write to pending pings queue.");
+        self.in_flight
+            .write()
+            .expect("Can't write to the in-flight pings.")
+            .clear();
 
         queue.retain(|ping| ping.is_deletion_request());
         log::trace!(
@@ -284,6 +597,47 @@ impl PingUploadManager {
         queue
     }
 
+    /// The number of requests from the front of the queue that make up the next upload task.
+    ///
+    /// This is always at least one for a non-empty queue, as long as `max_pings` is not zero.
+    fn take_batch(
+        &self,
+        queue: &mut VecDeque<PingRequest>,
+        metered: bool,
+        max_pings: usize,
+    ) -> Vec<PingRequest> {
+        let (max_count, max_bytes) = match self.batch_limits {
+            Some(limits) => (limits.max_count.max(1), limits.max_bytes),
+            None => (1, std::usize::MAX),
+        };
+        let max_count = max_count.min(max_pings);
+
+        // On metered networks, pings that are only uploaded on unmetered networks stay queued.
+        let mut total_bytes = 0;
+        let mut indices = Vec::new();
+        for (index, request) in queue.iter().enumerate() {
+            if indices.len() == max_count {
+                break;
+            }
+            if metered && !request.network_policy.allows_metered() {
+                continue;
+            }
+            total_bytes += request.body.len();
+            if !indices.is_empty() && total_bytes > max_bytes {
+                break;
+            }
+            indices.push(index);
+        }
+
+        let mut batch: Vec<_> = indices
+            .into_iter()
+            .rev()
+            .filter_map(|index| queue.remove(index))
+            .collect();
+        batch.reverse();
+        batch
+    }
+
     /// Gets the next `PingUploadTask`.
     ///
     /// ## Arguments
@@ -306,37 +660,79 @@ impl PingUploadManager {
             .write()
             .expect("Can't write to pending pings queue.");
         match queue.front() {
-            Some(request) => {
-                if let Some(rate_limiter) = &self.rate_limiter {
-                    let mut rate_limiter = rate_limiter
-                        .write()
-                        .expect("Can't write to the rate limiter.");
-                    if rate_limiter.get_state() == RateLimiterState::Throttled {
-                        log::info!(
-                            "Tried getting an upload task, but we are throttled at the moment."
-                        );
-                        return PingUploadTask::Wait;
-                    }
+            Some(_) => {
+                // Check the network first, so we don't use up the rate limit while waiting.
+                let metered = self.metered.load(Ordering::SeqCst);
+                if !self.online.load(Ordering::SeqCst) {
+                    return self.wait_for(WaitReason::Offline);
+                }
+                if metered && !queue.iter().any(|r| r.network_policy.allows_metered()) {
+                    return self.wait_for(WaitReason::Metered);
                 }
 
-                log::info!(
-                    "New upload task with id {} (path: {})",
-                    request.document_id,
-                    request.path
-                );
+                // Every ping in a batch counts against the rate limit.
+                let mut rate_limiter = self.rate_limiter.as_ref().map(|rate_limiter| {
+                    rate_limiter
+                        .write()
+                        .expect("Can't write to the rate limiter.")
+                });
+                let max_pings = match &mut rate_limiter {
+                    Some(rate_limiter) => {
+                        if rate_limiter.get_state() == RateLimiterState::Throttled {
+                            log::info!(
+                                "Tried getting an upload task, but we are throttled at the moment."
+                            );
+                            self.throttled_count.fetch_add(1, Ordering::SeqCst);
+                            return PingUploadTask::Wait;
+                        }
+                        rate_limiter.remaining() as usize
+                    }
+                    None => std::usize::MAX,
+                };
 
-                if log_ping {
-                    if let Some(body) = request.pretty_body() {
-                        chunked_log_info(&request.path, &body);
-                    } else {
-                        chunked_log_info(&request.path, "<invalid ping payload>");
+                let mut requests = self.take_batch(&mut queue, metered, max_pings);
+                if let Some(rate_limiter) = &mut rate_limiter {
+                    rate_limiter.increment(requests.len() as u32);
+                }
+                let mut in_flight = self
+                    .in_flight
+                    .write()
+                    .expect("Can't write to the in-flight pings.");
+                for request in &requests {
+                    in_flight.insert(
+                        request.document_id.clone(),
+                        InFlightPing {
+                            ping_name: request.ping_name.clone(),
+                            body_size: request.body.len(),
+                            submitted_at: request.submitted_at,
+                        },
+                    );
+
+                    log::info!(
+                        "New upload task with id {} (path: {})",
+                        request.document_id,
+                        request.path
+                    );
+
+                    if log_ping {
+                        if let Some(body) = request.pretty_body() {
+                            chunked_log_info(&request.path, &body);
+                        } else {
+                            chunked_log_info(&request.path, "<invalid ping payload>");
+                        }
                     }
                 }
 
-                PingUploadTask::Upload(queue.pop_front().unwrap())
+                if requests.len() == 1 {
+                    PingUploadTask::Upload(requests.pop().unwrap())
+                } else {
+                    PingUploadTask::UploadBatch(requests)
+                }
             }
             None => {
                 log::info!("No more pings to upload! You are done.");
+                // There is nothing left to wait for.
+                self.take_network_wait_time();
                 PingUploadTask::Done
             }
         }
@@ -380,11 +776,33 @@ impl PingUploadManager {
     /// `document_id` - The UUID of the ping in question.
     /// `status` - The HTTP status of the response.
     pub fn process_ping_upload_response(&self, document_id: &str, status: UploadResult) {
+        self.process_upload_response(document_id, status);
+    }
+
+    /// Processes the response from an attempt to upload a ping,
+    /// see [`process_ping_upload_response`](#method.process_ping_upload_response).
+    ///
+    /// # Return value
+    ///
+    /// Returns what is known about the ping if it was uploaded successfully
+    /// and had been handed out by this manager.
+    pub(crate) fn process_upload_response(
+        &self,
+        document_id: &str,
+        status: UploadResult,
+    ) -> Option<InFlightPing> {
+        let in_flight = self
+            .in_flight
+            .write()
+            .expect("Can't write to the in-flight pings.")
+            .remove(document_id);
+
         use UploadResult::*;
         match status {
             HttpStatus(status @ 200..=299) => {
                 log::info!("Ping {} successfully sent {}.", document_id, status);
                 self.directory_manager.delete_file(document_id);
+                return in_flight;
             }
 
             UnrecoverableFailure | HttpStatus(400..=499) => {
@@ -405,6 +823,7 @@ impl PingUploadManager {
                 self.enqueue_ping_from_file(&document_id);
             }
         };
+        None
     }
 }
 
@@ -516,7 +935,13 @@ mod test {
         }
 
         // Enqueue a ping
-        upload_manager.enqueue_ping(&Uuid::new_v4().to_string(), PATH, "", None);
+        upload_manager.enqueue_ping(
+            &Uuid::new_v4().to_string(),
+            PATH,
+            "",
+            PingMetadata::default(),
+            false,
+        );
 
         // Try and get the next request.
         // Verify request was returned
@@ -540,7 +965,13 @@ mod test {
         // Enqueue a ping multiple times
         let n = 10;
         for _ in 0..n {
-            upload_manager.enqueue_ping(&Uuid::new_v4().to_string(), PATH, "", None);
+            upload_manager.enqueue_ping(
+                &Uuid::new_v4().to_string(),
+                PATH,
+                "",
+                PingMetadata::default(),
+                false,
+            );
         }
 
         // Verify a request is returned for each submitted ping
@@ -573,7 +1004,13 @@ mod test {
 
         // Enqueue a ping multiple times
         for _ in 0..max_pings_per_interval {
-            upload_manager.enqueue_ping(&Uuid::new_v4().to_string(), PATH, "", None);
+            upload_manager.enqueue_ping(
+                &Uuid::new_v4().to_string(),
+                PATH,
+                "",
+                PingMetadata::default(),
+                false,
+            );
         }
 
         // Verify a request is returned for each submitted ping
@@ -586,7 +1023,13 @@ mod test {
 
         // Enqueue just one more ping.
         // We should still be within the default rate limit time.
-        upload_manager.enqueue_ping(&Uuid::new_v4().to_string(), PATH, "", None);
+        upload_manager.enqueue_ping(
+            &Uuid::new_v4().to_string(),
+            PATH,
+            "",
+            PingMetadata::default(),
+            false,
+        );
 
         // Verify that we are indeed told to wait because we are at capacity
         assert_eq!(PingUploadTask::Wait, upload_manager.get_upload_task(false));
@@ -612,7 +1055,13 @@ mod test {
 
         // Enqueue a ping multiple times
         for _ in 0..10 {
-            upload_manager.enqueue_ping(&Uuid::new_v4().to_string(), PATH, "", None);
+            upload_manager.enqueue_ping(
+                &Uuid::new_v4().to_string(),
+                PATH,
+                "",
+                PingMetadata::default(),
+                false,
+            );
         }
 
         // Clear the queue
@@ -860,7 +1309,7 @@ mod test {
         let path2 = format!("/submit/app_id/test-ping/1/{}", doc2);
 
         // Enqueue a ping
-        upload_manager.enqueue_ping(&doc1, &path1, "", None);
+        upload_manager.enqueue_ping(&doc1, &path1, "", PingMetadata::default(), false);
 
         // Try and get the first request.
         let req = match upload_manager.get_upload_task(false) {
@@ -870,7 +1319,7 @@ mod test {
         assert_eq!(doc1, req.document_id);
 
         // Schedule the next one while the first one is "in progress"
-        upload_manager.enqueue_ping(&doc2, &path2, "", None);
+        upload_manager.enqueue_ping(&doc2, &path2, "", PingMetadata::default(), false);
 
         // Mark as processed
         upload_manager.process_ping_upload_response(&req.document_id, HttpStatus(200));
@@ -942,8 +1391,8 @@ mod test {
         let path = format!("/submit/app_id/test-ping/1/{}", doc_id);
 
         // Try to enqueue a ping with the same doc_id twice
-        upload_manager.enqueue_ping(&doc_id, &path, "", None);
-        upload_manager.enqueue_ping(&doc_id, &path, "", None);
+        upload_manager.enqueue_ping(&doc_id, &path, "", PingMetadata::default(), false);
+        upload_manager.enqueue_ping(&doc_id, &path, "", PingMetadata::default(), false);
 
         // Get a task once
         match upload_manager.get_upload_task(false) {
@@ -954,4 +1403,345 @@ mod test {
         // There should be no more queued tasks
         assert_eq!(upload_manager.get_upload_task(false), PingUploadTask::Done);
     }
+
+    #[test]
+    fn pings_are_uploaded_by_priority() {
+        let dir = tempfile::tempdir().unwrap();
+        let upload_manager = PingUploadManager::new(dir.path(), "Testing", true);
+
+        let path =
+            |ping_name: &str, doc_id: &str| format!("/submit/app_id/{}/1/{}", ping_name, doc_id);
+        let low = Uuid::new_v4().to_string();
+        let high = Uuid::new_v4().to_string();
+        let deletion = Uuid::new_v4().to_string();
+        let other_low = Uuid::new_v4().to_string();
+
+        upload_manager.enqueue_ping(&low, &path("low", &low), "", PingMetadata::default(), false);
+        upload_manager.enqueue_ping(
+            &high,
+            &path("high", &high),
+            "",
+            PingMetadata {
+                priority: 5,
+                ..Default::default()
+            },
+            false,
+        );
+        upload_manager.enqueue_ping(
+            &deletion,
+            &path("deletion-request", &deletion),
+            "",
+            PingMetadata::default(),
+            true,
+        );
+        upload_manager.enqueue_ping(
+            &other_low,
+            &path("low", &other_low),
+            "",
+            PingMetadata::default(),
+            false,
+        );
+
+        for expected in &[deletion, high, low, other_low] {
+            match upload_manager.get_upload_task(false) {
+                PingUploadTask::Upload(request) => assert_eq!(expected, &request.document_id),
+                _ => panic!("Expected upload manager to return the next request!"),
+            }
+        }
+        assert_eq!(upload_manager.get_upload_task(false), PingUploadTask::Done);
+    }
+
+    #[test]
+    fn directory_scan_enqueues_pings_by_priority() {
+        let (mut glean, dir) = new_glean(None);
+
+        let backlog = PingType::new("backlog", true, true, vec![]);
+        let urgent = PingType::new("urgent", true, true, vec![]).with_priority(10);
+        glean.register_ping_type(&backlog);
+        glean.register_ping_type(&urgent);
+
+        for _ in 0..5 {
+            glean.submit_ping(&backlog, None).unwrap();
+        }
+        glean.submit_ping(&urgent, None).unwrap();
+        glean
+            .internal_pings
+            .deletion_request
+            .submit(&glean, None)
+            .unwrap();
+
+        let upload_manager = PingUploadManager::new(dir.path(), "Testing", true);
+        let mut ping_names = Vec::new();
+        while let PingUploadTask::Upload(request) = upload_manager.get_upload_task(false) {
+            ping_names.push(request.path.split('/').nth(3).unwrap().to_string());
+        }
+
+        assert_eq!(7, ping_names.len());
+        assert_eq!("deletion-request", ping_names[0]);
+        assert_eq!("urgent", ping_names[1]);
+        assert!(ping_names[2..].iter().all(|name| name == "backlog"));
+    }
+
+    #[test]
+    fn every_ping_in_a_batch_counts_against_the_rate_limit() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut upload_manager = PingUploadManager::new(dir.path(), "Testing", true);
+        upload_manager.set_rate_limiter(60, 3);
+        upload_manager.set_batch_limits(Some(BatchLimits {
+            max_count: 2,
+            max_bytes: 1024,
+        }));
+
+        for _ in 0..5 {
+            upload_manager.enqueue_ping(
+                &Uuid::new_v4().to_string(),
+                PATH,
+                "",
+                PingMetadata::default(),
+                false,
+            );
+        }
+        match upload_manager.get_upload_task(false) {
+            PingUploadTask::UploadBatch(requests) => assert_eq!(2, requests.len()),
+            _ => panic!("Expected upload manager to return a batch!"),
+        }
+        // Only a single ping is left in the current interval.
+        match upload_manager.get_upload_task(false) {
+            PingUploadTask::Upload(_) => {}
+            _ => panic!("Expected upload manager to return the next request!"),
+        }
+        assert_eq!(upload_manager.get_upload_task(false), PingUploadTask::Wait);
+    }
+
+    #[test]
+    fn pings_are_batched_within_limits() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut upload_manager = PingUploadManager::new(dir.path(), "Testing", true);
+        upload_manager.set_batch_limits(Some(BatchLimits {
+            max_count: 3,
+            max_bytes: 1024,
+        }));
+
+        for _ in 0..4 {
+            upload_manager.enqueue_ping(
+                &Uuid::new_v4().to_string(),
+                PATH,
+                "",
+                PingMetadata::default(),
+                false,
+            );
+        }
+        match upload_manager.get_upload_task(false) {
+            PingUploadTask::UploadBatch(requests) => assert_eq!(3, requests.len()),
+            _ => panic!("Expected upload manager to return a batch!"),
+        }
+        // A single remaining ping is not wrapped in a batch.
+        match upload_manager.get_upload_task(false) {
+            PingUploadTask::Upload(_) => {}
+            _ => panic!("Expected upload manager to return the next request!"),
+        }
+
+        // Bodies are gzipped, so use bodies that don't compress well to hit the byte limit.
+        let body = |seed: u8| -> String {
+            (0..600u32)
+                .map(|i| (b'a' + ((i * 7 + u32::from(seed) * 13) % 26) as u8) as char)
+                .collect()
+        };
+        upload_manager.set_batch_limits(Some(BatchLimits {
+            max_count: 3,
+            max_bytes: 20,
+        }));
+        upload_manager.enqueue_ping(
+            &Uuid::new_v4().to_string(),
+            PATH,
+            &body(1),
+            PingMetadata::default(),
+            false,
+        );
+        upload_manager.enqueue_ping(
+            &Uuid::new_v4().to_string(),
+            PATH,
+            &body(2),
+            PingMetadata::default(),
+            false,
+        );
+        // A ping exceeding the byte limit is still handed out on its own.
+        for _ in 0..2 {
+            match upload_manager.get_upload_task(false) {
+                PingUploadTask::Upload(_) => {}
+                _ => panic!("Expected upload manager to return the next request!"),
+            }
+        }
+        assert_eq!(upload_manager.get_upload_task(false), PingUploadTask::Done);
+    }
+
+    #[test]
+    fn only_failed_pings_of_a_batch_are_reenqueued() {
+        let (mut glean, _) = new_glean(None);
+        glean.set_upload_batch_limits(Some(BatchLimits {
+            max_count: 10,
+            max_bytes: 1024 * 1024,
+        }));
+
+        let ping_type = PingType::new("test", true, /* send_if_empty */ true, vec![]);
+        glean.register_ping_type(&ping_type);
+        for _ in 0..3 {
+            glean.submit_ping(&ping_type, None).unwrap();
+        }
+
+        let requests = match glean.get_upload_task() {
+            PingUploadTask::UploadBatch(requests) => requests,
+            _ => panic!("Expected upload manager to return a batch!"),
+        };
+        assert_eq!(3, requests.len());
+
+        let results = vec![
+            (requests[0].document_id.clone(), HttpStatus(200)),
+            (requests[1].document_id.clone(), RecoverableFailure),
+            (requests[2].document_id.clone(), HttpStatus(400)),
+        ];
+        glean.process_ping_upload_batch_response(results);
+
+        match glean.get_upload_task() {
+            PingUploadTask::Upload(request) => {
+                assert_eq!(requests[1].document_id, request.document_id)
+            }
+            _ => panic!("Expected upload manager to return the next request!"),
+        }
+        assert_eq!(glean.get_upload_task(), PingUploadTask::Done);
+    }
+
+    #[test]
+    fn pings_are_held_back_while_offline() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut upload_manager = PingUploadManager::new(dir.path(), "Testing", true);
+        upload_manager.set_rate_limiter(60, 1);
+
+        let doc_id = Uuid::new_v4().to_string();
+        upload_manager.enqueue_ping(&doc_id, PATH, "", PingMetadata::default(), false);
+
+        // Waiting doesn't use up the rate limit.
+        upload_manager.set_network_state(false, false);
+        for _ in 0..3 {
+            assert_eq!(
+                upload_manager.get_upload_task(false),
+                PingUploadTask::WaitFor(WaitReason::Offline, NETWORK_WAIT_DELAY_MS)
+            );
+        }
+
+        upload_manager.set_network_state(true, false);
+        match upload_manager.get_upload_task(false) {
+            PingUploadTask::Upload(request) => assert_eq!(doc_id, request.document_id),
+            _ => panic!("Expected upload manager to return the next request!"),
+        }
+        assert!(upload_manager.take_network_wait_time().is_some());
+        assert!(upload_manager.take_network_wait_time().is_none());
+    }
+
+    #[test]
+    fn unmetered_pings_are_held_back_on_metered_networks() {
+        let dir = tempfile::tempdir().unwrap();
+        let upload_manager = PingUploadManager::new(dir.path(), "Testing", true);
+
+        let unmetered = Uuid::new_v4().to_string();
+        let any = Uuid::new_v4().to_string();
+        upload_manager.enqueue_ping(
+            &unmetered,
+            PATH,
+            "",
+            PingMetadata {
+                network_policy: NetworkPolicy::Unmetered,
+                ..Default::default()
+            },
+            false,
+        );
+        upload_manager.enqueue_ping(&any, PATH, "", PingMetadata::default(), false);
+
+        upload_manager.set_network_state(true, true);
+        match upload_manager.get_upload_task(false) {
+            PingUploadTask::Upload(request) => assert_eq!(any, request.document_id),
+            _ => panic!("Expected upload manager to return the next request!"),
+        }
+        assert_eq!(
+            upload_manager.get_upload_task(false),
+            PingUploadTask::WaitFor(WaitReason::Metered, NETWORK_WAIT_DELAY_MS)
+        );
+
+        upload_manager.set_network_state(true, false);
+        match upload_manager.get_upload_task(false) {
+            PingUploadTask::Upload(request) => assert_eq!(unmetered, request.document_id),
+            _ => panic!("Expected upload manager to return the next request!"),
+        }
+        assert_eq!(upload_manager.get_upload_task(false), PingUploadTask::Done);
+    }
+
+    #[test]
+    fn throttled_requests_and_uploaded_pings_are_tracked() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut upload_manager = PingUploadManager::new(dir.path(), "Testing", true);
+        upload_manager.set_rate_limiter(60, 1);
+
+        for _ in 0..2 {
+            upload_manager.enqueue_ping(
+                &Uuid::new_v4().to_string(),
+                PATH,
+                "",
+                PingMetadata {
+                    ping: Some("custom".into()),
+                    submitted_at: Some(1),
+                    ..Default::default()
+                },
+                false,
+            );
+        }
+
+        let request = match upload_manager.get_upload_task(false) {
+            PingUploadTask::Upload(request) => request,
+            _ => panic!("Expected upload manager to return the next request!"),
+        };
+        assert_eq!(upload_manager.get_upload_task(false), PingUploadTask::Wait);
+        assert_eq!(upload_manager.get_upload_task(false), PingUploadTask::Wait);
+        assert_eq!(2, upload_manager.take_throttled_count());
+        assert_eq!(0, upload_manager.take_throttled_count());
+
+        let uploaded = upload_manager
+            .process_upload_response(&request.document_id, HttpStatus(200))
+            .unwrap();
+        assert_eq!(Some("custom".to_string()), uploaded.ping_name);
+        assert_eq!(request.body.len(), uploaded.body_size);
+        assert_eq!(Some(1), uploaded.submitted_at);
+
+        // Only the first response for a ping is tracked.
+        assert!(upload_manager
+            .process_upload_response(&request.document_id, HttpStatus(200))
+            .is_none());
+    }
+
+    #[test]
+    fn pings_without_a_response_are_no_longer_tracked() {
+        let dir = tempfile::tempdir().unwrap();
+        let upload_manager = PingUploadManager::new(dir.path(), "Testing", true);
+        let in_flight =
+            |upload_manager: &PingUploadManager| upload_manager.in_flight.read().unwrap().len();
+
+        let doc_id = Uuid::new_v4().to_string();
+        upload_manager.enqueue_ping(&doc_id, PATH, "", PingMetadata::default(), false);
+        match upload_manager.get_upload_task(false) {
+            PingUploadTask::Upload(_) => {}
+            _ => panic!("Expected upload manager to return the next request!"),
+        }
+        assert_eq!(1, in_flight(&upload_manager));
+
+        // Enqueued again, e.g. after a restart of the uploader.
+        upload_manager.enqueue_ping(&doc_id, PATH, "", PingMetadata::default(), false);
+        assert_eq!(0, in_flight(&upload_manager));
+
+        match upload_manager.get_upload_task(false) {
+            PingUploadTask::Upload(_) => {}
+            _ => panic!("Expected upload manager to return the next request!"),
+        }
+        assert_eq!(1, in_flight(&upload_manager));
+        drop(upload_manager.clear_ping_queue());
+        assert_eq!(0, in_flight(&upload_manager));
+    }
 }
@@ -0,0 +1,289 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Templates for the URL paths pings are uploaded to.
+
+use crate::error::{ErrorKind, Result};
+
+/// The path template of the Mozilla ingestion pipeline.
+pub(crate) const DEFAULT_PATH_TEMPLATE: &str = "/submit/{app_id}/{ping}/{schema_version}/{doc_id}";
+
+/// A value substituted into a path template.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Placeholder {
+    AppId,
+    Ping,
+    SchemaVersion,
+    DocId,
+    Channel,
+}
+
+impl Placeholder {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "app_id" => Some(Placeholder::AppId),
+            "ping" => Some(Placeholder::Ping),
+            "schema_version" => Some(Placeholder::SchemaVersion),
+            "doc_id" => Some(Placeholder::DocId),
+            "channel" => Some(Placeholder::Channel),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    Literal(String),
+    Placeholder(Placeholder),
+}
+
+/// The values to fill into a path template.
+pub(crate) struct PathValues<'a> {
+    pub app_id: &'a str,
+    pub ping: &'a str,
+    pub schema_version: u32,
+    pub doc_id: &'a str,
+    pub channel: &'a str,
+}
+
+/// A validated template for the URL path of pings.
+///
+/// Templates contain placeholders in braces, which are replaced when a ping is submitted:
+/// `{app_id}`, `{ping}`, `{schema_version}`, `{doc_id}` and `{channel}`.
+/// The channel is set by the application, so it is replaced by `unknown` unless it only
+/// consists of ASCII letters, digits, `.`, `_` and `-`.
+/// Every template must start with a `/` and contain the `{doc_id}` placeholder,
+/// so that each ping is uploaded to a unique path.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct PathTemplate {
+    segments: Vec<Segment>,
+}
+
+impl Default for PathTemplate {
+    fn default() -> Self {
+        // The default template is known to be valid.
+        Self::parse(DEFAULT_PATH_TEMPLATE).unwrap()
+    }
+}
+
+impl PathTemplate {
+    /// Parse and validate a path template.
+    ///
+    /// ## Arguments
+    ///
+    /// * `template` - The template, e.g. `/submit/{app_id}/{ping}/{schema_version}/{doc_id}`.
+    ///
+    /// ## Return value
+    ///
+    /// Returns an `InvalidPathTemplate` error if the template is malformed,
+    /// uses unknown placeholders or lacks the `{doc_id}` placeholder.
+    pub fn parse(template: &str) -> Result<Self> {
+        let invalid = |msg: &str| -> Result<Self> {
+            Err(ErrorKind::InvalidPathTemplate(format!("{} in '{}'", msg, template)).into())
+        };
+
+        if !template.starts_with('/') {
+            return invalid("Path must start with '/'");
+        }
+        if template
+            .chars()
+            .any(|c| c.is_whitespace() || c == '?' || c == '#')
+        {
+            return invalid("Path must not contain whitespace, a query or a fragment");
+        }
+
+        let mut segments = Vec::new();
+        let mut rest = template;
+        while !rest.is_empty() {
+            let start = rest.find(&['{', '}'][..]).unwrap_or(rest.len());
+            if start > 0 {
+                segments.push(Segment::Literal(rest[..start].to_string()));
+            }
+            rest = &rest[start..];
+            if rest.is_empty() {
+                break;
+            }
+            if rest.starts_with('}') {
+                return invalid("Unmatched '}'");
+            }
+
+            let end = match rest.find('}') {
+                Some(end) => end,
+                None => return invalid("Unmatched '{'"),
+            };
+            let name = &rest[1..end];
+            match Placeholder::from_name(name) {
+                Some(placeholder) => segments.push(Segment::Placeholder(placeholder)),
+                None => return invalid(&format!("Unknown placeholder '{{{}}}'", name)),
+            }
+            rest = &rest[end + 1..];
+        }
+
+        if !segments.contains(&Segment::Placeholder(Placeholder::DocId)) {
+            return invalid("Missing placeholder '{doc_id}'");
+        }
+
+        Ok(Self { segments })
+    }
+
+    /// Fill in the template.
+    pub fn render(&self, values: &PathValues) -> String {
+        let mut path = String::new();
+        for segment in &self.segments {
+            match segment {
+                Segment::Literal(literal) => path.push_str(literal),
+                Segment::Placeholder(Placeholder::AppId) => path.push_str(values.app_id),
+                Segment::Placeholder(Placeholder::Ping) => path.push_str(values.ping),
+                Segment::Placeholder(Placeholder::SchemaVersion) => {
+                    path.push_str(&values.schema_version.to_string())
+                }
+                Segment::Placeholder(Placeholder::DocId) => path.push_str(values.doc_id),
+                Segment::Placeholder(Placeholder::Channel) => {
+                    path.push_str(path_safe_channel(values.channel))
+                }
+            }
+        }
+        path
+    }
+}
+
+/// Get the channel to fill into a path.
+///
+/// Channels that could change the structure of the path, e.g. `a/b`, `x?y` or `..`,
+/// are replaced by `unknown`.
+fn path_safe_channel(channel: &str) -> &str {
+    let safe = !channel.is_empty()
+        && channel != "."
+        && channel != ".."
+        && channel
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '_' || c == '-');
+    if safe {
+        channel
+    } else {
+        "unknown"
+    }
+}
+
+/// Validate and normalize the server endpoint pings are uploaded to.
+///
+/// ## Arguments
+///
+/// * `endpoint` - The endpoint, e.g. `https://collector.example.com`.
+///
+/// ## Return value
+///
+/// Returns the endpoint without trailing slashes, or an `InvalidServerEndpoint` error
+/// if it is not an HTTP(S) URL or contains a query or fragment.
+pub(crate) fn validate_server_endpoint(endpoint: &str) -> Result<String> {
+    let host = ["https://", "http://"]
+        .iter()
+        .find(|scheme| endpoint.starts_with(*scheme))
+        .map(|scheme| &endpoint[scheme.len()..]);
+    let valid = match host {
+        Some(host) => {
+            !host.is_empty()
+                && !host.starts_with('/')
+                && !host
+                    .chars()
+                    .any(|c| c.is_whitespace() || c == '?' || c == '#')
+        }
+        None => false,
+    };
+
+    if !valid {
+        return Err(ErrorKind::InvalidServerEndpoint(endpoint.to_string()).into());
+    }
+    Ok(endpoint.trim_end_matches('/').to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn values() -> PathValues<'static> {
+        PathValues {
+            app_id: "org-mozilla-test",
+            ping: "metrics",
+            schema_version: 1,
+            doc_id: "doc",
+            channel: "nightly",
+        }
+    }
+
+    #[test]
+    fn default_template_matches_the_mozilla_pipeline() {
+        assert_eq!(
+            "/submit/org-mozilla-test/metrics/1/doc",
+            PathTemplate::default().render(&values())
+        );
+    }
+
+    #[test]
+    fn custom_templates_are_rendered() {
+        let template =
+            PathTemplate::parse("/ingest/{channel}/{app_id}-{ping}/v{schema_version}/{doc_id}")
+                .unwrap();
+        assert_eq!(
+            "/ingest/nightly/org-mozilla-test-metrics/v1/doc",
+            template.render(&values())
+        );
+    }
+
+    #[test]
+    fn unsafe_channels_are_replaced() {
+        let template = PathTemplate::parse("/ingest/{channel}/{doc_id}").unwrap();
+        let render = |channel| {
+            template.render(&PathValues {
+                channel,
+                ..values()
+            })
+        };
+
+        assert_eq!("/ingest/beta-2_rc.1/doc", render("beta-2_rc.1"));
+        for channel in &["beta 2", "a/b", "x?y", "x#y", "100%", "..", ".", "", "é"] {
+            assert_eq!("/ingest/unknown/doc", render(channel), "{}", channel);
+        }
+    }
+
+    #[test]
+    fn invalid_templates_are_rejected() {
+        for template in &[
+            "",
+            "submit/{doc_id}",
+            "/submit/{app_id}/{ping}",
+            "/submit/{unknown}/{doc_id}",
+            "/submit/{doc_id",
+            "/submit/doc_id}/{doc_id}",
+            "/submit/{{doc_id}}",
+            "/submit/{doc_id}?query",
+            "/submit/ {doc_id}",
+        ] {
+            assert!(PathTemplate::parse(template).is_err(), "{}", template);
+        }
+    }
+
+    #[test]
+    fn server_endpoints_are_validated() {
+        assert_eq!(
+            "https://collector.example.com",
+            validate_server_endpoint("https://collector.example.com/").unwrap()
+        );
+        assert_eq!(
+            "http://localhost:8080/glean",
+            validate_server_endpoint("http://localhost:8080/glean").unwrap()
+        );
+
+        for endpoint in &[
+            "",
+            "collector.example.com",
+            "ftp://example.com",
+            "https://",
+            "https:///path",
+            "https://example.com/?q",
+        ] {
+            assert!(validate_server_endpoint(endpoint).is_err(), "{}", endpoint);
+        }
+    }
+}
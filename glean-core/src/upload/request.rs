@@ -4,10 +4,12 @@
 
 //! Ping request representation.
 
+use std::borrow::Cow;
 use std::collections::HashMap;
 
 use chrono::prelude::{DateTime, Utc};
 use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use serde::{Deserialize, Serialize};
 use serde_json::{self, Value as JsonValue};
 use std::io::prelude::*;
 
@@ -16,6 +18,63 @@ use crate::system;
 /// A representation for request headers.
 pub type HeaderMap = HashMap<String, String>;
 
+/// Headers set by Glean itself, which custom headers can't override.
+const RESERVED_HEADERS: &[&str] = &[
+    "Content-Encoding",
+    "Content-Length",
+    "Content-Type",
+    "Date",
+    "User-Agent",
+    "X-Client-Type",
+    "X-Client-Version",
+    "X-Debug-ID",
+    "X-Source-Tags",
+];
+
+/// Whether the header is set by Glean itself. Header names are case-insensitive.
+pub(crate) fn is_reserved_header(name: &str) -> bool {
+    RESERVED_HEADERS
+        .iter()
+        .any(|reserved| reserved.eq_ignore_ascii_case(name))
+}
+
+/// Whether the header name is a token, as defined in [RFC 7230](https://tools.ietf.org/html/rfc7230#section-3.2.6).
+fn is_valid_header_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b))
+}
+
+/// Whether the header value can be sent without changing the rest of the request.
+fn is_valid_header_value(value: &str) -> bool {
+    !value
+        .bytes()
+        .any(|b| b == b'\r' || b == b'\n' || b == b'\0')
+}
+
+/// Remove all reserved and malformed headers from a map of custom headers.
+///
+/// Header names must be tokens and values must not contain CR, LF or NUL characters,
+/// so that a header can never inject other headers or requests.
+///
+/// Returns `false` if any header had to be removed.
+pub(crate) fn strip_reserved_headers(headers: &mut HeaderMap) -> bool {
+    let len = headers.len();
+    headers.retain(|name, value| {
+        if is_reserved_header(name) {
+            log::warn!("Ignoring custom header '{}': it is reserved by Glean", name);
+            false
+        } else if !is_valid_header_name(name) || !is_valid_header_value(value) {
+            log::warn!("Ignoring custom header {:?}: it is malformed", name);
+            false
+        } else {
+            true
+        }
+    });
+    headers.len() == len
+}
+
 /// Creates a formatted date string that can be used with Date headers.
 fn create_date_header_value(current_time: DateTime<Utc>) -> String {
     // Date headers are required to be in the following format:
@@ -42,9 +101,85 @@ fn create_user_agent_header_value(
     )
 }
 
+/// The network conditions under which a ping may be uploaded.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NetworkPolicy {
+    /// The ping is uploaded on any network.
+    Any,
+    /// The ping is only uploaded on unmetered networks.
+    Unmetered,
+}
+
+impl Default for NetworkPolicy {
+    fn default() -> Self {
+        NetworkPolicy::Any
+    }
+}
+
+impl NetworkPolicy {
+    /// Whether a ping with this policy may be uploaded on a metered network.
+    pub fn allows_metered(self) -> bool {
+        self == NetworkPolicy::Any
+    }
+}
+
+/// The compression applied to ping bodies before upload.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PingCompression {
+    /// Bodies are uploaded uncompressed.
+    None,
+    /// Bodies are compressed with gzip, at the given level (0-9).
+    Gzip(u32),
+    /// Bodies are compressed with zstd, at the given level (1-21).
+    Zstd(i32),
+}
+
+impl Default for PingCompression {
+    fn default() -> Self {
+        PingCompression::Gzip(Compression::default().level())
+    }
+}
+
+impl PingCompression {
+    /// The value of the `Content-Encoding` header for this compression,
+    /// also used as the label in the upload metrics.
+    pub fn content_encoding(&self) -> Option<&'static str> {
+        match self {
+            PingCompression::None => None,
+            PingCompression::Gzip(_) => Some("gzip"),
+            PingCompression::Zstd(_) => Some("zstd"),
+        }
+    }
+
+    /// Attempt to compress the contents of a ping.
+    ///
+    /// Returns `None` if no compression is configured or compression failed.
+    fn compress(&self, path: &str, content: &[u8]) -> Option<Vec<u8>> {
+        match *self {
+            PingCompression::None => None,
+            PingCompression::Gzip(level) => gzip_content(path, content, level),
+            #[cfg(not(target_arch = "wasm32"))]
+            PingCompression::Zstd(level) => match zstd::encode_all(content, level) {
+                Ok(compressed) => Some(compressed),
+                Err(e) => {
+                    log::error!("Failed to compress with zstd: {} - {:?}", path, e);
+                    None
+                }
+            },
+            // zstd is a C library and not available in WebAssembly.
+            #[cfg(target_arch = "wasm32")]
+            PingCompression::Zstd(_) => {
+                log::error!("zstd is not available, not compressing: {}", path);
+                None
+            }
+        }
+    }
+}
+
 /// Attempt to gzip the contents of a ping.
-fn gzip_content(path: &str, content: &[u8]) -> Option<Vec<u8>> {
-    let mut gzipper = GzEncoder::new(Vec::new(), Compression::default());
+fn gzip_content(path: &str, content: &[u8], level: u32) -> Option<Vec<u8>> {
+    let mut gzipper = GzEncoder::new(Vec::new(), Compression::new(level.min(9)));
 
     // Attempt to add the content to the gzipper.
     if let Err(e) = gzipper.write_all(content) {
@@ -59,7 +194,14 @@ pub struct Builder {
     document_id: Option<String>,
     path: Option<String>,
     body: Option<Vec<u8>>,
+    uncompressed_size: usize,
     headers: HeaderMap,
+    priority: u8,
+    network_policy: NetworkPolicy,
+    deletion_request: bool,
+    compression: PingCompression,
+    ping_name: Option<String>,
+    submitted_at: Option<i64>,
 }
 
 impl Builder {
@@ -85,7 +227,14 @@ impl Builder {
             document_id: None,
             path: None,
             body: None,
+            uncompressed_size: 0,
             headers,
+            priority: 0,
+            network_policy: NetworkPolicy::default(),
+            deletion_request: false,
+            compression: PingCompression::default(),
+            ping_name: None,
+            submitted_at: None,
         }
     }
 
@@ -101,36 +250,48 @@ impl Builder {
         self
     }
 
+    /// Sets the compression for the body of this request.
+    ///
+    /// This needs to be set before the body. Defaults to gzip.
+    pub fn compression(mut self, value: PingCompression) -> Self {
+        self.compression = value;
+        self
+    }
+
     /// Sets the body for this request.
     ///
-    /// This method will also attempt to gzip the body contents
+    /// This method will also attempt to compress the body contents
     /// and add headers related to the body that was just added.
     ///
     /// Namely these headers are the "Content-Length" with the length of the body
-    /// and in case we are successfull on gzipping the contents, the "Content-Encoding"="gzip".
+    /// and in case we are successfull on compressing the contents,
+    /// the "Content-Encoding" (e.g. "gzip").
     ///
     /// **Important**
-    /// If we are unable to gzip we don't panic and instead just set the uncompressed body.
+    /// If we are unable to compress we don't panic and instead just set the uncompressed body.
     ///
     /// # Panics
     ///
     /// This method will panic in case we try to set the body before setting the path.
     pub fn body<S: Into<String>>(mut self, value: S) -> Self {
-        // Attempt to gzip the body contents.
+        // Attempt to compress the body contents.
         let original_as_string = value.into();
-        let gzipped_content = gzip_content(
+        let compressed_content = self.compression.compress(
             self.path
                 .as_ref()
                 .expect("Path must be set before attempting to set the body"),
             original_as_string.as_bytes(),
         );
-        let add_gzip_header = gzipped_content.is_some();
-        let body = gzipped_content.unwrap_or_else(|| original_as_string.into_bytes());
+        let content_encoding = compressed_content
+            .as_ref()
+            .and(self.compression.content_encoding());
+        self.uncompressed_size = original_as_string.len();
+        let body = compressed_content.unwrap_or_else(|| original_as_string.into_bytes());
 
         // Include headers related to body
         self = self.header("Content-Length", &body.len().to_string());
-        if add_gzip_header {
-            self = self.header("Content-Encoding", "gzip");
+        if let Some(content_encoding) = content_encoding {
+            self = self.header("Content-Encoding", content_encoding);
         }
 
         self.body = Some(body);
@@ -143,6 +304,36 @@ impl Builder {
         self
     }
 
+    /// Sets the upload priority for this request.
+    pub fn priority(mut self, value: u8) -> Self {
+        self.priority = value;
+        self
+    }
+
+    /// Sets the network conditions under which this request may be uploaded.
+    pub fn network_policy(mut self, value: NetworkPolicy) -> Self {
+        self.network_policy = value;
+        self
+    }
+
+    /// Sets the name of the ping this request is for.
+    pub fn ping_name<S: Into<String>>(mut self, value: S) -> Self {
+        self.ping_name = Some(value.into());
+        self
+    }
+
+    /// Sets when the ping was submitted, in milliseconds since the UNIX epoch.
+    pub fn submitted_at(mut self, value: i64) -> Self {
+        self.submitted_at = Some(value);
+        self
+    }
+
+    /// Sets whether this request is for a `deletion-request` ping.
+    pub fn deletion_request(mut self, value: bool) -> Self {
+        self.deletion_request = value;
+        self
+    }
+
     /// Sets multiple headers for this request at once.
     pub fn headers(mut self, values: HeaderMap) -> Self {
         self.headers.extend(values);
@@ -166,7 +357,13 @@ impl Builder {
             body: self
                 .body
                 .expect("body must be set before attempting to build PingRequest"),
+            uncompressed_size: self.uncompressed_size,
             headers: self.headers,
+            priority: self.priority,
+            network_policy: self.network_policy,
+            deletion_request: self.deletion_request,
+            ping_name: self.ping_name,
+            submitted_at: self.submitted_at,
         }
     }
 }
@@ -179,12 +376,26 @@ pub struct PingRequest {
     pub document_id: String,
     /// The path for the server to upload the ping to.
     pub path: String,
-    /// The body of the request, as a byte array. If gzip encoded, then
+    /// The body of the request, as a byte array. If compressed, then
     /// the `headers` list will contain a `Content-Encoding` header with
-    /// the value `gzip`.
+    /// the compression used, e.g. `gzip`.
     pub body: Vec<u8>,
+    /// The size of the body before compression.
+    uncompressed_size: usize,
     /// A map with all the headers to be sent with the request.
     pub headers: HeaderMap,
+    /// The upload priority of the ping. Pings with a higher priority are uploaded first.
+    ///
+    /// `deletion-request` pings are always uploaded before any other ping.
+    pub priority: u8,
+    /// The network conditions under which the ping may be uploaded.
+    pub network_policy: NetworkPolicy,
+    /// Whether this request is for a `deletion-request` ping.
+    deletion_request: bool,
+    /// The name of the ping, if known.
+    pub(crate) ping_name: Option<String>,
+    /// When the ping was submitted, in milliseconds since the UNIX epoch, if known.
+    pub(crate) submitted_at: Option<i64>,
 }
 
 impl PingRequest {
@@ -200,12 +411,29 @@ impl PingRequest {
 
     /// Verifies if current request is for a deletion-request ping.
     pub fn is_deletion_request(&self) -> bool {
-        // The path format should be `/submit/<app_id>/<ping_name>/<schema_version/<doc_id>`
-        self.path
-            .split('/')
-            .nth(3)
-            .map(|url| url == "deletion-request")
-            .unwrap_or(false)
+        self.deletion_request
+    }
+
+    /// Whether this request needs to be uploaded before the `other` request.
+    ///
+    /// `deletion-request` pings come first, then pings by descending priority.
+    pub(crate) fn precedes(&self, other: &PingRequest) -> bool {
+        (self.is_deletion_request(), self.priority) > (other.is_deletion_request(), other.priority)
+    }
+
+    /// The value of the `Content-Encoding` header, if the body is compressed.
+    pub fn content_encoding(&self) -> Option<&str> {
+        self.headers.get("Content-Encoding").map(String::as_str)
+    }
+
+    /// The size of the compressed body in percent of the uncompressed body.
+    ///
+    /// Returns `None` if the body is not compressed.
+    pub(crate) fn compressed_size_percentage(&self) -> Option<u64> {
+        if self.content_encoding().is_none() || self.uncompressed_size == 0 {
+            return None;
+        }
+        Some((self.body.len() * 100 / self.uncompressed_size) as u64)
     }
 
     /// Decompress and pretty-format the ping payload
@@ -213,16 +441,38 @@ impl PingRequest {
     /// Should be used for logging when required.
     /// This decompresses the payload in memory.
     pub fn pretty_body(&self) -> Option<String> {
-        let mut gz = GzDecoder::new(&self.body[..]);
-        let mut s = String::with_capacity(self.body.len());
-
-        gz.read_to_string(&mut s)
-            .ok()
-            .map(|_| &s[..])
-            .or_else(|| std::str::from_utf8(&self.body).ok())
-            .and_then(|payload| serde_json::from_str::<JsonValue>(payload).ok())
+        self.payload()
             .and_then(|json| serde_json::to_string_pretty(&json).ok())
     }
+
+    /// Decompress the ping body.
+    ///
+    /// Returns the body as it is if it can't be decompressed, e.g. because it is not compressed.
+    pub(crate) fn decompressed_body(&self) -> Cow<'_, [u8]> {
+        let decompressed = match self.content_encoding() {
+            #[cfg(not(target_arch = "wasm32"))]
+            Some("zstd") => zstd::decode_all(&self.body[..]).ok(),
+            _ => {
+                let mut body = Vec::with_capacity(self.body.len());
+                GzDecoder::new(&self.body[..])
+                    .read_to_end(&mut body)
+                    .ok()
+                    .map(|_| body)
+            }
+        };
+
+        match decompressed {
+            Some(body) => Cow::Owned(body),
+            None => Cow::Borrowed(&self.body),
+        }
+    }
+
+    /// Decompress and parse the ping payload.
+    ///
+    /// Returns `None` if the body isn't valid JSON, e.g. because the whole payload is encrypted.
+    pub(crate) fn payload(&self) -> Option<JsonValue> {
+        serde_json::from_slice(&self.decompressed_body()).ok()
+    }
 }
 
 #[cfg(test)]
@@ -262,4 +512,75 @@ mod test {
         assert!(request.headers.contains_key("X-Client-Version"));
         assert!(request.headers.contains_key("Content-Length"));
     }
+
+    #[test]
+    fn reserved_headers_are_stripped() {
+        let mut headers = HeaderMap::new();
+        headers.insert("Authorization".into(), "Bearer token".into());
+        headers.insert("user-agent".into(), "Not Glean".into());
+        headers.insert("X-Debug-ID".into(), "tag".into());
+
+        assert!(!strip_reserved_headers(&mut headers));
+        assert_eq!(1, headers.len());
+        assert!(headers.contains_key("Authorization"));
+        assert!(strip_reserved_headers(&mut headers));
+    }
+
+    #[test]
+    fn malformed_headers_are_stripped() {
+        let mut headers = HeaderMap::new();
+        headers.insert("X-Valid".into(), "value; with \"quotes\"".into());
+        headers.insert("X-Injected".into(), "value\r\nX-Other: injected".into());
+        headers.insert("X-Newline".into(), "value\n".into());
+        headers.insert("X-Nul".into(), "value\0".into());
+        headers.insert("X Space".into(), "value".into());
+        headers.insert("X-Colon:".into(), "value".into());
+        headers.insert("".into(), "value".into());
+
+        assert!(!strip_reserved_headers(&mut headers));
+        assert_eq!(1, headers.len());
+        assert!(headers.contains_key("X-Valid"));
+    }
+
+    #[test]
+    fn bodies_are_compressed_as_configured() {
+        let body = r#"{"ping_info":{"seq":0},"metrics":{}}"#;
+        let build = |compression| {
+            PingRequest::builder("Rust")
+                .document_id("woop")
+                .path("/random/path/doesnt/matter")
+                .compression(compression)
+                .body(body)
+                .build()
+        };
+
+        let request = build(PingCompression::None);
+        assert_eq!(None, request.content_encoding());
+        assert_eq!(body.as_bytes(), &request.body[..]);
+        assert_eq!(None, request.compressed_size_percentage());
+
+        let request = build(PingCompression::Gzip(9));
+        assert_eq!(Some("gzip"), request.content_encoding());
+        assert!(request.compressed_size_percentage().is_some());
+
+        let request = build(PingCompression::Zstd(3));
+        assert_eq!(Some("zstd"), request.content_encoding());
+        assert_eq!(
+            request.headers["Content-Length"],
+            request.body.len().to_string()
+        );
+
+        let expected: JsonValue = serde_json::from_str(body).unwrap();
+        for compression in &[
+            PingCompression::None,
+            PingCompression::Gzip(1),
+            PingCompression::Zstd(19),
+        ] {
+            let pretty = build(*compression).pretty_body().unwrap();
+            assert_eq!(
+                expected,
+                serde_json::from_str::<JsonValue>(&pretty).unwrap()
+            );
+        }
+    }
 }
@@ -57,6 +57,12 @@ pub fn new_glean(tempdir: Option<tempfile::TempDir>) -> (Glean, tempfile::TempDi
         upload_enabled: true,
         max_events: None,
         delay_ping_lifetime_io: false,
+        key_provider: None,
+        upload_batch_limits: None,
+        ping_compression: Default::default(),
+        server_endpoint: None,
+        path_template: None,
+        storage_durability: Default::default(),
     };
     let glean = Glean::new(cfg).unwrap();
 
@@ -108,6 +114,8 @@ pub fn get_deletion_pings(data_path: &Path) -> Result<Vec<(String, JsonValue, Op
     get_pings(&data_path.join("deletion_request"))
 }
 
+// Reads through `std::fs`, so tests relying on it can't run against the
+// in-memory store of the `wasm` feature.
 fn get_pings(pings_dir: &Path) -> Result<Vec<(String, JsonValue, Option<JsonValue>)>> {
     let entries = read_dir(pings_dir)?;
     Ok(entries
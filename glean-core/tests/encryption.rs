@@ -0,0 +1,274 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+mod common;
+use crate::common::*;
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::Duration;
+
+use glean_core::metrics::*;
+use glean_core::upload::{PingUploadTask, UploadResult};
+use glean_core::{CommonMetricData, Configuration, EncryptionKey, Glean, KeyProvider, Lifetime};
+
+const SECRET: &str = "a-secret-value";
+
+#[derive(Debug, Default)]
+struct TestKeys {
+    current: RwLock<Option<u32>>,
+    keys: RwLock<HashMap<u32, EncryptionKey>>,
+}
+
+impl TestKeys {
+    fn with_key(key_id: u32, key: EncryptionKey) -> Arc<Self> {
+        let keys = Arc::new(Self::default());
+        keys.keys.write().unwrap().insert(key_id, key);
+        *keys.current.write().unwrap() = Some(key_id);
+        keys
+    }
+}
+
+impl KeyProvider for TestKeys {
+    fn current_key(&self) -> Option<(u32, EncryptionKey)> {
+        let current = (*self.current.read().unwrap())?;
+        self.key(current).map(|key| (current, key))
+    }
+
+    fn key(&self, key_id: u32) -> Option<EncryptionKey> {
+        self.keys.read().unwrap().get(&key_id).copied()
+    }
+}
+
+fn new_encrypted_glean(data_path: &Path, keys: Option<Arc<TestKeys>>) -> Glean {
+    let cfg = Configuration {
+        data_path: data_path.display().to_string(),
+        application_id: GLOBAL_APPLICATION_ID.into(),
+        language_binding_name: "Rust".into(),
+        upload_enabled: true,
+        max_events: None,
+        delay_ping_lifetime_io: false,
+        key_provider: keys.map(|keys| keys as Arc<dyn KeyProvider>),
+        upload_batch_limits: None,
+        ping_compression: Default::default(),
+        server_endpoint: None,
+        path_template: None,
+        storage_durability: Default::default(),
+    };
+    Glean::new(cfg).unwrap()
+}
+
+fn string() -> StringMetric {
+    StringMetric::new(CommonMetricData {
+        name: "string".into(),
+        category: "telemetry".into(),
+        send_in_pings: vec!["store1".into()],
+        disabled: false,
+        lifetime: Lifetime::User,
+        ..Default::default()
+    })
+}
+
+fn event() -> EventMetric {
+    EventMetric::new(
+        CommonMetricData {
+            name: "event".into(),
+            category: "telemetry".into(),
+            send_in_pings: vec!["store1".into()],
+            disabled: false,
+            lifetime: Lifetime::Ping,
+            ..Default::default()
+        },
+        vec!["value".into()],
+    )
+}
+
+fn record_secrets(glean: &mut Glean) {
+    string().set(glean, SECRET);
+
+    let mut extra = HashMap::new();
+    extra.insert(0, SECRET.to_string());
+    event().record(glean, 1000, Some(extra));
+
+    let ping = PingType::new("secret", true, true, vec![]);
+    glean.register_ping_type(&ping);
+    StringMetric::new(CommonMetricData {
+        name: "string".into(),
+        category: "telemetry".into(),
+        send_in_pings: vec!["secret".into()],
+        disabled: false,
+        lifetime: Lifetime::Ping,
+        ..Default::default()
+    })
+    .set(glean, SECRET);
+    assert!(glean.submit_ping(&ping, None).unwrap());
+}
+
+/// Whether the secret can be found in any file under the given directory.
+fn secret_on_disk(dir: &Path) -> bool {
+    fs::read_dir(dir).unwrap().any(|entry| {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            secret_on_disk(&path)
+        } else {
+            let content = fs::read(&path).unwrap();
+            content
+                .windows(SECRET.len())
+                .any(|window| window == SECRET.as_bytes())
+        }
+    })
+}
+
+/// Get the bodies of all pings pending upload,
+/// waiting for the pending pings directory to be processed.
+fn pending_ping_bodies(glean: &Glean) -> Vec<String> {
+    let mut bodies = Vec::new();
+    loop {
+        match glean.get_upload_task() {
+            PingUploadTask::Wait => thread::sleep(Duration::from_millis(10)),
+            PingUploadTask::Upload(request) => {
+                glean.process_ping_upload_response(
+                    &request.document_id,
+                    UploadResult::HttpStatus(200),
+                );
+                bodies.push(request.pretty_body().unwrap());
+            }
+            PingUploadTask::Done => return bodies,
+            PingUploadTask::UploadBatch(_) => unreachable!("Batching is not enabled"),
+            PingUploadTask::WaitFor(..) => unreachable!("The network state is not set"),
+        }
+    }
+}
+
+#[test]
+#[cfg(not(feature = "wasm"))]
+fn nothing_is_stored_in_plaintext() {
+    let dir = tempfile::tempdir().unwrap();
+    let keys = TestKeys::with_key(1, [1; 32]);
+
+    {
+        let mut glean = new_encrypted_glean(dir.path(), Some(keys.clone()));
+        record_secrets(&mut glean);
+    }
+    assert!(!secret_on_disk(dir.path()));
+
+    // Everything can be read back with the right key.
+    let glean = new_encrypted_glean(dir.path(), Some(keys));
+    assert_eq!(
+        Some(SECRET.to_string()),
+        string().test_get_value(&glean, "store1")
+    );
+    assert!(pending_ping_bodies(&glean)
+        .iter()
+        .any(|body| body.contains(SECRET)));
+
+    // Without encryption the same data is stored in plaintext.
+    let dir = tempfile::tempdir().unwrap();
+    {
+        let mut glean = new_encrypted_glean(dir.path(), None);
+        record_secrets(&mut glean);
+    }
+    assert!(secret_on_disk(dir.path()));
+}
+
+#[test]
+#[cfg(not(feature = "wasm"))]
+fn wrong_or_missing_keys_are_handled() {
+    let dir = tempfile::tempdir().unwrap();
+    {
+        let mut glean = new_encrypted_glean(dir.path(), Some(TestKeys::with_key(1, [1; 32])));
+        record_secrets(&mut glean);
+    }
+
+    for keys in [Some(TestKeys::with_key(1, [2; 32])), None].iter().cloned() {
+        let glean = new_encrypted_glean(dir.path(), keys);
+        assert_eq!(None, string().test_get_value(&glean, "store1"));
+        assert!(!pending_ping_bodies(&glean)
+            .iter()
+            .any(|body| body.contains(SECRET)));
+    }
+
+    // Nothing was deleted, so the data is still there once the key is back.
+    let glean = new_encrypted_glean(dir.path(), Some(TestKeys::with_key(1, [1; 32])));
+    assert_eq!(
+        Some(SECRET.to_string()),
+        string().test_get_value(&glean, "store1")
+    );
+    assert!(get_queued_pings(dir.path()).is_ok());
+}
+
+#[test]
+fn keys_can_be_rotated() {
+    let dir = tempfile::tempdir().unwrap();
+    let keys = TestKeys::with_key(1, [1; 32]);
+    {
+        let mut glean = new_encrypted_glean(dir.path(), Some(keys.clone()));
+        record_secrets(&mut glean);
+    }
+
+    // Rotate to a new key. Data written with the retired key can still be read.
+    keys.keys.write().unwrap().insert(2, [2; 32]);
+    *keys.current.write().unwrap() = Some(2);
+    {
+        let glean = new_encrypted_glean(dir.path(), Some(keys.clone()));
+        assert_eq!(
+            Some(SECRET.to_string()),
+            string().test_get_value(&glean, "store1")
+        );
+        glean.reencrypt_storage().unwrap();
+    }
+
+    // After re-encrypting, the retired key is no longer needed.
+    assert!(!secret_on_disk(dir.path()));
+    let mut glean = new_encrypted_glean(dir.path(), Some(TestKeys::with_key(2, [2; 32])));
+    assert_eq!(
+        Some(SECRET.to_string()),
+        string().test_get_value(&glean, "store1")
+    );
+
+    // Submit the stored events, so they can be checked in the ping.
+    glean.register_ping_type(&PingType::new("store1", true, true, vec![]));
+    glean.on_ready_to_submit_pings();
+    let bodies = pending_ping_bodies(&glean);
+    assert_eq!(2, bodies.len());
+    assert!(bodies.iter().all(|body| body.contains(SECRET)));
+    assert!(bodies.iter().any(|body| body.contains("\"events\"")));
+}
+
+#[test]
+fn undecryptable_data_is_kept() {
+    let dir = tempfile::tempdir().unwrap();
+    let counter = CounterMetric::new(CommonMetricData {
+        name: "counter".into(),
+        category: "telemetry".into(),
+        send_in_pings: vec!["store1".into()],
+        disabled: false,
+        lifetime: Lifetime::User,
+        ..Default::default()
+    });
+    {
+        let mut glean = new_encrypted_glean(dir.path(), Some(TestKeys::with_key(1, [1; 32])));
+        record_secrets(&mut glean);
+        counter.add(&glean, 5);
+    }
+
+    // Neither recording nor re-encrypting with another key replaces the stored data.
+    {
+        let glean = new_encrypted_glean(dir.path(), Some(TestKeys::with_key(2, [2; 32])));
+        counter.add(&glean, 1);
+        glean.reencrypt_storage().unwrap();
+    }
+
+    let mut glean = new_encrypted_glean(dir.path(), Some(TestKeys::with_key(1, [1; 32])));
+    assert_eq!(Some(5), counter.test_get_value(&glean, "store1"));
+
+    glean.register_ping_type(&PingType::new("store1", true, true, vec![]));
+    glean.on_ready_to_submit_pings();
+    assert!(pending_ping_bodies(&glean)
+        .iter()
+        .any(|body| body.contains("\"events\"")));
+}
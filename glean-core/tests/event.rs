@@ -6,6 +6,7 @@ mod common;
 use crate::common::*;
 
 use std::collections::HashMap;
+#[cfg(not(feature = "wasm"))]
 use std::fs;
 
 use glean_core::metrics::*;
@@ -92,6 +93,7 @@ fn snapshot_returns_none_if_nothing_is_recorded_in_the_store() {
 }
 
 #[test]
+#[cfg(not(feature = "wasm"))]
 fn snapshot_correctly_clears_the_stores() {
     let (glean, _t) = new_glean(None);
 
@@ -146,6 +148,7 @@ fn snapshot_correctly_clears_the_stores() {
 // the schema is much more useful.
 
 #[test]
+#[cfg(not(feature = "wasm"))]
 fn test_sending_of_event_ping_when_it_fills_up() {
     let (mut glean, _t) = new_glean(None);
 
@@ -0,0 +1,198 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+mod common;
+use crate::common::*;
+
+use glean_core::metrics::*;
+use glean_core::{test_get_num_recorded_errors, ErrorType};
+use glean_core::{CommonMetricData, Configuration, Glean, Lifetime};
+
+fn new_child_glean(data_path: &str) -> Glean {
+    let cfg = Configuration {
+        data_path: data_path.into(),
+        application_id: GLOBAL_APPLICATION_ID.into(),
+        language_binding_name: "Rust".into(),
+        upload_enabled: true,
+        max_events: None,
+        delay_ping_lifetime_io: false,
+        key_provider: None,
+        upload_batch_limits: None,
+        ping_compression: Default::default(),
+        server_endpoint: None,
+        path_template: None,
+        storage_durability: Default::default(),
+    };
+    Glean::new_for_ipc_child(&cfg).unwrap()
+}
+
+fn counter(name: &str) -> CounterMetric {
+    CounterMetric::new(CommonMetricData {
+        name: name.into(),
+        category: "telemetry".into(),
+        send_in_pings: vec!["store1".into()],
+        disabled: false,
+        lifetime: Lifetime::Ping,
+        ..Default::default()
+    })
+}
+
+#[test]
+fn child_data_is_merged_into_the_main_process() {
+    let (glean, t) = new_glean(None);
+    let child = new_child_glean(&t.path().display().to_string());
+
+    let metric = counter("counter");
+    metric.add(&glean, 2);
+    metric.add(&child, 3);
+    metric.add(&child, 4);
+
+    let string = StringMetric::new(CommonMetricData {
+        name: "string".into(),
+        category: "telemetry".into(),
+        send_in_pings: vec!["store1".into()],
+        disabled: false,
+        lifetime: Lifetime::Ping,
+        ..Default::default()
+    });
+    string.set(&glean, "parent");
+    string.set(&child, "child");
+
+    let timing = TimingDistributionMetric::new(
+        CommonMetricData {
+            name: "timing".into(),
+            category: "telemetry".into(),
+            send_in_pings: vec!["store1".into()],
+            disabled: false,
+            lifetime: Lifetime::Ping,
+            ..Default::default()
+        },
+        TimeUnit::Nanosecond,
+    );
+    timing.accumulate_samples_signed(&glean, vec![1, 2]);
+    timing.accumulate_samples_signed(&child, vec![3, 4, 5]);
+
+    let event = EventMetric::new(
+        CommonMetricData {
+            name: "event".into(),
+            category: "telemetry".into(),
+            send_in_pings: vec!["store1".into()],
+            disabled: false,
+            lifetime: Lifetime::Ping,
+            ..Default::default()
+        },
+        vec![],
+    );
+    event.record(&glean, 1000, None);
+    event.record(&child, 2000, None);
+
+    let payload = child.take_ipc_payload().unwrap().unwrap();
+    assert!(child.take_ipc_payload().unwrap().is_none());
+
+    // Nothing recorded in the child reaches the main process storage before the payload is applied.
+    assert_eq!(Some(2), metric.test_get_value(&glean, "store1"));
+    assert_eq!(1, event.test_get_value(&glean, "store1").unwrap().len());
+
+    glean.apply_ipc_payload(&payload).unwrap();
+
+    assert_eq!(Some(9), metric.test_get_value(&glean, "store1"));
+    assert_eq!(
+        Some("child".to_string()),
+        string.test_get_value(&glean, "store1")
+    );
+    let snapshot = timing.test_get_value(&glean, "store1").unwrap();
+    assert_eq!(15, snapshot.sum);
+    let events = event.test_get_value(&glean, "store1").unwrap();
+    assert_eq!(2, events.len());
+    assert_eq!(2000, events[1].timestamp);
+}
+
+#[test]
+fn dynamic_labels_are_validated_in_the_main_process() {
+    let (glean, t) = new_glean(None);
+    let child = new_child_glean(&t.path().display().to_string());
+
+    let labeled = LabeledMetric::new(counter("labeled"), None);
+    labeled.get("valid_label").add(&child, 1);
+    labeled.get("Not a valid label").add(&child, 1);
+
+    let payload = child.take_ipc_payload().unwrap().unwrap();
+    glean.apply_ipc_payload(&payload).unwrap();
+
+    assert_eq!(
+        Some(1),
+        labeled.get("valid_label").test_get_value(&glean, "store1")
+    );
+    assert_eq!(
+        Some(1),
+        labeled.get("__other__").test_get_value(&glean, "store1")
+    );
+    assert_eq!(
+        Ok(1),
+        test_get_num_recorded_errors(
+            &glean,
+            labeled.get_submetric().meta(),
+            ErrorType::InvalidLabel,
+            Some("store1")
+        )
+    );
+}
+
+#[test]
+fn errors_in_the_child_are_recorded_in_the_main_process() {
+    let (glean, t) = new_glean(None);
+    let child = new_child_glean(&t.path().display().to_string());
+
+    let metric = counter("counter");
+    metric.add(&child, -1);
+
+    let payload = child.take_ipc_payload().unwrap().unwrap();
+    glean.apply_ipc_payload(&payload).unwrap();
+
+    assert_eq!(None, metric.test_get_value(&glean, "store1"));
+    assert_eq!(
+        Ok(1),
+        test_get_num_recorded_errors(&glean, metric.meta(), ErrorType::InvalidValue, None)
+    );
+}
+
+#[test]
+fn payloads_are_discarded_when_upload_is_disabled() {
+    let (mut glean, t) = new_glean(None);
+    let mut child = new_child_glean(&t.path().display().to_string());
+
+    let metric = counter("counter");
+    metric.add(&child, 1);
+    let payload = child.take_ipc_payload().unwrap().unwrap();
+
+    glean.set_upload_enabled(false);
+    glean.apply_ipc_payload(&payload).unwrap();
+    glean.set_upload_enabled(true);
+    assert_eq!(None, metric.test_get_value(&glean, "store1"));
+
+    // Disabling upload in the child drops anything buffered.
+    metric.add(&child, 1);
+    child.set_upload_enabled(false);
+    assert!(child.take_ipc_payload().unwrap().is_none());
+}
+
+#[test]
+fn child_processes_do_not_submit_pings() {
+    let (_glean, t) = new_glean(None);
+    let mut child = new_child_glean(&t.path().display().to_string());
+
+    let ping = PingType::new("store1", true, true, vec![]);
+    child.register_ping_type(&ping);
+    counter("counter").add(&child, 1);
+
+    assert!(!child.submit_ping(&ping, None).unwrap());
+    assert!(get_queued_pings(t.path()).is_err());
+}
+
+#[test]
+fn invalid_payloads_are_rejected() {
+    let (glean, _t) = new_glean(None);
+
+    assert!(glean.apply_ipc_payload(&[0xff, 0xff, 0xff]).is_err());
+}
@@ -2,6 +2,9 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
+// These tests inspect pending pings on disk.
+#![cfg(not(feature = "wasm"))]
+
 mod common;
 use crate::common::*;
 
@@ -147,6 +147,7 @@ fn seq_number_must_be_sequential() {
 }
 
 #[test]
+#[cfg(not(feature = "wasm"))]
 fn clear_pending_pings() {
     let (mut glean, _) = new_glean(None);
     let ping_maker = PingMaker::new();
@@ -174,6 +175,7 @@ fn clear_pending_pings() {
 }
 
 #[test]
+#[cfg(not(feature = "wasm"))]
 fn no_pings_submitted_if_upload_disabled() {
     // Regression test, bug 1603571
 
@@ -196,6 +198,7 @@ fn no_pings_submitted_if_upload_disabled() {
 }
 
 #[test]
+#[cfg(not(feature = "wasm"))]
 fn metadata_is_correctly_added_when_necessary() {
     let (mut glean, _) = new_glean(None);
     glean.set_debug_view_tag("valid-tag");
@@ -0,0 +1,141 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+mod common;
+use crate::common::*;
+
+#[cfg(not(feature = "wasm"))]
+use std::fs;
+
+use glean_core::metrics::*;
+use glean_core::{CommonMetricData, ImportPolicy, Lifetime};
+
+fn client_id() -> UuidMetric {
+    UuidMetric::new(CommonMetricData {
+        name: "client_id".into(),
+        category: "".into(),
+        send_in_pings: vec!["glean_client_info".into()],
+        lifetime: Lifetime::User,
+        disabled: false,
+        ..Default::default()
+    })
+}
+
+fn counter() -> CounterMetric {
+    CounterMetric::new(CommonMetricData {
+        name: "counter".into(),
+        category: "telemetry".into(),
+        send_in_pings: vec!["store1".into()],
+        disabled: false,
+        lifetime: Lifetime::Ping,
+        ..Default::default()
+    })
+}
+
+fn event() -> EventMetric {
+    EventMetric::new(
+        CommonMetricData {
+            name: "event".into(),
+            category: "telemetry".into(),
+            send_in_pings: vec!["store1".into()],
+            disabled: false,
+            lifetime: Lifetime::Ping,
+            ..Default::default()
+        },
+        vec![],
+    )
+}
+
+#[test]
+fn state_round_trips_through_an_archive() {
+    let (mut glean, dir) = new_glean(None);
+    counter().add(&glean, 3);
+    event().record(&glean, 1000, None);
+    glean.set_experiment_active("experiment".into(), "branch".into(), None);
+
+    let pending = PingType::new("pending", true, true, vec![]);
+    glean.register_ping_type(&pending);
+    counter().add(&glean, 1);
+    CounterMetric::new(CommonMetricData {
+        name: "other".into(),
+        category: "telemetry".into(),
+        send_in_pings: vec!["pending".into()],
+        disabled: false,
+        lifetime: Lifetime::Ping,
+        ..Default::default()
+    })
+    .add(&glean, 1);
+    assert!(glean.submit_ping(&pending, None).unwrap());
+
+    let archive = dir.path().join("state");
+    glean.export_state(&archive).unwrap();
+
+    let (other, _t) = new_glean(None);
+    assert_ne!(
+        client_id().test_get_value(&glean, "glean_client_info"),
+        client_id().test_get_value(&other, "glean_client_info")
+    );
+
+    other
+        .import_state(&archive, ImportPolicy::Overwrite)
+        .unwrap();
+
+    assert_eq!(
+        client_id().test_get_value(&glean, "glean_client_info"),
+        client_id().test_get_value(&other, "glean_client_info")
+    );
+    assert_eq!(Some(4), counter().test_get_value(&other, "store1"));
+    assert_eq!(1, event().test_get_value(&other, "store1").unwrap().len());
+    assert!(other.test_is_experiment_active("experiment".into()));
+    // Imported pings are only picked up by the uploader on the next start,
+    // so look for them on disk.
+    #[cfg(not(feature = "wasm"))]
+    assert_eq!(1, get_queued_pings(_t.path()).unwrap().len());
+}
+
+#[test]
+fn merging_keeps_existing_state() {
+    let (glean, dir) = new_glean(None);
+    event().record(&glean, 1000, None);
+
+    let archive = dir.path().join("state");
+    glean.export_state(&archive).unwrap();
+
+    let (other, _t) = new_glean(None);
+    event().record(&other, 2000, None);
+    other.import_state(&archive, ImportPolicy::Merge).unwrap();
+    assert_eq!(2, event().test_get_value(&other, "store1").unwrap().len());
+
+    other
+        .import_state(&archive, ImportPolicy::Overwrite)
+        .unwrap();
+    assert_eq!(1, event().test_get_value(&other, "store1").unwrap().len());
+}
+
+#[test]
+// Corrupts the archive through `std::fs`, which the in-memory store doesn't see.
+#[cfg(not(feature = "wasm"))]
+fn incompatible_archives_are_refused() {
+    let (glean, _t) = new_glean(None);
+    counter().add(&glean, 1);
+
+    let archive_dir = tempfile::tempdir().unwrap();
+    let archive = archive_dir.path().join("state");
+    glean.export_state(&archive).unwrap();
+
+    // Bump the format version right after the marker.
+    let mut content = fs::read(&archive).unwrap();
+    content[8] = 0xff;
+    fs::write(&archive, content).unwrap();
+
+    let (other, _t) = new_glean(None);
+    counter().add(&other, 5);
+    assert!(other
+        .import_state(&archive, ImportPolicy::Overwrite)
+        .is_err());
+    assert_eq!(Some(5), counter().test_get_value(&other, "store1"));
+
+    fs::write(&archive, b"not a state archive").unwrap();
+    assert!(other.import_state(&archive, ImportPolicy::Merge).is_err());
+}
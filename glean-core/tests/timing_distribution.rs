@@ -144,7 +144,7 @@ fn timing_distributions_must_not_accumulate_negative_values() {
 fn the_accumulate_samples_api_correctly_stores_timing_values() {
     let (glean, _t) = new_glean(None);
 
-    let mut metric = TimingDistributionMetric::new(
+    let metric = TimingDistributionMetric::new(
         CommonMetricData {
             name: "distribution".into(),
             category: "telemetry".into(),
@@ -190,7 +190,7 @@ fn the_accumulate_samples_api_correctly_stores_timing_values() {
 fn the_accumulate_samples_api_correctly_handles_negative_values() {
     let (glean, _t) = new_glean(None);
 
-    let mut metric = TimingDistributionMetric::new(
+    let metric = TimingDistributionMetric::new(
         CommonMetricData {
             name: "distribution".into(),
             category: "telemetry".into(),
@@ -233,7 +233,7 @@ fn the_accumulate_samples_api_correctly_handles_negative_values() {
 fn the_accumulate_samples_api_correctly_handles_overflowing_values() {
     let (glean, _t) = new_glean(None);
 
-    let mut metric = TimingDistributionMetric::new(
+    let metric = TimingDistributionMetric::new(
         CommonMetricData {
             name: "distribution".into(),
             category: "telemetry".into(),
@@ -0,0 +1,132 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Tests for the `wasm` feature, which keeps all data in memory.
+//!
+//! These run on the host with `cargo test --features wasm --test wasm`
+//! and in a headless WebAssembly runtime with `make test-rust-wasm`.
+
+#![cfg(feature = "wasm")]
+
+#[cfg(target_arch = "wasm32")]
+use wasm_bindgen_test::wasm_bindgen_test as test;
+
+use glean_core::metrics::*;
+use glean_core::upload::{PingRequest, PingUploadTask, UploadResult};
+use glean_core::{CommonMetricData, Glean, Lifetime};
+use serde_json::Value as JsonValue;
+
+/// Create a new instance of Glean.
+///
+/// Data is shared by path for the lifetime of the process,
+/// so each test uses its own path.
+fn new_glean(data_path: &str) -> Glean {
+    let cfg = glean_core::Configuration {
+        data_path: data_path.into(),
+        application_id: "org.mozilla.glean.test.app".into(),
+        language_binding_name: "Rust".into(),
+        upload_enabled: true,
+        max_events: None,
+        delay_ping_lifetime_io: false,
+        key_provider: None,
+        upload_batch_limits: None,
+        ping_compression: Default::default(),
+        server_endpoint: None,
+        path_template: None,
+        storage_durability: Default::default(),
+    };
+    Glean::new(cfg).unwrap()
+}
+
+fn meta(name: &str, lifetime: Lifetime) -> CommonMetricData {
+    CommonMetricData {
+        name: name.into(),
+        category: "local".into(),
+        send_in_pings: vec!["store1".into()],
+        lifetime,
+        ..Default::default()
+    }
+}
+
+/// Get the next ping to upload, which must be available right away.
+fn next_request(glean: &Glean) -> PingRequest {
+    match glean.get_upload_task() {
+        PingUploadTask::Upload(request) => request,
+        task => panic!("Expected a ping to upload, got {:?}", task),
+    }
+}
+
+fn payload(request: &PingRequest) -> JsonValue {
+    serde_json::from_str(&request.pretty_body().unwrap()).unwrap()
+}
+
+#[test]
+fn metrics_are_kept_when_reopening_the_database() {
+    let data_path = "/glean/metrics_are_kept";
+    let metric = StringMetric::new(meta("string", Lifetime::User));
+
+    {
+        let glean = new_glean(data_path);
+        metric.set(&glean, "persisted");
+    }
+
+    let glean = new_glean(data_path);
+    assert_eq!(
+        Some("persisted".to_string()),
+        metric.test_get_value(&glean, "store1")
+    );
+}
+
+#[test]
+fn pending_pings_are_scanned_synchronously() {
+    let data_path = "/glean/pending_pings";
+    let ping = PingType::new("store1", true, false, vec![]);
+    let metric = StringMetric::new(meta("string", Lifetime::Ping));
+
+    let document_id = {
+        let mut glean = new_glean(data_path);
+        glean.register_ping_type(&ping);
+        metric.set(&glean, "submitted");
+        assert!(ping.submit(&glean, None).unwrap());
+        // The ping is not uploaded, so it stays pending.
+        next_request(&glean).document_id
+    };
+
+    let glean = new_glean(data_path);
+    let request = next_request(&glean);
+    assert_eq!(document_id, request.document_id);
+    assert_eq!(
+        "submitted",
+        payload(&request)["metrics"]["string"]["local.string"]
+    );
+
+    glean.process_ping_upload_response(&request.document_id, UploadResult::HttpStatus(200));
+    match glean.get_upload_task() {
+        PingUploadTask::Done => {}
+        task => panic!("Expected no more pings, got {:?}", task),
+    }
+}
+
+#[test]
+fn events_are_submitted_after_reopening() {
+    let data_path = "/glean/events";
+    let ping = PingType::new("store1", true, false, vec![]);
+    let event = EventMetric::new(meta("event", Lifetime::Ping), vec![]);
+
+    {
+        let glean = new_glean(data_path);
+        event.record(&glean, 1000, None);
+        assert!(event.test_has_value(&glean, "store1"));
+    }
+
+    let mut glean = new_glean(data_path);
+    glean.register_ping_type(&ping);
+    assert!(glean.on_ready_to_submit_pings());
+
+    let request = next_request(&glean);
+    let payload = payload(&request);
+    assert_eq!("startup", payload["ping_info"]["reason"]);
+    assert_eq!("event", payload["events"][0]["name"]);
+    assert!(!event.test_has_value(&glean, "store1"));
+}